@@ -0,0 +1,174 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Benchmarks for move generation and game replay. Run with `cargo bench`;
+//! pass a filter (e.g. `cargo bench -- perft`) to run a single group.
+
+use chesslib::*;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+/// Seed for the random games replayed by the benchmarks, fixed so runs
+/// are comparable.
+const SEED: u64 = 4829;
+
+/// Upper bound on the length of the random games, in plies.
+const MAX_PLIES: usize = 300;
+
+/// Counts the leaf nodes of the move tree `depth` plies deep.
+fn perft(state: &MoveState, depth: usize) -> usize {
+    let moves = candidate_moves(state);
+    if depth <= 1 {
+        return if depth == 1 { moves.len() } else { 1 };
+    }
+    moves.into_iter()
+        .map(|mv| {
+            let mut next = state.fork();
+            let legal_move = next.validate_move(mv).expect("candidate move is legal");
+            next.apply_move(legal_move).expect("legal move applies");
+            perft(&next, depth - 1)
+        })
+        .sum()
+}
+
+/// Plays a reproducible random game and returns its moves.
+fn random_game() -> Vec<Move> {
+    let mut board = EngineBoard::standard();
+    let mut strategy = RandomMover::seeded(SEED);
+    let mut moves = Vec::new();
+    while board.board_result().is_none() && moves.len() < MAX_PLIES {
+        let Some(mv) = board.choose_move(&mut strategy) else { break };
+        board.submit_move(mv).expect("chosen move is legal");
+        moves.push(mv);
+    }
+    moves
+}
+
+/// Returns the move states reached over `moves`, starting with the
+/// standard position.
+fn game_states(moves: &[Move]) -> Vec<MoveState> {
+    let mut state = MoveState::default();
+    let mut states = vec![state.fork()];
+    for &mv in moves {
+        let legal_move = state.validate_move(mv).expect("game move is legal");
+        state.apply_move(legal_move).expect("legal move applies");
+        states.push(state.fork());
+    }
+    states
+}
+
+fn move_generation(c: &mut Criterion) {
+    let states = game_states(&random_game());
+    let mut group = c.benchmark_group("movegen");
+    group.bench_function("candidate_moves", |b| {
+        b.iter(|| states.iter().map(|state| candidate_moves(black_box(state)).len()).sum::<usize>())
+    });
+    group.bench_function("legal_moves", |b| {
+        b.iter(|| {
+            states.iter()
+                .flat_map(|state| state.ours().iter().map(move |from| state.legal_moves(from)))
+                .map(|moves| moves.destinations().len())
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+}
+
+fn attack_tables(c: &mut Criterion) {
+    // Applying a move resets the state's attack tables, which are rebuilt
+    // on the next query; measure that rebuild from fresh states
+    let positions: Vec<Position> = game_states(&random_game()).into_iter()
+        .map(|state| AsRef::<Position>::as_ref(&state).clone())
+        .collect();
+    c.bench_function("reset/attack_tables", |b| {
+        b.iter_batched(
+            || positions.iter().cloned().map(MoveState::new).collect::<Vec<_>>(),
+            |states| states.iter().filter(|state| state.is_check()).count(),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn apply_move(c: &mut Criterion) {
+    let moves = random_game();
+    let states = game_states(&moves);
+    let legal_moves: Vec<LegalMove> = states.iter().zip(&moves)
+        .map(|(state, &mv)| state.validate_move(mv).expect("game move is legal"))
+        .collect();
+    c.bench_function("apply_move", |b| {
+        b.iter_batched(
+            || states.iter().map(MoveState::fork).collect::<Vec<_>>(),
+            |mut states| {
+                for (state, &mv) in states.iter_mut().zip(&legal_moves) {
+                    state.apply_move(mv).expect("legal move applies");
+                }
+                states
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn san_round_trip(c: &mut Criterion) {
+    let moves = random_game();
+    let states = game_states(&moves);
+    let legal_moves: Vec<LegalMove> = states.iter().zip(&moves)
+        .map(|(state, &mv)| state.validate_move(mv).expect("game move is legal"))
+        .collect();
+    let sans: Vec<String> = states.iter().zip(&legal_moves)
+        .map(|(state, &mv)| state.to_san(mv))
+        .collect();
+    let mut group = c.benchmark_group("san");
+    group.bench_function("to_san", |b| {
+        b.iter(|| {
+            states.iter().zip(&legal_moves)
+                .map(|(state, &mv)| state.to_san(black_box(mv)))
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("from_san", |b| {
+        b.iter(|| {
+            states.iter().zip(&sans)
+                .map(|(state, san)| state.from_san(black_box(san)).expect("SAN parses"))
+                .collect::<Vec<_>>()
+        })
+    });
+    group.finish();
+}
+
+fn replay(c: &mut Criterion) {
+    let moves = random_game();
+    let mut group = c.benchmark_group("replay");
+    group.bench_function("engine_board", |b| {
+        b.iter(|| EngineBoard::replay(None, black_box(&moves)).expect("game replays"))
+    });
+    group.bench_function("player_board", |b| {
+        b.iter(|| PlayerBoard::replay(Color::White, None, black_box(&moves)).expect("game replays"))
+    });
+    group.finish();
+}
+
+fn perft_standard(c: &mut Criterion) {
+    let state = MoveState::default();
+    // Reference node counts for the standard starting position
+    assert_eq!(perft(&state, 3), 8_902);
+    let mut group = c.benchmark_group("perft");
+    group.sample_size(10);
+    for depth in 1..=4 {
+        group.bench_function(format!("depth_{}", depth), |b| b.iter(|| perft(black_box(&state), depth)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, move_generation, attack_tables, apply_move, san_round_trip, replay, perft_standard);
+criterion_main!(benches);
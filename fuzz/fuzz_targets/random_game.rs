@@ -0,0 +1,37 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Plays a game chosen by the fuzzer's input: the first two bytes pick
+//! the back rank and each following byte picks one of the legal moves.
+//! Every position reached must be valid, and the `cross-check` feature
+//! checks its legal moves against the reference generator as they're
+//! listed. Run with `cargo fuzz run random_game`.
+
+#![no_main]
+
+use chesslib::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let [high, low, moves @ ..] = data else { return };
+    let id = BackRankId::try_from(u16::from_be_bytes([*high, *low]) as usize % 960).unwrap();
+    let mut state = MoveState::new(Position::starting(id).clone());
+    for &choice in moves {
+        state.validate().expect("valid move state");
+        let candidates = candidate_moves(&state);
+        let Some(&mv) = candidates.get(choice as usize % candidates.len().max(1)) else { return };
+        let legal_move = state.validate_move(mv).expect("candidate move is legal");
+        state.apply_move(legal_move).expect("legal move applies");
+    }
+});
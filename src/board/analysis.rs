@@ -0,0 +1,335 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Caching engine analysis by position. Positions are identified by a
+//! Zobrist hash, so transpositions share their analysis; a cache slot
+//! holds every line of a multi-PV search.
+
+use serde::{Deserialize, Serialize};
+
+use super::annotation::Eval;
+use super::castling::Castling;
+use super::material::Color;
+use super::moves::Move;
+use super::position::Position;
+use super::variant::Variant;
+use super::Turn;
+
+/// Piece-square keys, then the side to move, castling rights and the en
+/// passant file.
+const ZOBRIST_KEYS: usize = 12 * 64 + 1 + 4 + 8;
+const TURN_KEY: usize = 12 * 64;
+const CASTLING_KEYS: usize = TURN_KEY + 1;
+const EN_PASSANT_KEYS: usize = CASTLING_KEYS + 4;
+
+const ZOBRIST_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// Fixed pseudo-random keys (splitmix64), so hashes are the same in every
+/// build and can be persisted.
+static ZOBRIST: [u64; ZOBRIST_KEYS] = {
+    let mut keys = [0u64; ZOBRIST_KEYS];
+    let mut state = ZOBRIST_SEED;
+    let mut index = 0;
+    while index < ZOBRIST_KEYS {
+        keys[index] = splitmix64(state);
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        index += 1;
+    }
+    keys
+};
+
+/// One step of splitmix64: a well spread 64-bit value for each input.
+pub(crate) const fn splitmix64(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// The key of a variant, derived from its name so that variants
+/// registered in a different order keep their hashes. `Standard` has
+/// none, so the variant only matters when the rules differ.
+fn variant_key(variant: Variant) -> u64 {
+    match variant {
+        Variant::Standard => 0,
+        _ => variant.name().bytes().fold(!ZOBRIST_SEED, |hash, byte| splitmix64(hash ^ u64::from(byte))),
+    }
+}
+
+impl Position {
+    /// Returns the Zobrist hash of the position: its variant, pieces, side
+    /// to move, castling rights and en passant square. Positions of one
+    /// variant equal under `key` hash the same; unrelated ones collide
+    /// with negligible odds.
+    pub fn zobrist(&self) -> u64 {
+        let mut hash = variant_key(self.variant());
+        for (square, material) in self.placements() {
+            let piece = material.color().to_index() * 6 + material.piece().to_index();
+            hash ^= ZOBRIST[piece * 64 + square.to_index()];
+        }
+        if self.turn() == Color::Black {
+            hash ^= ZOBRIST[TURN_KEY];
+        }
+        let ours = self.our_castling();
+        let theirs = self.their_castling();
+        let (white, black) = match self.turn() {
+            Color::White => (&ours, &theirs),
+            Color::Black => (&theirs, &ours),
+        };
+        let rights = [white.oo(), white.ooo(), black.oo(), black.ooo()];
+        for (index, _) in rights.iter().enumerate().filter(|(_, &right)| right) {
+            hash ^= ZOBRIST[CASTLING_KEYS + index];
+        }
+        if let Some(square) = self.en_passant() {
+            hash ^= ZOBRIST[EN_PASSANT_KEYS + square.file().to_index()];
+        }
+        hash
+    }
+}
+
+/// A line found by an engine: its evaluation from White's point of view
+/// and the moves it expects, starting from the analysed position.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PvLine {
+    pub eval: Eval,
+    pub moves: Vec<Move>,
+}
+
+/// The analysis of one position, to a given depth.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AnalysisEntry {
+    pub hash: u64,
+    pub depth: u16,
+    /// The lines found, best first (`lines[0]` is MultiPV 1).
+    pub lines: Vec<PvLine>,
+    /// The cache generation the entry was stored in, see
+    /// `AnalysisCache::new_generation`.
+    pub generation: u32,
+}
+
+impl AnalysisEntry {
+    pub fn best_line(&self) -> Option<&PvLine> {
+        self.lines.first()
+    }
+
+    pub fn eval(&self) -> Option<Eval> {
+        self.best_line().map(|line| line.eval)
+    }
+}
+
+/// Decides whether new analysis displaces a different position's analysis
+/// occupying the same slot. The same position's analysis is replaced
+/// whenever the new one is at least as deep.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ReplacementPolicy {
+    /// The newest analysis always wins.
+    Always,
+    /// Deeper analysis is kept, unless it's from an earlier generation.
+    #[default]
+    DepthPreferred,
+}
+
+/// A fixed-size cache of engine analysis keyed by `Position::zobrist`,
+/// like an engine's transposition table. Each position maps to one slot;
+/// when two positions compete for a slot, the `ReplacementPolicy` picks
+/// which to keep.
+#[derive(Debug, Clone)]
+pub struct AnalysisCache {
+    slots: Vec<Option<AnalysisEntry>>,
+    policy: ReplacementPolicy,
+    generation: u32,
+    len: usize,
+}
+
+impl AnalysisCache {
+    /// Creates a cache holding at most `capacity` positions (at least one).
+    pub fn new(capacity: usize, policy: ReplacementPolicy) -> Self {
+        Self {
+            slots: vec![None; capacity.max(1)],
+            policy,
+            generation: 0,
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+        self.len = 0;
+    }
+
+    /// Starts a new generation, e.g. for a new search or once the game has
+    /// moved on, so that older analysis gives way under
+    /// `ReplacementPolicy::DepthPreferred`.
+    pub fn new_generation(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// Returns the analysis of `pos`, if cached.
+    pub fn get(&self, pos: &Position) -> Option<&AnalysisEntry> {
+        let hash = pos.zobrist();
+        self.slots[self.slot(hash)].as_ref().filter(|entry| entry.hash == hash)
+    }
+
+    /// Returns the analysis of `pos` if it's at least `depth` deep.
+    pub fn get_at_depth(&self, pos: &Position, depth: u16) -> Option<&AnalysisEntry> {
+        self.get(pos).filter(|entry| entry.depth >= depth)
+    }
+
+    /// Stores the analysis of `pos` to `depth`, with its lines best first.
+    /// Returns false if the policy kept the analysis already there.
+    pub fn insert(&mut self, pos: &Position, depth: u16, lines: Vec<PvLine>) -> bool {
+        let hash = pos.zobrist();
+        let slot = self.slot(hash);
+        if !self.replaces(slot, hash, depth) {
+            return false;
+        }
+        if self.slots[slot].is_none() {
+            self.len += 1;
+        }
+        self.slots[slot] = Some(AnalysisEntry { hash, depth, lines, generation: self.generation });
+        true
+    }
+
+    /// Stores one line of a multi-PV search as it's reported: `rank` 1 is
+    /// the best line. Lines of the same depth are merged into one entry;
+    /// a deeper search starts a new one. Returns false if the line wasn't
+    /// stored.
+    pub fn insert_line(&mut self, pos: &Position, depth: u16, rank: usize, line: PvLine) -> bool {
+        let Some(index) = rank.checked_sub(1) else { return false };
+        let hash = pos.zobrist();
+        let slot = self.slot(hash);
+        if let Some(entry) = self.slots[slot].as_mut().filter(|entry| entry.hash == hash && entry.depth == depth) {
+            if index > entry.lines.len() {
+                return false;
+            }
+            if index == entry.lines.len() {
+                entry.lines.push(line);
+            } else {
+                entry.lines[index] = line;
+            }
+            entry.generation = self.generation;
+            return true;
+        }
+        index == 0 && self.insert(pos, depth, vec![line])
+    }
+
+    fn slot(&self, hash: u64) -> usize {
+        (hash % self.slots.len() as u64) as usize
+    }
+
+    fn replaces(&self, slot: usize, hash: u64, depth: u16) -> bool {
+        let Some(entry) = &self.slots[slot] else { return true };
+        if entry.hash == hash {
+            return depth >= entry.depth;
+        }
+        match self.policy {
+            ReplacementPolicy::Always => true,
+            ReplacementPolicy::DepthPreferred => entry.generation != self.generation || depth >= entry.depth,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn play(moves: &[&str]) -> Position {
+        let moves: Vec<Move> = moves.iter().map(|uci| uci.parse().unwrap()).collect();
+        let board = EngineBoard::replay(None, &moves).unwrap();
+        let pos: &Position = board.as_ref();
+        pos.clone()
+    }
+
+    fn line(cp: i32, moves: &[&str]) -> PvLine {
+        PvLine { eval: Eval::Centipawns(cp), moves: moves.iter().map(|uci| uci.parse().unwrap()).collect() }
+    }
+
+    #[test]
+    fn test_zobrist() {
+        let start = Position::default();
+        // transpositions hash the same
+        let a = play(&["g1f3", "g8f6", "b1c3"]);
+        let b = play(&["b1c3", "g8f6", "g1f3"]);
+        assert_eq!(a.zobrist(), b.zobrist());
+        assert_ne!(a.zobrist(), start.zobrist());
+        // the side to move and castling rights count
+        let there_and_back = play(&["g1f3", "g8f6", "f3g1", "f6g8"]);
+        assert_eq!(there_and_back.zobrist(), start.zobrist());
+        let king_walk = play(&["e2e4", "e7e5", "e1e2", "e8e7", "e2e1", "e7e8"]);
+        assert_ne!(king_walk.zobrist(), play(&["e2e4", "e7e5"]).zobrist());
+        assert_ne!(play(&["g1f3"]).zobrist(), play(&["g1f3", "g8f6", "f3g1"]).zobrist());
+        // so does the variant
+        assert_ne!(Position::new_variant(BackRankId::default().into(), Variant::Antichess).zobrist(), start.zobrist());
+    }
+
+    #[test]
+    fn test_analysis_cache() {
+        let start = Position::default();
+        let e4 = play(&["e2e4"]);
+        let mut cache = AnalysisCache::new(64, ReplacementPolicy::DepthPreferred);
+        assert!(cache.insert(&start, 10, vec![line(30, &["e2e4"])]));
+        assert_eq!(cache.get(&start).unwrap().eval(), Some(Eval::Centipawns(30)));
+        assert!(cache.get(&e4).is_none());
+        assert!(cache.get_at_depth(&start, 12).is_none());
+        // shallower analysis of the same position is ignored
+        assert!(!cache.insert(&start, 8, vec![line(10, &["d2d4"])]));
+        assert!(cache.insert(&start, 12, vec![line(25, &["d2d4"])]));
+        assert_eq!(cache.len(), 1);
+
+        // multi-PV lines arrive one at a time
+        assert!(cache.insert_line(&e4, 15, 1, line(-20, &["c7c5"])));
+        assert!(!cache.insert_line(&e4, 15, 3, line(-40, &["e7e6"])));
+        assert!(cache.insert_line(&e4, 15, 2, line(-25, &["e7e5"])));
+        assert!(cache.insert_line(&e4, 15, 1, line(-15, &["c7c5", "g1f3"])));
+        let entry = cache.get(&e4).unwrap();
+        assert_eq!(entry.lines.len(), 2);
+        assert_eq!(entry.best_line().unwrap().moves.len(), 2);
+        assert!(cache.insert_line(&e4, 16, 1, line(-10, &["e7e5"])));
+        assert_eq!(cache.get(&e4).unwrap().lines.len(), 1);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_replacement_policies() {
+        let start = Position::default();
+        let e4 = play(&["e2e4"]);
+        // with a single slot every position competes for it
+        let mut cache = AnalysisCache::new(1, ReplacementPolicy::DepthPreferred);
+        cache.insert(&start, 20, vec![line(30, &["e2e4"])]);
+        assert!(!cache.insert(&e4, 10, vec![line(-20, &["c7c5"])]));
+        assert!(cache.get(&start).is_some());
+        cache.new_generation();
+        assert!(cache.insert(&e4, 10, vec![line(-20, &["c7c5"])]));
+        assert!(cache.get(&start).is_none());
+        assert_eq!(cache.len(), 1);
+
+        let mut cache = AnalysisCache::new(1, ReplacementPolicy::Always);
+        cache.insert(&start, 20, vec![line(30, &["e2e4"])]);
+        assert!(cache.insert(&e4, 10, vec![line(-20, &["c7c5"])]));
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
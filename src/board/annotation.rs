@@ -0,0 +1,567 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Write};
+use std::str::FromStr;
+use std::time::Duration;
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use super::square::{ParseError, Square};
+
+/// A Numeric Annotation Glyph judging a move. Only the six move
+/// assessments with a conventional symbol are supported.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, EnumIter)]
+pub enum Nag {
+    Good,
+    Mistake,
+    Brilliant,
+    Blunder,
+    Interesting,
+    Dubious,
+}
+
+impl Nag {
+    /// Returns the PGN code, written as `$<code>` in movetext.
+    pub const fn code(&self) -> u8 {
+        *self as u8 + 1
+    }
+    pub fn from_code(code: u8) -> Option<Self> {
+        Self::iter().find(|nag| nag.code() == code)
+    }
+    pub const fn symbol(&self) -> &'static str {
+        const SYMBOLS: [&str; 6] = ["!", "?", "!!", "??", "!?", "?!"];
+        SYMBOLS[*self as usize]
+    }
+}
+
+impl fmt::Display for Nag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+/// Parses either the symbol (`!?`) or the PGN code (`$5`).
+impl FromStr for Nag {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let by_code = || s.strip_prefix('$')
+            .and_then(|code| code.parse().ok())
+            .and_then(Self::from_code);
+        Self::iter()
+            .find(|nag| nag.symbol() == s)
+            .or_else(by_code)
+            .ok_or_else(|| ParseError::InvalidNag(s.to_string()))
+    }
+}
+
+/// An engine evaluation from White's point of view, as written in a PGN
+/// `[%eval ...]` command: pawns with two decimals (`0.35`, `-1.20`) or a
+/// forced mate in a number of moves (`#3`, `#-2` when Black mates).
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Eval {
+    Centipawns(i32),
+    Mate(i32),
+}
+
+impl fmt::Display for Eval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Eval::Centipawns(cp) => {
+                let sign = if cp < 0 { "-" } else { "" };
+                write!(f, "{}{}.{:02}", sign, cp.unsigned_abs() / 100, cp.unsigned_abs() % 100)
+            },
+            Eval::Mate(moves) => write!(f, "#{}", moves),
+        }
+    }
+}
+
+/// Parses the argument of an `[%eval ...]` command. Any search depth
+/// following a comma (`0.35,24`) is ignored.
+impl FromStr for Eval {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseError::InvalidEval(s.to_string());
+        let value = s.split(',').next().unwrap_or_default().trim();
+        if let Some(moves) = value.strip_prefix('#') {
+            return moves.parse().map(Eval::Mate).map_err(|_| invalid());
+        }
+        let pawns: f64 = value.parse().map_err(|_| invalid())?;
+        if !pawns.is_finite() || pawns.abs() > (i32::MAX / 100) as f64 {
+            return Err(invalid());
+        }
+        Ok(Eval::Centipawns((pawns * 100.0).round() as i32))
+    }
+}
+
+/// Formats a clock reading as in a PGN `[%clk ...]` command: `h:mm:ss`,
+/// with tenths of a second if there are any.
+pub fn format_clock(time: Duration) -> String {
+    let secs = time.as_secs();
+    let mut text = format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+    let tenths = time.subsec_millis() / 100;
+    if tenths > 0 {
+        let _ = write!(text, ".{}", tenths);
+    }
+    text
+}
+
+/// Parses the argument of a PGN `[%clk ...]` command (`h:mm:ss` with
+/// optional fractional seconds).
+pub fn parse_clock(s: &str) -> Result<Duration, ParseError> {
+    let invalid = || ParseError::InvalidClock(s.to_string());
+    let parts: Vec<&str> = s.trim().split(':').collect();
+    let [hours, minutes, seconds] = parts[..] else { return Err(invalid()) };
+    let (whole, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+    let number = |text: &str| text.parse::<u64>().map_err(|_| invalid());
+    let (hours, minutes, whole) = (number(hours)?, number(minutes)?, number(whole)?);
+    if minutes >= 60 || whole >= 60 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    // only milliseconds are kept
+    let millis = format!("{:0<3}", &fraction[..fraction.len().min(3)]);
+    let millis = number(&millis)?;
+    let secs = hours.checked_mul(3600)
+        .and_then(|secs| secs.checked_add(minutes * 60 + whole))
+        .ok_or_else(invalid)?;
+    Ok(Duration::from_secs(secs) + Duration::from_millis(millis))
+}
+
+/// The colors arrows and highlights are drawn in, written as their
+/// initial in PGN `[%cal ...]` and `[%csl ...]` commands.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, EnumIter)]
+pub enum MarkColor {
+    #[default]
+    Green,
+    Red,
+    Yellow,
+    Blue,
+}
+
+impl MarkColor {
+    pub const fn to_char(&self) -> char {
+        const CHARS: [char; 4] = ['G', 'R', 'Y', 'B'];
+        CHARS[*self as usize]
+    }
+    pub fn from_char(c: char) -> Option<Self> {
+        Self::iter().find(|color| color.to_char() == c)
+    }
+}
+
+/// An arrow drawn from one square to another.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Arrow {
+    pub from: Square,
+    pub to: Square,
+    pub color: MarkColor,
+}
+
+impl Arrow {
+    /// Returns a green arrow.
+    pub const fn new(from: Square, to: Square) -> Self {
+        Self::colored(from, to, MarkColor::Green)
+    }
+    pub const fn colored(from: Square, to: Square, color: MarkColor) -> Self {
+        Self { from, to, color }
+    }
+}
+
+/// A highlighted square.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Highlight {
+    pub square: Square,
+    pub color: MarkColor,
+}
+
+impl Highlight {
+    /// Returns a green highlight.
+    pub const fn new(square: Square) -> Self {
+        Self::colored(square, MarkColor::Green)
+    }
+    pub const fn colored(square: Square, color: MarkColor) -> Self {
+        Self { square, color }
+    }
+}
+
+/// Notes attached to a position (and so to the move leading to it):
+/// glyphs judging the move, a free-text comment, arrows and squares to
+/// highlight when the position is shown, and the mover's clock and an
+/// engine evaluation after the move.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Annotation {
+    pub nags: Vec<Nag>,
+    pub comment: String,
+    pub arrows: Vec<Arrow>,
+    pub highlights: Vec<Highlight>,
+    pub clock: Option<Duration>,
+    pub eval: Option<Eval>,
+}
+
+/// Leads every serialized `Annotation` so the layout can change without
+/// relying on self-describing formats
+const SERIALIZED_VERSION: u8 = 1;
+
+impl Serialize for Annotation {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer
+    {
+        use serde::ser::SerializeTuple;
+        let mut tuple = serializer.serialize_tuple(7)?;
+        tuple.serialize_element(&SERIALIZED_VERSION)?;
+        tuple.serialize_element(&self.nags)?;
+        tuple.serialize_element(&self.comment)?;
+        tuple.serialize_element(&self.arrows)?;
+        tuple.serialize_element(&self.highlights)?;
+        tuple.serialize_element(&self.clock)?;
+        tuple.serialize_element(&self.eval)?;
+        tuple.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Annotation {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>
+    {
+        struct AnnotationVisitor;
+        impl<'de> serde::de::Visitor<'de> for AnnotationVisitor {
+            type Value = Annotation;
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an Annotation condensed into a versioned 7-element tuple")
+            }
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+                where
+                    A: serde::de::SeqAccess<'de>,
+            {
+                let missing = || serde::de::Error::custom("Missing elements");
+                let version: u8 = seq.next_element()?.ok_or_else(missing)?;
+                if version != SERIALIZED_VERSION {
+                    return Err(serde::de::Error::custom(format!("Unsupported Annotation version {version}")));
+                }
+                Ok(Annotation {
+                    nags: seq.next_element()?.ok_or_else(missing)?,
+                    comment: seq.next_element()?.ok_or_else(missing)?,
+                    arrows: seq.next_element()?.ok_or_else(missing)?,
+                    highlights: seq.next_element()?.ok_or_else(missing)?,
+                    clock: seq.next_element()?.ok_or_else(missing)?,
+                    eval: seq.next_element()?.ok_or_else(missing)?,
+                })
+            }
+        }
+        deserializer.deserialize_tuple(7, AnnotationVisitor)
+    }
+}
+
+/// Which of the optional comment commands to write when exporting PGN.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PgnOptions {
+    /// Write `[%clk ...]` commands.
+    pub clocks: bool,
+    /// Write `[%eval ...]` commands.
+    pub evals: bool,
+}
+
+impl Default for PgnOptions {
+    fn default() -> Self {
+        Self { clocks: true, evals: true }
+    }
+}
+
+impl Annotation {
+    pub fn is_empty(&self) -> bool {
+        self.nags.is_empty()
+            && self.comment.is_empty()
+            && self.arrows.is_empty()
+            && self.highlights.is_empty()
+            && self.clock.is_none()
+            && self.eval.is_none()
+    }
+
+    /// Adds `nag` unless it's already present.
+    pub fn add_nag(&mut self, nag: Nag) {
+        if !self.nags.contains(&nag) {
+            self.nags.push(nag);
+        }
+    }
+
+    /// Draws `arrow` as a board does when one is drawn by hand: it's added,
+    /// or recolored if it's drawn in another color, or removed if it's
+    /// already drawn in the same color.
+    pub fn toggle_arrow(&mut self, arrow: Arrow) {
+        let same_squares = |drawn: &Arrow| drawn.from == arrow.from && drawn.to == arrow.to;
+        match self.arrows.iter().position(same_squares) {
+            Some(index) if self.arrows[index].color == arrow.color => {
+                self.arrows.remove(index);
+            },
+            Some(index) => self.arrows[index].color = arrow.color,
+            None => self.arrows.push(arrow),
+        }
+    }
+
+    /// Like `toggle_arrow`, for a highlighted square.
+    pub fn toggle_highlight(&mut self, highlight: Highlight) {
+        match self.highlights.iter().position(|drawn| drawn.square == highlight.square) {
+            Some(index) if self.highlights[index].color == highlight.color => {
+                self.highlights.remove(index);
+            },
+            Some(index) => self.highlights[index].color = highlight.color,
+            None => self.highlights.push(highlight),
+        }
+    }
+
+    /// Removes every arrow and highlight.
+    pub fn clear_marks(&mut self) {
+        self.arrows.clear();
+        self.highlights.clear();
+    }
+
+    /// Formats the annotation as it follows a move in PGN movetext: NAGs
+    /// as `$<code>`, then a `{...}` comment holding the text along with
+    /// the evaluation and clock as `[%eval ...]` and `[%clk ...]`, and
+    /// the highlights and arrows as `[%csl ...]` and `[%cal ...]`
+    /// commands. Returns an empty string if there's
+    /// nothing to export.
+    pub fn to_pgn(&self) -> String {
+        self.to_pgn_with(&PgnOptions::default())
+    }
+
+    /// Like `to_pgn`, leaving out the clock or evaluation as `options`
+    /// say.
+    pub fn to_pgn_with(&self, options: &PgnOptions) -> String {
+        let mut parts: Vec<String> = self.nags.iter()
+            .map(|nag| format!("${}", nag.code()))
+            .collect();
+
+        let mut comment = String::new();
+        if let Some(eval) = self.eval.filter(|_| options.evals) {
+            let _ = write!(comment, "[%eval {}]", eval);
+        }
+        if let Some(clock) = self.clock.filter(|_| options.clocks) {
+            let _ = write!(comment, "[%clk {}]", format_clock(clock));
+        }
+        if !self.highlights.is_empty() {
+            let squares: Vec<String> = self.highlights.iter()
+                .map(|highlight| format!("{}{}", highlight.color.to_char(), highlight.square))
+                .collect();
+            let _ = write!(comment, "[%csl {}]", squares.join(","));
+        }
+        if !self.arrows.is_empty() {
+            let arrows: Vec<String> = self.arrows.iter()
+                .map(|arrow| format!("{}{}{}", arrow.color.to_char(), arrow.from, arrow.to))
+                .collect();
+            let _ = write!(comment, "[%cal {}]", arrows.join(","));
+        }
+        // Braces can't be escaped inside a PGN comment
+        let text = self.comment.replace(['{', '}'], "");
+        let text = text.trim();
+        if !text.is_empty() {
+            if !comment.is_empty() {
+                comment.push(' ');
+            }
+            comment.push_str(text);
+        }
+        if !comment.is_empty() {
+            parts.push(format!("{{{}}}", comment));
+        }
+        parts.join(" ")
+    }
+
+    /// Reads the contents of a PGN `{...}` comment into this annotation:
+    /// `[%clk ...]`, `[%eval ...]`, `[%csl ...]` and `[%cal ...]`
+    /// commands fill in the matching fields and the remaining text is
+    /// appended to `comment`.
+    /// Other or malformed commands are kept as text.
+    pub fn read_pgn_comment(&mut self, comment: &str) {
+        let mut text = String::new();
+        let mut rest = comment;
+        while let Some(start) = rest.find("[%") {
+            let Some(end) = rest[start..].find(']').map(|end| start + end) else { break };
+            text.push_str(&rest[..start]);
+            let command = &rest[start + 2..end];
+            if !self.read_command(command) {
+                text.push_str(&rest[start..=end]);
+            }
+            rest = &rest[end + 1..];
+        }
+        text.push_str(rest);
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !text.is_empty() {
+            if !self.comment.is_empty() {
+                self.comment.push(' ');
+            }
+            self.comment.push_str(&text);
+        }
+    }
+
+    /// Applies a single `[%name args]` command, returning false if it
+    /// isn't recognized or can't be parsed.
+    fn read_command(&mut self, command: &str) -> bool {
+        let (name, args) = command.trim().split_once(char::is_whitespace).unwrap_or((command, ""));
+        let args = args.trim();
+        // colored squares look like `Gd5`, with the color first
+        let squares = |arg: &str| {
+            let color = MarkColor::from_char(arg.chars().next()?)?;
+            let squares = arg.get(1..)?.as_bytes().chunks(2)
+                .map(|chunk| std::str::from_utf8(chunk).ok()?.parse::<Square>().ok())
+                .collect::<Option<Vec<_>>>()?;
+            Some((color, squares))
+        };
+        match name {
+            "clk" => parse_clock(args).map(|clock| self.clock = Some(clock)).is_ok(),
+            "eval" => args.parse().map(|eval| self.eval = Some(eval)).is_ok(),
+            "csl" => {
+                let Some(highlights) = args.split(',').map(|arg| {
+                    let (color, squares) = squares(arg.trim())?;
+                    match squares[..] {
+                        [square] => Some(Highlight::colored(square, color)),
+                        _ => None,
+                    }
+                }).collect::<Option<Vec<_>>>() else { return false };
+                self.highlights.extend(highlights);
+                true
+            },
+            "cal" => {
+                let Some(arrows) = args.split(',').map(|arg| {
+                    let (color, squares) = squares(arg.trim())?;
+                    match squares[..] {
+                        [from, to] => Some(Arrow::colored(from, to, color)),
+                        _ => None,
+                    }
+                }).collect::<Option<Vec<_>>>() else { return false };
+                self.arrows.extend(arrows);
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Square::*;
+
+    #[test]
+    fn test_nag_codes_and_symbols() {
+        for nag in Nag::iter() {
+            assert_eq!(Nag::from_code(nag.code()), Some(nag));
+            assert_eq!(nag.symbol().parse::<Nag>().unwrap(), nag);
+            assert_eq!(format!("${}", nag.code()).parse::<Nag>().unwrap(), nag);
+        }
+        assert_eq!(Nag::Interesting.code(), 5);
+        assert!("$7".parse::<Nag>().is_err());
+        assert!("!!!".parse::<Nag>().is_err());
+    }
+
+    #[test]
+    fn test_to_pgn() {
+        let mut annotation = Annotation::default();
+        assert!(annotation.is_empty());
+        assert_eq!(annotation.to_pgn(), "");
+
+        annotation.add_nag(Nag::Good);
+        annotation.add_nag(Nag::Good);
+        assert_eq!(annotation.to_pgn(), "$1");
+
+        annotation.comment = "Controls {the} centre ".to_string();
+        annotation.arrows.push(Arrow::new(G1, F3));
+        annotation.arrows.push(Arrow::new(D2, D4));
+        annotation.highlights.push(Highlight::colored(D5, MarkColor::Red));
+        assert_eq!(
+            annotation.to_pgn(),
+            "$1 {[%csl Rd5][%cal Gg1f3,Gd2d4] Controls the centre}",
+        );
+    }
+
+    #[test]
+    fn test_eval_and_clock_formats() {
+        for (text, eval) in [("0.35", Eval::Centipawns(35)), ("-1.20", Eval::Centipawns(-120)),
+                             ("-0.05", Eval::Centipawns(-5)), ("#3", Eval::Mate(3)), ("#-2", Eval::Mate(-2))] {
+            assert_eq!(text.parse::<Eval>().unwrap(), eval);
+            assert_eq!(eval.to_string(), text);
+        }
+        assert_eq!("1.5,24".parse::<Eval>().unwrap(), Eval::Centipawns(150));
+        assert!("#".parse::<Eval>().is_err());
+        assert!("nan".parse::<Eval>().is_err());
+
+        assert_eq!(format_clock(Duration::from_secs(3723)), "1:02:03");
+        assert_eq!(format_clock(Duration::from_millis(9_950)), "0:00:09.9");
+        assert_eq!(parse_clock("1:02:03").unwrap(), Duration::from_secs(3723));
+        assert_eq!(parse_clock("0:00:09.95").unwrap(), Duration::from_millis(9_950));
+        assert!(parse_clock("0:61:00").is_err());
+        assert!(parse_clock("5:00").is_err());
+        assert_eq!(parse_clock("18446744073709551615:00:00"), Err(ParseError::InvalidClock("18446744073709551615:00:00".to_string())));
+    }
+
+    #[test]
+    fn test_clock_and_eval_comments() {
+        let mut annotation = Annotation {
+            comment: "Best".to_string(),
+            clock: Some(Duration::from_secs(295)),
+            eval: Some(Eval::Centipawns(17)),
+            ..Annotation::default()
+        };
+        annotation.add_nag(Nag::Good);
+        assert_eq!(annotation.to_pgn(), "$1 {[%eval 0.17][%clk 0:04:55] Best}");
+        let options = PgnOptions { clocks: false, ..PgnOptions::default() };
+        assert_eq!(annotation.to_pgn_with(&options), "$1 {[%eval 0.17] Best}");
+
+        let mut read = Annotation::default();
+        read.read_pgn_comment(" [%eval 0.17] [%clk 0:04:55] Best ");
+        read.read_pgn_comment("[%csl Rd5][%cal Gg1f3,Yd2d4] [%emt 0:00:03] [%clk x] [%csl Xd5] still");
+        assert_eq!(read.eval, annotation.eval);
+        assert_eq!(read.clock, annotation.clock);
+        assert_eq!(read.highlights, vec![Highlight::colored(D5, MarkColor::Red)]);
+        assert_eq!(read.arrows, vec![Arrow::new(G1, F3), Arrow::colored(D2, D4, MarkColor::Yellow)]);
+        assert_eq!(read.comment, "Best [%emt 0:00:03] [%clk x] [%csl Xd5] still");
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let annotation = Annotation {
+            nags: vec![Nag::Dubious],
+            comment: "Too slow".to_string(),
+            arrows: vec![Arrow::new(E7, E5)],
+            highlights: vec![Highlight::new(E5), Highlight::colored(D4, MarkColor::Blue)],
+            clock: Some(Duration::from_millis(61_500)),
+            eval: Some(Eval::Mate(-2)),
+        };
+        let json = serde_json::to_string(&annotation).unwrap();
+        assert_eq!(serde_json::from_str::<Annotation>(&json).unwrap(), annotation);
+        assert!(json.starts_with("[1,"));
+        assert!(serde_json::from_str::<Annotation>(&json.replacen("[1,", "[2,", 1)).is_err());
+    }
+
+    #[test]
+    fn test_toggle_marks() {
+        let mut annotation = Annotation::default();
+        annotation.toggle_arrow(Arrow::new(G1, F3));
+        annotation.toggle_arrow(Arrow::new(E2, E4));
+        annotation.toggle_arrow(Arrow::colored(G1, F3, MarkColor::Red));
+        assert_eq!(annotation.arrows, vec![Arrow::colored(G1, F3, MarkColor::Red), Arrow::new(E2, E4)]);
+        annotation.toggle_arrow(Arrow::colored(G1, F3, MarkColor::Red));
+        assert_eq!(annotation.arrows, vec![Arrow::new(E2, E4)]);
+
+        annotation.toggle_highlight(Highlight::new(D5));
+        annotation.toggle_highlight(Highlight::colored(D5, MarkColor::Yellow));
+        assert_eq!(annotation.highlights, vec![Highlight::colored(D5, MarkColor::Yellow)]);
+        annotation.toggle_highlight(Highlight::colored(D5, MarkColor::Yellow));
+        assert!(annotation.highlights.is_empty());
+        annotation.toggle_highlight(Highlight::new(D5));
+        annotation.clear_marks();
+        assert!(annotation.is_empty());
+    }
+}
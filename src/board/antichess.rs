@@ -0,0 +1,124 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use super::material::Color;
+use super::moves::{LegalMove, MoveSet, MoveState, Promotion};
+use super::play::BoardResult;
+use super::rules::Rules;
+
+/// Losing chess: captures are compulsory, the king is an ordinary piece
+/// that can't castle, pawns may promote to a king, and a player wins by
+/// losing all their pieces or being stalemated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct AntichessRules;
+
+impl Rules for AntichessRules {
+    fn has_royal_king(&self) -> bool {
+        false
+    }
+
+    fn allows_castling(&self) -> bool {
+        false
+    }
+
+    fn has_insufficient_material(&self) -> bool {
+        false
+    }
+
+    fn promotions(&self) -> &'static [Promotion] {
+        use Promotion::*;
+        &[Queen, Rook, Bishop, Knight, King]
+    }
+
+    fn restrict_moves(&self, state: &MoveState, moves: MoveSet<LegalMove>) -> MoveSet<LegalMove> {
+        match state.can_capture() {
+            true => moves.captures(state.as_ref()),
+            false => moves,
+        }
+    }
+
+    fn no_moves_result(&self, color: Color, _in_check: bool) -> BoardResult {
+        BoardResult::VariantWin(color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn mv(uci: &str) -> Move {
+        uci.parse().unwrap()
+    }
+
+    fn state(board: &EngineBoard) -> MoveState {
+        let pos: &Position = board.as_ref();
+        MoveState::new(pos.clone())
+    }
+
+    fn antichess() -> EngineBoard {
+        EngineBoard::plays_both_from(Position::new_variant(BackRankId::default().into(), Variant::Antichess))
+    }
+
+    #[test]
+    fn test_antichess_forced_captures() {
+        let mut board = antichess();
+        board.submit_move(mv("e2e3")).unwrap();
+        board.submit_move(mv("b7b5")).unwrap();
+        // the bishop can take on b5, so nothing else may move
+        assert!(board.submit_move(mv("a2a3")).is_err());
+        assert_eq!(candidate_moves(&state(&board)), vec![mv("f1b5")]);
+        board.submit_move(mv("f1b5")).unwrap();
+
+        // kings may castle in neither direction and may walk into attacks
+        let pos = Position::from_variant_fen(Variant::Antichess, "8/8/8/3r4/8/8/8/4K3 w - - 0 1").unwrap();
+        let state = MoveState::new(pos);
+        assert!(!state.is_check());
+        assert_eq!(state.legal_moves(Square::E1).destinations().len(), 5);
+        assert!(Position::from_variant_fen(Variant::Antichess, "4k3/8/8/8/8/8/8/4K2R w K - 0 1").is_err());
+    }
+
+    #[test]
+    fn test_antichess_win_by_losing_everything() {
+        let pos = Position::from_variant_fen(Variant::Antichess, "8/8/8/8/8/8/p7/1R6 b - - 0 1").unwrap();
+        let mut board = EngineBoard::plays_both_from(pos);
+        // the pawn must take and may promote to a king
+        let state = state(&board);
+        let legal = state.validate_move(mv("a2b1k")).unwrap();
+        assert_eq!(state.to_san(legal), "axb1=K");
+        assert_eq!(candidate_moves(&state).len(), 5);
+        board.submit_move(mv("a2b1k")).unwrap();
+        assert_eq!(board.board_result(), Some(BoardResult::VariantWin(Color::White)));
+    }
+
+    #[test]
+    fn test_antichess_random_games_finish() {
+        let mut mover = RandomMover::seeded(7);
+        for _ in 0..20 {
+            let mut board = antichess();
+            while board.board_result().is_none() {
+                let mv = mover.choose_move(&state(&board)).unwrap();
+                board.submit_move(mv).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_standard_king_promotion() {
+        let pos = Position::from_fen("8/4P3/8/8/8/8/8/k6K w - - 0 1").unwrap();
+        let state = MoveState::new(pos);
+        assert!(state.validate_move(mv("e7e8k")).is_err());
+        assert!(state.validate_move(mv("e7e8q")).is_ok());
+        assert_eq!(candidate_moves(&state).iter().filter(|mv| mv.promotion.is_some()).count(), 4);
+    }
+}
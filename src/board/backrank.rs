@@ -13,19 +13,21 @@
 //    limitations under the License.
 
 use std::fmt::Display;
+use std::time::{SystemTime, UNIX_EPOCH};
 use rand::{thread_rng, Rng};
 use thiserror::Error;
-use anyhow::Result;
+use crate::error::Result;
 use std::ops::{Index, IndexMut};
 use once_cell::sync::Lazy;
 use std::hash::{Hash, Hasher};
 use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
 
 use super::square::File;
 use super::material::Piece;
 use Piece::{King, Queen, Rook, Bishop, Knight, Pawn};
 
-#[derive(Error, Debug, Serialize, Deserialize)]
+#[derive(Error, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum BackRankError {
     #[error("Expecting 1 king, 1 queen, and 2 of each other piece")]
     ArgError,
@@ -42,25 +44,83 @@ pub enum BackRankError {
 use BackRankError::*;
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(try_from = "usize")]
 pub struct BackRankId(usize);
 
+impl TryFrom<usize> for BackRankId {
+    type Error = BackRankError;
+    fn try_from(index: usize) -> Result<Self, Self::Error> {
+        if index >= 960 {
+            return Err(OutOfRange);
+        }
+        Ok(Self(index))
+    }
+}
+
 impl BackRankId {
     pub const STANDARD: Self = Self(518);
 
     pub fn shuffled() -> Self {
-        let index = thread_rng().gen_range(0..960usize);
-        Self(index)
+        Self::shuffled_with(&mut thread_rng())
     }
 
-    pub fn try_from<I: Into<usize>>(index: I) -> Result<Self> {
-        let index: usize = index.into();
-        if index >= 960 {
-            return Err(OutOfRange.into());
-        }
-        Ok(Self(index))
+    /// Picks a back rank with `rng`, e.g. a seeded one so every board of
+    /// a tournament round gets the same Chess960 position.
+    pub fn shuffled_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        Self(rng.gen_range(0..960usize))
+    }
+
+    pub fn to_index(&self) -> usize {
+        self.0
+    }
+
+    /// Derives a back rank from `seed`, the same on every platform so
+    /// that servers and clients agree without talking to each other. The
+    /// id is the 64-bit FNV-1a hash of the seed's UTF-8 bytes modulo 960.
+    pub fn from_seed(seed: &str) -> Self {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0100_0000_01b3;
+        let hash = seed.bytes().fold(OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(PRIME));
+        Self((hash % 960) as usize)
+    }
+
+    /// Returns the back rank of the daily Chess960 challenge for a date,
+    /// seeded with the date as `YYYY-MM-DD`.
+    pub fn daily(year: i32, month: u8, day: u8) -> Self {
+        Self::from_seed(&format!("{year:04}-{month:02}-{day:02}"))
+    }
+
+    /// Returns the back rank of the daily challenge for the UTC date of
+    /// `time`.
+    pub fn daily_at(time: SystemTime) -> Self {
+        let days = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / (24 * 60 * 60);
+        let (year, month, day) = civil_date(days as i64);
+        Self::daily(year, month, day)
+    }
+
+    /// Returns the back rank of the weekly challenge for an ISO week,
+    /// seeded with the week as `YYYY-Www`.
+    pub fn weekly(year: i32, week: u8) -> Self {
+        Self::from_seed(&format!("{year:04}-W{week:02}"))
     }
 }
 
+/// Converts days since 1970-01-01 to a (year, month, day) date in the
+/// proleptic Gregorian calendar.
+fn civil_date(days: i64) -> (i32, u8, u8) {
+    // shift the epoch to 0000-03-01 so leap days end each 400-year era
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year as i32, month as u8, day as u8)
+}
+
 impl Default for BackRankId {
     fn default() -> Self {
         Self::STANDARD
@@ -109,6 +169,23 @@ pub trait BackRanks: AsRef<BackRank> {
         let backrank: &BackRank = self.as_ref();
         backrank.knights
     }
+    #[inline]
+    fn br_piece_at_file(&self, file: File) -> Piece {
+        let backrank: &BackRank = self.as_ref();
+        backrank[file]
+    }
+    /// Returns the files holding `piece` from left to right (none for
+    /// pawns).
+    fn br_files_of(&self, piece: Piece) -> impl Iterator<Item=File> {
+        self.br_iter()
+            .filter(move |(_, p)| *p == piece)
+            .map(|(file, _)| file)
+    }
+    /// Iterates over the back rank from the a-file to the h-file.
+    fn br_iter(&self) -> impl Iterator<Item=(File, Piece)> {
+        let pieces = self.br_pieces();
+        File::iter().zip(pieces)
+    }
 }
 
 
@@ -187,13 +264,7 @@ impl BackRank {
         let queen = place(Queen, extract(6));
 
         // place knights on two of 5 remaining empty slots
-        const SKIP_TABLE: [(usize, usize); 10] = [
-            (0, 0), (0, 1), (0, 2), (0, 3),
-            (1, 1), (1, 2), (1, 3),
-            (2, 2), (2, 3),
-            (3, 3),
-        ];
-        let (skip1, skip2) = SKIP_TABLE[extract(10)];
+        let (skip1, skip2) = KNIGHT_SKIPS[extract(10)];
         let knights = [
             place(Knight, skip1), 
             place(Knight, skip2), 
@@ -211,6 +282,33 @@ impl BackRank {
         Self { id: BackRankId(id % 960), pieces, king, queen, rooks, bishops, knights }
     }
 
+    /// Returns the id of the Chess960 back rank with `pieces` from the
+    /// a-file to the h-file, reversing the numbering used by `new`, or
+    /// `None` if it isn't a valid Chess960 setup.
+    pub fn identify(pieces: &[Piece; 8]) -> Option<BackRankId> {
+        let files_of = |piece: Piece| (0..8).filter(move |&index| pieces[index] == piece);
+        let bishops: Vec<usize> = files_of(Bishop).collect();
+        let light = bishops.iter().find(|&&index| index % 2 == 1)?;
+        let dark = bishops.iter().find(|&&index| index % 2 == 0)?;
+        if bishops.len() != 2 {
+            return None;
+        }
+        // the slots left empty as each piece is placed in turn
+        let mut empty: Vec<usize> = (0..8).filter(|index| pieces[*index] != Bishop).collect();
+        let mut take = |piece: Piece| {
+            let slot = empty.iter().position(|&index| pieces[index] == piece)?;
+            empty.remove(slot);
+            Some(slot)
+        };
+        let queen = take(Queen)?;
+        let knights = (take(Knight)?, take(Knight)?);
+        let knights = KNIGHT_SKIPS.iter().position(|&skips| skips == knights)?;
+        if empty.iter().map(|&index| pieces[index]).ne([Rook, King, Rook]) {
+            return None;
+        }
+        BackRankId::try_from(light / 2 + 4 * (dark / 2 + 4 * (queen + 6 * knights))).ok()
+    }
+
     /// Creates a standard back rank configuration suitable for the
     /// standard chess game.
     pub fn standard() -> Self {
@@ -223,6 +321,10 @@ impl BackRank {
         *Self::lookup(BackRankId::shuffled())
     }
 
+    pub fn shuffled_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        *Self::lookup(BackRankId::shuffled_with(rng))
+    }
+
     pub fn lookup(id: BackRankId) -> &'static BackRank {
         &BACKRANKS[id.0]
     }
@@ -247,6 +349,16 @@ impl BackRank {
     }
 }
 
+impl Display for BackRank {
+    /// Writes the setup string, e.g. "RNBQKBNR" for standard chess.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for piece in self.pieces {
+            write!(f, "{}", piece.to_char())?;
+        }
+        Ok(())
+    }
+}
+
 impl Index<File> for BackRank {
     type Output = Piece;
     fn index(&self, file: File) -> &Self::Output {
@@ -260,6 +372,15 @@ impl IndexMut<File> for BackRank {
     }
 }
 
+/// How many empty slots to skip when placing each knight, indexed by
+/// the knights' digit of a back rank id.
+const KNIGHT_SKIPS: [(usize, usize); 10] = [
+    (0, 0), (0, 1), (0, 2), (0, 3),
+    (1, 1), (1, 2), (1, 3),
+    (2, 2), (2, 3),
+    (3, 3),
+];
+
 static BACKRANKS: Lazy<Vec<BackRank>> = Lazy::new(|| {
     let mut result = Vec::new();
     for id in 0..960 {
@@ -276,6 +397,21 @@ mod tests {
     use super::*;
     use super::File::*;
 
+    #[test]
+    fn test_layout_queries() {
+        let backrank = BackRank::standard();
+        assert_eq!(backrank.to_string(), "RNBQKBNR");
+        assert_eq!(backrank.br_piece_at_file(FileD), Queen);
+        assert_eq!(backrank.br_files_of(Rook).collect::<Vec<_>>(), vec![FileA, FileH]);
+        assert_eq!(backrank.br_files_of(Pawn).count(), 0);
+        let layout: Vec<(File, Piece)> = backrank.br_iter().collect();
+        assert_eq!(layout.len(), 8);
+        assert_eq!(layout[4], (FileE, King));
+
+        let backrank = BackRank::lookup(BackRankId::try_from(0usize).unwrap());
+        assert_eq!(backrank.to_string(), "BBQNNRKR");
+        assert_eq!(backrank.br_files_of(King).collect::<Vec<_>>(), vec![backrank.king()]);
+    }
     #[test]
     fn test_backrank_id_518_is_standard() {
         let index: usize = 518;
@@ -298,6 +434,44 @@ mod tests {
         assert!(BackRankId::try_from(index).is_err());
     }
     #[test]
+    fn test_identify() {
+        for index in 0..960usize {
+            let id = BackRankId::try_from(index).unwrap();
+            assert_eq!(BackRank::identify(&BackRank::lookup(id).br_pieces()), Some(id));
+        }
+        // bishops on the same color, king outside the rooks, extra pieces
+        assert_eq!(BackRank::identify(&[Rook, Bishop, Knight, Bishop, King, Queen, Knight, Rook]), None);
+        assert_eq!(BackRank::identify(&[King, Rook, Bishop, Queen, Rook, Bishop, Knight, Knight]), None);
+        assert_eq!(BackRank::identify(&[Rook, Knight, Bishop, Queen, King, Bishop, Queen, Rook]), None);
+        assert_eq!(BackRank::identify(&[Pawn; 8]), None);
+    }
+    #[test]
+    fn test_seeded_shuffle() {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+        let ids: Vec<BackRankId> = (0..2)
+            .map(|_| BackRankId::shuffled_with(&mut StdRng::seed_from_u64(42)))
+            .collect();
+        assert_eq!(ids[0], ids[1]);
+        let mut rng = StdRng::seed_from_u64(42);
+        assert_eq!(BackRank::shuffled_with(&mut rng).id(), ids[0]);
+    }
+    #[test]
+    fn test_daily_backrank() {
+        use std::time::Duration;
+        // pinned so clients in other languages can check their hash
+        assert_eq!(BackRankId::from_seed("").to_index(), 677);
+        assert_eq!(BackRankId::from_seed("2024-02-29").to_index(), 876);
+        assert_eq!(BackRankId::daily(2024, 2, 29), BackRankId::from_seed("2024-02-29"));
+        assert_eq!(BackRankId::weekly(2024, 9), BackRankId::from_seed("2024-W09"));
+        // 2024-02-29T23:59:59Z
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_251_199);
+        assert_eq!(BackRankId::daily_at(time), BackRankId::daily(2024, 2, 29));
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+    }
+    #[test]
     fn test_backranks_are_unique() {
         let mut visited = HashSet::new();
         for index in 0..960usize {
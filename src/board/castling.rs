@@ -13,10 +13,11 @@
 //    limitations under the License.
 
 use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
 
-use super::backrank::{BackRank, BackRanks};
+use super::backrank::{BackRank, BackRankId, BackRanks};
 use super::square::{Square, File, Rank, Mask};
-use super::material::{Color, Pair};
+use super::material::{Color, Material, Pair, Piece};
 use super::position::between;
 
 use File::*;
@@ -68,10 +69,11 @@ pub trait Castling: AsRef<BackRank> + AsRef<CastlingRights> {
         let rights: &CastlingRights = self.as_ref();
         Square::new(FileD, rights.rank())
     }
+    /// Returns the squares that must be empty to castle short: those the
+    /// king and rook cross or land on, apart from their own. In Chess960
+    /// these can lie beyond the squares between king and rook.
     fn oo_blocking_lane(&self) -> Mask {
-        let rook_src = self.oo_rook_src();
-        let king_src = self.king_src();
-        between(king_src, rook_src)
+        castling_lane(self.king_src(), self.oo_king_dest(), self.oo_rook_src(), self.oo_rook_dest())
     }
     fn oo_attacking_lane(&self) -> Mask {
         let king_dest = self.oo_king_dest();
@@ -79,9 +81,7 @@ pub trait Castling: AsRef<BackRank> + AsRef<CastlingRights> {
         between(king_src, king_dest) | king_dest
     }
     fn ooo_blocking_lane(&self) -> Mask {
-        let rook_src = self.ooo_rook_src();
-        let king_src = self.king_src();
-        between(rook_src, king_src)
+        castling_lane(self.king_src(), self.ooo_king_dest(), self.ooo_rook_src(), self.ooo_rook_dest())
     }
     fn ooo_attacking_lane(&self) -> Mask {
         let king_dest = self.ooo_king_dest();
@@ -90,6 +90,12 @@ pub trait Castling: AsRef<BackRank> + AsRef<CastlingRights> {
     }
 }
 
+fn castling_lane(king_src: Square, king_dest: Square, rook_src: Square, rook_dest: Square) -> Mask {
+    let lane = between(king_src, king_dest) | king_dest.to_mask()
+        | between(rook_src, rook_dest) | rook_dest.to_mask();
+    lane & !king_src.to_mask() & !rook_src.to_mask()
+}
+
 pub trait CastlingMut: Castling + AsMut<CastlingRights> {
     fn update(&mut self, square: Square) {
         let king = self.king_src();
@@ -226,6 +232,55 @@ impl CastlingRights {
     }
 }
 
+/// Derives every castling right `contents` allows, for a position whose
+/// back rank isn't known (e.g. from a board editor): a side may castle
+/// with a rook on its back rank if its king is on the back rank between
+/// that rook and another square. Both sides share a back rank, so when
+/// their kings or rooks stand on different files the back rank giving
+/// the most rights is chosen, preferring the one White's pieces still
+/// show, then the standard one, then the outermost rooks.
+pub fn infer_castling(contents: &[(Square, Material)]) -> (&'static BackRank, Pair<CastlingRights>) {
+    let has = |color: Color, piece: Piece, file: File| {
+        contents.contains(&(Square::new(file, Rank::back_rank(color)), Material::new(color, piece)))
+    };
+    let rights = |backrank: &BackRank| {
+        let [ooo_rook, oo_rook] = backrank.rooks();
+        let side = |color: Color| {
+            let king = has(color, Piece::King, backrank.king());
+            CastlingRights::new(color, king && has(color, Piece::Rook, oo_rook), king && has(color, Piece::Rook, ooo_rook))
+        };
+        Pair::new(side(Color::White), side(Color::Black))
+    };
+    let count = |rights: &Pair<CastlingRights>| Color::iter()
+        .map(|color| rights[color].oo() as usize + rights[color].ooo() as usize)
+        .sum::<usize>();
+    let preference = backrank_preference(contents);
+    all_backranks()
+        .map(|backrank| (backrank, rights(backrank)))
+        .max_by_key(|(backrank, rights)| {
+            let [ooo_rook, oo_rook] = backrank.rooks();
+            let spread = oo_rook.to_index() - ooo_rook.to_index();
+            (count(rights), preference(backrank), spread)
+        })
+        .expect("there are 960 back ranks")
+}
+
+/// Returns every back rank in order of id.
+pub(crate) fn all_backranks() -> impl Iterator<Item = &'static BackRank> {
+    (0..960usize).filter_map(|index| BackRankId::try_from(index).ok()).map(BackRank::lookup)
+}
+
+/// Ranks the back ranks a position holding `contents` may have started
+/// from when its castling rights fit several: the one White's pieces
+/// still show comes first, so Chess960 starting positions keep their own
+/// back rank, then the standard one.
+pub(crate) fn backrank_preference(contents: &[(Square, Material)]) -> impl Fn(&BackRank) -> (bool, bool) + '_ {
+    let shows = |backrank: &BackRank| File::iter().all(|file| {
+        contents.contains(&(Square::new(file, Rank::back_rank(Color::White)), Material::new(Color::White, backrank[file])))
+    });
+    move |backrank| (shows(backrank), backrank.id() == BackRankId::STANDARD)
+}
+
 impl Default for Pair<CastlingRights> {
     fn default() -> Self {
         Pair::new(
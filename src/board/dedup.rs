@@ -0,0 +1,269 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Fingerprinting games to find duplicates, e.g. when importing a large
+//! PGN archive. A fingerprint only depends on the moves and positions, so
+//! the same game with different tags, comments or castling notation
+//! fingerprints the same.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::analysis::splitmix64;
+use super::material::Piece;
+use super::moves::{LegalMove, Move, MoveState};
+use super::pgn::{PgnError, PgnGame};
+use super::play::{replay_moves, ReplayError};
+use super::position::Position;
+
+/// By default, a game that stops at most this many plies short of another
+/// is taken to be a truncated copy of it.
+const DEFAULT_TOLERANCE: usize = 2;
+
+/// A stable hash of a game: its starting position and moves, and the
+/// position it ends in. Like `Position::zobrist`, it's the same in every
+/// build, so it can be persisted.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GameFingerprint {
+    pub hash: u64,
+    /// The `Position::zobrist` of the final position.
+    pub end: u64,
+    pub plies: usize,
+}
+
+impl GameFingerprint {
+    /// Fingerprints the game of `moves` played from `start`, failing if a
+    /// move is illegal.
+    pub fn new(start: &Position, moves: &[Move]) -> Result<Self, ReplayError> {
+        Ok(Line::replay(start, moves)?.fingerprint())
+    }
+
+    /// Fingerprints a game read from PGN.
+    pub fn from_pgn(game: &PgnGame) -> Result<Self, PgnError> {
+        Ok(Self::new(&game.start_position()?, &game.moves)?)
+    }
+
+    /// Fingerprints `history`, which every move must be legal in.
+    pub(crate) fn from_history(start: &Position, history: &[LegalMove]) -> Self {
+        Line::from_history(start, history).fingerprint()
+    }
+}
+
+/// Why a game was taken for a duplicate, with the index of the earlier
+/// game (counting from zero, in the order games were added).
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Duplicate {
+    /// The same moves from the same position.
+    Exact(usize),
+    /// One game is the other with its last few moves missing, e.g. a copy
+    /// saved before the game ended.
+    Truncated(usize),
+    /// The same final position from the same start in as many moves, but
+    /// with the moves in another order.
+    Transposed(usize),
+}
+
+impl Duplicate {
+    pub fn original(&self) -> usize {
+        match *self {
+            Duplicate::Exact(index) | Duplicate::Truncated(index) | Duplicate::Transposed(index) => index,
+        }
+    }
+}
+
+/// Finds exact and near duplicates among the games added to it.
+#[derive(Debug, Clone)]
+pub struct DuplicateFinder {
+    tolerance: usize,
+    games: usize,
+    /// The hash of each game's moves.
+    lines: HashMap<u64, usize>,
+    /// The hashes of each game's moves but the last `1..=tolerance`.
+    truncations: HashMap<u64, usize>,
+    endings: HashMap<(u64, u64, usize), usize>,
+}
+
+impl Default for DuplicateFinder {
+    fn default() -> Self {
+        Self::with_tolerance(DEFAULT_TOLERANCE)
+    }
+}
+
+impl DuplicateFinder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a finder that takes a game stopping at most `tolerance`
+    /// plies short of another for a truncated copy of it. With a tolerance
+    /// of zero, only exact and transposed duplicates are found.
+    pub fn with_tolerance(tolerance: usize) -> Self {
+        Self {
+            tolerance,
+            games: 0,
+            lines: HashMap::new(),
+            truncations: HashMap::new(),
+            endings: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of games added.
+    pub fn len(&self) -> usize {
+        self.games
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games == 0
+    }
+
+    /// Adds the game of `moves` played from `start`, returning the first
+    /// earlier game it duplicates, if any. Duplicates are added too, so
+    /// indices count every game. Nothing is added if a move is illegal.
+    pub fn add(&mut self, start: &Position, moves: &[Move]) -> Result<Option<Duplicate>, ReplayError> {
+        let line = Line::replay(start, moves)?;
+        Ok(self.add_line(line))
+    }
+
+    /// Adds a game read from PGN, as `add`.
+    pub fn add_pgn(&mut self, game: &PgnGame) -> Result<Option<Duplicate>, PgnError> {
+        Ok(self.add(&game.start_position()?, &game.moves)?)
+    }
+
+    fn add_line(&mut self, line: Line) -> Option<Duplicate> {
+        let index = self.games;
+        self.games += 1;
+        let plies = line.hashes.len() - 1;
+        let full = line.hashes[plies];
+        // truncations must keep at least one move, or every game would
+        // be a truncated copy of every other from the same start
+        let shorter = (1..=self.tolerance.min(plies.saturating_sub(1)))
+            .map(|missing| line.hashes[plies - missing]);
+        let ending = (line.hashes[0], line.end, plies);
+
+        let duplicate = if let Some(&original) = self.lines.get(&full) {
+            Some(Duplicate::Exact(original))
+        } else if let Some(&original) = self.truncations.get(&full) {
+            Some(Duplicate::Truncated(original))
+        } else if let Some(original) = shorter.clone().find_map(|hash| self.lines.get(&hash)) {
+            Some(Duplicate::Truncated(*original))
+        } else {
+            self.endings.get(&ending).map(|&original| Duplicate::Transposed(original))
+        };
+
+        self.lines.entry(full).or_insert(index);
+        for hash in shorter {
+            self.truncations.entry(hash).or_insert(index);
+        }
+        self.endings.entry(ending).or_insert(index);
+        duplicate
+    }
+}
+
+/// The running hashes of a game's moves, `hashes[ply]` covering the
+/// starting position and the moves before `ply`.
+struct Line {
+    hashes: Vec<u64>,
+    end: u64,
+}
+
+impl Line {
+    fn replay(start: &Position, moves: &[Move]) -> Result<Self, ReplayError> {
+        let history = replay_moves(&mut MoveState::new(start.clone()), moves)?;
+        Ok(Self::from_history(start, &history))
+    }
+
+    fn from_history(start: &Position, history: &[LegalMove]) -> Self {
+        let mut pos = start.clone();
+        let mut hash = splitmix64(pos.zobrist());
+        let mut hashes = Vec::with_capacity(history.len() + 1);
+        hashes.push(hash);
+        for &mv in history {
+            // castling may be entered in more than one way
+            hash = splitmix64(hash ^ move_code(mv.to_move(&pos)));
+            hashes.push(hash);
+            // every move in the history was legal when it was played
+            let _ = pos.apply_move(mv);
+        }
+        Self { hashes, end: pos.zobrist() }
+    }
+
+    fn fingerprint(&self) -> GameFingerprint {
+        let plies = self.hashes.len() - 1;
+        GameFingerprint { hash: splitmix64(self.hashes[plies] ^ self.end), end: self.end, plies }
+    }
+}
+
+fn move_code(mv: Move) -> u64 {
+    let promotion = mv.promotion.map_or(0, |promotion| Piece::from(promotion).to_index() + 1);
+    (mv.from.to_index() | mv.to.to_index() << 6 | promotion << 12) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn moves(uci: &str) -> Vec<Move> {
+        uci.split_whitespace().map(|mv| mv.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let start = Position::default();
+        let game = moves("e2e4 e7e5 g1f3 b8c6");
+        let fingerprint = GameFingerprint::new(&start, &game).unwrap();
+        assert_eq!(fingerprint.plies, 4);
+        assert_eq!(fingerprint, GameFingerprint::new(&start, &game).unwrap());
+        assert_ne!(fingerprint, GameFingerprint::new(&start, &moves("g1f3 b8c6 e2e4 e7e5")).unwrap());
+        assert_ne!(fingerprint.hash, GameFingerprint::new(&start, &game[..3]).unwrap().hash);
+        assert!(GameFingerprint::new(&start, &moves("e2e5")).is_err());
+        let antichess = Position::new_variant(BackRankId::default().into(), Variant::Antichess);
+        assert_ne!(GameFingerprint::new(&antichess, &game).unwrap(), fingerprint);
+
+        let board = EngineBoard::replay(None, &game).unwrap();
+        assert_eq!(board.fingerprint(), fingerprint);
+        let pos: &Position = board.as_ref();
+        assert_eq!(fingerprint.end, pos.zobrist());
+
+        // castling notation doesn't matter
+        let pos = Position::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(GameFingerprint::new(&pos, &moves("e1g1")).unwrap(), GameFingerprint::new(&pos, &moves("e1h1")).unwrap());
+
+        let pgn = PgnGame::parse_all("[Event \"Casual\"]\n\n1. e4 {best by test} e5 2. Nf3 Nc6 *").unwrap();
+        assert_eq!(GameFingerprint::from_pgn(&pgn[0]).unwrap(), fingerprint);
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let start = Position::default();
+        let mut finder = DuplicateFinder::new();
+        assert_eq!(finder.add(&start, &moves("e2e4 e7e5 g1f3 b8c6 f1b5")), Ok(None));
+        assert_eq!(finder.add(&start, &moves("d2d4 d7d5")), Ok(None));
+        assert_eq!(finder.add(&start, &moves("e2e4 e7e5 g1f3 b8c6 f1b5")), Ok(Some(Duplicate::Exact(0))));
+        assert_eq!(finder.add(&start, &moves("e2e4 e7e5 g1f3")), Ok(Some(Duplicate::Truncated(0))));
+        assert_eq!(finder.add(&start, &moves("d2d4 d7d5 c2c4 e7e6")), Ok(Some(Duplicate::Truncated(1))));
+        assert_eq!(finder.add(&start, &moves("g1f3 b8c6 e2e4 e7e5 f1b5")), Ok(Some(Duplicate::Transposed(0))));
+        // too far apart to be a truncated copy
+        assert_eq!(finder.add(&start, &moves("e2e4 e7e5 g1f3 b8c6 f1b5 a7a6 b5a4 g8f6")), Ok(None));
+        assert!(finder.add(&start, &moves("e2e4 e2e4")).is_err());
+        assert_eq!(finder.len(), 7);
+
+        let mut finder = DuplicateFinder::with_tolerance(0);
+        finder.add(&start, &moves("e2e4 e7e5 g1f3")).unwrap();
+        assert_eq!(finder.add(&start, &moves("e2e4 e7e5")), Ok(None));
+        let from = Position::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+        assert_eq!(finder.add(&from, &moves("e2e4 e7e5 g1f3")), Err(ReplayError {
+            index: 1, mv: "e7e5".parse().unwrap(), reason: ReplayFailure::EmptySquare(Square::E7),
+        }));
+    }
+}
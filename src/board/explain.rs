@@ -0,0 +1,326 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Explanations for rejected moves, so a UI can say why a piece snapped
+//! back instead of a generic "Not a legal move".
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::castling::Castling;
+use super::material::Piece;
+use super::moves::{check_promotion, reach, LegalMove, LegalMoves, Move, MoveSet, MoveState, PreMoves};
+use super::position::{between, Pos, Position};
+use super::square::Square;
+use super::{AnalysisBoard, EngineBoard, PlayerBoard, Turn};
+
+/// Why a move is illegal, as far as can be told from the position.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IllegalReason {
+    /// The game has ended.
+    GameOver,
+    EmptySquare,
+    /// The piece belongs to the opponent.
+    NotYourPiece,
+    /// The piece is ours, but it's the opponent's move.
+    WrongTurn,
+    /// The piece doesn't move that way.
+    Unreachable,
+    /// A piece on this square stands in the way.
+    Blocked(Square),
+    /// The destination holds one of the mover's own pieces.
+    OwnPiece,
+    /// A pawn moved diagonally without anything to capture.
+    NothingToCapture,
+    /// The promotion isn't possible for this move or in this variant.
+    InvalidPromotion,
+    /// The piece is pinned to its king.
+    Pinned,
+    /// The king is in check and the move doesn't deal with it.
+    InCheck,
+    /// The king would move onto an attacked square.
+    KingIntoCheck,
+    /// The move would expose the king in some other way, e.g. an en
+    /// passant capture clearing the king's rank.
+    ExposesKing,
+    NoCastlingRights,
+    CastlingOutOfCheck,
+    /// A piece stands between the king or rook and its destination.
+    CastlingBlocked,
+    /// The king would pass through or land on an attacked square, or one
+    /// only the castling rook shields.
+    CastlingThroughCheck,
+    /// A capture is available and the variant makes capturing compulsory.
+    MustCapture,
+}
+
+impl fmt::Display for IllegalReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GameOver => write!(f, "The game is over"),
+            Self::EmptySquare => write!(f, "There is no piece to move"),
+            Self::NotYourPiece => write!(f, "That piece belongs to your opponent"),
+            Self::WrongTurn => write!(f, "It's not your turn"),
+            Self::Unreachable => write!(f, "That piece doesn't move that way"),
+            Self::Blocked(square) => write!(f, "The piece on {square} is in the way"),
+            Self::OwnPiece => write!(f, "You can't capture your own piece"),
+            Self::NothingToCapture => write!(f, "Pawns only move diagonally to capture"),
+            Self::InvalidPromotion => write!(f, "That promotion isn't allowed"),
+            Self::Pinned => write!(f, "That piece is pinned to your king"),
+            Self::InCheck => write!(f, "Your king is in check"),
+            Self::KingIntoCheck => write!(f, "Your king can't move into check"),
+            Self::ExposesKing => write!(f, "That move would leave your king in check"),
+            Self::NoCastlingRights => write!(f, "You can no longer castle on that side"),
+            Self::CastlingOutOfCheck => write!(f, "You can't castle out of check"),
+            Self::CastlingBlocked => write!(f, "Pieces are in the way of castling"),
+            Self::CastlingThroughCheck => write!(f, "Your king can't castle through check"),
+            Self::MustCapture => write!(f, "A capture is available and must be made"),
+        }
+    }
+}
+
+impl MoveState {
+    /// Explains why `mv` can't be played here, or returns `None` if it
+    /// can. The first problem found is reported, checking the piece, the
+    /// way it moves and what stands in its way before the king's safety.
+    pub fn explain_illegal(&self, mv: Move) -> Option<IllegalReason> {
+        use IllegalReason::*;
+        if self.validate_move(mv).is_ok() {
+            return None;
+        }
+        let pos: &Position = self.as_ref();
+        let Some(material) = pos.contents(mv.from) else {
+            return Some(EmptySquare);
+        };
+        if material.color() != pos.turn() {
+            return Some(WrongTurn);
+        }
+        if material.piece() == Piece::King {
+            if let Some(reason) = self.explain_castling(mv) {
+                return Some(reason);
+            }
+        }
+        if pos.ours().contains(mv.to) {
+            return Some(OwnPiece);
+        }
+        if !reach(material.color(), material.piece(), mv.from).contains(mv.to) {
+            return Some(Unreachable);
+        }
+        let obstacles = between(mv.from, mv.to) & pos.occupied();
+        if material.piece() != Piece::Knight {
+            if let Some(square) = obstacles.iter().min_by_key(|square| square.distance(mv.from)) {
+                return Some(Blocked(square));
+            }
+        }
+        if material.piece() == Piece::Pawn {
+            let forward = mv.from.file() == mv.to.file();
+            if forward && pos.occupied().contains(mv.to) {
+                return Some(Blocked(mv.to));
+            }
+            if !forward && !pos.theirs().contains(mv.to) && pos.en_passant() != Some(mv.to) {
+                return Some(NothingToCapture);
+            }
+        }
+        if check_promotion(pos, *material, mv).is_err() {
+            return Some(InvalidPromotion);
+        }
+        let mut single = MoveSet::new();
+        single.insert(mv.to, LegalMove::Standard(mv.from, mv.to));
+        if pos.rules().restrict_moves(self, single).destinations().is_empty() {
+            return Some(MustCapture);
+        }
+        if !pos.rules().has_royal_king() {
+            return Some(Unreachable);
+        }
+        if material.piece() == Piece::King {
+            Some(KingIntoCheck)
+        } else if self.pinned(mv.from).is_some_and(|lane| !lane.contains(mv.to)) {
+            Some(Pinned)
+        } else if self.is_check() {
+            Some(InCheck)
+        } else {
+            Some(ExposesKing)
+        }
+    }
+
+    /// Explains a king move that looks like castling: two or more files
+    /// along the back rank, or onto one of our castling rooks.
+    fn explain_castling(&self, mv: Move) -> Option<IllegalReason> {
+        use IllegalReason::*;
+        let pos: &Position = self.as_ref();
+        let castling = pos.our_castling();
+        if mv.from != castling.king_src() || mv.from.rank() != mv.to.rank() {
+            return None;
+        }
+        let short = mv.to.file() > mv.from.file();
+        let (rook, king_dest, rook_dest) = match short {
+            true => (castling.oo_rook_src(), castling.oo_king_dest(), castling.oo_rook_dest()),
+            false => (castling.ooo_rook_src(), castling.ooo_king_dest(), castling.ooo_rook_dest()),
+        };
+        let onto_rook = mv.to == rook && pos.ours().contains(rook);
+        let far = mv.from.file().to_index().abs_diff(mv.to.file().to_index()) > 1;
+        let looks_like_castling = onto_rook || (far && mv.to == king_dest);
+        if !looks_like_castling {
+            return None;
+        }
+        let allowed = pos.rules().allows_castling() && if short { castling.oo() } else { castling.ooo() };
+        if !allowed {
+            return Some(NoCastlingRights);
+        }
+        if self.is_check() {
+            return Some(CastlingOutOfCheck);
+        }
+        let (lane, attacked) = match short {
+            true => (castling.oo_blocking_lane(), castling.oo_attacking_lane()),
+            false => (castling.ooo_blocking_lane(), castling.ooo_attacking_lane()),
+        };
+        if self.is_lane_blocked(lane) {
+            return Some(CastlingBlocked);
+        }
+        if self.is_lane_attacked(attacked) || self.is_attacked(king_dest) || self.is_castling_exposing(king_dest, rook, rook_dest) {
+            return Some(CastlingThroughCheck);
+        }
+        None
+    }
+}
+
+impl EngineBoard {
+    /// Explains why `mv` would be rejected by `submit_move`, or returns
+    /// `None` if it would be played.
+    pub fn explain_illegal(&self, mv: Move) -> Option<IllegalReason> {
+        if self.board_result().is_some() && !self.post_game_analysis() {
+            return Some(IllegalReason::GameOver);
+        }
+        AsRef::<MoveState>::as_ref(&self.state).explain_illegal(mv)
+    }
+}
+
+impl PlayerBoard {
+    /// Explains why `mv` would be rejected by `submit_our_move`, or
+    /// returns `None` if it would be played. On their turn our move would
+    /// be queued as a pre-move, and only `WrongTurn` is reported if that
+    /// isn't possible either.
+    pub fn explain_illegal(&self, mv: Move) -> Option<IllegalReason> {
+        if self.result().is_some() {
+            return Some(IllegalReason::GameOver);
+        }
+        let pos: &Position = self.as_ref();
+        if pos.contents(mv.from).is_some_and(|material| material.color() != self.side()) {
+            return Some(IllegalReason::NotYourPiece);
+        }
+        if !self.our_turn() {
+            return self.state.preview().validate_pre_move(mv).err().map(|_| IllegalReason::WrongTurn);
+        }
+        AsRef::<MoveState>::as_ref(&self.state).explain_illegal(mv)
+    }
+}
+
+impl AnalysisBoard {
+    /// Explains why `mv` would be rejected by `submit_move`, or returns
+    /// `None` if it would be played. Either side may move, from the
+    /// reviewed position.
+    pub fn explain_illegal(&self, mv: Move) -> Option<IllegalReason> {
+        AsRef::<MoveState>::as_ref(&self.state).explain_illegal(mv)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use IllegalReason::*;
+
+    fn mv(uci: &str) -> Move {
+        uci.parse().unwrap()
+    }
+
+    fn explain(fen: &str, uci: &str) -> Option<IllegalReason> {
+        MoveState::new(Position::from_fen(fen).unwrap()).explain_illegal(mv(uci))
+    }
+
+    #[test]
+    fn test_piece_movement() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(explain(start, "e2e4"), None);
+        assert_eq!(explain(start, "e3e4"), Some(EmptySquare));
+        assert_eq!(explain(start, "e7e5"), Some(WrongTurn));
+        assert_eq!(explain(start, "f1c4"), Some(Blocked(Square::E2)));
+        assert_eq!(explain(start, "d1d2"), Some(OwnPiece));
+        assert_eq!(explain(start, "g1g3"), Some(Unreachable));
+        assert_eq!(explain(start, "e2d3"), Some(NothingToCapture));
+        assert_eq!(explain(start, "e2e3q"), Some(InvalidPromotion));
+        assert_eq!(explain("4k3/8/8/8/8/4p3/4P3/4K3 w - - 0 1", "e2e4"), Some(Blocked(Square::E3)));
+        assert_eq!(explain("4k3/8/8/8/8/4p3/4P3/4K3 w - - 0 1", "e2e3"), Some(Blocked(Square::E3)));
+        // a pawn reaching the last rank must name its promotion
+        assert_eq!(explain("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8"), Some(InvalidPromotion));
+        assert_eq!(explain("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8k"), Some(InvalidPromotion));
+    }
+
+    #[test]
+    fn test_king_safety() {
+        // the bishop on e2 is pinned by the rook on e8
+        let pinned = "4r1k1/8/8/8/8/8/4B3/4K3 w - - 0 1";
+        assert_eq!(explain(pinned, "e2d3"), Some(Pinned));
+        assert_eq!(explain(pinned, "e1e2"), Some(OwnPiece));
+        assert_eq!(explain(pinned, "e1f2"), None);
+        assert_eq!(explain("4r1k1/8/8/8/8/8/8/4K3 w - - 0 1", "e1e2"), Some(KingIntoCheck));
+        assert_eq!(explain("4r1k1/8/8/8/8/8/P7/4K3 w - - 0 1", "a2a3"), Some(InCheck));
+        // en passant would clear the rank between king and rook
+        assert_eq!(explain("8/8/8/K2pP2r/8/8/8/7k w - d6 0 1", "e5d6"), Some(ExposesKing));
+    }
+
+    #[test]
+    fn test_castling() {
+        assert_eq!(explain("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1"), None);
+        assert_eq!(explain("r3k2r/8/8/8/8/8/8/R3K2R w Qkq - 0 1", "e1g1"), Some(NoCastlingRights));
+        assert_eq!(explain("r3k2r/8/8/8/8/8/8/RN2K2R w KQkq - 0 1", "e1c1"), Some(CastlingBlocked));
+        assert_eq!(explain("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1a1"), None);
+        assert_eq!(explain("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1c1"), None);
+        assert_eq!(explain("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1b1"), Some(Unreachable));
+        assert_eq!(explain("4kr2/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1g1"), Some(CastlingThroughCheck));
+        assert_eq!(explain("4k3/8/8/8/8/8/8/R3K1nR w KQ - 0 1", "e1g1"), Some(CastlingBlocked));
+        assert_eq!(explain("4k3/4r3/8/8/8/8/8/R3K2R w KQ - 0 1", "e1g1"), Some(CastlingOutOfCheck));
+    }
+
+    #[test]
+    fn test_boards() {
+        let mut board = EngineBoard::standard();
+        for uci in ["f2f3", "e7e5", "g2g4", "d8h4"] {
+            board.submit_move(mv(uci)).unwrap();
+        }
+        assert_eq!(board.explain_illegal(mv("e1f2")), Some(GameOver));
+        board.set_post_game_analysis(true);
+        assert_eq!(board.explain_illegal(mv("e1f2")), Some(KingIntoCheck));
+
+        let mut board = PlayerBoard::plays_white(None);
+        assert_eq!(board.explain_illegal(mv("e7e5")), Some(NotYourPiece));
+        board.submit_our_move(mv("e2e4")).unwrap();
+        assert_eq!(board.explain_illegal(mv("d2d4")), None);
+        assert_eq!(board.explain_illegal(mv("d2d5")), Some(WrongTurn));
+        board.set_result(GameResult::Win(Color::White, WinReason::Resigned));
+        assert_eq!(board.explain_illegal(mv("d2d4")), Some(GameOver));
+        let analysis = board.enter_analysis_mode().unwrap();
+        assert_eq!(analysis.explain_illegal(mv("e7e5")), None);
+        assert_eq!(analysis.explain_illegal(mv("d2d4")), Some(WrongTurn));
+        assert_eq!(IllegalReason::Blocked(Square::E2).to_string(), "The piece on e2 is in the way");
+    }
+
+    #[test]
+    fn test_forced_capture() {
+        let mut board = EngineBoard::plays_both_from(Position::new_variant(BackRankId::default().into(), Variant::Antichess));
+        board.submit_move(mv("e2e3")).unwrap();
+        board.submit_move(mv("b7b5")).unwrap();
+        assert_eq!(board.explain_illegal(mv("a2a3")), Some(MustCapture));
+        assert_eq!(board.explain_illegal(mv("e1g1")), Some(NoCastlingRights));
+    }
+}
@@ -0,0 +1,316 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Turning clicks and drags on a `PlayerBoard` into moves, so every front
+//! end selects, deselects and moves pieces the same way. A piece can be
+//! moved by clicking it then its destination, by dragging it there, or
+//! both, and touch-move can hold the player to the first piece they pick.
+
+use crate::error::{ChessError, Result};
+use super::moves::{Move, Promotion};
+use super::play::PromotionPrompt;
+use super::square::{Mask, Square};
+use super::PlayerBoard;
+
+/// How a piece may be moved once it's selected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum InputMethod {
+    /// Click the piece, then click its destination.
+    ClickClick,
+    /// Drag the piece onto its destination.
+    DragDrop,
+    #[default]
+    Both,
+}
+
+impl InputMethod {
+    fn clicks(&self) -> bool {
+        *self != InputMethod::DragDrop
+    }
+    fn drags(&self) -> bool {
+        *self != InputMethod::ClickClick
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct InputPolicy {
+    pub method: InputMethod,
+    /// Once a piece that can move is selected on our turn, it can't be
+    /// deselected or swapped for another until it's moved. Pre-moves and
+    /// analysis aren't held to it.
+    pub touch_move: bool,
+}
+
+impl InputPolicy {
+    pub const fn new(method: InputMethod, touch_move: bool) -> Self {
+        Self { method, touch_move }
+    }
+}
+
+/// What an input event did, for the front end to reflect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputOutcome {
+    /// A piece is selected, and can move to the given squares.
+    Selected(Square, Mask),
+    Deselected,
+    /// The move was played, or queued as a pre-move. A promotion under
+    /// `PromotionPolicy::AutoQueen` is reported without its piece.
+    Moved(Move),
+    /// The move is a promotion: finish it with `PlayerInput::promote`.
+    /// The piece stays selected meanwhile.
+    Promotion(PromotionPrompt),
+    /// The event changed nothing, e.g. a click on an empty square with
+    /// nothing selected, or an attempt to let go of a touched piece.
+    Ignored,
+}
+
+/// A piece being dragged, and whether it was already selected when the
+/// drag started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Drag {
+    from: Square,
+    was_selected: bool,
+}
+
+/// The selection state of one player's board under an `InputPolicy`.
+/// Every event takes the board, so selections always reflect its current
+/// legal (pre-)moves; call `clear` after changing the board any other way
+/// than through this, such as taking moves back.
+#[derive(Debug, Clone, Default)]
+pub struct PlayerInput {
+    policy: InputPolicy,
+    selected: Option<Square>,
+    /// Whether the selected piece was touched under touch-move.
+    touched: bool,
+    drag: Option<Drag>,
+    prompt: Option<PromotionPrompt>,
+}
+
+impl PlayerInput {
+    pub fn new(policy: InputPolicy) -> Self {
+        Self { policy, ..Self::default() }
+    }
+
+    pub fn policy(&self) -> InputPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: InputPolicy) {
+        self.policy = policy;
+        self.touched &= policy.touch_move;
+    }
+
+    pub fn selected(&self) -> Option<Square> {
+        self.selected
+    }
+
+    /// Returns the square of the piece being dragged, if any.
+    pub fn dragging(&self) -> Option<Square> {
+        self.drag.map(|drag| drag.from)
+    }
+
+    /// Returns true if touch-move holds the player to the selected piece.
+    pub fn is_locked(&self, board: &PlayerBoard) -> bool {
+        self.touched && board.our_turn() && self.selected
+            .is_some_and(|from| !board.move_destinations(from).is_empty())
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new(self.policy);
+    }
+
+    /// Handles a click (or tap) on `square`: selecting the piece there,
+    /// moving the selected piece there, or deselecting.
+    pub fn click(&mut self, board: &mut PlayerBoard, square: Square) -> Result<InputOutcome> {
+        let locked = self.is_locked(board);
+        match self.selected {
+            Some(from) if from == square => match locked {
+                true => Ok(InputOutcome::Ignored),
+                false => Ok(self.deselect()),
+            },
+            Some(from) if self.policy.method.clicks()
+                && board.move_destinations(from).contains(square) => self.submit(board, from, square),
+            _ if locked => Ok(InputOutcome::Ignored),
+            _ if !board.move_destinations(square).is_empty() => Ok(self.select(board, square)),
+            Some(_) => Ok(self.deselect()),
+            None => Ok(InputOutcome::Ignored),
+        }
+    }
+
+    /// Handles picking up the piece on `square` to drag it.
+    pub fn drag_start(&mut self, board: &PlayerBoard, square: Square) -> InputOutcome {
+        let movable = !board.move_destinations(square).is_empty();
+        let held = self.is_locked(board) && self.selected != Some(square);
+        if !self.policy.method.drags() || !movable || held {
+            return InputOutcome::Ignored;
+        }
+        self.drag = Some(Drag { from: square, was_selected: self.selected == Some(square) });
+        self.select(board, square)
+    }
+
+    /// Handles dropping the dragged piece on `square`, or off the board
+    /// if `None`. Dropping a piece back where it was picked up acts as a
+    /// click: it stays selected for a click-click move, unless it was
+    /// already selected, when it's deselected. Any other drop that isn't
+    /// a move puts the piece back and deselects it.
+    pub fn drop(&mut self, board: &mut PlayerBoard, square: Option<Square>) -> Result<InputOutcome> {
+        let Some(drag) = self.drag.take() else { return Ok(InputOutcome::Ignored) };
+        let locked = self.is_locked(board);
+        match square {
+            Some(to) if to == drag.from => match drag.was_selected && !locked {
+                true => Ok(self.deselect()),
+                false => Ok(InputOutcome::Selected(drag.from, board.move_destinations(drag.from))),
+            },
+            Some(to) if board.move_destinations(drag.from).contains(to) => self.submit(board, drag.from, to),
+            _ if locked => Ok(InputOutcome::Ignored),
+            _ => Ok(self.deselect()),
+        }
+    }
+
+    /// Finishes a move that needed a promotion piece.
+    pub fn promote(&mut self, board: &mut PlayerBoard, promotion: Promotion) -> Result<InputOutcome> {
+        let Some(prompt) = self.prompt.take() else { return Ok(InputOutcome::Ignored) };
+        let mv = prompt.choose(promotion);
+        let result = board.submit_our_move(mv);
+        self.finish(result.map(|()| mv))
+    }
+
+    /// Dismisses a promotion prompt without moving. The piece stays
+    /// selected.
+    pub fn cancel_promotion(&mut self) {
+        self.prompt = None;
+    }
+
+    fn select(&mut self, board: &PlayerBoard, square: Square) -> InputOutcome {
+        self.selected = Some(square);
+        self.prompt = None;
+        self.touched = self.policy.touch_move && board.our_turn();
+        InputOutcome::Selected(square, board.move_destinations(square))
+    }
+
+    fn deselect(&mut self) -> InputOutcome {
+        self.clear();
+        InputOutcome::Deselected
+    }
+
+    fn submit(&mut self, board: &mut PlayerBoard, from: Square, to: Square) -> Result<InputOutcome> {
+        let mv = Move::new(from, to, None);
+        match board.submit_our_move(mv) {
+            Err(ChessError::NeedsPromotion(prompt)) => {
+                self.prompt = Some(prompt.clone());
+                Ok(InputOutcome::Promotion(prompt))
+            },
+            result => self.finish(result.map(|()| mv)),
+        }
+    }
+
+    /// Clears the selection once a move has been submitted, whether or
+    /// not the board took it.
+    fn finish(&mut self, result: Result<Move>) -> Result<InputOutcome> {
+        self.clear();
+        result.map(InputOutcome::Moved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use Square::*;
+
+    #[test]
+    fn test_click_click() {
+        let mut board = PlayerBoard::plays_white(None);
+        let mut input = PlayerInput::default();
+        assert_eq!(input.click(&mut board, E4), Ok(InputOutcome::Ignored));
+        let outcome = input.click(&mut board, G1).unwrap();
+        assert_eq!(outcome, InputOutcome::Selected(G1, F3.to_mask() | H3.to_mask()));
+        // another of our pieces switches the selection, a second click
+        // on it deselects it
+        assert!(matches!(input.click(&mut board, E2), Ok(InputOutcome::Selected(E2, _))));
+        assert_eq!(input.click(&mut board, E2), Ok(InputOutcome::Deselected));
+        // a square that isn't a destination deselects too
+        input.click(&mut board, E2).unwrap();
+        assert_eq!(input.click(&mut board, E5), Ok(InputOutcome::Deselected));
+        assert_eq!(input.selected(), None);
+
+        input.click(&mut board, E2).unwrap();
+        assert_eq!(input.click(&mut board, E4), Ok(InputOutcome::Moved("e2e4".parse().unwrap())));
+        assert_eq!(input.selected(), None);
+        // on their turn the same clicks queue pre-moves
+        input.click(&mut board, D2).unwrap();
+        assert_eq!(input.click(&mut board, D4), Ok(InputOutcome::Moved("d2d4".parse().unwrap())));
+        assert_eq!(board.queued_pre_moves().len(), 1);
+
+        // drag-only boards don't move on clicks
+        let mut board = PlayerBoard::plays_white(None);
+        let mut input = PlayerInput::new(InputPolicy::new(InputMethod::DragDrop, false));
+        input.click(&mut board, E2).unwrap();
+        assert_eq!(input.click(&mut board, E4), Ok(InputOutcome::Deselected));
+    }
+
+    #[test]
+    fn test_drag_drop() {
+        let mut board = PlayerBoard::plays_white(None);
+        let mut input = PlayerInput::default();
+        assert_eq!(input.drag_start(&board, E7), InputOutcome::Ignored);
+        assert!(matches!(input.drag_start(&board, E2), InputOutcome::Selected(E2, _)));
+        assert_eq!(input.dragging(), Some(E2));
+        // dropped where it was picked up, it stays selected for a click
+        assert!(matches!(input.drop(&mut board, Some(E2)), Ok(InputOutcome::Selected(E2, _))));
+        // dragged again and put back, it's deselected as a second click
+        input.drag_start(&board, E2);
+        assert_eq!(input.drop(&mut board, Some(E2)), Ok(InputOutcome::Deselected));
+        // dropped off the board or off target, it goes back
+        input.drag_start(&board, E2);
+        assert_eq!(input.drop(&mut board, None), Ok(InputOutcome::Deselected));
+        input.drag_start(&board, E2);
+        assert_eq!(input.drop(&mut board, Some(E5)), Ok(InputOutcome::Deselected));
+        input.drag_start(&board, E2);
+        assert_eq!(input.drop(&mut board, Some(E4)), Ok(InputOutcome::Moved("e2e4".parse().unwrap())));
+        assert_eq!(input.drop(&mut board, Some(E4)), Ok(InputOutcome::Ignored));
+
+        let mut input = PlayerInput::new(InputPolicy::new(InputMethod::ClickClick, false));
+        assert_eq!(input.drag_start(&board, D2), InputOutcome::Ignored);
+    }
+
+    #[test]
+    fn test_touch_move_and_promotion() {
+        let position = Position::from_fen("7k/P7/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        let mut board = PlayerBoard::plays_from(Color::White, position);
+        let mut input = PlayerInput::new(InputPolicy::new(InputMethod::Both, true));
+        input.click(&mut board, A1).unwrap();
+        assert!(input.is_locked(&board));
+        // the touched king must move
+        assert_eq!(input.click(&mut board, A1), Ok(InputOutcome::Ignored));
+        assert_eq!(input.click(&mut board, A7), Ok(InputOutcome::Ignored));
+        assert_eq!(input.drag_start(&board, A7), InputOutcome::Ignored);
+        input.drag_start(&board, A1);
+        assert_eq!(input.drop(&mut board, Some(H1)), Ok(InputOutcome::Ignored));
+        assert_eq!(input.selected(), Some(A1));
+        input.set_policy(InputPolicy::default());
+        assert!(!input.is_locked(&board));
+        input.clear();
+
+        input.click(&mut board, A7).unwrap();
+        let prompt = match input.click(&mut board, A8) {
+            Ok(InputOutcome::Promotion(prompt)) => prompt,
+            other => panic!("expected a prompt, got {other:?}"),
+        };
+        assert_eq!(input.selected(), Some(A7));
+        let rook = prompt.choose(Promotion::Rook);
+        assert_eq!(input.promote(&mut board, Promotion::Rook), Ok(InputOutcome::Moved(rook)));
+        assert_eq!(board.current()[A8], Some(Material::new(Color::White, Piece::Rook)));
+        assert_eq!(input.promote(&mut board, Promotion::Rook), Ok(InputOutcome::Ignored));
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use serde::{Deserialize, Serialize};
+
+use super::material::Color;
+use super::position::{MatingMaterial, Pos, Position};
+use super::square::Mask;
+
+/// The rules deciding when there's too little material left to win.
+/// Servers disagree both on which positions are drawn automatically and
+/// on whether running out of time loses against an opponent who can't
+/// checkmate, so the policy covers both.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum InsufficientMaterial {
+    /// FIDE Laws 5.2.2 and 6.9: the game is drawn once neither side could
+    /// checkmate by any series of legal moves, and a player who runs out
+    /// of time only loses if the opponent could.
+    Fide,
+    /// USCF rules: dead positions are drawn as under FIDE, but running out
+    /// of time is a draw if the opponent has only a king, a king and a
+    /// minor piece, or two knights against a lone king.
+    Uscf,
+    /// chess.com's heuristics: a lone king against at most two knights,
+    /// or a minor piece against a minor piece, is drawn automatically,
+    /// and running out of time is a draw if the opponent has at most a
+    /// minor piece.
+    #[default]
+    ChessCom,
+    /// lichess: as FIDE.
+    Lichess,
+}
+
+impl InsufficientMaterial {
+    /// Returns true if the game ends in a draw in `pos`.
+    pub fn is_draw(&self, pos: &Position) -> bool {
+        match self {
+            Self::ChessCom => is_chess_com_draw(pos),
+            Self::Fide | Self::Uscf | Self::Lichess => {
+                !could_mate(pos, Color::White) && !could_mate(pos, Color::Black)
+            },
+        }
+    }
+
+    /// Returns true if `winner` wins when their opponent runs out of time
+    /// in `pos`. If not, the game is a draw.
+    pub fn can_win_on_time(&self, pos: &Position, winner: Color) -> bool {
+        use MatingMaterial::*;
+        match (self, pos.mating_material(winner)) {
+            (Self::Fide | Self::Lichess, _) => could_mate(pos, winner),
+            (_, Sufficient) => true,
+            (_, LoneKing | OneKnight | OneBishop) => false,
+            (Self::Uscf, TwoKnights) => pos.mating_material(!winner) != LoneKing,
+            (Self::ChessCom, TwoKnights) => true,
+        }
+    }
+}
+
+fn is_chess_com_draw(pos: &Position) -> bool {
+    use MatingMaterial::*;
+    match pos.our_mating_material() {
+        Sufficient => false,
+        ours => match (ours, pos.their_mating_material()) {
+            (_, Sufficient) => false,
+            (LoneKing, _) => true,
+            (_, LoneKing) => true,
+            (TwoKnights, _) => false,
+            (_, TwoKnights) => false,
+            _ => true,
+        }
+    }
+}
+
+/// Returns true if `side` could checkmate by some series of legal moves,
+/// however unlikely, given help from the opponent's pieces.
+fn could_mate(pos: &Position, side: Color) -> bool {
+    let ours = pos.occupied_by(side) & !pos.kings();
+    let theirs = pos.occupied_by(!side) & !pos.kings();
+    if !(ours & (pos.pawns() | pos.rooks() | pos.queens())).is_empty() {
+        return true;
+    }
+    let knights = ours & pos.knights();
+    let bishops = ours & pos.bishops();
+    match (knights.len(), bishops.len()) {
+        (0, 0) => false,
+        // A lone knight needs an enemy piece to block the king's escape
+        (1, 0) => !theirs.is_empty(),
+        // Bishops that all stand on one color need a blocker on the other
+        (0, _) if on_one_color(bishops) => {
+            let their_bishops = theirs & pos.bishops();
+            their_bishops != theirs || !on_one_color(bishops | their_bishops)
+        },
+        _ => true,
+    }
+}
+
+fn on_one_color(mask: Mask) -> bool {
+    let mut colors = mask.iter().map(|square| square.color());
+    match colors.next() {
+        Some(first) => colors.all(|color| color == first),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use strum::IntoEnumIterator;
+    use Square::*;
+    use InsufficientMaterial::*;
+
+    const POLICIES: [InsufficientMaterial; 4] = [Fide, Uscf, ChessCom, Lichess];
+
+    /// Kings on e1 and e8, plus `pieces`.
+    fn position(pieces: &[(Square, Material)]) -> Position {
+        let mut pos = Position::default();
+        for square in Square::iter() {
+            pos = pos.set_contents(square, None);
+        }
+        pos = pos.set_contents(E1, Some(Material::WK)).set_contents(E8, Some(Material::BK));
+        for (square, material) in pieces {
+            pos = pos.set_contents(*square, Some(*material));
+        }
+        pos
+    }
+
+    #[test]
+    fn test_is_draw() {
+        let draws = |pieces: &[(Square, Material)]| -> Vec<bool> {
+            let pos = position(pieces);
+            POLICIES.iter().map(|policy| policy.is_draw(&pos)).collect()
+        };
+        assert_eq!(draws(&[]), [true; 4]);
+        assert_eq!(draws(&[(C1, Material::WB)]), [true; 4]);
+        assert_eq!(draws(&[(C1, Material::WB), (F8, Material::BB)]), [true; 4]);
+        // Mates are possible with opposite colored bishops or knights
+        assert_eq!(draws(&[(C1, Material::WB), (C8, Material::BB)]), [false, false, true, false]);
+        assert_eq!(draws(&[(B1, Material::WN), (B8, Material::BN)]), [false, false, true, false]);
+        assert_eq!(draws(&[(B1, Material::WN), (G1, Material::WN)]), [false, false, true, false]);
+        assert_eq!(draws(&[(B1, Material::WN), (G1, Material::WN), (B8, Material::BN)]), [false; 4]);
+        assert_eq!(draws(&[(A2, Material::WP)]), [false; 4]);
+    }
+
+    #[test]
+    fn test_can_win_on_time() {
+        let wins = |pieces: &[(Square, Material)]| -> Vec<bool> {
+            let pos = position(pieces);
+            POLICIES.iter().map(|policy| policy.can_win_on_time(&pos, Color::White)).collect()
+        };
+        assert_eq!(wins(&[(A7, Material::BP)]), [false; 4]);
+        assert_eq!(wins(&[(B1, Material::WN)]), [false; 4]);
+        assert_eq!(wins(&[(B1, Material::WN), (A7, Material::BP)]), [true, false, false, true]);
+        assert_eq!(wins(&[(C1, Material::WB), (F8, Material::BB)]), [false; 4]);
+        assert_eq!(wins(&[(B1, Material::WN), (G1, Material::WN)]), [true, false, true, true]);
+        assert_eq!(
+            wins(&[(B1, Material::WN), (G1, Material::WN), (A7, Material::BP)]),
+            [true; 4],
+        );
+        assert_eq!(wins(&[(D1, Material::WQ)]), [true; 4]);
+    }
+}
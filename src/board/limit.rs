@@ -0,0 +1,118 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Limits how often moves (including pre-moves) may be submitted: a
+/// sustained `per_second` rate with up to `burst` submissions in quick
+/// succession.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RateLimit {
+    pub per_second: u32,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub const fn new(per_second: u32, burst: u32) -> Self {
+        Self { per_second, burst }
+    }
+}
+
+impl Default for RateLimit {
+    /// Generous enough for bullet games with pre-moves, but stops clients
+    /// flooding a server with submissions.
+    fn default() -> Self {
+        Self::new(10, 20)
+    }
+}
+
+/// A submission was rejected because the rate limit was exceeded.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Too many submissions, retry after {retry_after:?}")]
+pub struct RateLimited {
+    pub retry_after: Duration,
+}
+
+/// Enforces a `RateLimit` (as a token bucket, implemented with the
+/// generic cell rate algorithm so no timer is needed).
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    interval: Duration,
+    tolerance: Duration,
+    next_free: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        let interval = Duration::from_secs(1) / limit.per_second.max(1);
+        Self {
+            interval,
+            tolerance: interval * limit.burst.saturating_sub(1),
+            next_free: None,
+        }
+    }
+
+    /// Records a submission at `now`, or rejects it if it would exceed
+    /// the limit. Rejected submissions don't count against the limit.
+    pub fn check(&mut self, now: Instant) -> Result<(), RateLimited> {
+        let next_free = self.next_free.map_or(now, |next_free| next_free.max(now));
+        if let Some(allowed_at) = next_free.checked_sub(self.tolerance) {
+            if allowed_at > now {
+                return Err(RateLimited { retry_after: allowed_at - now });
+            }
+        }
+        self.next_free = Some(next_free + self.interval);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_sustained_rate() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(RateLimit::new(4, 3));
+        for _ in 0..3 {
+            assert!(limiter.check(start).is_ok());
+        }
+        let expected = RateLimited { retry_after: Duration::from_millis(250) };
+        assert_eq!(limiter.check(start), Err(expected));
+        assert!(limiter.check(start + Duration::from_millis(250)).is_ok());
+        assert!(limiter.check(start + Duration::from_millis(250)).is_err());
+        // after a quiet period, the full burst is available again
+        let later = start + Duration::from_secs(5);
+        for _ in 0..3 {
+            assert!(limiter.check(later).is_ok());
+        }
+        assert!(limiter.check(later).is_err());
+    }
+
+    #[test]
+    fn test_board_rejects_flood() {
+        use crate::*;
+        let mut board = PlayerBoard::plays_white(None);
+        board.set_rate_limit(Some(RateLimit::new(1, 2)));
+        board.submit_our_move("e2e4".parse().unwrap()).unwrap();
+        board.submit_our_move("d2d4".parse().unwrap()).unwrap();
+        let error = board.submit_our_move("g1f3".parse().unwrap()).unwrap_err();
+        assert!(matches!(error, ChessError::RateLimited(_)));
+        assert_eq!(board.queued_pre_moves().len(), 1);
+
+        board.set_rate_limit(None);
+        assert!(board.submit_our_move("g1f3".parse().unwrap()).is_ok());
+    }
+}
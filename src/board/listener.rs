@@ -0,0 +1,265 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use super::square::Square;
+use super::material::{Color, Material};
+use super::moves::{LegalMove, Move, MoveState};
+use super::play::BoardResult;
+use super::position::{Pos, Position};
+use super::Turn;
+
+/// Receives notifications about changes to a board, e.g. to play sounds
+/// or trigger animations. Every callback has an empty default so
+/// listeners only implement what they need.
+///
+/// For each applied move, `on_move_played` and `on_move_applied` are
+/// called first, followed by `on_capture`, `on_promotion`, `on_check` and
+/// `on_result` as applicable.
+pub trait BoardListener: Send + Sync {
+    /// `mv` took the game from `before` to `after`, for listeners that
+    /// need the whole position (e.g. `GameRecorder`).
+    fn on_move_played(&mut self, _before: &MoveState, _mv: LegalMove, _after: &MoveState) {}
+    fn on_move_applied(&mut self, _color: Color, _mv: LegalMove) {}
+    /// `captured` was taken on `square` (for en passant, the square of
+    /// the captured pawn rather than the destination).
+    fn on_capture(&mut self, _square: Square, _captured: Material) {}
+    fn on_promotion(&mut self, _square: Square, _promoted: Material) {}
+    /// The side to move, `color`, is in check.
+    fn on_check(&mut self, _color: Color) {}
+    fn on_result(&mut self, _result: BoardResult) {}
+    /// A queued pre-move was played automatically after the opponent's move.
+    fn on_pre_move_applied(&mut self, _mv: Move) {}
+    /// A queued pre-move was dropped, either because it became illegal or
+    /// because the queue was cancelled.
+    fn on_pre_move_discarded(&mut self, _mv: Move) {}
+}
+
+#[derive(Default)]
+pub(crate) struct Listeners {
+    listeners: Vec<Box<dyn BoardListener>>,
+}
+
+impl Listeners {
+    pub fn push(&mut self, listener: Box<dyn BoardListener>) {
+        self.listeners.push(listener);
+    }
+
+    pub fn clear(&mut self) {
+        self.listeners.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    /// Notifies listeners that `mv` took the game from `before` to `after`.
+    pub fn move_applied(&mut self, before: &MoveState, mv: LegalMove, after: &MoveState) {
+        if self.listeners.is_empty() {
+            return;
+        }
+        let state = before;
+        let before: &Position = state.as_ref();
+        let color = before.turn();
+        let captured = mv.capture_square(before)
+            .and_then(|square| before.contents(square).map(|material| (square, material)));
+        for listener in self.listeners.iter_mut() {
+            listener.on_move_played(state, mv, after);
+            listener.on_move_applied(color, mv);
+            if let Some((square, material)) = captured {
+                listener.on_capture(square, material);
+            }
+            if let Some(promotion) = mv.promotion() {
+                listener.on_promotion(mv.to(before), Material::new(color, promotion.into()));
+            }
+            if after.is_check() {
+                listener.on_check(after.turn());
+            }
+        }
+    }
+
+    pub fn result(&mut self, result: BoardResult) {
+        for listener in self.listeners.iter_mut() {
+            listener.on_result(result);
+        }
+    }
+
+    pub fn pre_move_applied(&mut self, mv: Move) {
+        for listener in self.listeners.iter_mut() {
+            listener.on_pre_move_applied(mv);
+        }
+    }
+
+    pub fn pre_moves_discarded(&mut self, moves: &[Move]) {
+        for listener in self.listeners.iter_mut() {
+            for mv in moves {
+                listener.on_pre_move_discarded(*mv);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::*;
+    use Square::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Event {
+        Moved(Color),
+        Capture(Square, Material),
+        Promotion(Square, Material),
+        Check(Color),
+        Result(BoardResult),
+        PreMoveApplied(Move),
+        PreMoveDiscarded(Move),
+    }
+
+    #[derive(Default, Clone)]
+    struct Recorder(Arc<Mutex<Vec<Event>>>);
+
+    impl Recorder {
+        fn take(&self) -> Vec<Event> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+        fn push(&self, event: Event) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    impl BoardListener for Recorder {
+        fn on_move_applied(&mut self, color: Color, _mv: LegalMove) {
+            self.push(Event::Moved(color));
+        }
+        fn on_capture(&mut self, square: Square, captured: Material) {
+            self.push(Event::Capture(square, captured));
+        }
+        fn on_promotion(&mut self, square: Square, promoted: Material) {
+            self.push(Event::Promotion(square, promoted));
+        }
+        fn on_check(&mut self, color: Color) {
+            self.push(Event::Check(color));
+        }
+        fn on_result(&mut self, result: BoardResult) {
+            self.push(Event::Result(result));
+        }
+        fn on_pre_move_applied(&mut self, mv: Move) {
+            self.push(Event::PreMoveApplied(mv));
+        }
+        fn on_pre_move_discarded(&mut self, mv: Move) {
+            self.push(Event::PreMoveDiscarded(mv));
+        }
+    }
+
+    fn mv(uci: &str) -> Move {
+        uci.parse().unwrap()
+    }
+
+    #[test]
+    fn test_capture_and_check() {
+        let recorder = Recorder::default();
+        let mut board = EngineBoard::standard();
+        board.add_listener(recorder.clone());
+        for uci in ["e2e4", "f7f5", "e4f5", "g7g5"] {
+            board.submit_move(mv(uci)).unwrap();
+        }
+        recorder.take();
+        board.submit_move(mv("d1h5")).unwrap();
+        assert_eq!(recorder.take(), vec![
+            Event::Moved(Color::White),
+            Event::Check(Color::Black),
+            Event::Result(BoardResult::CheckMate(Color::White)),
+        ]);
+
+        let mut board = EngineBoard::standard();
+        board.add_listener(recorder.clone());
+        for uci in ["e2e4", "d7d5", "e4d5"] {
+            board.submit_move(mv(uci)).unwrap();
+        }
+        assert_eq!(recorder.take()[2..], [Event::Moved(Color::White), Event::Capture(D5, Material::BP)]);
+    }
+
+    #[test]
+    fn test_en_passant_capture_square() {
+        let recorder = Recorder::default();
+        let mut board = EngineBoard::standard();
+        board.add_listener(recorder.clone());
+        for uci in ["e2e4", "a7a6", "e4e5", "d7d5", "e5d6"] {
+            board.submit_move(mv(uci)).unwrap();
+        }
+        assert_eq!(recorder.take().last(), Some(&Event::Capture(D5, Material::BP)));
+    }
+
+    #[test]
+    fn test_pre_moves_applied_and_discarded() {
+        let recorder = Recorder::default();
+        let mut board = PlayerBoard::plays_white(None);
+        board.add_listener(recorder.clone());
+        board.submit_our_move(mv("e2e4")).unwrap();
+        board.submit_our_move(mv("d2d4")).unwrap();
+        board.submit_their_move(mv("d7d5")).unwrap();
+        assert_eq!(recorder.take(), vec![
+            Event::Moved(Color::White),
+            Event::Moved(Color::Black),
+            Event::Moved(Color::White),
+            Event::PreMoveApplied(mv("d2d4")),
+        ]);
+
+        board.submit_our_move(mv("d4e5")).unwrap();
+        board.submit_our_move(mv("e4d5")).unwrap();
+        board.submit_their_move(mv("e7e6")).unwrap();
+        assert_eq!(recorder.take(), vec![
+            Event::Moved(Color::Black),
+            Event::PreMoveDiscarded(mv("d4e5")),
+            Event::PreMoveDiscarded(mv("e4d5")),
+        ]);
+
+        board.submit_our_move(mv("e4d5")).unwrap();
+        recorder.take();
+        board.submit_our_move(mv("g1f3")).unwrap();
+        board.cancel_pre_moves();
+        assert_eq!(recorder.take(), vec![Event::PreMoveDiscarded(mv("g1f3"))]);
+    }
+
+    #[test]
+    fn test_promotion() {
+        let recorder = Recorder::default();
+        let mut board = EngineBoard::standard();
+        board.add_listener(recorder.clone());
+        // 1. h4 g5 2. hxg5 Nf6 3. gxf6 Rg8 4. fxe7 Rg7 5. exd8=Q+
+        for uci in ["h2h4", "g7g5", "h4g5", "g8f6", "g5f6", "h8g8", "f6e7", "g8g7"] {
+            board.submit_move(mv(uci)).unwrap();
+        }
+        recorder.take();
+        board.submit_move(mv("e7d8q")).unwrap();
+        assert_eq!(recorder.take(), vec![
+            Event::Moved(Color::White),
+            Event::Capture(D8, Material::BQ),
+            Event::Promotion(D8, Material::WQ),
+            Event::Check(Color::Black),
+        ]);
+    }
+
+    #[test]
+    fn test_result() {
+        let recorder = Recorder::default();
+        let mut board = EngineBoard::standard();
+        board.add_listener(recorder.clone());
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        for uci in shuffle.iter().cycle().take(8) {
+            board.submit_move(mv(uci)).unwrap();
+        }
+        assert_eq!(recorder.take().last(), Some(&Event::Result(BoardResult::Repetition)));
+    }
+}
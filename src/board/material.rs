@@ -14,10 +14,12 @@
 
 use std::ops::{Not, Index, IndexMut};
 use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 use strum_macros::EnumIter;
 use strum_macros::Display;
 use serde::{Deserialize, Serialize};
 
+use super::square::ParseError;
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Material {
@@ -75,6 +77,56 @@ impl Material {
     pub fn to_index(&self) -> usize {
         self.color.to_index() * 2 + self.piece.to_index()
     }
+
+    /// Returns the FEN letter for this material: uppercase for white
+    /// ('K', 'Q', ...) and lowercase for black ('k', 'q', ...).
+    pub fn to_char(&self) -> char {
+        let c = self.piece.to_char();
+        match self.color {
+            White => c,
+            Black => c.to_ascii_lowercase(),
+        }
+    }
+
+    /// Returns the unicode chess symbol for this material ('♔' .. '♟').
+    pub fn to_unicode(&self) -> char {
+        const WHITE: [char; 6] = ['♙', '♘', '♗', '♖', '♕', '♔'];
+        const BLACK: [char; 6] = ['♟', '♞', '♝', '♜', '♛', '♚'];
+        match self.color {
+            White => WHITE[self.piece.to_index()],
+            Black => BLACK[self.piece.to_index()],
+        }
+    }
+
+    /// Parses a FEN letter ('K', 'q', ...) or a unicode chess symbol.
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'K' | '♔' => Some(Self::WK),
+            'Q' | '♕' => Some(Self::WQ),
+            'R' | '♖' => Some(Self::WR),
+            'B' | '♗' => Some(Self::WB),
+            'N' | '♘' => Some(Self::WN),
+            'P' | '♙' => Some(Self::WP),
+            'k' | '♚' => Some(Self::BK),
+            'q' | '♛' => Some(Self::BQ),
+            'r' | '♜' => Some(Self::BR),
+            'b' | '♝' => Some(Self::BB),
+            'n' | '♞' => Some(Self::BN),
+            'p' | '♟' => Some(Self::BP),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Material {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Self::from_char(c),
+            _ => None,
+        }.ok_or_else(|| ParseError::InvalidMaterial(s.to_string()))
+    }
 }
 
 
@@ -205,6 +257,17 @@ impl Piece {
     pub fn to_index(&self) -> usize {
         *self as usize
     }
+    /// Returns the conventional point value of this piece (the king is
+    /// valued at zero since it can never be captured).
+    pub const fn value(&self) -> i32 {
+        const VALUES: [i32; 6] = [1, 3, 3, 5, 9, 0];
+        VALUES[*self as usize]
+    }
+    /// Returns the uppercase letter used for this piece in FEN and SAN.
+    pub fn to_char(&self) -> char {
+        const CHARS: [char; 6] = ['P', 'N', 'B', 'R', 'Q', 'K'];
+        CHARS[self.to_index()]
+    }
     pub fn is_king(&self) -> bool {
         matches!(*self, King)
     }
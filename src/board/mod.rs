@@ -25,13 +25,14 @@
 //! [x] Enforce three-fold repetition rule
 //! [ ] Enforce five-fold repetition rule
 //! [x] Enforce fifty-move rule
-//! [x] Recognize insuffient mating material (using chess.com's heuristics)
+//! [x] Recognize insuffient mating material (FIDE, USCF, chess.com or lichess rules)
 //! [ ] Time Controls
 //! [x] Engine mode (see below for description)
 //! [x] Player mode (see below for description)
 //! [x] Review prior positions
 //! [ ] Take backs (still need to look into configuration options)
 //! [ ] Recognize some dead positions (unlikely to implement this fully)
+//! [x] Antichess (see `Variant` and `Rules`)
 //! [ ] Other chess variants such as Crazyhouse, 3-Check, etc.
 //! 
 //! Some of the key abstractions include:
@@ -68,7 +69,7 @@
 //!   toggle the turn. Note that there is no mechanism to undo or 
 //!   roll back a position to a previous state. That functionality
 //!   is handled by `ReviewState` which holds onto all historical 
-//!   positions (indirectly via `MoveState`).
+//!   positions.
 //! 
 //! * `MoveState` encapsulates a single position but also tracks 
 //!   the squares that are attacking or being attacked by other
@@ -80,40 +81,126 @@
 //!   corresponding methods in its contained position.
 //! 
 //! * `ReviewState` is used to efficiently step backward or forward
-//!   through the historical positions in a game. It contains a list
-//!   of `MoveState`s and supports skipping directly to the starting
-//!   position or the end or to any in the middle. It can be cloned
+//!   through the historical positions in a game. It contains a tree
+//!   of `Position`s (rebuilding a `MoveState` on demand) and supports skipping directly to the starting
+//!   position or the end or to any in the middle. Alternate lines can
+//!   be added from any position as variations. It can be cloned
 //!   and/or truncated to support "take-back" functionality.
 //!   
-//! * This crate supports two modes of play: `EngineBoard` and
-//!   `PlayerBoard`. An `EngineBoard` plays both sides of a game,
+//! * This crate supports three modes of play: `EngineBoard`,
+//!   `PlayerBoard` and `AnalysisBoard`. An `EngineBoard` plays both sides of a game,
 //!   applying successive moves of alternating color. It is designed
 //!   to be used by an engine or a server that receives and applies
 //!   moves from each player in turn. No pre-moves or reviewing prior 
 //!   positions is allowed. A `PlayerBoard` plays one side of a game.
 //!   It holds on to `ReviewState` and tracks pre-moves (automatically
 //!   applying or discarding them after receiving an opponent's move).
+//!   An `AnalysisBoard` moves either side from whichever position is
+//!   being reviewed, branching into variations as it goes.
 //! 
+//! * All of the types above are `Send` and `Sync`: positions hold only
+//!   owned data plus a `&'static BackRank`, so boards can be moved
+//!   between threads freely. To share a single board between threads or
+//!   async tasks, wrap it in an `ArcBoard`, which guards the board with
+//!   a read-write lock and only grants access within a closure.
+//! 
+
+use crate::error::Result;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use listener::Listeners;
+use trace::GameSpan;
 
-use anyhow::Result;
+/// Builds a `[Mask; 64]` lookup table at compile time, evaluating `$mask`
+/// for each `$square`.
+macro_rules! square_table {
+    (|$square:ident| $mask:expr) => {{
+        let mut table = [Mask::empty(); 64];
+        let mut index = 0;
+        while index < 64 {
+            let $square = Square::from_index(index);
+            table[index] = $mask;
+            index += 1;
+        }
+        table
+    }};
+}
 
+mod analysis;
+mod annotation;
+mod antichess;
 mod backrank;
 mod castling;
+mod dedup;
+mod explain;
+mod input;
+mod insufficient;
+mod limit;
+mod listener;
 mod square;
 mod material;
 mod moves;
+mod notation;
+mod odds;
+mod pgn;
+#[cfg(feature = "ml")]
+mod planes;
+mod placement;
 mod play;
 mod position;
+mod puzzle;
+mod recorder;
+mod reference;
 mod review;
+mod rules;
+mod shared;
+mod signature;
+mod simulate;
+mod stats;
+mod strategy;
+mod study;
+mod trace;
+mod variant;
+mod view;
+mod visibility;
 
+pub use analysis::*;
+pub use annotation::*;
+pub use antichess::*;
 pub use backrank::*;
 pub use castling::*;
+pub use dedup::*;
+pub use explain::*;
+pub use input::*;
+pub use insufficient::*;
+pub use limit::*;
+pub use listener::BoardListener;
 pub use square::*;
 pub use material::*;
 pub use moves::*;
+pub use odds::*;
+pub use pgn::*;
+#[cfg(feature = "ml")]
+pub use planes::*;
+pub use placement::*;
 pub use play::*;
 pub use position::*;
+pub use puzzle::*;
+pub use recorder::*;
+pub use reference::*;
 pub use review::*;
+pub use rules::*;
+pub use shared::*;
+pub use signature::*;
+pub use simulate::*;
+pub use stats::*;
+pub use strategy::*;
+pub use study::*;
+pub use variant::*;
+pub use view::*;
+pub use visibility::*;
 
 pub trait Turn {
     fn turn(&self) -> Color;
@@ -121,25 +208,79 @@ pub trait Turn {
 
 pub type EngineBoard = Board<play::EngineMode>;
 pub type PlayerBoard = Board<play::PlayerMode>;
+pub type AnalysisBoard = Board<play::AnalysisMode>;
 
 pub struct Board<T> {
     state: PlayState<T>,
+    listeners: Listeners,
+    limiters: Option<Pair<RateLimiter>>,
+    span: GameSpan,
 }
 
 impl<T> Board<T> {
     pub fn plays_white(id: Option<BackRankId>) -> PlayerBoard {
         PlayerBoard {
-            state: PlayState::plays_white(id)
+            state: PlayState::plays_white(id),
+            listeners: Listeners::default(),
+            limiters: None,
+            span: GameSpan::new("player", id.unwrap_or_default()),
         }
     }
     pub fn plays_black(id: Option<BackRankId>) -> PlayerBoard{
         PlayerBoard {
-            state: PlayState::plays_black(id)
+            state: PlayState::plays_black(id),
+            listeners: Listeners::default(),
+            limiters: None,
+            span: GameSpan::new("player", id.unwrap_or_default()),
+        }
+    }
+    /// Plays `side` from `position`, e.g. to let a trainer present a
+    /// puzzle. Black moves first if it's Black's turn in `position`.
+    pub fn plays_from(side: Color, position: Position) -> PlayerBoard {
+        let id = position.backrank().id();
+        PlayerBoard {
+            state: PlayState::plays_from(side, position),
+            listeners: Listeners::default(),
+            limiters: None,
+            span: GameSpan::new("player", id),
         }
     }
     pub fn plays_both(id: Option<BackRankId>) -> EngineBoard {
         EngineBoard {
-            state: PlayState::plays_both(id)
+            state: PlayState::plays_both(id),
+            listeners: Listeners::default(),
+            limiters: None,
+            span: GameSpan::new("engine", id.unwrap_or_default()),
+        }
+    }
+    /// Plays both sides from `position`, e.g. a game of a `Variant` set up
+    /// with `Position::new_variant`.
+    pub fn plays_both_from(position: Position) -> EngineBoard {
+        let id = position.backrank().id();
+        EngineBoard {
+            state: PlayState::plays_both_from(position),
+            listeners: Listeners::default(),
+            limiters: None,
+            span: GameSpan::new("engine", id),
+        }
+    }
+    /// Analyses both sides from the starting position for `id`.
+    pub fn analyse(id: Option<BackRankId>) -> AnalysisBoard {
+        AnalysisBoard {
+            state: PlayState::analyse(id),
+            listeners: Listeners::default(),
+            limiters: None,
+            span: GameSpan::new("analysis", id.unwrap_or_default()),
+        }
+    }
+    /// Analyses both sides from `position`, e.g. one set up in an editor.
+    pub fn analyse_from(position: Position) -> AnalysisBoard {
+        let id = position.backrank().id();
+        AnalysisBoard {
+            state: PlayState::analyse_from(position),
+            listeners: Listeners::default(),
+            limiters: None,
+            span: GameSpan::new("analysis", id),
         }
     }
 
@@ -171,13 +312,241 @@ impl PlayerBoard {
     pub fn move_destinations(&self, from: Square) -> Mask {
         self.state.move_destinations(from)
     }
+    /// Returns the destinations of every square in one call, indexed by
+    /// `Square::to_index`, e.g. so a web client can prefetch all of its
+    /// hover highlights.
+    pub fn all_move_destinations(&self) -> [Mask; 64] {
+        self.state.all_move_destinations()
+    }
+    /// Plays `mv` if it's our turn or queues it as a pre-move otherwise.
+    /// Fails with `RateLimited` if a rate limit is set and exceeded.
+    ///
+    /// Once the game is over this fails with `GameOver` (see
+    /// `enter_analysis_mode`).
     pub fn submit_our_move(&mut self, mv: Move) -> Result<()> {
-        self.state.submit_our_move(mv)
+        self.check_rate_limit(self.state.side())?;
+        let plies = self.state.history().len();
+        self.state.submit_our_move(mv)?;
+        self.notify_moves_since(plies);
+        Ok(())
     }
     pub fn submit_their_move(&mut self, mv: Move) -> Result<()> {
-        self.state.submit_their_move(mv)
+        let plies = self.state.history().len();
+        let pre_moves = self.state.queued_pre_moves().to_vec();
+        self.state.submit_their_move(mv)?;
+        self.notify_moves_since(plies);
+        if self.listeners.is_empty() || pre_moves.is_empty() {
+            return Ok(());
+        }
+        // At most one pre-move is applied; the ones that follow it are
+        // either queued again or discarded
+        let applied = self.state.history().len() - plies - 1;
+        if applied > 0 {
+            self.listeners.pre_move_applied(pre_moves[0]);
+        }
+        let kept = applied + self.state.queued_pre_moves().len();
+        self.listeners.pre_moves_discarded(&pre_moves[kept..]);
+        Ok(())
+    }
+    pub fn cancel_pre_moves(&mut self) {
+        let pre_moves = self.state.queued_pre_moves().to_vec();
+        self.state.cancel_pre_moves();
+        self.listeners.pre_moves_discarded(&pre_moves);
+    }
+    pub fn queued_pre_moves(&self) -> &[Move] {
+        self.state.queued_pre_moves()
+    }
+    pub fn preview_premove_chain(&self, replies: &[Move]) -> Option<PreMoveBreak> {
+        self.state.preview_premove_chain(replies)
+    }
+
+    pub fn view(&self) -> &Position {
+        self.state.view()
+    }
+
+    /// Adds `mv`, played from the reviewed position at `at`, as a
+    /// variation and switches to it (see `ReviewState::add_variation`).
+    pub fn add_variation(&mut self, at: MoveId, mv: Move) -> Result<()> {
+        self.state.add_variation(at, mv)
+    }
+    pub fn variations(&self) -> usize {
+        self.state.review_state().variations()
+    }
+    pub fn next_variation(&mut self) -> Result<(), ReviewError> {
+        self.state.review_state_mut().next_variation()
+    }
+    pub fn prev_variation(&mut self) -> Result<(), ReviewError> {
+        self.state.review_state_mut().prev_variation()
+    }
+    pub fn promote_variation(&mut self) -> Result<(), ReviewError> {
+        self.state.review_state_mut().promote_variation()
+    }
+    pub fn delete_variation(&mut self) -> Result<(), ReviewError> {
+        self.state.review_state_mut().delete_variation()
     }
 
+    /// Returns the annotation of the reviewed position at `at`, if any
+    /// (see `ReviewState::annotation`).
+    pub fn annotation(&self, at: &MoveId) -> Option<&Annotation> {
+        self.state.annotation(at)
+    }
+    pub fn annotation_mut(&mut self, at: &MoveId) -> Result<&mut Annotation, ReviewError> {
+        self.state.annotation_mut(at)
+    }
+    /// Iterates over the reviewed line with the SAN of each move (see
+    /// `ReviewState::iter_san`).
+    pub fn iter_san(&self) -> impl Iterator<Item = (MoveId, &Position, Option<String>)> + '_ {
+        self.state.review_state().iter_san()
+    }
+    /// Selects which positions are kept for review, e.g. to save memory
+    /// on mobile or WASM clients (see `HistoryRetention`).
+    pub fn set_history_retention(&mut self, retention: HistoryRetention) {
+        self.state.set_history_retention(retention);
+    }
+    /// Drops positions rebuilt for review since the last call that the
+    /// history retention doesn't keep.
+    pub fn compact_history(&mut self) {
+        self.state.review_state_mut().compact();
+    }
+    pub fn annotations(&self) -> BTreeMap<MoveId, Annotation> {
+        self.state.review_state().annotations()
+    }
+    pub fn set_annotations(&mut self, annotations: BTreeMap<MoveId, Annotation>) -> Result<(), ReviewError> {
+        self.state.review_state_mut().set_annotations(annotations)
+    }
+
+    /// Reconstructs a game played by `side` from `moves` (both sides'
+    /// moves in order), failing with the index of the first bad move and
+    /// why it was rejected.
+    pub fn replay(side: Color, id: Option<BackRankId>, moves: &[Move]) -> Result<Self, ReplayError> {
+        match Self::replay_partial(side, id, moves) {
+            (board, None) => Ok(board),
+            (_, Some(error)) => Err(error),
+        }
+    }
+    /// Like `replay`, but also returns the board with every move before
+    /// the failure applied.
+    pub fn replay_partial(side: Color, id: Option<BackRankId>, moves: &[Move]) -> (Self, Option<ReplayError>) {
+        let mut board = match side {
+            Color::White => Self::plays_white(id),
+            Color::Black => Self::plays_black(id),
+        };
+        for (index, mv) in moves.iter().enumerate() {
+            let result = play::check_replay_move(board.state.as_ref(), index, *mv)
+                .and_then(|_| {
+                    let submitted = if board.our_turn() {
+                        board.state.submit_our_move(*mv)
+                    } else {
+                        board.state.submit_their_move(*mv)
+                    };
+                    submitted.map_err(|_| {
+                        ReplayError { index, mv: *mv, reason: ReplayFailure::Illegal }
+                    })
+                });
+            if let Err(error) = result {
+                return (board, Some(error));
+            }
+        }
+        (board, None)
+    }
+
+    fn notify_moves_since(&mut self, plies: usize) {
+        if self.listeners.is_empty() && !self.span.wants_moves() {
+            return;
+        }
+        // the review only keeps positions, so the states are rebuilt
+        let review = self.state.review_state();
+        for (index, mv) in self.state.history().iter().enumerate().skip(plies) {
+            let (Some(before), Some(after)) = (review.game_state_at(&(MoveId::START + index)), review.game_state_at(&(MoveId::START + (index + 1)))) else {
+                break;
+            };
+            if self.span.wants_moves() {
+                self.span.move_applied(&before, *mv, MoveId::START + index, after.as_ref());
+            }
+            self.listeners.move_applied(&before, *mv, &after);
+        }
+    }
+
+    /// Returns the result of the game under the standard rules, as the
+    /// server would see it.
+    pub fn board_result(&self) -> Option<BoardResult> {
+        self.state.board_result()
+    }
+    /// Records the result the server announced, e.g. after a resignation,
+    /// a timeout or an agreed draw. Moves are refused from then on and any
+    /// queued pre-moves are discarded.
+    pub fn set_result(&mut self, result: impl Into<PlayResult>) {
+        self.cancel_pre_moves();
+        self.state.set_result(result);
+    }
+    /// Returns the result announced by the server (see `set_result`), or
+    /// else the one reached on the board.
+    pub fn result(&self) -> Option<PlayResult> {
+        self.state.result()
+    }
+    /// Returns an analysis board for the finished game, where both sides'
+    /// moves are played from its reviewed position, keeping its
+    /// annotations and variations. The player board itself stays locked.
+    /// Fails with `GameInProgress` until the game has a result.
+    pub fn enter_analysis_mode(&self) -> Result<AnalysisBoard> {
+        let id = AsRef::<BackRank>::as_ref(self).id();
+        Ok(AnalysisBoard {
+            state: self.state.to_analysis()?,
+            listeners: Listeners::default(),
+            limiters: None,
+            span: GameSpan::new("analysis", id),
+        })
+    }
+    /// Same as `enter_analysis_mode`.
+    pub fn to_analysis(&self) -> Result<AnalysisBoard> {
+        self.enter_analysis_mode()
+    }
+    /// Returns how many times the current position has occurred, as the
+    /// server would count it.
+    pub fn repetition_count(&self) -> usize {
+        self.state.repetition_count()
+    }
+    /// Counts `prior`, the keys (see `Position::key`) of the positions
+    /// before the starting position, oldest first, towards threefold
+    /// repetition, as the server should.
+    pub fn set_prior_positions(&mut self, prior: Vec<PositionKey>) {
+        self.state.set_prior_positions(prior);
+    }
+    /// Selects the rules for insufficient material (chess.com's by
+    /// default), which should match the server's.
+    pub fn set_insufficient_material(&mut self, policy: InsufficientMaterial) {
+        self.state.set_insufficient_material(policy);
+    }
+    /// Selects how promotions submitted without a piece are handled:
+    /// prompting for one (the default) or auto-queening.
+    pub fn set_promotion_policy(&mut self, policy: PromotionPolicy) {
+        self.state.set_promotion_policy(policy);
+    }
+    pub fn promotion_policy(&self) -> PromotionPolicy {
+        self.state.promotion_policy()
+    }
+    /// Lists the draws the side to move could claim (see `DrawClaim`).
+    pub fn claimable_draws(&self) -> Vec<DrawClaim> {
+        self.state.claimable_draws()
+    }
+    /// Ends the game with a draw claimed by the side to move. A claim with
+    /// a move is only allowed on our turn and plays the move first.
+    pub fn claim_draw(&mut self, claim: DrawClaim) -> Result<BoardResult> {
+        self.state.check_draw_claim(claim)?;
+        if let Some(mv) = claim.mv() {
+            self.submit_our_move(mv)?;
+        }
+        // the game was still on, and moves don't announce results on a
+        // player board
+        let result = self.state.end_by_claim(claim);
+        self.listeners.result(result);
+        Ok(result)
+    }
+
+    #[inline]
+    pub fn side(&self) -> Color {
+        self.state.side()
+    }
     #[inline]
     pub fn our_turn(&self) -> bool {
         self.state.our_turn()
@@ -195,19 +564,327 @@ impl EngineBoard {
     pub fn shuffled() -> Self {
         Self::plays_both(Some(BackRankId::shuffled()))
     }
+    /// Plays `mv` for the side to move. Fails with `RateLimited` if a rate
+    /// limit is set and exceeded by that side, and with `GameOver` once the
+    /// game has ended (unless post-game analysis is allowed).
     pub fn submit_move(&mut self, mv: Move) -> Result<MoveId> {
-        self.state.submit_move(mv)
+        self.check_rate_limit(self.turn())?;
+        if self.listeners.is_empty() && !self.span.wants_moves() {
+            return self.state.submit_move(mv);
+        }
+        let before: &MoveState = self.state.as_ref();
+        let before = before.fork();
+        let had_result = self.state.board_result().is_some();
+        let move_id = self.state.submit_move(mv).inspect_err(|error| {
+            self.span.move_rejected(mv, error);
+        })?;
+        if let Some(mv) = self.state.history().last() {
+            self.span.move_applied(&before, *mv, move_id, self.state.as_ref());
+            self.listeners.move_applied(&before, *mv, self.state.as_ref());
+        }
+        if let Some(result) = self.state.board_result().filter(|_| !had_result) {
+            self.span.result(result);
+            self.listeners.result(result);
+        }
+        Ok(move_id)
+    }
+    /// Reconstructs a game by playing `moves` from the starting position
+    /// for `id`, failing with the index of the first bad move and why it
+    /// was rejected.
+    pub fn replay(id: Option<BackRankId>, moves: &[Move]) -> Result<Self, ReplayError> {
+        match Self::replay_partial(id, moves) {
+            (board, None) => Ok(board),
+            (_, Some(error)) => Err(error),
+        }
+    }
+    /// Like `replay`, but also returns the board with every move before
+    /// the failure applied.
+    pub fn replay_partial(id: Option<BackRankId>, moves: &[Move]) -> (Self, Option<ReplayError>) {
+        Self::replay_onto(Self::plays_both(id), moves)
+    }
+    /// Rebuilds a game from a FEN and the moves played from it. Fails
+    /// with `ParseError::InvalidFen` saying what's wrong with the FEN, or
+    /// with a `ReplayError` giving the index of the first bad move.
+    pub fn from_fen_and_moves(fen: &str, moves: &[Move]) -> Result<Self> {
+        let position = Position::from_fen(fen)?;
+        match Self::replay_onto(Self::plays_both_from(position), moves) {
+            (board, None) => Ok(board),
+            (_, Some(error)) => Err(error.into()),
+        }
+    }
+    /// Rebuilds the first game of a PGN text, from the position given by
+    /// its `FEN` tag if it has one. Fails with a `PgnError` naming the
+    /// offending tag, FEN or move token, or like `from_fen_and_moves` with
+    /// a `ReplayError` for a move that can't be played.
+    pub fn from_pgn(pgn: &str) -> Result<Self> {
+        let game: PgnGame = pgn.parse()?;
+        match Self::replay_onto(Self::plays_both_from(game.start_position()?), &game.moves) {
+            (board, None) => Ok(board),
+            (_, Some(error)) => Err(error.into()),
+        }
+    }
+    fn replay_onto(mut board: Self, moves: &[Move]) -> (Self, Option<ReplayError>) {
+        for (index, mv) in moves.iter().enumerate() {
+            let result = play::check_replay_move(board.state.as_ref(), index, *mv)
+                .and_then(|_| board.state.submit_move(*mv).map_err(|_| {
+                    ReplayError { index, mv: *mv, reason: ReplayFailure::Illegal }
+                }));
+            if let Err(error) = result {
+                return (board, Some(error));
+            }
+        }
+        (board, None)
     }
     pub fn board_result(&self) -> Option<BoardResult> {
         self.state.board_result()
     }
+    /// Returns how many times the current position has occurred; the game
+    /// is drawn when it reaches three.
+    pub fn repetition_count(&self) -> usize {
+        self.state.repetition_count()
+    }
+    /// Counts `prior`, the keys (see `Position::key`) of the positions
+    /// before the starting position, oldest first, towards threefold
+    /// repetition, e.g. for a game imported mid-stream. They're ignored
+    /// if the starting position follows a capture or pawn move (its
+    /// halfmove clock is zero). Call it before play starts; the result
+    /// isn't re-evaluated.
+    pub fn set_prior_positions(&mut self, prior: Vec<PositionKey>) {
+        self.state.set_prior_positions(prior);
+    }
+    pub fn plies(&self) -> usize {
+        self.state.plies()
+    }
+    /// Lists the draws the side to move could claim (see `DrawClaim`).
+    pub fn claimable_draws(&self) -> Vec<DrawClaim> {
+        self.state.claimable_draws()
+    }
+    /// Ends the game with a draw claimed by the side to move, playing the
+    /// claim's move first if it has one.
+    pub fn claim_draw(&mut self, claim: DrawClaim) -> Result<BoardResult> {
+        self.state.check_draw_claim(claim)?;
+        if let Some(mv) = claim.mv() {
+            self.submit_move(mv)?;
+        }
+        let had_result = self.state.board_result().is_some();
+        let result = self.state.end_by_claim(claim);
+        if !had_result {
+            self.span.result(result);
+            self.listeners.result(result);
+        }
+        Ok(result)
+    }
+    /// Installs a policy that can end the game under custom rules (see
+    /// `ResultPolicy`).
+    pub fn set_result_policy<P: ResultPolicy + 'static>(&mut self, policy: P) {
+        self.state.set_result_policy(Some(Arc::new(policy)));
+    }
+    pub fn clear_result_policy(&mut self) {
+        self.state.set_result_policy(None);
+    }
+    /// Selects the rules for insufficient material (chess.com's by
+    /// default). Only later moves are affected.
+    pub fn set_insufficient_material(&mut self, policy: InsufficientMaterial) {
+        self.state.set_insufficient_material(policy);
+    }
+    pub fn insufficient_material(&self) -> InsufficientMaterial {
+        self.state.insufficient_material()
+    }
+    /// Returns true if `color` wins when their opponent runs out of time
+    /// (see `InsufficientMaterial::can_win_on_time`).
+    pub fn can_win_on_time(&self, color: Color) -> bool {
+        self.state.can_win_on_time(color)
+    }
+    /// Marks the game as an Armageddon game (see
+    /// `PlayState::set_armageddon`).
+    pub fn set_armageddon(&mut self, armageddon: bool) {
+        self.state.set_armageddon(armageddon);
+    }
+    pub fn is_armageddon(&self) -> bool {
+        self.state.is_armageddon()
+    }
+    /// Allows moves after the game has ended (see
+    /// `PlayState::set_post_game_analysis`). Disallowed by default.
+    pub fn set_post_game_analysis(&mut self, allowed: bool) {
+        self.state.set_post_game_analysis(allowed);
+    }
+    pub fn post_game_analysis(&self) -> bool {
+        self.state.post_game_analysis()
+    }
+    pub fn take_back(&mut self, plies: usize) -> Result<(), TakebackError> {
+        self.state.take_back(plies)
+    }
+}
+
+impl AnalysisBoard {
+    /// Plays `mv` for the side to move in the reviewed position, into the
+    /// variation tree (see `PlayState::submit_move`).
+    pub fn submit_move(&mut self, mv: Move) -> Result<MoveId> {
+        if self.listeners.is_empty() {
+            return self.state.submit_move(mv);
+        }
+        let before: &MoveState = self.state.as_ref();
+        let before = before.fork();
+        let move_id = self.state.submit_move(mv)?;
+        if let Some(mv) = self.state.history().last() {
+            self.listeners.move_applied(&before, *mv, self.state.as_ref());
+        }
+        Ok(move_id)
+    }
+    /// Deletes the last `plies` moves leading to the reviewed position and
+    /// whatever followed them.
+    pub fn take_back(&mut self, plies: usize) -> Result<(), TakebackError> {
+        self.state.take_back(plies)
+    }
+    /// Starts over from `position`, dropping all moves and annotations.
+    pub fn set_position(&mut self, position: Position) {
+        self.state.set_position(position);
+    }
+    pub fn move_destinations(&self, from: Square) -> Mask {
+        self.state.legal_moves(from).destinations()
+    }
+    pub fn variations(&self) -> usize {
+        self.state.review_state().variations()
+    }
+    pub fn next_variation(&mut self) -> Result<(), ReviewError> {
+        self.state.next_variation()
+    }
+    pub fn prev_variation(&mut self) -> Result<(), ReviewError> {
+        self.state.prev_variation()
+    }
+    pub fn promote_variation(&mut self) -> Result<(), ReviewError> {
+        self.state.promote_variation()
+    }
+    pub fn annotation(&self, at: &MoveId) -> Option<&Annotation> {
+        self.state.review_state().annotation(at)
+    }
+    pub fn annotation_mut(&mut self, at: &MoveId) -> Result<&mut Annotation, ReviewError> {
+        self.state.review_state_mut().annotation_mut(at)
+    }
+    /// Records an engine's evaluation of the position at `at` of the
+    /// current line.
+    pub fn set_eval(&mut self, at: &MoveId, eval: Eval) -> Result<(), ReviewError> {
+        self.annotation_mut(at)?.eval = Some(eval);
+        Ok(())
+    }
+    /// Returns the annotations of the current line, e.g. for a
+    /// `PgnGame` (see `PgnGame::with_annotations`).
+    pub fn annotations(&self) -> BTreeMap<MoveId, Annotation> {
+        self.state.review_state().annotations()
+    }
+    /// Iterates over the current line with the SAN of each move.
+    pub fn iter_san(&self) -> impl Iterator<Item = (MoveId, &Position, Option<String>)> + '_ {
+        self.state.review_state().iter_san()
+    }
+}
+
+impl Review for AnalysisBoard {
+    fn len(&self) -> usize {
+        self.state.len()
+    }
+    fn offset(&self) -> &MoveId {
+        self.state.offset()
+    }
+    fn get(&self, offset: &MoveId) -> Option<&Position> {
+        self.state.get(offset)
+    }
+}
+
+impl ReviewMut for AnalysisBoard {
+    fn set_offset(&mut self, offset: MoveId) -> Result<(), ReviewError> {
+        self.state.set_offset(offset)
+    }
 }
 
 impl<T> Board<T> {
+    /// Registers a listener to be notified of moves, captures, checks,
+    /// results and pre-move changes on this board.
+    pub fn add_listener<L: BoardListener + 'static>(&mut self, listener: L) {
+        self.listeners.push(Box::new(listener));
+    }
+    pub fn clear_listeners(&mut self) {
+        self.listeners.clear();
+    }
+    /// Limits how often moves can be submitted for each side, or removes
+    /// the limit if `None`. Boards have no limit by default.
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.limiters = limit.map(|limit| {
+            Pair::new(RateLimiter::new(limit), RateLimiter::new(limit))
+        });
+    }
+    fn check_rate_limit(&mut self, color: Color) -> Result<(), RateLimited> {
+        match &mut self.limiters {
+            Some(limiters) => limiters[color].check(Instant::now()),
+            None => Ok(()),
+        }
+    }
+    /// Returns the pieces `color` has captured so far, oldest first, e.g.
+    /// for a captured pieces tray.
+    pub fn captured(&self, color: Color) -> Vec<Piece> {
+        self.state.captured(color).to_vec()
+    }
+    /// Returns White's material advantage in conventional points (see
+    /// `Position::material_balance`). Unlike the captured pieces, this
+    /// accounts for promotions.
+    pub fn material_balance(&self) -> i32 {
+        let pos: &Position = self.state.as_ref();
+        pos.material_balance()
+    }
+    /// Returns statistics over the moves played so far, e.g. for a
+    /// post-game summary.
+    pub fn stats(&self) -> GameStats {
+        self.state.stats()
+    }
+    /// Returns the fingerprint of the moves played so far, e.g. to spot a
+    /// game that was already saved.
+    pub fn fingerprint(&self) -> GameFingerprint {
+        self.state.fingerprint()
+    }
+    /// Returns the plies since the last capture or pawn move.
+    pub fn moves_since_progress(&self) -> usize {
+        let pos: &Position = self.state.as_ref();
+        pos.moves_since_progress()
+    }
+    /// Returns how many more plies without a capture or pawn move draw
+    /// the game under the fifty-move rule, e.g. for a "draw in N moves"
+    /// indicator.
+    pub fn plies_until_fifty_move_draw(&self) -> usize {
+        let pos: &Position = self.state.as_ref();
+        pos.plies_until_fifty_move_draw()
+    }
     pub fn backrank_id(&self) -> BackRankId {
         let backrank: &BackRank = self.state.as_ref();
         backrank.id()
     }
+    /// Returns the moves of the game so far, e.g. to save it and `replay`
+    /// it later.
+    pub fn moves(&self) -> Vec<Move> {
+        self.state.moves()
+    }
+    pub fn choose_move<S: Strategy>(&self, strategy: &mut S) -> Option<Move> {
+        strategy.choose_move(self.state.as_ref())
+    }
+    /// Returns up to `max_n` suggested moves for the side to play using
+    /// the default `Hints`.
+    pub fn hints(&self, max_n: usize) -> Vec<Move> {
+        self.hints_with(&mut Hints::new(), max_n)
+    }
+    pub fn hints_with<H: HintStrategy>(&self, strategy: &mut H, max_n: usize) -> Vec<Move> {
+        strategy.hints(self.state.as_ref(), max_n)
+    }
+    /// Returns every square attacked by `color` in the current game
+    /// position, e.g. to show attacked squares. Pieces defended by their
+    /// own side count as attacked.
+    pub fn attacked_squares(&self, color: Color) -> Mask {
+        let state: &MoveState = self.state.as_ref();
+        state.attacked_squares(color)
+    }
+    /// Returns the pieces of `color` attacking `square` in the current
+    /// game position.
+    pub fn attackers_by(&self, square: Square, color: Color) -> Mask {
+        let state: &MoveState = self.state.as_ref();
+        state.attackers_by(square, color)
+    }
 }
 
 impl Review for PlayerBoard {
@@ -223,7 +900,7 @@ impl Review for PlayerBoard {
 }
 
 impl ReviewMut for PlayerBoard {
-    fn set_offset(&mut self, offset: MoveId) {
+    fn set_offset(&mut self, offset: MoveId) -> Result<(), ReviewError> {
         self.state.set_offset(offset)
     }
 }
@@ -12,21 +12,22 @@
 //    See the License for the specific language governing permissions and
 //    limitations under the License.
 
-use anyhow::Result;
+use crate::error::Result;
 use thiserror::Error;
-use once_cell::sync::Lazy;
+use once_cell::sync::OnceCell;
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::fmt;
+use std::str::FromStr;
 use std::ops::{BitOr, BitOrAssign};
 use std::ops::Index;
-use strum::IntoEnumIterator;
 use serde::{Deserialize, Serialize};
 
 use super::backrank::BackRank;
 use super::castling::Castling;
-use super::square::{Square, File, Rank, Mask, Direction, Offset};
-use super::material::{Piece, Color};
-use super::position::{Position, Pos, MoveId};
+use super::square::{Square, Rank, Mask, Direction, ParseError};
+use super::material::{Piece, Color, Material, Pair};
+use super::position::{Position, Pos, MoveId, InvariantError};
 use super::position::{between, blocked, shielded};
 use super::position::{ALL_LINES, HORIZONTALS, DIAGONALS};
 use super::Turn;
@@ -34,20 +35,80 @@ use super::Turn;
 use Color::*;
 use Rank::*;
 use Piece::*;
+use Direction::{Up, Down, Left, Right};
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MoveError {
     #[error("Not a legal move")]
     InvalidMove,
+    #[error("Not your turn")]
+    WrongTurn,
+    #[error("No material of the moving side on {0}")]
+    EmptySquare(Square),
+    #[error("Maximum number of moves exceeded")]
+    MoveLimit,
 }
 use MoveError::*;
 
 #[derive(Debug, Clone)]
 pub struct MoveState {
     position: Position,
+    tables: OnceCell<Arc<AttackTables>>,
+    /// The squares the last applied move left and landed on.
+    last_move: Mask,
+}
+
+/// Checks, attackers and pins derived from a position. The tables are
+/// immutable once computed so copies of a `MoveState` share them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AttackTables {
     checks: Mask,
-    attackers: [Mask; 64],
+    attackers: Pair<[Mask; 64]>,
+    attacked: Pair<Mask>,
     pinned: [Option<Mask>; 64],
+    /// Whether the side to move has a capture, ignoring checks and pins.
+    captures: bool,
+}
+
+impl AttackTables {
+    fn compute(state: &MoveState) -> Self {
+        let mut tables = Self {
+            checks: Mask::empty(),
+            attackers: Pair::new([Mask::empty(); 64], [Mask::empty(); 64]),
+            attacked: Pair::new(Mask::empty(), Mask::empty()),
+            pinned: [None; 64],
+            captures: false,
+        };
+        for (from, material) in state.position.placements() {
+            let color = material.color();
+            let attacked = state.attacked(from);
+            for to in attacked.iter() {
+                tables.attackers[color][to] |= from.to_mask();
+            }
+            tables.attacked[color] |= attacked;
+        }
+        let en_passant = state.position.en_passant()
+            .is_some_and(|target| state.our_pawns().pawn_attacks(state.turn()).contains(target));
+        tables.captures = en_passant || !(tables.attacked[state.turn()] & state.theirs()).is_empty();
+        // Without a royal king there are no checks or pins
+        if !state.position.rules().has_royal_king() {
+            return tables;
+        }
+        let king = state.our_king();
+        tables.checks = tables.attackers[!state.turn()][king];
+        for from in state.their_line_pieces_through(king).iter() {
+            let lane = between(from, king);
+            let blockers = lane & state.occupied();
+            if blockers.len() == 1 {
+                let blockers = blockers & state.ours();
+                if let Some(square) = blockers.iter().next() {
+                    // a pinned piece may still capture the pinning piece
+                    tables.pinned[square] = Some(lane | from);
+                }
+            }
+        }
+        tables
+    }
 }
 
 impl Default for MoveState {
@@ -85,33 +146,66 @@ impl LegalMoves for MoveState {}
 
 impl MoveState {
     pub fn new(position: Position) -> Self {
-        let mut result = Self {
+        Self {
             position,
-            checks: Mask::empty(),
-            attackers: [Mask::empty(); 64],
-            pinned: [None; 64],
-        };
-        result.init();
-        result
+            tables: OnceCell::new(),
+            last_move: Mask::empty(),
+        }
     }
 
-    pub fn apply_move(&mut self, mv: LegalMove) -> MoveId {
-        let move_id = self.position.apply_move(mv);
+    /// Returns a cheap copy that shares this state's attack tables.
+    pub fn fork(&self) -> Self {
+        self.clone()
+    }
+
+    pub fn apply_move(&mut self, mv: LegalMove) -> Result<MoveId, MoveError> {
+        let squares = mv.from(&self.position).to_mask() | mv.to(&self.position);
+        let move_id = self.position.apply_move(mv)?;
+        self.last_move = squares;
         self.reset();
-        move_id
+        self.debug_validate();
+        Ok(move_id)
     }
 
-    pub fn apply_pre_move(&mut self, mv: PreMove) {
-        self.position.apply_pre_move(mv);
+    pub fn apply_pre_move(&mut self, mv: PreMove) -> Result<(), MoveError> {
+        self.position.apply_pre_move(mv)
+    }
+
+    /// Verifies the position (see `Position::validate`) and that the
+    /// cached checks, attackers and pins match a fresh computation.
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        self.position.validate()?;
+        if let Some(tables) = self.tables.get() {
+            if **tables != AttackTables::compute(self) {
+                return Err(InvariantError::StaleAttacks);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `validate` in debug builds and panics on failure. This is a
+    /// no-op in release builds.
+    #[inline]
+    pub fn debug_validate(&self) {
+        #[cfg(debug_assertions)]
+        if let Err(error) = self.validate() {
+            panic!("invalid move state: {error}");
+        }
     }
 
     #[inline]
     pub fn is_check(&self) -> bool {
-        !self.checks.is_empty()
+        !self.tables().checks.is_empty()
     }
     #[inline]
     pub fn is_double_check(&self) -> bool {
-        self.checks.len() > 1
+        self.tables().checks.len() > 1
+    }
+    /// Returns true if the side to move attacks one of their pieces (or
+    /// can capture en passant), whether or not the capture is legal.
+    #[inline]
+    pub fn can_capture(&self) -> bool {
+        self.tables().captures
     }
     #[inline]
     pub fn is_attacked(&self, square: Square) -> bool {
@@ -121,17 +215,70 @@ impl MoveState {
     pub fn is_pinned(&self, square: Square) -> bool {
         self.pinned(square).is_some()
     }
+    /// Returns the pieces giving check.
     #[inline]
     pub fn checks(&self) -> Mask {
-        self.checks
-    }
+        self.tables().checks
+    }
+    /// Returns the empty squares between the king and the pieces checking
+    /// it, i.e. where a check could be blocked. Contact and knight checks
+    /// contribute nothing.
+    pub fn check_ray(&self) -> Mask {
+        if !self.is_check() {
+            return Mask::empty();
+        }
+        let king = self.our_king();
+        self.checks().iter()
+            .fold(Mask::empty(), |ray, from| ray | between(from, king))
+    }
+    /// Returns the pieces of `color`, other than the king, that are
+    /// attacked by the opponent and not defended.
+    pub fn hanging(&self, color: Color) -> Mask {
+        let pieces = match color == self.turn() {
+            true => self.ours(),
+            false => self.theirs(),
+        };
+        (pieces & !self.kings()).iter()
+            .filter(|&square| {
+                self.is_attacked_by(square, !color) && !self.is_attacked_by(square, color)
+            })
+            .fold(Mask::empty(), |mask, square| mask | square)
+    }
+    /// Returns the from and to squares of the last move applied to this
+    /// state, or an empty mask if none has been. For castling these are
+    /// the king's squares.
+    pub fn last_move_squares(&self) -> Mask {
+        self.last_move
+    }
+    /// Returns their pieces attacking `square`.
     #[inline]
     pub fn attackers(&self, square: Square) -> Mask {
-        self.attackers[square.to_index()]
+        self.attackers_by(square, !self.turn())
+    }
+    /// Returns the pieces of `color` attacking (or, for a square holding
+    /// a piece of the same color, defending) `square`.
+    #[inline]
+    pub fn attackers_by(&self, square: Square, color: Color) -> Mask {
+        self.tables().attackers[color][square]
+    }
+    #[inline]
+    pub fn is_attacked_by(&self, square: Square, color: Color) -> bool {
+        !self.attackers_by(square, color).is_empty()
+    }
+    /// Returns every square attacked by a piece of `color`, whichever
+    /// side is to play.
+    #[inline]
+    pub fn attacked_squares(&self, color: Color) -> Mask {
+        self.tables().attacked[color]
+    }
+    /// Returns the squares attacked by the piece on `square`, or an empty
+    /// mask if the square is empty.
+    pub fn attacks_from(&self, square: Square) -> Mask {
+        self.attacked(square)
     }
     #[inline]
     pub fn pinned(&self, square: Square) -> Option<Mask> {
-        self.pinned[square.to_index()]
+        self.tables().pinned[square.to_index()]
     }
 
     pub fn is_lane_blocked(&self, lane: Mask) -> bool {
@@ -143,33 +290,12 @@ impl MoveState {
     }
 
     fn reset(&mut self) {
-        self.checks = Mask::empty();
-        self.attackers = [Mask::empty(); 64];
-        self.pinned = [None; 64];
-        self.init();
+        self.tables = OnceCell::new();
     }
 
-    fn init(&mut self) {
-        for from in self.theirs().iter() {
-            for to in self.attacked(from).iter() {
-                self.attackers[to] |= from.to_mask();
-            }
-        }
-        let king = self.our_king();
-        self.checks = self.attackers(king);
-        for from in self.their_line_pieces().iter() {
-            let lane = between(from, king);
-            if !lane.is_empty() {
-                let blockers = lane & self.occupied();
-                if blockers.len() == 1 {
-                    let blockers = blockers & self.ours();
-                    if !blockers.is_empty() {
-                        let square = blockers.iter().next().unwrap();
-                        self.pinned[square] = Some(lane);
-                    }
-                }
-            }
-        }
+    #[inline]
+    fn tables(&self) -> &AttackTables {
+        self.tables.get_or_init(|| Arc::new(AttackTables::compute(self)))
     }
 
     fn attacked(&self, from: Square) -> Mask {
@@ -189,20 +315,68 @@ impl MoveState {
         Mask::empty()
     }
 
-    fn exclude_blocked_attacks(&self, from: Square, mut mask: Mask) -> Mask {
-        let theirs: Mask = self.theirs() & mask;
-        for square in theirs.iter() {
-            // exclude squares blocked by their own pieces
-            mask &= !blocked(from, square);
+    /// Returns their line pieces that would attack `square` if nothing
+    /// stood in between.
+    fn their_line_pieces_through(&self, square: Square) -> Mask {
+        let horizontals = self.horizontals() & HORIZONTALS[square];
+        let diagonals = self.diagonals() & DIAGONALS[square];
+        (horizontals | diagonals) & self.theirs() & !square.to_mask()
+    }
+
+    /// Returns true if capturing en passant from `from` to `to` leaves our
+    /// king attacked by a line piece. Both pawns leave their squares, so
+    /// this covers the capturing pawn's pin as well as a king exposed
+    /// along the rank, or along a diagonal through the captured pawn.
+    fn is_en_passant_exposing(&self, from: Square, to: Square) -> bool {
+        if !self.position.rules().has_royal_king() {
+            return false;
         }
-        let ours: Mask = self.ours() & mask;
-        for square in ours.iter() {
-            // exclude squares shielded by our pieces
+        let captured = Square::new(to.file(), from.rank());
+        let occupied = (self.occupied() & !from.to_mask() & !captured.to_mask()) | to.to_mask();
+        let king = self.our_king();
+        self.their_line_pieces_through(king).iter()
+            .any(|square| (between(square, king) & occupied).is_empty())
+    }
+
+    /// Returns true if castling leaves our king attacked by a line piece
+    /// that the castling rook was shielding its destination from, which
+    /// can happen in Chess960, e.g. with their rook behind ours in the
+    /// corner.
+    pub(crate) fn is_castling_exposing(&self, king_dest: Square, rook_src: Square, rook_dest: Square) -> bool {
+        if !self.position.rules().has_royal_king() {
+            return false;
+        }
+        let king_src = self.our_king();
+        let occupied = (self.occupied() & !king_src.to_mask() & !rook_src.to_mask())
+            | king_dest.to_mask() | rook_dest.to_mask();
+        self.their_line_pieces_through(king_dest).iter()
+            .any(|square| (between(square, king_dest) & occupied).is_empty())
+    }
+
+    fn exclude_blocked_attacks(&self, from: Square, mut mask: Mask) -> Mask {
+        let occupied: Mask = self.occupied() & mask;
+        for square in occupied.iter() {
+            // exclude squares shielded by any piece; the piece itself is
+            // attacked (or, for their own pieces, defended)
             mask &= !shielded(from, square);
         }
         mask
     }
 
+    /// Returns the squares a piece other than the king may move to when
+    /// our king is in check: the checking piece and, for a line piece,
+    /// the squares between it and the king. Every square qualifies when
+    /// there's no check and none does in double check.
+    fn evasions(&self) -> Mask {
+        let checks = self.checks();
+        let mut checkers = checks.iter();
+        match (checkers.next(), checkers.next()) {
+            (None, _) => Mask::all(),
+            (Some(checker), None) => checks | between(checker, self.our_king()),
+            _ => Mask::empty(),
+        }
+    }
+
     fn exclude_blocked_moves(&self, from: Square, mut mask: Mask) -> Mask {
         let ours: Mask = self.ours() & mask;
         for square in ours.iter() {
@@ -219,6 +393,33 @@ impl MoveState {
 
 }
 
+/// Checks the promotion piece of a (pre-)move of `material`. A pawn
+/// reaching its promotion rank must name a piece the rules allow, and no
+/// other move may name one.
+pub(crate) fn check_promotion(pos: &Position, material: Material, mv: Move) -> Result<()> {
+    let promotes = material.piece() == Pawn && mv.to.rank().is_promotion_rank(material.color());
+    let valid = match mv.promotion {
+        Some(promotion) => promotes && pos.rules().promotions().contains(&promotion),
+        None => !promotes,
+    };
+    if !valid {
+        return Err(InvalidMove.into());
+    }
+    Ok(())
+}
+
+/// Adds a castling (pre-)move as a drag target. Dropping the king onto
+/// its own rook always castles, as 960 interfaces expect. The king's
+/// destination castles too, unless the king doesn't move or the
+/// destination is a single step away, where dropping the king is a plain
+/// king move.
+fn insert_castle<T: Copy>(result: &mut MoveSet<T>, king_src: Square, king_dest: Square, rook_src: Square, mv: T) {
+    if !KING_MOVES[king_src].contains(king_dest) && king_dest != king_src {
+        result.insert(king_dest, mv);
+    }
+    result.insert(rook_src, mv);
+}
+
 pub trait LegalMoves: AsRef<Position> + AsRef<MoveState> {
 
     fn validate_move(&self, mv: Move) -> Result<LegalMove> {
@@ -229,17 +430,10 @@ pub trait LegalMoves: AsRef<Position> + AsRef<MoveState> {
         // Safety: above validation ensures there's material at `from`
         // and that it's the proper color
         let pos: &Position = self.as_ref();
-        let material = pos[mv.from].unwrap();
-        if mv.promotion.is_some() {
-            if material.piece() != Pawn {
-                return Err(InvalidMove.into());
-            }
-            if mv.to.rank().is_back_rank(!material.color()) {
-                return Err(InvalidMove.into());
-            }
-            Ok(LegalMove::Promoting(mv.from, mv.to, mv.promotion.unwrap()))
-        } else {
-            Ok(legal_moves.get(mv.from).unwrap())
+        check_promotion(pos, pos[mv.from].unwrap(), mv)?;
+        match mv.promotion {
+            Some(promotion) => Ok(LegalMove::Promoting(mv.from, mv.to, promotion)),
+            None => Ok(legal_moves[mv.to]),
         }
     }
 
@@ -258,12 +452,32 @@ pub trait LegalMoves: AsRef<Position> + AsRef<MoveState> {
                 }
             }
         }
+        result = pos.rules().restrict_moves(self.as_ref(), result);
+        #[cfg(feature = "cross-check")]
+        if pos.variant() == super::variant::Variant::Standard {
+            super::reference::cross_check(pos, from, &result);
+        }
         result
     }
     fn all_king_moves(&self, from: Square) -> MoveSet<LegalMove> {
+        let pos: &Position = self.as_ref();
+        if !pos.rules().has_royal_king() {
+            return self.unrestricted_king_moves(from);
+        }
         self.standard_king_moves(from) | self.all_castle_moves()
     }
 
+    /// Returns the moves of a king that isn't royal, which may move onto
+    /// attacked squares like any other piece.
+    fn unrestricted_king_moves(&self, from: Square) -> MoveSet<LegalMove> {
+        let state: &MoveState = self.as_ref();
+        let mut result = MoveSet::new();
+        for dest in (KING_MOVES[from] & !state.ours()).iter() {
+            result.insert(dest, LegalMove::Standard(from, dest));
+        }
+        result
+    }
+
     fn standard_king_moves(&self, from: Square) -> MoveSet<LegalMove> {
         let state: &MoveState = self.as_ref();
         let mut destinations = KING_MOVES[from] & !state.ours();
@@ -303,10 +517,11 @@ pub trait LegalMoves: AsRef<Position> + AsRef<MoveState> {
             !state.is_lane_attacked(castling.oo_attacking_lane())
         {
             let king_dest = castling.oo_king_dest();
-            if !state.is_attacked(king_dest) {
-                let rook_src = castling.oo_rook_src();
-                result.insert(king_dest, LegalMove::ShortCastle);
-                result.insert(rook_src, LegalMove::ShortCastle);
+            let rook_src = castling.oo_rook_src();
+            if !state.is_attacked(king_dest)
+                && !state.is_castling_exposing(king_dest, rook_src, castling.oo_rook_dest())
+            {
+                insert_castle(&mut result, castling.king_src(), king_dest, rook_src, LegalMove::ShortCastle);
             }
         }
         result
@@ -323,10 +538,11 @@ pub trait LegalMoves: AsRef<Position> + AsRef<MoveState> {
             !state.is_lane_attacked(castling.ooo_attacking_lane())
         {
             let king_dest = castling.ooo_king_dest();
-            if !state.is_attacked(king_dest) {
-                let rook_src = castling.ooo_rook_src();
-                result.insert(king_dest, LegalMove::LongCastle);
-                result.insert(rook_src, LegalMove::LongCastle);
+            let rook_src = castling.ooo_rook_src();
+            if !state.is_attacked(king_dest)
+                && !state.is_castling_exposing(king_dest, rook_src, castling.ooo_rook_dest())
+            {
+                insert_castle(&mut result, castling.king_src(), king_dest, rook_src, LegalMove::LongCastle);
             }
         }
         result
@@ -355,7 +571,10 @@ pub trait LegalMoves: AsRef<Position> + AsRef<MoveState> {
             if let Some(lane) = state.pinned(from) {
                 destinations &= lane;
             }
-            let destinations = state.exclude_blocked_moves(from, destinations);
+            let mut destinations = state.exclude_blocked_moves(from, destinations);
+            // restrict movement if in check (only once blocked squares are
+            // excluded, since the blocking pieces may be masked out)
+            destinations &= state.evasions();
             for dest in destinations.iter() {
                 result.insert(dest, LegalMove::Standard(from, dest));
             }
@@ -369,6 +588,8 @@ pub trait LegalMoves: AsRef<Position> + AsRef<MoveState> {
         if !state.is_double_check() && state.pinned(from).is_none() {
             let mut destinations = KNIGHT_MOVES[from];
             destinations &= !state.ours();
+            // restrict movement if in check
+            destinations &= state.evasions();
             for dest in destinations.iter() {
                 result.insert(dest, LegalMove::Standard(from, dest))
             }
@@ -401,7 +622,8 @@ pub trait LegalMoves: AsRef<Position> + AsRef<MoveState> {
             advances &= !pos.occupied();
             // exclude captures that don't target their pieces
             captures &= pos.theirs();
-            let destinations = advances | captures;
+            // restrict movement if in check
+            let destinations = (advances | captures) & state.evasions();
             for dest in destinations.iter() {
                 result.insert(dest, LegalMove::Standard(from, dest));
             }
@@ -422,8 +644,9 @@ pub trait LegalMoves: AsRef<Position> + AsRef<MoveState> {
             if let Some(lane) = state.pinned(from) {
                 destinations &= lane;
             }
-            // exclude occupied squares
+            // exclude occupied squares and restrict movement if in check
             destinations &= !pos.occupied();
+            destinations &= state.evasions();
             for dest in destinations.iter() {
                 let between = between(from, dest);
                 if (between & pos.occupied()).is_empty() {
@@ -446,12 +669,19 @@ pub trait LegalMoves: AsRef<Position> + AsRef<MoveState> {
                 };
                 // exclude any non-en-passant squares
                 destinations &= target.to_mask();
-                // restrict movement if pinned
-                if let Some(lane) = state.pinned(from) {
-                    destinations &= lane;
-                }
                 for dest in destinations.iter() {
-                    result.insert(dest, LegalMove::EnPassant(from, dest));
+                    // in check, the capture must take the checking pawn
+                    // or block the check
+                    let captured = Square::new(dest.file(), from.rank());
+                    let evasions = state.evasions();
+                    if !evasions.contains(captured) && !evasions.contains(dest) {
+                        continue;
+                    }
+                    // exclude captures that expose our king, including
+                    // when pinned
+                    if !state.is_en_passant_exposing(from, dest) {
+                        result.insert(dest, LegalMove::EnPassant(from, dest));
+                    }
                 }
             }
         }
@@ -470,42 +700,14 @@ pub trait PreMoves: AsRef<Position> {
         // Safety: above validation ensures there's material at `from`
         // and that it's the proper color
         let pos: &Position = self.as_ref();
-        let material = pos[mv.from].unwrap();
-        if mv.promotion.is_some() {
-            if material.piece() != Pawn {
-                return Err(InvalidMove.into());
-            }
-            if mv.to.rank().is_back_rank(!material.color()) {
-                return Err(InvalidMove.into());
-            }
-            Ok(PreMove::Promoting(mv.from, mv.to, mv.promotion.unwrap()))
-        } else {
-            Ok(pre_moves.get(mv.from).unwrap())
+        check_promotion(pos, pos[mv.from].unwrap(), mv)?;
+        match mv.promotion {
+            Some(promotion) => Ok(PreMove::Promoting(mv.from, mv.to, promotion)),
+            None => Ok(pre_moves[mv.to]),
         }
     }
 
     fn pre_moves(&self, from: Square) -> MoveSet<PreMove> {
-        let short_castle_targets = || -> Mask {
-            let mut mask = Mask::empty();
-            let pos: &Position = self.as_ref();
-            let castling = pos.their_castling();
-            if castling.oo() {
-                mask |= castling.oo_king_dest().to_mask();
-                mask |= castling.oo_rook_dest().to_mask();
-            }
-            mask
-        };
-        let long_castle_targets = || -> Mask {
-            let mut mask = Mask::empty();
-            let pos: &Position = self.as_ref();
-            let castling = pos.their_castling();
-            if castling.ooo() {
-                mask |= castling.ooo_king_dest().to_mask();
-                mask |= castling.ooo_rook_dest().to_mask();
-            }
-            mask
-        };
-
         let mut result = MoveSet::new();
         let pos: &Position = self.as_ref();
         if let Some(material) = pos.contents(from) {
@@ -515,11 +717,12 @@ pub trait PreMoves: AsRef<Position> {
                         for dest in KING_MOVES[from].iter() {
                             result.insert(dest, PreMove::Standard(from, dest));
                         }
-                        for dest in short_castle_targets().iter() {
-                            result.insert(dest, PreMove::ShortCastle);
+                        let castling = pos.their_castling();
+                        if castling.oo() {
+                            insert_castle(&mut result, from, castling.oo_king_dest(), castling.oo_rook_src(), PreMove::ShortCastle);
                         }
-                        for dest in long_castle_targets().iter() {
-                            result.insert(dest, PreMove::LongCastle);
+                        if castling.ooo() {
+                            insert_castle(&mut result, from, castling.ooo_king_dest(), castling.ooo_rook_src(), PreMove::LongCastle);
                         }
                     },
                     Queen => {
@@ -543,7 +746,7 @@ pub trait PreMoves: AsRef<Position> {
                         }
                     },
                     Pawn => {
-                        let destinations = match pos.turn() {
+                        let destinations = match material.color() {
                             White => WHITE_PAWN_MOVES[from],
                             Black => BLACK_PAWN_MOVES[from],
                         };
@@ -572,12 +775,59 @@ impl Move {
     }
 }
 
+impl FromStr for Move {
+    type Err = ParseError;
+
+    /// Parses a move in UCI notation (e.g. "e2e4" or "e7e8q") or with
+    /// the squares separated by a dash (e.g. "e2-e4" or "e7-e8=Q").
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseError::InvalidMove(s.to_string());
+        let from = s.get(0..2).ok_or_else(error)?.parse().map_err(|_| error())?;
+        let rest = s.get(2..).ok_or_else(error)?;
+        let (rest, dashed) = match rest.strip_prefix('-') {
+            Some(rest) => (rest, true),
+            None => (rest, false),
+        };
+        let to = rest.get(0..2).ok_or_else(error)?.parse().map_err(|_| error())?;
+        let promotion = match rest.get(2..).ok_or_else(error)? {
+            "" => None,
+            p => {
+                // "=Q" is only accepted in the dashed form
+                let p = if dashed { p.strip_prefix('=').unwrap_or(p) } else { p };
+                Some(p.parse().map_err(|_| error())?)
+            },
+        };
+        Ok(Self::new(from, to, promotion))
+    }
+}
+
+impl TryFrom<&str> for Move {
+    type Error = ParseError;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Formats the move in UCI notation (e.g. "e2e4" or "e7e8q").
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.from, self.to)?;
+        if let Some(promotion) = self.promotion {
+            write!(f, "{}", promotion)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Promotion {
     Queen,
     Rook,
     Bishop,
     Knight,
+    /// Only allowed in variants where the king isn't royal (see
+    /// `Rules::promotions`).
+    King,
 }
 
 impl From<Promotion> for Piece {
@@ -587,10 +837,42 @@ impl From<Promotion> for Piece {
             Promotion::Rook => Piece::Rook,
             Promotion::Bishop => Piece::Bishop,
             Promotion::Knight => Piece::Knight,
+            Promotion::King => Piece::King,
         }
     }
 }
 
+impl Promotion {
+    pub fn try_from_char(c: char) -> Option<Self> {
+        match c {
+            'q' | 'Q' => Some(Promotion::Queen),
+            'r' | 'R' => Some(Promotion::Rook),
+            'b' | 'B' => Some(Promotion::Bishop),
+            'n' | 'N' => Some(Promotion::Knight),
+            'k' | 'K' => Some(Promotion::King),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Promotion {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Self::try_from_char(c),
+            _ => None,
+        }.ok_or_else(|| ParseError::InvalidPromotion(s.to_string()))
+    }
+}
+
+impl TryFrom<&str> for Promotion {
+    type Error = ParseError;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
 impl fmt::Display for Promotion {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = match *self {
@@ -598,8 +880,13 @@ impl fmt::Display for Promotion {
             Promotion::Rook => "r",
             Promotion::Bishop => "b",
             Promotion::Knight => "n",
+            Promotion::King => "k",
         };
-        write!(f, "({})", s)
+        if f.alternate() {
+            write!(f, "({})", s)
+        } else {
+            write!(f, "{}", s)
+        }
     }
 }
 
@@ -623,6 +910,77 @@ pub enum LegalMove {
     LongCastle,
 }
 
+/// Castling moves carry no squares, so the accessors below take the
+/// position the move is played from (with the mover to play) to look up
+/// the king's squares.
+impl LegalMove {
+    /// Returns the square the moving piece leaves: the king's square for
+    /// castling.
+    pub fn from(&self, pos: &Position) -> Square {
+        match *self {
+            LegalMove::Standard(from, _)
+            | LegalMove::DoubleAdvance(from, _)
+            | LegalMove::EnPassant(from, _)
+            | LegalMove::Promoting(from, _, _) => from,
+            LegalMove::ShortCastle | LegalMove::LongCastle => pos.our_king_src(),
+        }
+    }
+
+    /// Returns the square the moving piece lands on: the king's
+    /// destination for castling.
+    pub fn to(&self, pos: &Position) -> Square {
+        match *self {
+            LegalMove::Standard(_, to)
+            | LegalMove::DoubleAdvance(_, to)
+            | LegalMove::EnPassant(_, to)
+            | LegalMove::Promoting(_, to, _) => to,
+            LegalMove::ShortCastle => pos.our_oo_king_dest(),
+            LegalMove::LongCastle => pos.our_ooo_king_dest(),
+        }
+    }
+
+    /// Returns the square of the piece this move captures, if any. It
+    /// differs from `to` for en passant captures.
+    pub fn capture_square(&self, pos: &Position) -> Option<Square> {
+        match *self {
+            LegalMove::Standard(_, to) | LegalMove::Promoting(_, to, _) => {
+                pos.theirs().contains(to).then_some(to)
+            },
+            LegalMove::EnPassant(from, to) => Some(Square::new(to.file(), from.rank())),
+            _ => None,
+        }
+    }
+
+    pub fn is_capture(&self, pos: &Position) -> bool {
+        self.capture_square(pos).is_some()
+    }
+
+    pub const fn is_castle(&self) -> bool {
+        matches!(self, LegalMove::ShortCastle | LegalMove::LongCastle)
+    }
+
+    pub const fn promotion(&self) -> Option<Promotion> {
+        match *self {
+            LegalMove::Promoting(_, _, promotion) => Some(promotion),
+            _ => None,
+        }
+    }
+
+    /// Returns the move as a player would submit it. Castling is given
+    /// as the king's move to its destination, or onto the rook when the
+    /// king doesn't move (as can happen in Chess960); `validate_move`
+    /// accepts either.
+    pub fn to_move(&self, pos: &Position) -> Move {
+        let from = self.from(pos);
+        let to = match *self {
+            LegalMove::ShortCastle if self.to(pos) == from => pos.our_oo_rook_src(),
+            LegalMove::LongCastle if self.to(pos) == from => pos.our_ooo_rook_src(),
+            _ => self.to(pos),
+        };
+        Move::new(from, to, self.promotion())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MoveSet<T> {
     destinations: Mask,
@@ -654,6 +1012,21 @@ impl<T: Copy> MoveSet<T> {
     }
 }
 
+impl MoveSet<LegalMove> {
+    /// Keeps only the captures, e.g. for variants where capturing is
+    /// forced.
+    pub fn captures(self, pos: &Position) -> Self {
+        let mut result = Self::new();
+        for dest in self.destinations.iter() {
+            let mv = self.map[&dest];
+            if mv.is_capture(pos) {
+                result.insert(dest, mv);
+            }
+        }
+        result
+    }
+}
+
 impl<T: Copy> Default for MoveSet<T> {
     fn default() -> Self {
         Self::new()
@@ -683,166 +1056,152 @@ impl<T: Copy> Index<Square> for MoveSet<T> {
 }
 
 
-static KING_MOVES: Lazy<[Mask; 64]> = Lazy::new(|| {
-    let mut array = [Mask::default(); 64];
-    for square in Square::iter() {
-        array[square] = Mask::from_squares(
-            Direction::iter().filter_map(|dir| square + dir)
-        );
-    }
-    array
-});
+static KING_MOVES: [Mask; 64] = square_table!(|square| Mask::king_ring(square));
 
-static QUEEN_MOVES: Lazy<[Mask; 64]> = Lazy::new(|| {
-    let mut array = [Mask::default(); 64];
-    for square in Square::iter() {
-        array[square] = !square.to_mask() & ALL_LINES[square];
-    }
-    array
+static QUEEN_MOVES: [Mask; 64] = square_table!(|square| {
+    Mask::new(ALL_LINES[square.to_index()].inner() & !square.to_mask().inner())
 });
 
-static ROOK_MOVES: Lazy<[Mask; 64]> = Lazy::new(|| {
-    let mut array = [Mask::default(); 64];
-    for square in Square::iter() {
-        array[square] = !square.to_mask() & HORIZONTALS[square];
-    }
-    array
+static ROOK_MOVES: [Mask; 64] = square_table!(|square| {
+    Mask::new(HORIZONTALS[square.to_index()].inner() & !square.to_mask().inner())
 });
 
-static BISHOP_MOVES: Lazy<[Mask; 64]> = Lazy::new(|| {
-    let mut array = [Mask::default(); 64];
-    for square in Square::iter() {
-        array[square] = !square.to_mask() & DIAGONALS[square];
-    }
-    array
+static BISHOP_MOVES: [Mask; 64] = square_table!(|square| {
+    Mask::new(DIAGONALS[square.to_index()].inner() & !square.to_mask().inner())
 });
 
-static KNIGHT_MOVES: Lazy<[Mask; 64]> = Lazy::new(|| {
-    const OFFSETS: [Offset; 8] = [
-        Offset::new(-2, -1),
-        Offset::new(-2, 1),
-        Offset::new(2, -1),
-        Offset::new(2, 1),
-        Offset::new(-1, -2),
-        Offset::new(-1, 2),
-        Offset::new(1, -2),
-        Offset::new(1, 2),
-    ];
-    let mut array = [Mask::default(); 64];
-    for square in Square::iter() {
-        array[square] = Mask::from_squares(
-            OFFSETS.into_iter().filter_map(|offset| square + offset)
-        );
-    }
-    array
+static KNIGHT_MOVES: [Mask; 64] = square_table!(|square| {
+    let square = square.to_mask();
+    let (up, down) = (square.shift(Up).shift(Up), square.shift(Down).shift(Down));
+    let (left, right) = (square.shift(Left).shift(Left), square.shift(Right).shift(Right));
+    Mask::union(&[
+        up.shift(Left), up.shift(Right),
+        down.shift(Left), down.shift(Right),
+        left.shift(Up), left.shift(Down),
+        right.shift(Up), right.shift(Down),
+    ])
 });
 
-static WHITE_PAWN_MOVES: Lazy<[Mask; 64]> = Lazy::new(|| {
-    let advances1 = &WHITE_SINGLE_ADVANCES;
-    let advances2 = &WHITE_DOUBLE_ADVANCES;
-    let attacks = &WHITE_PAWN_ATTACKS;
-    let mut array = [Mask::default(); 64];
-    for square in Square::iter() {
-        array[square] = advances1[square] | advances2[square] | attacks[square];
+/// Returns the square a pawn of `color` on `square` advances to, if any.
+const fn single_advance(color: Color, square: Square) -> Mask {
+    match (color, square.rank()) {
+        (_, Rank1 | Rank8) => Mask::empty(),
+        (White, _) => square.to_mask().shift(Up),
+        (Black, _) => square.to_mask().shift(Down),
     }
-    array
-});
-
+}
 
-static WHITE_SINGLE_ADVANCES: Lazy<[Mask; 64]> = Lazy::new(|| {
-    const OFFSET: Offset = Offset::new(0, -1);
-    let mut array = [Mask::empty(); 64];
-    for rank in Rank::iter() {
-        match rank {
-            Rank1 | Rank8 => {},
-            _ => {
-                for file in File::iter() {
-                    let square = Square::new(file, rank);
-                    array[square] = (square + OFFSET).unwrap().to_mask();
-                }
-            },
-        };
+/// Returns the square a pawn of `color` on `square` reaches with a double
+/// advance, if any.
+const fn double_advance(color: Color, square: Square) -> Mask {
+    match (color, square.rank()) {
+        (White, Rank2) => square.to_mask().shift(Up).shift(Up),
+        (Black, Rank7) => square.to_mask().shift(Down).shift(Down),
+        _ => Mask::empty(),
     }
-    array
-});
+}
 
-static WHITE_DOUBLE_ADVANCES: Lazy<[Mask; 64]> = Lazy::new(|| {
-    const OFFSET: Offset = Offset::new(0, -2);
-    let mut array = [Mask::default(); 64];
-    for file in File::iter() {
-        let square = Square::new(file, Rank2);
-        array[square] = (square + OFFSET).unwrap().to_mask();
+/// Returns the squares a pawn of `color` on `square` attacks.
+const fn pawn_attacks(color: Color, square: Square) -> Mask {
+    match square.rank() {
+        Rank1 | Rank8 => Mask::empty(),
+        _ => square.to_mask().pawn_attacks(color),
     }
-    array
-});
+}
 
-static WHITE_PAWN_ATTACKS: Lazy<[Mask; 64]> = Lazy::new(|| {
-    const OFFSETS: [Offset; 2] = [Offset::new(-1, -1), Offset::new(1, -1)];
-    let mut array = [Mask::default(); 64];
-    for square in Square::iter() {
-        if matches!(square.rank(), Rank1 | Rank8) { continue }
-        array[square] = Mask::from_squares(
-            OFFSETS.into_iter().filter_map(|offset| square + offset)
-        );
-    }
-    array
-});
+static WHITE_PAWN_MOVES: [Mask; 64] = square_table!(|square| Mask::union(&[
+    single_advance(White, square), double_advance(White, square), pawn_attacks(White, square),
+]));
 
-static BLACK_PAWN_MOVES: Lazy<[Mask; 64]> = Lazy::new(|| {
-    let advances1 = &BLACK_SINGLE_ADVANCES;
-    let advances2 = &BLACK_DOUBLE_ADVANCES;
-    let attacks = &BLACK_PAWN_ATTACKS;
-    let mut array = [Mask::default(); 64];
-    for square in Square::iter() {
-        array[square] = advances1[square] | advances2[square] | attacks[square];
-    }
-    array
-});
+static WHITE_SINGLE_ADVANCES: [Mask; 64] = square_table!(|square| single_advance(White, square));
 
-static BLACK_SINGLE_ADVANCES: Lazy<[Mask; 64]> = Lazy::new(|| {
-    const OFFSET: Offset = Offset::new(0, 1);
-    let mut array = [Mask::empty(); 64];
-    for rank in Rank::iter() {
-        match rank {
-            Rank1 | Rank8 => {},
-            _ => {
-                for file in File::iter() {
-                    let square = Square::new(file, rank);
-                    array[square] = (square + OFFSET).unwrap().to_mask();
-                }
-            },
-        };
-    }
-    array
-});
+static WHITE_DOUBLE_ADVANCES: [Mask; 64] = square_table!(|square| double_advance(White, square));
 
-static BLACK_DOUBLE_ADVANCES: Lazy<[Mask; 64]> = Lazy::new(|| {
-    const OFFSET: Offset = Offset::new(0, 2);
-    let mut array = [Mask::default(); 64];
-    for file in File::iter() {
-        let square = Square::new(file, Rank7);
-        array[square] = (square + OFFSET).unwrap().to_mask();
-    }
-    array
-});
+static WHITE_PAWN_ATTACKS: [Mask; 64] = square_table!(|square| pawn_attacks(White, square));
+
+static BLACK_PAWN_MOVES: [Mask; 64] = square_table!(|square| Mask::union(&[
+    single_advance(Black, square), double_advance(Black, square), pawn_attacks(Black, square),
+]));
+
+static BLACK_SINGLE_ADVANCES: [Mask; 64] = square_table!(|square| single_advance(Black, square));
+
+static BLACK_DOUBLE_ADVANCES: [Mask; 64] = square_table!(|square| double_advance(Black, square));
 
-static BLACK_PAWN_ATTACKS: Lazy<[Mask; 64]> = Lazy::new(|| {
-    const OFFSETS: [Offset; 2] = [Offset::new(-1, 1), Offset::new(1, 1)];
-    let mut array = [Mask::default(); 64];
-    for square in Square::iter() {
-        if matches!(square.rank(), Rank1 | Rank8) { continue }
-        array[square] = Mask::from_squares(
-            OFFSETS.into_iter().filter_map(|offset| square + offset)
-        );
+static BLACK_PAWN_ATTACKS: [Mask; 64] = square_table!(|square| pawn_attacks(Black, square));
+
+/// Returns the squares a piece of `color` on `from` could move to on an
+/// empty board, counting pawn captures and double advances but not
+/// castling.
+pub(super) fn reach(color: Color, piece: Piece, from: Square) -> Mask {
+    match (piece, color) {
+        (King, _) => KING_MOVES[from],
+        (Queen, _) => QUEEN_MOVES[from],
+        (Rook, _) => ROOK_MOVES[from],
+        (Bishop, _) => BISHOP_MOVES[from],
+        (Knight, _) => KNIGHT_MOVES[from],
+        (Pawn, White) => WHITE_PAWN_MOVES[from],
+        (Pawn, Black) => BLACK_PAWN_MOVES[from],
     }
-    array
-});
+}
 
 #[cfg(test)]
 mod tests {
     use crate::*;
     use Square::*;
 
+    #[test]
+    fn test_fork_shares_tables_until_a_move_is_applied() {
+        let state = MoveState::default();
+        // attackers are the opponent's pieces, so black's at the start
+        assert!(state.is_attacked(F6));
+        let mut fork = state.fork();
+        let shared = |a: &MoveState, b: &MoveState| {
+            std::sync::Arc::ptr_eq(a.tables.get().unwrap(), b.tables.get().unwrap())
+        };
+        assert!(shared(&state, &fork));
+        fork.apply_move(LegalMove::DoubleAdvance(E2, E4)).unwrap();
+        assert!(fork.tables.get().is_none());
+        assert!(fork.is_attacked(F3));
+        assert!(!shared(&state, &fork));
+        // the original is unaffected
+        assert_eq!(state.turn(), Color::White);
+        assert!(state.legal_moves(E2).destinations().contains(E4));
+    }
+    #[test]
+    fn test_highlight_masks() {
+        let mut state = MoveState::default();
+        assert!(state.last_move_squares().is_empty());
+        state.apply_move(LegalMove::DoubleAdvance(E2, E4)).unwrap();
+        assert_eq!(state.last_move_squares(), E2.to_mask() | E4);
+        assert!(state.check_ray().is_empty());
+
+        // the queen on h4 checks along the diagonal, the knight on d3 by contact
+        let position = Position::from_fen("4k3/8/8/8/7q/3n4/8/4K3 w - - 0 1").unwrap();
+        let state = MoveState::new(position);
+        assert_eq!(state.checks(), H4.to_mask() | D3);
+        assert_eq!(state.check_ray(), G3.to_mask() | F2);
+
+        // the knight on c3 is attacked and undefended, the bishop on e2 is defended
+        let position = Position::from_fen("4k3/8/8/8/1p6/2N5/3rB3/4K3 w - - 0 1").unwrap();
+        let state = MoveState::new(position);
+        assert_eq!(state.hanging(Color::White), C3.to_mask());
+        assert_eq!(state.hanging(Color::Black), D2.to_mask());
+    }
+    #[test]
+    fn test_attacks_for_both_colors() {
+        let state = MoveState::default();
+        // the same tables answer for the side to play and its opponent
+        assert_eq!(state.attackers_by(F3, Color::White), G1.to_mask() | E2 | G2);
+        assert_eq!(state.attackers_by(F6, Color::Black), G8.to_mask() | E7 | G7);
+        assert_eq!(state.attackers(F6), state.attackers_by(F6, Color::Black));
+        assert!(state.is_attacked_by(D2, Color::White));
+        assert!(!state.is_attacked_by(E4, Color::White));
+        let third_rank = Mask::from_squares([A3, B3, C3, D3, E3, F3, G3, H3]);
+        assert_eq!(state.attacked_squares(Color::White) & third_rank, third_rank);
+        assert_eq!(state.attacked_squares(Color::White).len(), 22);
+        assert_eq!(state.attacks_from(B1), A3.to_mask() | C3 | D2);
+        assert!(state.attacks_from(E4).is_empty());
+    }
     #[test]
     fn test_white_can_move_first() {
         let state = MoveState::default();
@@ -864,7 +1223,7 @@ mod tests {
     #[test]
     fn test_black_pawn_advance() {
         let mut state = MoveState::default();
-        state.apply_move(LegalMove::DoubleAdvance(E2, E4));
+        state.apply_move(LegalMove::DoubleAdvance(E2, E4)).unwrap();
         let destinations = state.legal_moves(E7).destinations();
         assert!(destinations.contains(E6));
     }
@@ -896,7 +1255,7 @@ mod tests {
     #[test]
     fn test_black_pawn_double_advance() {
         let mut state = MoveState::default();
-        state.apply_move(LegalMove::DoubleAdvance(E2, E4));
+        state.apply_move(LegalMove::DoubleAdvance(E2, E4)).unwrap();
         let destinations = state.legal_moves(E7).destinations();
         assert!(destinations.contains(E5));
     }
@@ -932,7 +1291,7 @@ mod tests {
         let destinations = state.legal_moves(C2).destinations();
         assert!(destinations.contains(D3));
         assert!(!destinations.contains(B3));
-        state.apply_move(LegalMove::Standard(E2, D3));
+        state.apply_move(LegalMove::Standard(E2, D3)).unwrap();
         assert_eq!(state.contents(D3), &Some(Material::WP));
         assert_eq!(state.contents(E2), &None);
     }
@@ -950,7 +1309,7 @@ mod tests {
         let destinations = state.legal_moves(C7).destinations();
         assert!(destinations.contains(D6));
         assert!(!destinations.contains(B6));
-        state.apply_move(LegalMove::Standard(E7, D6));
+        state.apply_move(LegalMove::Standard(E7, D6)).unwrap();
         assert_eq!(state.contents(D6), &Some(Material::BP));
         assert_eq!(state.contents(E7), &None);
     }
@@ -961,7 +1320,7 @@ mod tests {
         let mut state = MoveState::new(position);
         let destinations = state.legal_moves(B7).destinations();
         assert!(destinations.contains(A8));
-        state.apply_move(LegalMove::Promoting(B7, A8, Promotion::Queen));
+        state.apply_move(LegalMove::Promoting(B7, A8, Promotion::Queen)).unwrap();
         assert_eq!(state.contents(A8), &Some(Material::WQ));
     }
     #[test]
@@ -972,15 +1331,90 @@ mod tests {
         let mut state = MoveState::new(position);
         let destinations = state.legal_moves(B2).destinations();
         assert!(destinations.contains(A1));
-        state.apply_move(LegalMove::Promoting(B2, A1, Promotion::Knight));
+        state.apply_move(LegalMove::Promoting(B2, A1, Promotion::Knight)).unwrap();
         assert_eq!(state.contents(A1), &Some(Material::BN));
     }
     #[test]
+    fn test_promotion_validation() {
+        // each side has a pawn that can promote by pushing or capturing
+        let position = Position::from_fen("1n5k/P7/8/8/8/8/p7/1N5K w - - 0 1").unwrap();
+        let black_to_move = Position::from_fen("1n5k/P7/8/8/8/8/p7/1N5K b - - 0 1").unwrap();
+        let cases = [(Color::White, A7, A8), (Color::White, A7, B8), (Color::Black, A2, A1), (Color::Black, A2, B1)];
+        for (color, from, to) in cases {
+            let (on_turn, off_turn) = match color {
+                Color::White => (&position, &black_to_move),
+                Color::Black => (&black_to_move, &position),
+            };
+            let state = MoveState::new(on_turn.clone());
+            assert_eq!(state.validate_move(Move::new(from, to, None)), Err(MoveError::InvalidMove.into()));
+            assert_eq!(off_turn.validate_pre_move(Move::new(from, to, None)), Err(MoveError::InvalidMove.into()));
+            for promotion in [Promotion::Queen, Promotion::Rook, Promotion::Bishop, Promotion::Knight] {
+                let mv = Move::new(from, to, Some(promotion));
+                assert_eq!(state.validate_move(mv), Ok(LegalMove::Promoting(from, to, promotion)));
+                assert_eq!(off_turn.validate_pre_move(mv), Ok(PreMove::Promoting(from, to, promotion)));
+            }
+            let king = Move::new(from, to, Some(Promotion::King));
+            assert!(state.validate_move(king).is_err());
+            assert!(off_turn.validate_pre_move(king).is_err());
+        }
+
+        // only pawns reaching the last rank may name a promotion piece
+        let position = Position::from_fen("7k/8/P7/8/8/8/8/1N5K w - - 0 1").unwrap();
+        let state = MoveState::new(position.clone());
+        assert!(state.validate_move(Move::new(A6, A7, Some(Promotion::Queen))).is_err());
+        assert!(state.validate_move(Move::new(B1, C3, Some(Promotion::Queen))).is_err());
+        let position = Position::from_fen("7k/8/P7/8/8/8/8/1N5K b - - 0 1").unwrap();
+        assert!(position.validate_pre_move(Move::new(A6, A7, Some(Promotion::Queen))).is_err());
+        assert!(position.validate_pre_move(Move::new(B1, C3, Some(Promotion::Queen))).is_err());
+    }
+    #[test]
+    fn test_chess960_castling_targets() {
+        let inferred = |fen: &str| Position::from_fen(fen).unwrap().with_inferred_castling();
+        // the king's destination is a single step away, so only dropping
+        // the king onto the rook castles
+        let state = MoveState::new(inferred("4k3/8/8/8/8/8/8/R4KR1 w - - 0 1"));
+        let moves = state.legal_moves(F1);
+        assert_eq!(moves.get(G1), Some(LegalMove::ShortCastle));
+        assert_eq!(moves.get(E1), Some(LegalMove::Standard(F1, E1)));
+        assert_eq!(moves.get(C1), Some(LegalMove::LongCastle));
+        assert_eq!(moves.get(A1), Some(LegalMove::LongCastle));
+        assert_eq!(state.validate_move(Move::new(F1, G1, None)), Ok(LegalMove::ShortCastle));
+        // the king stays on g1
+        let state = MoveState::new(inferred("3k4/8/8/8/8/8/8/4R1KR w - - 0 1"));
+        let moves = state.legal_moves(G1);
+        assert!(!moves.contains(G1));
+        assert_eq!(moves.get(H1), Some(LegalMove::ShortCastle));
+        // the long castle lands the king on c1, a single step away
+        let state = MoveState::new(inferred("4k3/8/8/8/8/8/8/RK3N2 w - - 0 1"));
+        assert_eq!(state.legal_moves(B1).get(A1), Some(LegalMove::LongCastle));
+        assert_eq!(state.legal_moves(B1).get(C1), Some(LegalMove::Standard(B1, C1)));
+        // pre-moves follow the same conventions
+        let pos = inferred("4k3/8/8/8/8/8/8/R4KR1 b - - 0 1");
+        assert_eq!(pos.validate_pre_move(Move::new(F1, G1, None)), Ok(PreMove::ShortCastle));
+        assert_eq!(pos.validate_pre_move(Move::new(F1, E1, None)), Ok(PreMove::Standard(F1, E1)));
+        let pos = Position::from_fen("4k3/8/8/8/8/8/8/R3K2R b KQ - 0 1").unwrap();
+        assert_eq!(pos.validate_pre_move(Move::new(E1, F1, None)), Ok(PreMove::Standard(E1, F1)));
+        assert_eq!(pos.validate_pre_move(Move::new(E1, G1, None)), Ok(PreMove::ShortCastle));
+        assert_eq!(pos.validate_pre_move(Move::new(E1, A1, None)), Ok(PreMove::LongCastle));
+    }
+    #[test]
+    fn test_chess960_castling_lanes() {
+        let inferred = |fen: &str| Position::from_fen(fen).unwrap().with_inferred_castling();
+        // the king and rook land beyond the squares between them, which
+        // must be empty too
+        let state = MoveState::new(inferred("4k3/8/8/8/8/8/8/RK1B4 w - - 0 1"));
+        assert!(!state.legal_moves(B1).contains(A1));
+        assert_eq!(state.legal_moves(B1).get(C1), Some(LegalMove::Standard(B1, C1)));
+        // the castling rook may shield the king's destination
+        let state = MoveState::new(inferred("4k3/8/8/8/8/8/8/rR3KR1 w - - 0 1"));
+        assert!(!state.legal_moves(F1).contains(B1));
+    }
+    #[test]
     fn test_double_advance_enables_en_passant() {
         let position = Position::default()
             .set_contents(D4, Some(Material::BP));
         let mut state = MoveState::new(position);
-        state.apply_move(LegalMove::DoubleAdvance(E2, E4));
+        state.apply_move(LegalMove::DoubleAdvance(E2, E4)).unwrap();
         let destinations = state.legal_moves(D4).destinations();
         assert!(destinations.contains(E3));
     }
@@ -993,7 +1427,7 @@ mod tests {
         let mut state = MoveState::new(position);
         let destinations = state.legal_moves(A5).destinations();
         assert!(destinations.contains(B6));
-        state.apply_move(LegalMove::EnPassant(A5, B6));
+        state.apply_move(LegalMove::EnPassant(A5, B6)).unwrap();
         assert_eq!(state.contents(B6), &Some(Material::WP));
         assert_eq!(state.contents(B5), &None);
         assert_eq!(state.contents(A5), &None);
@@ -1008,12 +1442,162 @@ mod tests {
         let mut state = MoveState::new(position);
         let destinations = state.legal_moves(A4).destinations();
         assert!(destinations.contains(B3));
-        state.apply_move(LegalMove::EnPassant(A4, B3));
+        state.apply_move(LegalMove::EnPassant(A4, B3)).unwrap();
         assert_eq!(state.contents(B3), &Some(Material::BP));
         assert_eq!(state.contents(B4), &None);
         assert_eq!(state.contents(A4), &None);
     }
     #[test]
+    fn test_en_passant_exposing_king_along_rank() {
+        // both pawns leave the fifth rank, exposing the king to the rook
+        let position = Position::default()
+            .set_contents(E1, None)
+            .clear_white_oo()
+            .clear_white_ooo()
+            .set_contents(A5, Some(Material::WK))
+            .set_contents(D5, Some(Material::BP))
+            .set_contents(E5, Some(Material::WP))
+            .set_contents(H5, Some(Material::BR))
+            .set_en_passant(Some(D6));
+        let state = MoveState::new(position.clone());
+        assert!(!state.is_pinned(E5));
+        assert_eq!(state.legal_moves(E5).destinations(), E6.to_mask());
+        // another piece on the rank keeps the king covered
+        let state = MoveState::new(position.set_contents(G5, Some(Material::WN)));
+        assert_eq!(state.legal_moves(E5).destinations(), D6.to_mask() | E6);
+    }
+    #[test]
+    fn test_en_passant_exposing_king_along_diagonal() {
+        // the captured pawn shields the king from the bishop
+        let position = Position::default()
+            .set_contents(E1, None)
+            .clear_white_oo()
+            .clear_white_ooo()
+            .set_contents(F3, Some(Material::WK))
+            .set_contents(D5, Some(Material::BP))
+            .set_contents(E5, Some(Material::WP))
+            .set_contents(C6, Some(Material::BB))
+            .set_en_passant(Some(D6));
+        let state = MoveState::new(position);
+        assert_eq!(state.legal_moves(E5).destinations(), E6.to_mask());
+    }
+    #[test]
+    fn test_pinned_en_passant_along_pin() {
+        // the pawn is pinned on the diagonal but captures along it
+        let position = Position::default()
+            .set_contents(E1, None)
+            .clear_white_oo()
+            .clear_white_ooo()
+            .set_contents(H2, Some(Material::WK))
+            .set_contents(E5, Some(Material::WP))
+            .set_contents(D5, Some(Material::BP))
+            .set_contents(C7, None)
+            .set_contents(B8, Some(Material::BB))
+            .set_en_passant(Some(D6));
+        let state = MoveState::new(position);
+        assert!(state.is_pinned(E5));
+        assert_eq!(state.legal_moves(E5).destinations(), D6.to_mask());
+    }
+    #[test]
+    fn test_pins_match_the_pinning_piece() {
+        // a bishop on the file doesn't pin, and a pinned piece may
+        // capture the piece pinning it
+        let position = Position::default()
+            .set_contents(E2, None)
+            .set_contents(E4, Some(Material::WR))
+            .set_contents(E7, None)
+            .set_contents(E6, Some(Material::BB))
+            .set_contents(D2, None)
+            .set_contents(C3, Some(Material::WB))
+            .set_contents(B4, Some(Material::BQ));
+        let state = MoveState::new(position);
+        assert!(!state.is_pinned(E4));
+        assert!(state.legal_moves(E4).destinations().contains(F4));
+        assert_eq!(state.pinned(C3), Some(C3.to_mask() | B4 | D2));
+        assert_eq!(state.legal_moves(C3).destinations(), B4.to_mask() | D2);
+    }
+    #[test]
+    fn test_single_check_restricts_moves_to_block_or_capture() {
+        let position = Position::default()
+            .set_contents(E2, None)
+            .set_contents(E5, Some(Material::BR))
+            .set_contents(C4, Some(Material::WN));
+        let state = MoveState::new(position);
+        assert_eq!(state.checks(), E5.to_mask());
+        assert_eq!(state.legal_moves(D1).destinations(), E2.to_mask());
+        assert_eq!(state.legal_moves(F1).destinations(), E2.to_mask());
+        assert_eq!(state.legal_moves(G1).destinations(), E2.to_mask());
+        assert_eq!(state.legal_moves(C4).destinations(), E3.to_mask() | E5);
+        assert!(state.legal_moves(B1).destinations().is_empty());
+        assert!(state.legal_moves(D2).destinations().is_empty());
+        assert_eq!(candidate_moves(&state).len(), 5);
+    }
+    #[test]
+    fn test_single_check_pawn_evasions() {
+        // a bishop check blocked by an advance or captured
+        let position = Position::default()
+            .set_contents(D2, None)
+            .set_contents(B4, Some(Material::BB))
+            .set_contents(A3, Some(Material::WP))
+            .set_contents(A2, None);
+        let state = MoveState::new(position);
+        assert_eq!(state.legal_moves(C2).destinations(), C3.to_mask());
+        assert_eq!(state.legal_moves(A3).destinations(), B4.to_mask());
+        assert!(state.legal_moves(B2).destinations().is_empty());
+        // a rook check along the fourth rank blocked by double advances
+        let position = Position::default()
+            .set_contents(E1, None)
+            .clear_white_oo()
+            .clear_white_ooo()
+            .set_contents(H4, Some(Material::WK))
+            .set_contents(A4, Some(Material::BR));
+        let state = MoveState::new(position);
+        assert_eq!(state.legal_moves(B2).destinations(), B4.to_mask());
+        assert!(state.legal_moves(A2).destinations().is_empty());
+        assert!(state.legal_moves(H2).destinations().is_empty());
+    }
+    #[test]
+    fn test_en_passant_captures_checking_pawn() {
+        let position = Position::default()
+            .set_contents(E1, None)
+            .clear_white_oo()
+            .clear_white_ooo()
+            .set_contents(E4, Some(Material::WK))
+            .set_contents(E5, Some(Material::WP))
+            .set_contents(D7, None)
+            .set_contents(D5, Some(Material::BP))
+            .set_en_passant(Some(D6));
+        let state = MoveState::new(position);
+        assert_eq!(state.checks(), D5.to_mask());
+        assert_eq!(state.legal_moves(E5).destinations(), D6.to_mask());
+        // the queen defends the checking pawn
+        assert!(!state.legal_moves(E4).destinations().contains(D5));
+    }
+    #[test]
+    fn test_double_check_allows_only_king_moves() {
+        let position = Position::default()
+            .set_contents(D2, None)
+            .set_contents(B4, Some(Material::BB))
+            .set_contents(D3, Some(Material::BN));
+        let state = MoveState::new(position);
+        assert!(state.is_double_check());
+        // the pawns could capture the knight, but not both checkers
+        assert!(state.legal_moves(C2).destinations().is_empty());
+        assert!(state.legal_moves(E2).destinations().is_empty());
+        // and the king has nowhere to go
+        assert!(candidate_moves(&state).is_empty());
+    }
+    #[test]
+    fn test_king_cannot_capture_defended_checker() {
+        let position = Position::default()
+            .set_contents(F2, Some(Material::BQ))
+            .set_contents(C5, Some(Material::BB));
+        let state = MoveState::new(position);
+        assert!(state.is_check());
+        assert!(state.is_attacked(F2));
+        assert!(candidate_moves(&state).is_empty());
+    }
+    #[test]
     fn test_king_moves_one_square() {
         let position = Position::default()
             .set_contents(E2, None);
@@ -1021,7 +1605,7 @@ mod tests {
         let destinations = state.legal_moves(E1).destinations();
         assert!(destinations.contains(E2));
         assert!(!destinations.contains(E3));
-        state.apply_move(LegalMove::Standard(E1, E2));
+        state.apply_move(LegalMove::Standard(E1, E2)).unwrap();
         assert_eq!(state.contents(E2), &Some(Material::WK));
         assert_eq!(state.contents(E1), &None);
     }
@@ -1040,13 +1624,41 @@ mod tests {
         let destinations = state.legal_moves(E1).destinations();
         assert!(destinations.contains(G1));
         assert!(destinations.contains(H1));
-        state.apply_move(LegalMove::ShortCastle);
+        state.apply_move(LegalMove::ShortCastle).unwrap();
         assert_eq!(state.contents(G1), &Some(Material::WK));
         assert_eq!(state.contents(F1), &Some(Material::WR));
         assert_eq!(state.contents(E1), &None);
         assert_eq!(state.contents(H1), &None);
     }
     #[test]
+    fn test_legal_move_accessors() {
+        let position = Position::default()
+            .set_contents(F1, None)
+            .set_contents(G1, None)
+            .set_contents(G7, Some(Material::WP))
+            .set_en_passant(Some(B6))
+            .set_contents(B5, Some(Material::BP))
+            .set_contents(A5, Some(Material::WP));
+        let castle = LegalMove::ShortCastle;
+        assert!(castle.is_castle());
+        assert_eq!((castle.from(&position), castle.to(&position)), (E1, G1));
+        assert!(!castle.is_capture(&position));
+        assert_eq!(castle.to_move(&position), Move::new(E1, G1, None));
+        let en_passant = LegalMove::EnPassant(A5, B6);
+        assert_eq!(en_passant.capture_square(&position), Some(B5));
+        assert_eq!(en_passant.to(&position), B6);
+        let promotion = LegalMove::Promoting(G7, F8, Promotion::Knight);
+        assert!(promotion.is_capture(&position));
+        assert_eq!(promotion.promotion(), Some(Promotion::Knight));
+        assert_eq!(promotion.to_move(&position), Move::new(G7, F8, Some(Promotion::Knight)));
+        assert!(!LegalMove::Standard(A5, A6).is_capture(&position));
+        // moves round trip through `validate_move`
+        let state = MoveState::new(position.clone());
+        for mv in [castle, en_passant, promotion] {
+            assert_eq!(state.validate_move(mv.to_move(&position)).unwrap(), mv);
+        }
+    }
+    #[test]
     fn test_long_castle() {
         let position = Position::default()
             .set_contents(B1, None)
@@ -1056,7 +1668,7 @@ mod tests {
         let destinations = state.legal_moves(E1).destinations();
         assert!(destinations.contains(C1));
         assert!(destinations.contains(A1));
-        state.apply_move(LegalMove::LongCastle);
+        state.apply_move(LegalMove::LongCastle).unwrap();
         assert_eq!(state.contents(C1), &Some(Material::WK));
         assert_eq!(state.contents(D1), &Some(Material::WR));
         assert_eq!(state.contents(E1), &None);
@@ -1138,7 +1750,7 @@ mod tests {
         let destinations = state.legal_moves(E1).destinations();
         assert!(destinations.contains(C1));
         assert!(destinations.contains(A1));
-        state.apply_move(LegalMove::LongCastle);
+        state.apply_move(LegalMove::LongCastle).unwrap();
         assert_eq!(state.contents(C1), &Some(Material::WK));
         assert_eq!(state.contents(D1), &Some(Material::WR));
         assert_eq!(state.contents(E1), &None);
@@ -1156,7 +1768,7 @@ mod tests {
         let destinations = state.legal_moves(E8).destinations();
         assert!(destinations.contains(C8));
         assert!(destinations.contains(A8));
-        state.apply_move(LegalMove::LongCastle);
+        state.apply_move(LegalMove::LongCastle).unwrap();
         assert_eq!(state.contents(C8), &Some(Material::BK));
         assert_eq!(state.contents(D8), &Some(Material::BR));
         assert_eq!(state.contents(E8), &None);
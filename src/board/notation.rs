@@ -0,0 +1,547 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Standard notations for positions (FEN) and moves (SAN), including
+//! their Chess960 conventions: castling rights are given by rook file
+//! (Shredder-FEN) for non-standard back ranks, and castling is always
+//! written O-O/O-O-O even when the king "moves" onto its rook's square.
+
+use std::fmt::Write;
+use strum::IntoEnumIterator;
+
+use super::backrank::{BackRank, BackRankId, BackRanks};
+use super::castling::{all_backranks, backrank_preference, Castling, CastlingRights};
+use super::material::{Color, Material, Pair, Piece};
+use super::moves::{LegalMove, LegalMoves, MoveState, Promotion};
+use super::position::{Pos, Position};
+use super::reference::is_attacked_by;
+use super::square::{File, ParseError, Rank, Square};
+use super::strategy::candidate_moves;
+use super::variant::Variant;
+use super::Turn;
+
+impl Position {
+    /// Formats the position in Forsyth-Edwards Notation.
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        let mut empty = 0;
+        for square in Square::iter() {
+            match self.contents(square) {
+                Some(material) => {
+                    if empty > 0 {
+                        let _ = write!(fen, "{}", empty);
+                        empty = 0;
+                    }
+                    fen.push(material.to_char());
+                },
+                None => empty += 1,
+            }
+            if square.file_index() == 7 {
+                if empty > 0 {
+                    let _ = write!(fen, "{}", empty);
+                    empty = 0;
+                }
+                if square.rank_index() < 7 {
+                    fen.push('/');
+                }
+            }
+        }
+
+        fen.push(' ');
+        fen.push(match self.turn() {
+            Color::White => 'w',
+            Color::Black => 'b',
+        });
+
+        fen.push(' ');
+        let (white, black) = match self.turn() {
+            Color::White => (self.our_castling(), self.their_castling()),
+            Color::Black => (self.their_castling(), self.our_castling()),
+        };
+        let backrank: &BackRank = self.as_ref();
+        let [ooo, oo] = if backrank.id() == BackRankId::STANDARD {
+            ['Q', 'K']
+        } else {
+            self.br_rook_files().map(|file| file.to_char().to_ascii_uppercase())
+        };
+        let rights = [
+            (white.oo(), oo), (white.ooo(), ooo),
+            (black.oo(), oo.to_ascii_lowercase()), (black.ooo(), ooo.to_ascii_lowercase()),
+        ];
+        let before = fen.len();
+        fen.extend(rights.iter().filter(|(allowed, _)| *allowed).map(|(_, c)| c));
+        if fen.len() == before {
+            fen.push('-');
+        }
+
+        match self.en_passant() {
+            Some(square) => { let _ = write!(fen, " {}", square); },
+            None => fen.push_str(" -"),
+        }
+        let _ = write!(fen, " {} {}", self.halfmove_clock(), self.fullmove_number());
+        fen
+    }
+
+    /// Reads a position in Forsyth-Edwards Notation. Castling rights may
+    /// be written as `KQkq`, by rook file (Shredder-FEN, `HAha`) or in
+    /// X-FEN's mix of the two; the back rank is chosen to match the king
+    /// and castling rooks. The halfmove clock and fullmove number may be
+    /// left out. `MoveId`s count from this position, so a board set up
+    /// from it starts at `MoveId::START` (or the next id if Black is to
+    /// move).
+    pub fn from_fen(fen: &str) -> Result<Self, ParseError> {
+        Self::from_variant_fen(Variant::Standard, fen)
+    }
+
+    /// Reads a position of a game of `variant` in Forsyth-Edwards
+    /// Notation, like `from_fen`.
+    pub fn from_variant_fen(variant: Variant, fen: &str) -> Result<Self, ParseError> {
+        let invalid = |reason: &str| ParseError::InvalidFen(fen.to_string(), reason.to_string());
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if !(4..=6).contains(&fields.len()) {
+            return Err(invalid("expecting 4 to 6 fields"));
+        }
+
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(invalid("expecting 8 ranks"));
+        }
+        let mut contents = Vec::new();
+        for (rank_index, rank) in ranks.iter().enumerate() {
+            let mut file_index = 0;
+            for c in rank.chars() {
+                if let Some(empty) = c.to_digit(10).filter(|empty| (1..=8).contains(empty)) {
+                    file_index += empty as usize;
+                } else {
+                    let material = Material::from_char(c).ok_or_else(|| invalid("unknown piece"))?;
+                    if file_index < 8 {
+                        contents.push((Square::from_index(rank_index * 8 + file_index), material));
+                    }
+                    file_index += 1;
+                }
+            }
+            if file_index != 8 {
+                return Err(invalid("expecting 8 squares per rank"));
+            }
+        }
+
+        let turn = match fields[1] {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err(invalid("side to move must be w or b")),
+        };
+        if !variant.rules().allows_castling() && fields[2] != "-" {
+            return Err(invalid("castling isn't allowed in this variant"));
+        }
+        let (backrank, castling) = fen_castling(&contents, fields[2]).map_err(invalid)?;
+        let en_passant = match fields[3] {
+            "-" => None,
+            square => Some(square.parse().map_err(|_| invalid("invalid en passant square"))?),
+        };
+        let halfmove_clock = match fields.get(4) {
+            Some(clock) => clock.parse().map_err(|_| invalid("invalid halfmove clock"))?,
+            None => 0,
+        };
+        let fullmove_number = match fields.get(5) {
+            Some(number) => number.parse().map_err(|_| invalid("invalid fullmove number"))?,
+            None => 1,
+        };
+
+        let pos = Position::from_parts(
+            contents, backrank, variant, castling, en_passant, turn, halfmove_clock, fullmove_number,
+        ).map_err(|error| invalid(&error.to_string()))?;
+        let their_king = (pos.kings() & pos.occupied_by(!turn)).iter().next();
+        if variant.rules().has_royal_king() && their_king.is_some_and(|king| is_attacked_by(&pos, king, turn)) {
+            return Err(invalid("the side not to move is in check"));
+        }
+        Ok(pos)
+    }
+}
+
+/// Reads the castling field of a FEN for the pieces in `contents`,
+/// returning a back rank with the king and rook files the rights refer
+/// to (the standard one if it fits) along with the rights.
+fn fen_castling(
+    contents: &[(Square, Material)],
+    field: &str,
+) -> Result<(&'static BackRank, Pair<CastlingRights>), &'static str> {
+    let mut rights = Pair::new(
+        CastlingRights::new(Color::White, false, false),
+        CastlingRights::new(Color::Black, false, false),
+    );
+    // the files of the king, the long castling rook and the short one
+    let mut king: Option<File> = None;
+    let mut rooks: [Option<File>; 2] = [None, None];
+    let settle = |slot: &mut Option<File>, file: File| match *slot {
+        Some(existing) if existing != file => Err("castling rights need a different back rank per side"),
+        _ => {
+            *slot = Some(file);
+            Ok(())
+        },
+    };
+    for c in field.chars().filter(|_| field != "-") {
+        let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+        let rank = Rank::back_rank(color);
+        let on_rank = |piece: Piece| contents.iter()
+            .filter(move |(square, material)| square.rank() == rank && *material == Material::new(color, piece))
+            .map(|(square, _)| square.file());
+        let king_file = on_rank(Piece::King).next().ok_or("castling rights without a king on the back rank")?;
+        let rook_file = match c.to_ascii_lowercase() {
+            // the outermost rook on either side of the king
+            'k' => on_rank(Piece::Rook).filter(|&file| file > king_file).max(),
+            'q' => on_rank(Piece::Rook).filter(|&file| file < king_file).min(),
+            c => File::try_from_char(c).filter(|&file| on_rank(Piece::Rook).any(|rook| rook == file)),
+        }.ok_or("castling rights without a rook")?;
+        if rook_file == king_file {
+            return Err("castling rights without a rook");
+        }
+        let short = rook_file > king_file;
+        settle(&mut king, king_file)?;
+        settle(&mut rooks[short as usize], rook_file)?;
+        let current = rights[color];
+        rights[color] = CastlingRights::new(color, current.oo() || short, current.ooo() || !short);
+    }
+    let fits = |backrank: &BackRank| {
+        king.is_none_or(|king| backrank.king() == king)
+            && rooks.iter().zip(backrank.rooks()).all(|(rook, file)| rook.is_none_or(|rook| rook == file))
+    };
+    let preference = backrank_preference(contents);
+    let backrank = all_backranks()
+        .filter(|backrank| fits(backrank))
+        .max_by_key(|backrank| preference(backrank))
+        .ok_or("no back rank matches the castling rights")?;
+    Ok((backrank, rights))
+}
+
+impl MoveState {
+    /// Formats `mv`, a legal move in this position, in Standard Algebraic
+    /// Notation (e.g. "Nbd7", "exd6", "e8=Q+", "O-O").
+    pub fn to_san(&self, mv: LegalMove) -> String {
+        let mut san = match mv {
+            LegalMove::ShortCastle => "O-O".to_string(),
+            LegalMove::LongCastle => "O-O-O".to_string(),
+            LegalMove::Standard(from, to)
+            | LegalMove::DoubleAdvance(from, to)
+            | LegalMove::EnPassant(from, to)
+            | LegalMove::Promoting(from, to, _) => self.san_body(mv, from, to),
+        };
+        let mut next = self.fork();
+        if next.apply_move(mv).is_ok() && next.is_check() {
+            san.push(if candidate_moves(&next).is_empty() { '#' } else { '+' });
+        }
+        san
+    }
+
+    /// Parses `san`, a move in Standard Algebraic Notation, into the legal
+    /// move it denotes. Check and annotation suffixes are ignored, and
+    /// castling may also be written with zeros. A king move onto one of
+    /// its own rooks (as in Chess960 interfaces) is read as castling with
+    /// that rook, while a plain king move to a castling destination stays
+    /// a plain king move.
+    pub fn from_san(&self, san: &str) -> Result<LegalMove, ParseError> {
+        let invalid = || ParseError::InvalidSan(san.to_string());
+        let text = san.trim_end_matches(['+', '#', '!', '?']);
+        let castle = match text {
+            "O-O" | "0-0" => Some(LegalMove::ShortCastle),
+            "O-O-O" | "0-0-0" => Some(LegalMove::LongCastle),
+            _ => None,
+        };
+        if let Some(castle) = castle {
+            return self.castle(castle).ok_or_else(invalid);
+        }
+
+        let mut chars: Vec<char> = text.chars().collect();
+        let piece = match chars.first().and_then(|&c| Material::from_char(c)) {
+            Some(material) if chars[0].is_ascii_uppercase() && material.piece() != Piece::Pawn => {
+                chars.remove(0);
+                material.piece()
+            },
+            _ => Piece::Pawn,
+        };
+        let mut promotion = None;
+        if piece == Piece::Pawn && chars.len() > 2 {
+            if let Some(promoted) = Promotion::try_from_char(chars[chars.len() - 1]) {
+                promotion = Some(promoted);
+                chars.pop();
+                if chars.last() == Some(&'=') {
+                    chars.pop();
+                }
+            }
+        }
+        if chars.len() < 2 {
+            return Err(invalid());
+        }
+        let to = Square::try_from_chars(chars[chars.len() - 2], chars[chars.len() - 1])
+            .ok_or_else(invalid)?;
+        let mut qualifier = &chars[..chars.len() - 2];
+        if qualifier.last() == Some(&'x') {
+            qualifier = &qualifier[..qualifier.len() - 1];
+        }
+        let (file, rank) = match *qualifier {
+            [] => (None, None),
+            [c] => match (File::try_from_char(c), Rank::try_from_char(c)) {
+                (Some(file), _) => (Some(file), None),
+                (_, Some(rank)) => (None, Some(rank)),
+                _ => return Err(invalid()),
+            },
+            [f, r] => match (File::try_from_char(f), Rank::try_from_char(r)) {
+                (Some(file), Some(rank)) => (Some(file), Some(rank)),
+                _ => return Err(invalid()),
+            },
+            _ => return Err(invalid()),
+        };
+
+        let candidates: Vec<LegalMove> = self.ours().iter()
+            .filter(|&from| self.contents(from).map(|m| m.piece()) == Some(piece))
+            .filter(|&from| file.is_none_or(|file| from.file() == file))
+            .filter(|&from| rank.is_none_or(|rank| from.rank() == rank))
+            .filter_map(|from| match piece {
+                // castling moves are handled below
+                Piece::King => self.standard_king_moves(from).get(to),
+                _ => self.legal_moves(from).get(to),
+            })
+            .collect();
+        let mv = match candidates[..] {
+            [mv] => mv,
+            [] if piece == Piece::King => {
+                let castling = AsRef::<Position>::as_ref(self).our_castling();
+                let castle = if to == castling.oo_rook_src() {
+                    LegalMove::ShortCastle
+                } else if to == castling.ooo_rook_src() {
+                    LegalMove::LongCastle
+                } else {
+                    return Err(invalid());
+                };
+                return self.castle(castle).ok_or_else(invalid);
+            },
+            [] => return Err(invalid()),
+            _ => return Err(ParseError::AmbiguousSan(san.to_string())),
+        };
+        let promotes = piece == Piece::Pawn && to.rank().is_promotion_rank(self.turn());
+        match (mv, promotion) {
+            (LegalMove::Standard(from, to), Some(promotion)) if promotes => {
+                Ok(LegalMove::Promoting(from, to, promotion))
+            },
+            (mv, None) if !promotes => Ok(mv),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Returns `castle` if it's currently legal.
+    fn castle(&self, castle: LegalMove) -> Option<LegalMove> {
+        let moves = self.all_castle_moves();
+        moves.destinations().iter()
+            .any(|square| moves.get(square) == Some(castle))
+            .then_some(castle)
+    }
+
+    fn san_body(&self, mv: LegalMove, from: Square, to: Square) -> String {
+        let mut san = String::new();
+        let piece = self.contents(from).map(|material| material.piece());
+        let capture = matches!(mv, LegalMove::EnPassant(..)) || self.contents(to).is_some();
+        if piece == Some(Piece::Pawn) {
+            if capture {
+                let _ = write!(san, "{}x", from.file());
+            }
+            let _ = write!(san, "{}", to);
+            if let LegalMove::Promoting(_, _, promotion) = mv {
+                let _ = write!(san, "={}", Piece::from(promotion).to_char());
+            }
+            return san;
+        }
+        if let Some(piece) = piece {
+            san.push(piece.to_char());
+            // other pieces of the same kind that could also move to `to`
+            let rivals: Vec<Square> = self.ours().iter()
+                .filter(|&square| square != from)
+                .filter(|&square| self.contents(square).map(|m| m.piece()) == Some(piece))
+                .filter(|&square| self.legal_moves(square).contains(to))
+                .collect();
+            if !rivals.is_empty() {
+                if rivals.iter().all(|square| square.file() != from.file()) {
+                    let _ = write!(san, "{}", from.file());
+                } else if rivals.iter().all(|square| square.rank() != from.rank()) {
+                    let _ = write!(san, "{}", from.rank());
+                } else {
+                    let _ = write!(san, "{}", from);
+                }
+            }
+        }
+        if capture {
+            san.push('x');
+        }
+        let _ = write!(san, "{}", to);
+        san
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use Square::*;
+
+    #[test]
+    fn test_fen() {
+        let mut state = MoveState::default();
+        assert_eq!(
+            AsRef::<Position>::as_ref(&state).to_fen(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        );
+        state.apply_move(LegalMove::DoubleAdvance(E2, E4)).unwrap();
+        assert_eq!(
+            AsRef::<Position>::as_ref(&state).to_fen(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+        );
+        state.apply_move(LegalMove::Standard(G8, F6)).unwrap();
+        state.apply_move(LegalMove::Standard(E1, E2)).unwrap();
+        assert_eq!(
+            AsRef::<Position>::as_ref(&state).to_fen(),
+            "rnbqkb1r/pppppppp/5n2/8/4P3/8/PPPPKPPP/RNBQ1BNR b kq - 2 2",
+        );
+    }
+
+    #[test]
+    fn test_from_fen() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b Kq e3 0 3",
+            "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 12 40",
+        ] {
+            assert_eq!(Position::from_fen(fen).unwrap().to_fen(), fen);
+        }
+        for index in 0..960usize {
+            let id = BackRankId::try_from(index).unwrap();
+            let fen = Position::starting(id).to_fen();
+            let pos = Position::from_fen(&fen).unwrap();
+            assert_eq!(pos.to_fen(), fen);
+            assert_eq!(Position::starting_id(&fen), Some(id));
+        }
+
+        // X-FEN rights pick the outermost rooks
+        let pos = Position::from_fen("1r2k1r1/8/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap();
+        assert_eq!(pos.to_fen(), "1r2k1r1/8/8/8/8/8/8/R3K2R w KQ - 0 1");
+        let pos = Position::from_fen("rk4r1/8/8/8/8/8/8/RK4R1 b GAg - 3").unwrap();
+        assert_eq!(pos.to_fen(), "rk4r1/8/8/8/8/8/8/RK4R1 b GAg - 3 1");
+        assert_eq!(pos.turn(), Color::Black);
+
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq",
+            "8/8/8/8/8/8/8/8 w - - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBN1 w KQkq - 0 1",
+            "4k3/8/8/8/8/8/8/4K2R w Kk - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 x - - 0 1",
+            "4k3/9/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/4R3/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w - e3 0 1",
+        ] {
+            assert!(matches!(Position::from_fen(fen), Err(ParseError::InvalidFen(..))), "{}", fen);
+        }
+        assert!(Position::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -").is_ok());
+    }
+
+    #[test]
+    fn test_san() {
+        use LegalMove::*;
+        let mut state = MoveState::default();
+        let mut play = |mv: LegalMove| {
+            let san = state.to_san(mv);
+            state.apply_move(mv).unwrap();
+            san
+        };
+        // 1. e4 d5 2. exd5 Nf6 3. Nc3 Nbd7 4. Bb5 e5 5. dxe6 Ke7 6. exd7
+        let sans: Vec<String> = [
+            DoubleAdvance(E2, E4), DoubleAdvance(D7, D5), Standard(E4, D5),
+            Standard(G8, F6), Standard(B1, C3), Standard(B8, D7),
+            Standard(F1, B5), DoubleAdvance(E7, E5), EnPassant(D5, E6),
+            Standard(E8, E7), Standard(E6, D7),
+        ].into_iter().map(&mut play).collect();
+        assert_eq!(sans, [
+            "e4", "d5", "exd5", "Nf6", "Nc3", "Nbd7", "Bb5", "e5", "dxe6", "Ke7", "exd7",
+        ]);
+        assert_eq!(play(Standard(F6, D7)), "Nxd7");
+        assert_eq!(play(Standard(G1, F3)), "Nf3");
+        assert_eq!(play(Standard(H7, H6)), "h6");
+        assert_eq!(play(ShortCastle), "O-O");
+    }
+
+    #[test]
+    fn test_san_promotion_and_check() {
+        let position = Position::default()
+            .set_contents(B7, Some(Material::WP))
+            .set_contents(B8, None)
+            .set_contents(C8, None)
+            .set_contents(D8, None);
+        let state = MoveState::new(position);
+        let mv = LegalMove::Promoting(B7, B8, Promotion::Queen);
+        assert_eq!(state.to_san(mv), "b8=Q+");
+        let mv = LegalMove::Promoting(B7, A8, Promotion::Knight);
+        assert_eq!(state.to_san(mv), "bxa8=N");
+    }
+
+    #[test]
+    fn test_from_san() {
+        let mut state = MoveState::default();
+        for san in ["d4", "a6", "Nf3", "a5"] {
+            state.apply_move(state.from_san(san).unwrap()).unwrap();
+        }
+        for mv in candidate_moves(&state) {
+            let mv = state.validate_move(mv).unwrap();
+            assert_eq!(state.from_san(&state.to_san(mv)), Ok(mv));
+        }
+        assert_eq!(state.from_san("Nd2"), Err(ParseError::AmbiguousSan("Nd2".to_string())));
+        assert_eq!(state.from_san("Nfd2!?"), Ok(LegalMove::Standard(F3, D2)));
+        assert_eq!(state.from_san("Nb1d2"), Ok(LegalMove::Standard(B1, D2)));
+        assert!(state.from_san("Qd3+").is_ok());
+        assert!(state.from_san("Qd5").is_err());
+        assert!(state.from_san("O-O").is_err());
+        assert!(state.from_san("d9").is_err());
+    }
+
+    #[test]
+    fn test_from_san_promotion() {
+        let position = Position::default()
+            .set_contents(B7, Some(Material::WP))
+            .set_contents(B8, None);
+        let state = MoveState::new(position);
+        assert_eq!(state.from_san("b8=N"), Ok(LegalMove::Promoting(B7, B8, Promotion::Knight)));
+        assert_eq!(state.from_san("bxa8Q"), Ok(LegalMove::Promoting(B7, A8, Promotion::Queen)));
+        assert!(state.from_san("b8").is_err());
+        assert!(state.from_san("a3=Q").is_err());
+    }
+
+    #[test]
+    fn test_chess960_notation() {
+        // the king on f1 castles short with the rook next to it on g1
+        let id = (0..960usize)
+            .map(|index| BackRankId::try_from(index).unwrap())
+            .find(|&id| {
+                let backrank = BackRank::lookup(id);
+                backrank.king() == File::FileF && backrank.rooks()[1] == File::FileG
+            })
+            .unwrap();
+        let mut state = MoveState::new(Position::new(id.into()));
+        let fen = AsRef::<Position>::as_ref(&state).to_fen();
+        let ooo = BackRank::lookup(id).rooks()[0].to_char();
+        let rights = format!("G{}g{}", ooo.to_ascii_uppercase(), ooo);
+        assert_eq!(fen.split(' ').nth(2), Some(rights.as_str()));
+
+        assert_eq!(state.from_san("O-O"), Ok(LegalMove::ShortCastle));
+        assert_eq!(state.from_san("Kxg1"), Ok(LegalMove::ShortCastle));
+        assert_eq!(state.from_san("Kg1"), Ok(LegalMove::ShortCastle));
+        assert_eq!(state.to_san(LegalMove::ShortCastle), "O-O");
+        state.apply_move(LegalMove::ShortCastle).unwrap();
+        let fen = AsRef::<Position>::as_ref(&state).to_fen();
+        assert_eq!(fen.split(' ').nth(2), Some(format!("g{}", ooo).as_str()));
+    }
+}
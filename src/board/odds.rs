@@ -0,0 +1,161 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Handicap ("odds") games, in which the stronger player starts without
+//! some of their material.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use strum_macros::EnumIter;
+
+use super::backrank::BackRank;
+use super::castling::CastlingRights;
+use super::material::{Color, Pair};
+use super::position::Position;
+use super::square::{File, Rank, Square};
+use super::variant::Variant;
+
+/// A named odds setup. The pieces given are taken from the queenside
+/// where there's a choice, as is customary, and follow the back rank in
+/// Chess960.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, EnumIter)]
+pub enum Odds {
+    /// The f-pawn.
+    Pawn,
+    /// The f-pawn, and the side receiving odds moves first.
+    PawnAndMove,
+    /// The queen's knight.
+    Knight,
+    /// Both knights.
+    TwoKnights,
+    /// The queen's rook.
+    Rook,
+    /// The queen's rook and knight.
+    RookAndKnight,
+    Queen,
+}
+
+impl Odds {
+    /// Returns true if the side receiving odds moves first, even when
+    /// they play Black.
+    pub fn receiver_moves_first(&self) -> bool {
+        *self == Self::PawnAndMove
+    }
+
+    /// Returns the squares emptied when `giver` gives these odds.
+    pub fn removed(&self, backrank: &BackRank, giver: Color) -> Vec<Square> {
+        let rank = Rank::back_rank(giver);
+        let [queens_knight, kings_knight] = backrank.knights();
+        let files = match self {
+            Self::Pawn | Self::PawnAndMove => {
+                let pawns = match giver {
+                    Color::White => Rank::Rank2,
+                    Color::Black => Rank::Rank7,
+                };
+                return vec![Square::new(File::FileF, pawns)];
+            }
+            Self::Knight => vec![queens_knight],
+            Self::TwoKnights => vec![queens_knight, kings_knight],
+            Self::Rook => vec![backrank.rooks()[0]],
+            Self::RookAndKnight => vec![backrank.rooks()[0], queens_knight],
+            Self::Queen => vec![backrank.queen()],
+        };
+        files.into_iter().map(|file| Square::new(file, rank)).collect()
+    }
+
+    /// Returns the starting position of `backrank` with the odds given by
+    /// `giver`. Castling rights are dropped for any rook given.
+    pub fn position(&self, backrank: &'static BackRank, giver: Color) -> Position {
+        let start = Position::new(backrank);
+        let removed = self.removed(backrank, giver);
+        let contents = (0..64).map(Square::from_index)
+            .filter(|square| !removed.contains(square))
+            .filter_map(|square| start.squares()[square].map(|material| (square, material)));
+        let rank = Rank::back_rank(giver);
+        let [queenside, kingside] = backrank.rooks();
+        let mut castling = Pair::<CastlingRights>::default();
+        if removed.contains(&Square::new(queenside, rank)) {
+            castling[giver].clear_ooo();
+        }
+        if removed.contains(&Square::new(kingside, rank)) {
+            castling[giver].clear_oo();
+        }
+        let turn = match self.receiver_moves_first() {
+            true => !giver,
+            false => Color::White,
+        };
+        Position::from_parts(contents, backrank, Variant::Standard, castling, None, turn, 0, 1)
+            .expect("odds positions are valid")
+    }
+}
+
+impl fmt::Display for Odds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Pawn => "pawn odds",
+            Self::PawnAndMove => "pawn and move",
+            Self::Knight => "knight odds",
+            Self::TwoKnights => "two knights odds",
+            Self::Rook => "rook odds",
+            Self::RookAndKnight => "rook and knight odds",
+            Self::Queen => "queen odds",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use strum::IntoEnumIterator;
+    use crate::*;
+    use Square::*;
+
+    #[test]
+    fn test_odds_positions() {
+        let standard = BackRankId::default().into();
+        let pos = Odds::Knight.position(standard, Color::White);
+        assert_eq!(pos.to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/R1BQKBNR w KQkq - 0 1");
+        let pos = Odds::Rook.position(standard, Color::Black);
+        assert_eq!(pos.to_fen(), "1nbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQk - 0 1");
+        let pos = Odds::PawnAndMove.position(standard, Color::Black);
+        assert_eq!(pos.to_fen(), "rnbqkbnr/ppppp1pp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        let pos = Odds::PawnAndMove.position(standard, Color::White);
+        assert_eq!(pos.to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPP1PP/RNBQKBNR b KQkq - 0 1");
+        assert_eq!(Odds::RookAndKnight.to_string(), "rook and knight odds");
+    }
+
+    #[test]
+    fn test_odds_games() {
+        for id in [BackRankId::default(), BackRankId::try_from(0usize).unwrap()] {
+            for odds in Odds::iter() {
+                for giver in [Color::White, Color::Black] {
+                    let pos = odds.position(id.into(), giver);
+                    assert!(pos.validate().is_ok(), "{odds} in {id}");
+                    for square in odds.removed(pos.backrank(), giver) {
+                        assert_eq!(pos.squares()[square], None);
+                    }
+                    assert!(!candidate_moves(&MoveState::new(pos)).is_empty());
+                }
+            }
+        }
+        // castling is still possible on the side that kept its rook
+        let mut board = EngineBoard::plays_both_from(Odds::Rook.position(BackRankId::default().into(), Color::White));
+        for mv in ["e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "g8f6"] {
+            board.submit_move(mv.parse().unwrap()).unwrap();
+        }
+        assert!(board.submit_move("e1g1".parse().unwrap()).is_ok());
+        assert_eq!(AsRef::<Position>::as_ref(&board).squares()[G1], Some(Material::WK));
+    }
+}
@@ -0,0 +1,454 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Reading and writing whole games in Portable Game Notation. Only the
+//! main line is kept: variations are skipped when reading. Comments,
+//! NAGs and the `[%clk]`, `[%eval]`, `[%csl]` and `[%cal]` commands are
+//! read into an `Annotation` per move.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+use super::annotation::{Annotation, Eval, Nag, PgnOptions};
+use super::backrank::BackRankId;
+use super::moves::{LegalMoves, Move, MoveState};
+use super::play::{check_replay_move, ReplayError, ReplayFailure};
+use super::position::{MoveId, Position};
+use super::square::ParseError;
+use super::Turn;
+use super::material::Color;
+
+/// Movetext lines are wrapped at this width when exporting.
+const LINE_WIDTH: usize = 80;
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PgnError {
+    #[error("Malformed tag pair: {0:?}")]
+    InvalidTag(String),
+    #[error("Unterminated {0} in movetext")]
+    Unterminated(&'static str),
+    /// The `FEN` tag isn't the starting position of any back rank.
+    #[error("Unsupported starting position: {0:?}")]
+    UnsupportedFen(String),
+    /// The `FEN` tag isn't a valid position.
+    #[error("Invalid starting position: {0}")]
+    InvalidFen(ParseError),
+    /// The move at `index` (counting from 0) couldn't be read.
+    #[error("Cannot read move {index} ({token:?}): {error}")]
+    InvalidMove { index: usize, token: String, error: ParseError },
+    /// A move can't be exported because it isn't legal.
+    #[error(transparent)]
+    Replay(#[from] ReplayError),
+}
+
+/// A game as recorded in PGN: its tag pairs, the moves of the main line
+/// and the annotation following each move.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct PgnGame {
+    /// Tag pairs in the order they're written.
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<Move>,
+    /// Annotations keyed by the position they follow, as returned by
+    /// `PlayerBoard::annotations`: the annotation of the n-th move is at
+    /// `MoveId::from_ply(n)`, and one at `MoveId::START` comes before the
+    /// first move.
+    pub annotations: BTreeMap<MoveId, Annotation>,
+}
+
+impl PgnGame {
+    /// Creates a record of `moves` played from the starting position for
+    /// `id`, adding the `Variant`, `SetUp` and `FEN` tags for Chess960.
+    pub fn new(id: BackRankId, moves: Vec<Move>) -> Self {
+        let mut game = Self { moves, ..Self::default() };
+        if id != BackRankId::default() {
+            game.set_tag("Variant", "Chess960");
+            game.set_tag("SetUp", "1");
+            game.set_tag("FEN", &Position::starting(id).to_fen());
+        }
+        game
+    }
+
+    pub fn with_annotations(mut self, annotations: BTreeMap<MoveId, Annotation>) -> Self {
+        self.annotations = annotations;
+        self
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Sets the tag `name`, replacing any existing value.
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, existing)) => *existing = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Returns the back rank the game starts from: the standard one
+    /// unless a `FEN` tag gives the starting position of another.
+    pub fn backrank_id(&self) -> Result<BackRankId, PgnError> {
+        let Some(fen) = self.tag("FEN") else { return Ok(BackRankId::default()) };
+        Position::starting_id(fen).ok_or_else(|| PgnError::UnsupportedFen(fen.to_string()))
+    }
+
+    /// Returns the position the game starts from: the standard starting
+    /// position unless a `FEN` tag gives another, e.g. for a puzzle or an
+    /// adjourned game.
+    pub fn start_position(&self) -> Result<Position, PgnError> {
+        let Some(fen) = self.tag("FEN") else { return Ok(Position::starting(BackRankId::default()).clone()) };
+        match Position::starting_id(fen) {
+            Some(id) => Ok(Position::starting(id).clone()),
+            None => Position::from_fen(fen).map_err(PgnError::InvalidFen),
+        }
+    }
+
+    /// Returns the annotation following the position at `at`, if any.
+    pub fn annotation(&self, at: &MoveId) -> Option<&Annotation> {
+        self.annotations.get(at)
+    }
+
+    /// Returns the mover's clock reading recorded after the move leading
+    /// to `at`.
+    pub fn clock(&self, at: &MoveId) -> Option<Duration> {
+        self.annotation(at).and_then(|annotation| annotation.clock)
+    }
+
+    /// Returns the evaluation recorded after the move leading to `at`.
+    pub fn eval(&self, at: &MoveId) -> Option<Eval> {
+        self.annotation(at).and_then(|annotation| annotation.eval)
+    }
+
+    /// Reads every game in `text`, e.g. a PGN database or archive.
+    pub fn parse_all(text: &str) -> Result<Vec<Self>, PgnError> {
+        let mut games = Vec::new();
+        let mut rest = text;
+        while !rest.trim().is_empty() {
+            let (game, remaining) = parse_game(rest)?;
+            games.push(game);
+            rest = remaining;
+        }
+        Ok(games)
+    }
+
+    /// Formats the game as PGN, with the annotations' clock readings and
+    /// evaluations included as `options` say. The movetext ends with the
+    /// `Result` tag, or `*` if there isn't one. Fails if a move isn't
+    /// legal.
+    pub fn to_pgn(&self, options: &PgnOptions) -> Result<String, PgnError> {
+        let mut pgn = String::new();
+        for (name, value) in &self.tags {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, value));
+        }
+        if !self.tags.is_empty() {
+            pgn.push('\n');
+        }
+
+        let mut tokens = Vec::new();
+        let annotate = |tokens: &mut Vec<String>, at: MoveId| {
+            let text = self.annotation(&at)
+                .map(|annotation| annotation.to_pgn_with(options))
+                .unwrap_or_default();
+            let annotated = !text.is_empty();
+            if annotated {
+                tokens.push(text);
+            }
+            annotated
+        };
+        let mut state = MoveState::new(self.start_position()?);
+        // Black's move needs its number if something comes between it
+        // and White's move
+        let mut numbered = !annotate(&mut tokens, MoveId::START);
+        for (index, &mv) in self.moves.iter().enumerate() {
+            let illegal = || ReplayError { index, mv, reason: ReplayFailure::Illegal };
+            check_replay_move(&state, index, mv)?;
+            let legal = state.validate_move(mv).map_err(|_| illegal())?;
+            let pos: &Position = state.as_ref();
+            match pos.turn() {
+                Color::White => tokens.push(format!("{}.", pos.fullmove_number())),
+                Color::Black if !numbered => tokens.push(format!("{}...", pos.fullmove_number())),
+                Color::Black => {},
+            }
+            tokens.push(state.to_san(legal));
+            state.apply_move(legal).map_err(|_| illegal())?;
+            let at = MoveId::from_ply(index + 1).unwrap_or(MoveId::MAX);
+            numbered = !annotate(&mut tokens, at);
+        }
+        let result = self.tag("Result").filter(|result| RESULTS.contains(result));
+        tokens.push(result.unwrap_or("*").to_string());
+
+        let mut line_len = 0;
+        for token in tokens {
+            if line_len > 0 && line_len + 1 + token.len() > LINE_WIDTH {
+                pgn.push('\n');
+                line_len = 0;
+            } else if line_len > 0 {
+                pgn.push(' ');
+                line_len += 1;
+            }
+            line_len += token.len();
+            pgn.push_str(&token);
+        }
+        pgn.push('\n');
+        Ok(pgn)
+    }
+}
+
+/// Reads the first game in a PGN text.
+impl FromStr for PgnGame {
+    type Err = PgnError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_game(s).map(|(game, _)| game)
+    }
+}
+
+/// Reads one game from the start of `text`, returning it with the text
+/// that follows it.
+fn parse_game(text: &str) -> Result<(PgnGame, &str), PgnError> {
+    let mut game = PgnGame::default();
+    let mut rest = text.trim_start();
+    while rest.starts_with('[') {
+        let (tag, remaining) = parse_tag(rest)?;
+        game.tags.push(tag);
+        rest = remaining.trim_start();
+    }
+
+    let mut state = MoveState::new(game.start_position()?);
+    let comment = |game: &mut PgnGame, text: &str| {
+        let at = MoveId::from_ply(game.moves.len()).unwrap_or(MoveId::MAX);
+        game.annotations.entry(at).or_default().read_pgn_comment(text);
+    };
+    loop {
+        rest = rest.trim_start();
+        let Some(next) = rest.chars().next() else { break };
+        match next {
+            // the next game's tags
+            '[' => break,
+            '{' => {
+                let end = rest.find('}').ok_or(PgnError::Unterminated("comment"))?;
+                comment(&mut game, &rest[1..end]);
+                rest = &rest[end + 1..];
+            },
+            ';' => {
+                let end = rest.find('\n').unwrap_or(rest.len());
+                comment(&mut game, &rest[1..end]);
+                rest = &rest[end..];
+            },
+            '(' => rest = skip_variation(rest)?,
+            ')' => rest = &rest[1..],
+            '$' => {
+                let end = rest[1..].find(|c: char| !c.is_ascii_digit()).map_or(rest.len(), |end| end + 1);
+                // only the move assessments are supported
+                if let Some(nag) = rest[1..end].parse().ok().and_then(Nag::from_code) {
+                    annotate_nag(&mut game, nag);
+                }
+                rest = &rest[end..];
+            },
+            _ => {
+                let end = rest.find(|c: char| c.is_whitespace() || "{}();$[".contains(c))
+                    .unwrap_or(rest.len());
+                let token = &rest[..end];
+                rest = &rest[end..];
+                if RESULTS.contains(&token) {
+                    if game.tag("Result").is_none() {
+                        game.set_tag("Result", token);
+                    }
+                    break;
+                }
+                read_move(&mut game, &mut state, token)?;
+            },
+        }
+    }
+    Ok((game, rest))
+}
+
+/// Reads a `[Name "Value"]` tag pair from the start of `text`.
+fn parse_tag(text: &str) -> Result<((String, String), &str), PgnError> {
+    let invalid = || PgnError::InvalidTag(text.lines().next().unwrap_or_default().to_string());
+    let body = text[1..].trim_start();
+    let name_end = body.find(|c: char| c.is_whitespace() || c == '"').ok_or_else(invalid)?;
+    let name = &body[..name_end];
+    let quoted = body[name_end..].trim_start().strip_prefix('"').ok_or_else(invalid)?;
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    let close = loop {
+        match chars.next().ok_or_else(invalid)? {
+            (_, '\\') => value.extend(chars.next().map(|(_, c)| c)),
+            (index, '"') => break index,
+            (_, c) => value.push(c),
+        }
+    };
+    let rest = quoted[close + 1..].trim_start().strip_prefix(']').ok_or_else(invalid)?;
+    if name.is_empty() {
+        return Err(invalid());
+    }
+    Ok(((name.to_string(), value), rest))
+}
+
+/// Skips the variation (and any nested in it) at the start of `text`.
+fn skip_variation(text: &str) -> Result<&str, PgnError> {
+    let mut depth = 0;
+    let mut in_comment = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            '{' => in_comment = true,
+            '}' => in_comment = false,
+            '(' if !in_comment => depth += 1,
+            ')' if !in_comment => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(&text[index + 1..]);
+                }
+            },
+            _ => {},
+        }
+    }
+    Err(PgnError::Unterminated("variation"))
+}
+
+/// Reads a movetext token other than a comment, NAG or result: a move,
+/// perhaps preceded by its number and followed by a glyph.
+fn read_move(game: &mut PgnGame, state: &mut MoveState, token: &str) -> Result<(), PgnError> {
+    // move numbers may be written apart from the move ("1.", "1...")
+    // or joined to it ("1.e4")
+    let san = match token.rfind('.') {
+        Some(dot) if token.starts_with(|c: char| c.is_ascii_digit()) => &token[dot + 1..],
+        _ => token,
+    };
+    if san.is_empty() {
+        return Ok(());
+    }
+    let index = game.moves.len();
+    let invalid = |error| PgnError::InvalidMove { index, token: token.to_string(), error };
+    let legal = state.from_san(san).map_err(invalid)?;
+    let mv = legal.to_move(state.as_ref());
+    state.apply_move(legal).map_err(|error| invalid(ParseError::InvalidSan(error.to_string())))?;
+    game.moves.push(mv);
+    let glyph = san.trim_start_matches(|c: char| c != '!' && c != '?');
+    if let Ok(nag) = glyph.parse() {
+        annotate_nag(game, nag);
+    }
+    Ok(())
+}
+
+/// Adds `nag` to the annotation of the last move read.
+fn annotate_nag(game: &mut PgnGame, nag: Nag) {
+    let at = MoveId::from_ply(game.moves.len()).unwrap_or(MoveId::MAX);
+    game.annotations.entry(at).or_default().add_nag(nag);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    fn mv(uci: &str) -> Move {
+        uci.parse().unwrap()
+    }
+
+    fn ply(ply: usize) -> MoveId {
+        MoveId::from_ply(ply).unwrap()
+    }
+
+    #[test]
+    fn test_export_with_clocks_and_evals() {
+        let moves = ["e2e4", "e7e5", "g1f3", "b8c6"].map(mv).to_vec();
+        let mut annotations = BTreeMap::new();
+        for (index, secs) in [300, 298, 295, 290].into_iter().enumerate() {
+            let annotation = Annotation { clock: Some(Duration::from_secs(secs)), ..Annotation::default() };
+            annotations.insert(ply(index + 1), annotation);
+        }
+        annotations.get_mut(&ply(3)).unwrap().eval = Some(Eval::Centipawns(30));
+        let mut game = PgnGame::new(BackRankId::default(), moves).with_annotations(annotations);
+        game.set_tag("White", "Alice \"A\"");
+        game.set_tag("Result", "*");
+
+        let pgn = game.to_pgn(&PgnOptions::default()).unwrap();
+        assert_eq!(pgn, concat!(
+            "[White \"Alice \\\"A\\\"\"]\n[Result \"*\"]\n\n",
+            "1. e4 {[%clk 0:05:00]} 1... e5 {[%clk 0:04:58]} 2. Nf3\n",
+            "{[%eval 0.30][%clk 0:04:55]} 2... Nc6 {[%clk 0:04:50]} *\n",
+        ));
+        assert_eq!(pgn.parse::<PgnGame>().unwrap(), game);
+
+        let options = PgnOptions { clocks: false, evals: false };
+        let pgn = game.to_pgn(&options).unwrap();
+        assert!(pgn.ends_with("\n\n1. e4 e5 2. Nf3 Nc6 *\n"));
+    }
+
+    #[test]
+    fn test_parse_annotated_game() {
+        let pgn = r#"
+            [Event "Casual"]
+            [Result "1-0"]
+
+            {Start} 1.e4 e5 2. Bc4 $5 (2. Nf3 Nc6 {a (comment)} (2... d6)) 2... Nc6
+            3. Qh5 Nf6?? { [%eval #1] [%clk 0:01:02.5] Oops } 4. Qxf7# 1-0
+            [Event "Next"]
+        "#;
+        let games = PgnGame::parse_all(pgn).unwrap();
+        assert_eq!(games.len(), 2);
+        let game = &games[0];
+        assert_eq!(game.tag("Event"), Some("Casual"));
+        assert_eq!(game.moves.len(), 7);
+        assert_eq!(game.moves[6], mv("h5f7"));
+        assert_eq!(game.annotation(&MoveId::START).unwrap().comment, "Start");
+        assert_eq!(game.annotation(&ply(3)).unwrap().nags, vec![Nag::Interesting]);
+        assert_eq!(game.annotation(&ply(6)).unwrap().nags, vec![Nag::Blunder]);
+        assert_eq!(game.eval(&ply(6)), Some(Eval::Mate(1)));
+        assert_eq!(game.clock(&ply(6)), Some(Duration::from_millis(62_500)));
+        assert_eq!(game.annotation(&ply(6)).unwrap().comment, "Oops");
+        assert_eq!(games[1].tag("Event"), Some("Next"));
+        assert!(games[1].moves.is_empty());
+
+        let mut board = PlayerBoard::replay(Color::White, Some(game.backrank_id().unwrap()), &game.moves).unwrap();
+        board.set_annotations(game.annotations.clone()).unwrap();
+        assert_eq!(board.annotation(&ply(6)).unwrap().clock, Some(Duration::from_millis(62_500)));
+        assert_eq!(board.moves(), game.moves);
+    }
+
+    #[test]
+    fn test_chess960_start() {
+        let id = BackRankId::try_from(0usize).unwrap();
+        let moves = ["b2b3", "b7b6"].map(mv).to_vec();
+        let game = PgnGame::new(id, moves.clone());
+        assert_eq!(game.tag("Variant"), Some("Chess960"));
+        let pgn = game.to_pgn(&PgnOptions::default()).unwrap();
+        let read: PgnGame = pgn.parse().unwrap();
+        assert_eq!(read.backrank_id(), Ok(id));
+        assert_eq!(read.moves, moves);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = "1. e4 e5 2. Ke3".parse::<PgnGame>().unwrap_err();
+        assert!(matches!(error, PgnError::InvalidMove { index: 2, ref token, .. } if token == "Ke3"));
+        assert_eq!("1. e4 {never closed".parse::<PgnGame>(), Err(PgnError::Unterminated("comment")));
+        assert_eq!("1. e4 (1. d4".parse::<PgnGame>(), Err(PgnError::Unterminated("variation")));
+        assert!(matches!("[Event Casual]".parse::<PgnGame>(), Err(PgnError::InvalidTag(_))));
+        let fen = "8/8/8/8/8/8/8/8 w - - 0 1";
+        let error = format!("[FEN \"{}\"]\n\n*", fen).parse::<PgnGame>().unwrap_err();
+        assert!(matches!(error, PgnError::InvalidFen(ParseError::InvalidFen(..))));
+
+        let game = PgnGame::new(BackRankId::default(), vec![mv("e2e5")]);
+        assert!(matches!(game.to_pgn(&PgnOptions::default()), Err(PgnError::Replay(_))));
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Piece placement for renderers and network snapshots. Squares are
+//! always listed in drawing order: `A8` to `H8`, then down rank by rank
+//! to `H1` (i.e. by `Square::to_index`), whichever side is shown at the
+//! bottom.
+
+use super::material::{Color, Material, Piece};
+use super::position::Position;
+use super::square::{ParseError, Square};
+
+impl Position {
+    /// Returns the occupied squares and their contents in drawing order.
+    pub fn placements(&self) -> impl Iterator<Item = (Square, Material)> + '_ {
+        (0..64).map(Square::from_index)
+            .filter_map(|square| self.squares()[square].map(|material| (square, material)))
+    }
+
+    /// Returns the contents of every square, indexed by
+    /// `Square::to_index`.
+    pub fn placement_array(&self) -> [Option<Material>; 64] {
+        std::array::from_fn(|index| self.squares()[Square::from_index(index)])
+    }
+
+    /// Packs the placement into 32 bytes (see `PackedPlacement`).
+    pub fn packed_placement(&self) -> PackedPlacement {
+        PackedPlacement::pack(&self.placement_array())
+    }
+}
+
+/// A piece placement packed into one nibble per square, two squares per
+/// byte with the lower square index in the high nibble. A nibble is 0 for
+/// an empty square, otherwise the piece index plus one (`Pawn` = 1 ..
+/// `King` = 6) with bit 3 set for black. Only the placement is kept:
+/// turn, castling rights, etc. need sending separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PackedPlacement([u8; 32]);
+
+impl PackedPlacement {
+    pub fn pack(squares: &[Option<Material>; 64]) -> Self {
+        let mut bytes = [0; 32];
+        for (index, material) in squares.iter().enumerate() {
+            let shift = if index.is_multiple_of(2) { 4 } else { 0 };
+            bytes[index / 2] |= Self::to_nibble(*material) << shift;
+        }
+        Self(bytes)
+    }
+
+    /// Returns the contents of every square, indexed by
+    /// `Square::to_index`, failing on a nibble that names no material.
+    pub fn unpack(&self) -> Result<[Option<Material>; 64], ParseError> {
+        let mut squares = [None; 64];
+        for (index, square) in squares.iter_mut().enumerate() {
+            let byte = self.0[index / 2];
+            let nibble = if index.is_multiple_of(2) { byte >> 4 } else { byte & 0x0f };
+            *square = Self::from_nibble(nibble)?;
+        }
+        Ok(squares)
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0
+    }
+
+    fn to_nibble(material: Option<Material>) -> u8 {
+        let Some(material) = material else {
+            return 0;
+        };
+        let color = match material.color() {
+            Color::White => 0,
+            Color::Black => 8,
+        };
+        color | (material.piece().to_index() as u8 + 1)
+    }
+
+    fn from_nibble(nibble: u8) -> Result<Option<Material>, ParseError> {
+        let color = match nibble & 8 {
+            0 => Color::White,
+            _ => Color::Black,
+        };
+        match nibble & 7 {
+            0 if nibble == 0 => Ok(None),
+            piece @ 1..=6 => Ok(Some(Material::new(color, Piece::from_index(piece as usize - 1)))),
+            _ => Err(ParseError::InvalidMaterial(format!("nibble {nibble:#x}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use Square::*;
+
+    #[test]
+    fn test_placements() {
+        let pos = Position::from_fen("4k3/8/8/8/8/8/4P3/4K2R w K - 0 1").unwrap();
+        let placements: Vec<_> = pos.placements().collect();
+        assert_eq!(placements, vec![(E8, Material::BK), (E2, Material::WP), (E1, Material::WK), (H1, Material::WR)]);
+        let array = pos.placement_array();
+        assert_eq!(array[E8.to_index()], Some(Material::BK));
+        assert_eq!(array.iter().flatten().count(), 4);
+    }
+
+    #[test]
+    fn test_packed_placement() {
+        let pos = Position::new(BackRankId::default().into());
+        let packed = pos.packed_placement();
+        let bytes = packed.to_bytes();
+        // a8 is a black rook, b8 a black knight
+        assert_eq!(bytes[0], 0xca);
+        assert_eq!(PackedPlacement::from_bytes(bytes).unpack().unwrap(), pos.placement_array());
+
+        let mut bad = bytes;
+        bad[16] = 0x70;
+        assert!(PackedPlacement::from_bytes(bad).unpack().is_err());
+        bad[16] = 0x08;
+        assert!(PackedPlacement::from_bytes(bad).unpack().is_err());
+    }
+}
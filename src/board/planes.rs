@@ -0,0 +1,250 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Encodings of positions and moves for training neural networks, with
+//! the `ml` feature.
+//!
+//! A position is a stack of 8×8 bitplanes, each square in
+//! `Square::to_index` order (a8 first): one plane per color and piece,
+//! then planes for the side to move, the castling rights, the en passant
+//! square and the fifty-move count. The planes are from White's point of
+//! view; `Position::swap_colors` gives the side to move's.
+//!
+//! A move is an index into a policy of `POLICY_SIZE` entries as in
+//! AlphaZero: 73 planes over the square the move starts from. The first
+//! 56 are queen-like moves, by direction and distance; then 8 knight
+//! moves; then underpromotions to a knight, bishop or rook, capturing
+//! towards the a-file, advancing or capturing towards the h-file.
+
+use ndarray::Array3;
+
+use super::castling::Castling;
+use super::material::{Color, Piece};
+use super::moves::{Move, Promotion};
+use super::position::{Pos, Position, FIFTY_MOVE_PLIES};
+use super::square::{Rank, Square};
+use super::Turn;
+
+/// The planes of each piece, White's then Black's, in `Piece::to_index`
+/// order.
+const PIECE_PLANES: usize = 12;
+
+const TURN_PLANE: usize = PIECE_PLANES;
+/// White's O-O and O-O-O, then Black's.
+const CASTLING_PLANES: usize = TURN_PLANE + 1;
+const EN_PASSANT_PLANE: usize = CASTLING_PLANES + 4;
+const FIFTY_MOVE_PLANE: usize = EN_PASSANT_PLANE + 1;
+
+/// The number of planes `Position::to_planes` encodes.
+pub const PLANES: usize = FIFTY_MOVE_PLANE + 1;
+
+/// File and rank steps (towards the h-file and the eighth rank) of the
+/// queen-like move directions, clockwise from north.
+const DIRECTIONS: [(isize, isize); 8] = [(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)];
+
+const KNIGHT_STEPS: [(isize, isize); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+
+const QUEEN_MOVE_PLANES: usize = 56;
+const KNIGHT_MOVE_PLANES: usize = QUEEN_MOVE_PLANES + 8;
+const UNDERPROMOTIONS: [Promotion; 3] = [Promotion::Knight, Promotion::Bishop, Promotion::Rook];
+const MOVE_PLANES: usize = KNIGHT_MOVE_PLANES + 9;
+
+/// The number of entries of a policy (see `Move::policy_index`).
+pub const POLICY_SIZE: usize = MOVE_PLANES * 64;
+
+impl Position {
+    /// Returns the position's `PLANES` bitplanes, plane by plane: a piece
+    /// plane is 1.0 where a piece of its kind stands, the side to move
+    /// plane is all 1.0 if White is to move, a castling plane is all 1.0
+    /// while the right is held and the en passant plane marks the target
+    /// square. The fifty-move plane is filled with the plies since the
+    /// last capture or pawn move, over 100.
+    pub fn to_planes(&self) -> Vec<f32> {
+        let mut planes = vec![0.0; PLANES * 64];
+        let mut fill = |plane: usize, value: f32| planes[plane * 64..(plane + 1) * 64].fill(value);
+        if self.turn() == Color::White {
+            fill(TURN_PLANE, 1.0);
+        }
+        let ours = self.our_castling();
+        let theirs = self.their_castling();
+        let (white, black) = match self.turn() {
+            Color::White => (&ours, &theirs),
+            Color::Black => (&theirs, &ours),
+        };
+        let rights = [white.oo(), white.ooo(), black.oo(), black.ooo()];
+        for (index, _) in rights.iter().enumerate().filter(|(_, &right)| right) {
+            fill(CASTLING_PLANES + index, 1.0);
+        }
+        fill(FIFTY_MOVE_PLANE, self.moves_since_progress() as f32 / FIFTY_MOVE_PLIES as f32);
+        for (square, material) in self.placements() {
+            let plane = material.color().to_index() * 6 + material.piece().to_index();
+            planes[plane * 64 + square.to_index()] = 1.0;
+        }
+        if let Some(square) = self.en_passant() {
+            planes[EN_PASSANT_PLANE * 64 + square.to_index()] = 1.0;
+        }
+        planes
+    }
+
+    /// Returns `to_planes` shaped as planes × ranks × files, ranks from
+    /// the eighth.
+    pub fn to_array(&self) -> Array3<f32> {
+        Array3::from_shape_vec((PLANES, 8, 8), self.to_planes()).expect("PLANES 8×8 planes")
+    }
+
+    /// Returns the move at `index` of a policy, if it stays on the board. A
+    /// pawn reaching its last rank with a queen-like move promotes to a
+    /// queen. The move may still be illegal.
+    pub fn policy_move(&self, index: usize) -> Option<Move> {
+        if index >= POLICY_SIZE {
+            return None;
+        }
+        let (plane, from) = (index / 64, Square::from_index(index % 64));
+        let (step, distance, promotion) = match plane {
+            plane if plane < QUEEN_MOVE_PLANES => (DIRECTIONS[plane / 7], plane % 7 + 1, None),
+            plane if plane < KNIGHT_MOVE_PLANES => (KNIGHT_STEPS[plane - QUEEN_MOVE_PLANES], 1, None),
+            plane => {
+                let plane = plane - KNIGHT_MOVE_PLANES;
+                let forward = match from.rank() {
+                    Rank::Rank7 => 1,
+                    Rank::Rank2 => -1,
+                    _ => return None,
+                };
+                ((plane as isize % 3 - 1, forward), 1, Some(UNDERPROMOTIONS[plane / 3]))
+            },
+        };
+        let to = step_from(from, step.0 * distance as isize, step.1 * distance as isize)?;
+        let promotion = promotion.or_else(|| {
+            let pawn = (*self.contents(from)).filter(|material| material.piece() == Piece::Pawn)?;
+            to.rank().is_promotion_rank(pawn.color()).then_some(Promotion::Queen)
+        });
+        Some(Move::new(from, to, promotion))
+    }
+}
+
+impl Move {
+    /// Returns the move's index in a policy of `POLICY_SIZE` entries, or
+    /// `None` if no piece moves that way (or it promotes to a king, as in
+    /// antichess). A promotion to a queen has the index of the same move
+    /// without a promotion.
+    pub fn policy_index(&self) -> Option<usize> {
+        let files = self.to.file_index() as isize - self.from.file_index() as isize;
+        // rank indices count from the eighth rank
+        let ranks = self.from.rank_index() as isize - self.to.rank_index() as isize;
+        let plane = match self.promotion {
+            Some(Promotion::Queen) | None => match (files.abs(), ranks.abs()) {
+                (0, 0) => return None,
+                (1, 2) | (2, 1) => {
+                    let knight = KNIGHT_STEPS.iter().position(|&step| step == (files, ranks))?;
+                    QUEEN_MOVE_PLANES + knight
+                },
+                (x, y) if x == 0 || y == 0 || x == y => {
+                    let distance = x.max(y);
+                    let direction = DIRECTIONS.iter().position(|&step| step == (files / distance, ranks / distance))?;
+                    direction * 7 + distance as usize - 1
+                },
+                _ => return None,
+            },
+            Some(Promotion::King) => return None,
+            Some(promotion) => {
+                if ranks.abs() != 1 || files.abs() > 1 {
+                    return None;
+                }
+                let piece = UNDERPROMOTIONS.iter().position(|&p| p == promotion)?;
+                KNIGHT_MOVE_PLANES + piece * 3 + (files + 1) as usize
+            },
+        };
+        Some(plane * 64 + self.from.to_index())
+    }
+}
+
+/// Returns the square `files` towards the h-file and `ranks` towards the
+/// eighth rank from `square`, if it's on the board.
+fn step_from(square: Square, files: isize, ranks: isize) -> Option<Square> {
+    let file = square.file_index() as isize + files;
+    let rank = square.rank_index() as isize - ranks;
+    ((0..8).contains(&file) && (0..8).contains(&rank)).then(|| Square::from_index(rank as usize * 8 + file as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use Square::*;
+
+    #[test]
+    fn test_planes() {
+        let mut board = EngineBoard::standard();
+        board.submit_move("e2e4".parse().unwrap()).unwrap();
+        let pos: &Position = board.as_ref();
+        let planes = pos.to_planes();
+        assert_eq!(planes.len(), PLANES * 64);
+        let plane = |index: usize| &planes[index * 64..(index + 1) * 64];
+        // White's pawns, then Black's king
+        assert_eq!(plane(Piece::Pawn.to_index())[E4.to_index()], 1.0);
+        assert_eq!(plane(Piece::Pawn.to_index())[E2.to_index()], 0.0);
+        assert_eq!(plane(6 + Piece::King.to_index()).iter().sum::<f32>(), 1.0);
+        assert_eq!(plane(6 + Piece::King.to_index())[E8.to_index()], 1.0);
+        // Black to move, every castling right, e3 en passant
+        assert!(plane(12).iter().all(|&value| value == 0.0));
+        assert!((13..17).all(|index| plane(index).iter().all(|&value| value == 1.0)));
+        assert_eq!(plane(17).iter().sum::<f32>(), 1.0);
+        assert_eq!(plane(17)[E3.to_index()], 1.0);
+        assert!(plane(18).iter().all(|&value| value == 0.0));
+
+        let array = pos.to_array();
+        assert_eq!(array.shape(), [PLANES, 8, 8]);
+        // e4 is the fifth rank from the eighth, on the fifth file
+        assert_eq!(array[[Piece::Pawn.to_index(), 4, 4]], 1.0);
+    }
+
+    #[test]
+    fn test_policy_index() {
+        let mv = |uci: &str| uci.parse::<Move>().unwrap();
+        let pos = Position::from_fen("r3k3/1P6/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        let moves = ["e1g1", "h1h8", "b7a8q", "b7a8n", "b7b8r", "b7b8b", "e1f2", "b7b8q"];
+        let mut indices = Vec::new();
+        for uci in moves {
+            let index = mv(uci).policy_index().unwrap();
+            assert!(index < POLICY_SIZE);
+            assert_eq!(pos.policy_move(index), Some(mv(uci)), "{uci}");
+            indices.push(index);
+        }
+        indices.sort();
+        indices.dedup();
+        assert_eq!(indices.len(), moves.len());
+        // a queen promotion shares its index with the plain move
+        assert_eq!(mv("b7a8q").policy_index(), Move::new(B7, A8, None).policy_index());
+        assert_eq!(mv("g1f3").policy_index(), Some(64 * (56 + 7) + G1.to_index()));
+        assert_eq!(mv("e2e4").policy_index(), Some(64 + E2.to_index()));
+        assert_eq!(Move::new(E1, E1, None).policy_index(), None);
+        assert_eq!(mv("a1c2").policy_index().and_then(|index| pos.policy_move(index)), Some(mv("a1c2")));
+        assert_eq!(mv("a1d2").policy_index(), None);
+        assert_eq!(pos.policy_move(POLICY_SIZE), None);
+
+        // black underpromotions mirror White's
+        let pos = Position::from_fen("4k3/8/8/8/8/8/6p1/4K2R b - - 0 1").unwrap();
+        for uci in ["g2h1n", "g2g1b", "g2f1r", "g2h1q"] {
+            assert_eq!(pos.policy_move(mv(uci).policy_index().unwrap()), Some(mv(uci)));
+        }
+
+        // every legal move has its own index
+        let state = MoveState::new(Position::default());
+        let mut indices: Vec<usize> = candidate_moves(&state).iter()
+            .map(|mv| mv.policy_index().unwrap())
+            .collect();
+        indices.sort();
+        indices.dedup();
+        assert_eq!(indices.len(), 20);
+    }
+}
@@ -13,38 +13,280 @@
 //    limitations under the License.
 
 
-use anyhow::Result;
+use crate::error::Result;
 use std::collections::HashMap;
+use std::fmt;
 use std::ops::Index;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
+use super::annotation::Annotation;
 use super::backrank::{BackRank, BackRanks, BackRankId};
+use super::dedup::GameFingerprint;
 use super::square::{Square, Mask};
-use super::material::{Material, Color};
-use super::moves::{LegalMove, LegalMoves, PreMoves, Move, MoveState};
-use super::position::{MoveId, Pos, Position, PositionKey, MatingMaterial};
-use super::review::{Review, ReviewMut, ReviewState};
+use super::material::{Material, Color, Pair, Piece};
+use super::moves::{LegalMove, LegalMoves, PreMoves, Move, MoveError, MoveState, Promotion};
+use super::insufficient::InsufficientMaterial;
+use super::position::{MoveId, Pos, Position, PositionKey, FIFTY_MOVE_PLIES};
+use super::review::{HistoryRetention, Review, ReviewMut, ReviewState, ReviewError};
+use super::stats::GameStats;
+use super::strategy::candidate_moves;
 use super::Turn;
+use crate::error::ChessError;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum BoardResult {
     CheckMate(Color),
     StaleMate,
     Insufficient,
     Repetition,
     FiftyMoves,
+    /// Ended by a `ResultPolicy`, with the winner or `None` for a draw.
+    Adjudicated(Option<Color>),
+    /// Won by reaching the goal of a variant, e.g. losing every piece in
+    /// Antichess (see `Rules::no_moves_result`).
+    VariantWin(Color),
+}
+
+/// The result of a game as a player board knows it: reached on the board,
+/// or announced by the server for a reason the board can't see, e.g. a
+/// resignation or timeout (see `PlayerBoard::set_result`). A `GameResult`
+/// converts into it.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PlayResult {
+    Board(BoardResult),
+    /// Announced by the server, with the winner or `None` for a draw.
+    Announced(Option<Color>),
+}
+
+impl From<BoardResult> for PlayResult {
+    fn from(value: BoardResult) -> Self {
+        Self::Board(value)
+    }
+}
+
+/// A draw the side to move may claim under FIDE Law 9.2 and 9.3. A claim
+/// with a move is made before playing it: the move is played, and the
+/// position it leads to completes the threefold repetition or the fifty
+/// moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DrawClaim {
+    Repetition(Option<Move>),
+    FiftyMoves(Option<Move>),
+}
+
+impl DrawClaim {
+    /// Returns the move played with the claim, if any.
+    pub fn mv(&self) -> Option<Move> {
+        match self {
+            Self::Repetition(mv) | Self::FiftyMoves(mv) => *mv,
+        }
+    }
+
+    pub fn result(&self) -> BoardResult {
+        match self {
+            Self::Repetition(_) => BoardResult::Repetition,
+            Self::FiftyMoves(_) => BoardResult::FiftyMoves,
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrawClaimError {
+    #[error("The draw cannot be claimed: {0:?}")]
+    NotClaimable(DrawClaim),
+}
+
+/// Decides custom results for an `EngineBoard`, e.g. for training modes
+/// or score-based adjudication. The policy is consulted after every move,
+/// before the built-in checks; returning a result ends the game, while
+/// `None` leaves it to the standard rules.
+pub trait ResultPolicy: Send + Sync {
+    fn board_result(&self, state: &MoveState, history: &[LegalMove]) -> Option<BoardResult>;
+}
+
+impl<F> ResultPolicy for F
+where
+    F: Fn(&MoveState, &[LegalMove]) -> Option<BoardResult> + Send + Sync,
+{
+    fn board_result(&self, state: &MoveState, history: &[LegalMove]) -> Option<BoardResult> {
+        self(state, history)
+    }
+}
+
+impl fmt::Debug for dyn ResultPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ResultPolicy")
+    }
+}
+
+/// How a `PlayerBoard` treats a pawn move onto the last rank submitted
+/// without a promotion piece.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PromotionPolicy {
+    /// Fail with `ChessError::NeedsPromotion` so the player can be asked
+    /// which piece they want.
+    #[default]
+    Prompt,
+    /// Promote to a queen.
+    AutoQueen,
+}
+
+/// A promoting move submitted without a promotion piece: the move is
+/// legal once one of `choices` is picked.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct PromotionPrompt {
+    pub from: Square,
+    pub to: Square,
+    pub choices: Vec<Promotion>,
+}
+
+impl PromotionPrompt {
+    /// Returns the move promoting to `promotion`.
+    pub fn choose(&self, promotion: Promotion) -> Move {
+        Move::new(self.from, self.to, Some(promotion))
+    }
+}
+
+/// Identifies where a queued chain of pre-moves would break when
+/// previewed against a hypothesized sequence of opponent replies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PreMoveBreak {
+    /// The opponent reply at this index is not a legal move.
+    IllegalReply(usize),
+    /// The pre-move at this index would be discarded after the
+    /// corresponding opponent reply.
+    Discarded(usize),
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TakebackError {
+    #[error("Cannot take back {requested} moves, only {played} have been played")]
+    NotEnoughMoves { requested: usize, played: usize },
+    #[error("Failed to replay the game: {0}")]
+    Replay(#[from] MoveError),
+}
+
+/// Why a move of a replayed game was rejected.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayFailure {
+    #[error("no piece on {0}")]
+    EmptySquare(Square),
+    #[error("the piece on {0} belongs to the side not to move")]
+    WrongTurn(Square),
+    #[error("the promotion is missing or not allowed")]
+    MalformedPromotion,
+    #[error("not a legal move")]
+    Illegal,
+}
+
+/// A replayed game could not be reconstructed because the move at
+/// `index` failed.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Cannot replay move {index} ({mv:?}): {reason}")]
+pub struct ReplayError {
+    pub index: usize,
+    pub mv: Move,
+    pub reason: ReplayFailure,
+}
+
+/// Checks that `mv`, the move at `index` of a replayed game, can be
+/// played in `state`. Unlike `validate_move`, a pawn reaching the back
+/// rank must name its promotion.
+pub(crate) fn check_replay_move(state: &MoveState, index: usize, mv: Move) -> Result<(), ReplayError> {
+    let fail = |reason| Err(ReplayError { index, mv, reason });
+    let pos: &Position = state.as_ref();
+    let Some(material) = pos.contents(mv.from) else {
+        return fail(ReplayFailure::EmptySquare(mv.from));
+    };
+    if material.color() != pos.turn() {
+        return fail(ReplayFailure::WrongTurn(mv.from));
+    }
+    if !state.legal_moves(mv.from).contains(mv.to) {
+        return fail(ReplayFailure::Illegal);
+    }
+    let promotes = material.piece() == Piece::Pawn && mv.to.rank().is_promotion_rank(material.color());
+    if promotes != mv.promotion.is_some() {
+        return fail(ReplayFailure::MalformedPromotion);
+    }
+    Ok(())
+}
+
+/// Plays `moves`, the moves of a replayed game, from `state`, checking
+/// each with `check_replay_move`, and returns them as legal moves. On
+/// failure `state` is left before the move that couldn't be played.
+pub(crate) fn replay_moves(state: &mut MoveState, moves: &[Move]) -> Result<Vec<LegalMove>, ReplayError> {
+    let mut played = Vec::with_capacity(moves.len());
+    for (index, &mv) in moves.iter().enumerate() {
+        check_replay_move(state, index, mv)?;
+        let illegal = ReplayError { index, mv, reason: ReplayFailure::Illegal };
+        let legal = state.validate_move(mv).map_err(|_| illegal)?;
+        state.apply_move(legal).map_err(|_| illegal)?;
+        played.push(legal);
+    }
+    Ok(played)
+}
+
+/// Counts how often each position has occurred since the last capture
+/// or pawn move.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Repetitions(HashMap<PositionKey, u8>);
+
+impl Repetitions {
+    /// Starts counting at `start`, which was preceded by the positions
+    /// `prior` (oldest first), e.g. from earlier in an imported game.
+    pub fn new(prior: &[PositionKey], start: &Position) -> Self {
+        let mut repetitions = Self::default();
+        for key in prior {
+            *repetitions.0.entry(*key).or_insert(0) += 1;
+        }
+        repetitions.record(start);
+        repetitions
+    }
+
+    /// Records `pos`, reached by a move, and returns how many times it
+    /// has occurred.
+    pub fn record(&mut self, pos: &Position) -> u8 {
+        if pos.moves_since_progress() == 0 {
+            // This is an optimization: moving a pawn or capturing a piece
+            // is a trap-door event... no future position could be the same as
+            // any position prior to the move
+            self.0.clear();
+        }
+        let count = self.0.entry(pos.key()).or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    /// Returns how many times `pos` has occurred.
+    pub fn count(&self, pos: &Position) -> u8 {
+        self.0.get(&pos.key()).copied().unwrap_or(0)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct EngineMode {
-    repetitions: HashMap<PositionKey, u8>,
+    /// The positions before the start of the game, see `set_prior_positions`.
+    prior: Vec<PositionKey>,
+    repetitions: Repetitions,
     board_result: Option<BoardResult>,
+    policy: Option<Arc<dyn ResultPolicy>>,
+    insufficient: InsufficientMaterial,
+    analysis: bool,
+    armageddon: bool,
 }
 
 impl EngineMode {
-    fn new() -> Self {
+    fn new(start: &Position) -> Self {
         Self {
-            repetitions: HashMap::new(),
+            prior: Vec::new(),
+            repetitions: Repetitions::new(&[], start),
             board_result: None,
+            policy: None,
+            insufficient: InsufficientMaterial::default(),
+            analysis: false,
+            armageddon: false,
         }
     }
 }
@@ -55,24 +297,45 @@ pub struct PlayerMode {
     preview: Option<Position>,
     review: ReviewState,
     pre_moves: Vec<Move>,
+    repetitions: Repetitions,
+    board_result: Option<BoardResult>,
+    /// The result announced by the server, see `set_result`.
+    result: Option<PlayResult>,
+    insufficient: InsufficientMaterial,
+    promotion: PromotionPolicy,
 }
 
 impl PlayerMode {
-    fn new(side: Color, id: BackRankId) -> Self {
+    fn new(side: Color, start: &Position) -> Self {
         Self {
             side,
             preview: None,
-            review: ReviewState::new(id.into()),
+            review: ReviewState::from_position(start.clone()),
             pre_moves: Vec::new(),
+            repetitions: Repetitions::new(&[], start),
+            board_result: None,
+            result: None,
+            insufficient: InsufficientMaterial::default(),
+            promotion: PromotionPolicy::default(),
         }
     }
 }
 
+/// Free analysis of both sides: the current position is always the one
+/// being reviewed, and moves branch off into the review's variation tree.
+#[derive(Debug, Clone)]
+pub struct AnalysisMode {
+    review: ReviewState,
+}
+
 #[derive(Debug, Clone)]
 pub struct PlayState<T> {
     mode: T,
+    start: Position,
     move_state: MoveState,
     history: Vec<LegalMove>,
+    /// The pieces each side has captured, in the order they were taken.
+    captured: Pair<Vec<Piece>>,
 }
 
 impl<T> AsRef<BackRank> for PlayState<T> {
@@ -83,38 +346,62 @@ impl<T> AsRef<BackRank> for PlayState<T> {
 impl<T> BackRanks for PlayState<T> {}
 
 impl PlayState<PlayerMode> {
-    fn new(mode: PlayerMode, id: BackRankId) -> Self {
-        let move_state = MoveState::new(Position::new(id.into()));
+    fn new(side: Color, start: Position) -> Self {
         Self {
-            mode,
-            move_state,
+            mode: PlayerMode::new(side, &start),
+            move_state: MoveState::new(start.clone()),
+            start,
             history: Vec::new(),
+            captured: Pair::default(),
         }
     }
     pub fn plays_white(id: Option<BackRankId>) -> PlayState<PlayerMode> {
-        let id = id.unwrap_or_default();
-        let mode = PlayerMode::new(Color::White, id);
-        Self::new(mode, id)
+        Self::new(Color::White, Position::new(id.unwrap_or_default().into()))
     }
     pub fn plays_black(id: Option<BackRankId>) -> PlayState<PlayerMode> {
-        let id = id.unwrap_or_default();
-        let mode = PlayerMode::new(Color::Black, id);
-        Self::new(mode, id)
+        Self::new(Color::Black, Position::new(id.unwrap_or_default().into()))
+    }
+    /// Plays `side` from `position` rather than from the starting position,
+    /// e.g. for puzzles or setups.
+    pub fn plays_from(side: Color, position: Position) -> PlayState<PlayerMode> {
+        Self::new(side, position)
     }
 }
 impl PlayState<EngineMode> {
-    fn new(mode: EngineMode, id: BackRankId) -> Self {
-        let move_state = MoveState::new(Position::new(id.into()));
+    fn new(start: Position) -> Self {
         Self {
-            mode,
-            move_state,
+            mode: EngineMode::new(&start),
+            move_state: MoveState::new(start.clone()),
+            start,
             history: Vec::new(),
+            captured: Pair::default(),
         }
     }
     pub fn plays_both(id: Option<BackRankId>) -> PlayState<EngineMode> {
-        let id = id.unwrap_or_default();
-        let mode = EngineMode::new();
-        Self::new(mode, id)
+        Self::new(Position::new(id.unwrap_or_default().into()))
+    }
+    /// Plays both sides from `position`, e.g. for a variant or a setup.
+    pub fn plays_both_from(position: Position) -> PlayState<EngineMode> {
+        Self::new(position)
+    }
+}
+
+impl PlayState<AnalysisMode> {
+    fn new(start: Position) -> Self {
+        Self {
+            mode: AnalysisMode { review: ReviewState::from_position(start.clone()) },
+            move_state: MoveState::new(start.clone()),
+            start,
+            history: Vec::new(),
+            captured: Pair::default(),
+        }
+    }
+    pub fn analyse(id: Option<BackRankId>) -> PlayState<AnalysisMode> {
+        Self::new(Position::new(id.unwrap_or_default().into()))
+    }
+    /// Analyses from `position`, e.g. one set up in an editor.
+    pub fn analyse_from(position: Position) -> PlayState<AnalysisMode> {
+        Self::new(position)
     }
 }
 
@@ -147,6 +434,132 @@ impl<T> AsRef<MoveState> for PlayState<T> {
 
 impl<T> Pos for PlayState<T> {}
 
+impl<T> PlayState<T> {
+    pub(crate) fn history(&self) -> &[LegalMove] {
+        &self.history
+    }
+
+    /// Counts the positions of the game so far, which was preceded by
+    /// `prior`.
+    fn count_repetitions(&self, prior: &[PositionKey]) -> Repetitions {
+        let mut repetitions = Repetitions::new(prior, &self.start);
+        let mut pos = self.start.clone();
+        for &mv in &self.history {
+            // every move in the history was legal when it was played
+            let _ = pos.apply_move(mv);
+            repetitions.record(&pos);
+        }
+        repetitions
+    }
+
+    pub(crate) fn stats(&self) -> GameStats {
+        GameStats::from_history(&self.start, &self.history)
+    }
+
+    pub(crate) fn fingerprint(&self) -> GameFingerprint {
+        GameFingerprint::from_history(&self.start, &self.history)
+    }
+
+    /// Returns the pieces `color` has captured so far, oldest first.
+    pub(crate) fn captured(&self, color: Color) -> &[Piece] {
+        &self.captured[color]
+    }
+
+    /// Applies `mv` for the side to move and records it in the history,
+    /// along with the piece it captures, if any.
+    fn play(&mut self, mv: LegalMove) -> Result<MoveId, MoveError> {
+        let mover = self.turn();
+        let pos: &Position = self.move_state.as_ref();
+        let captured = mv.capture_square(pos)
+            .and_then(|square| *pos.contents(square))
+            .map(|material| material.piece());
+        let move_id = self.move_state.apply_move(mv)?;
+        self.history.push(mv);
+        if let Some(piece) = captured {
+            self.captured[mover].push(piece);
+        }
+        Ok(move_id)
+    }
+
+    /// Returns the moves played so far, replaying the history from the
+    /// starting position to recover each move's squares.
+    pub(crate) fn moves(&self) -> Vec<Move> {
+        let mut pos = self.start.clone();
+        self.history.iter().map(|&mv| {
+            let played = mv.to_move(&pos);
+            // every move in the history was legal when it was played
+            let _ = pos.apply_move(mv);
+            played
+        }).collect()
+    }
+
+    /// Applies the rules of the position's variant to the current
+    /// position, which has occurred `repetitions` times.
+    fn standard_result(&self, repetitions: u8, insufficient: InsufficientMaterial) -> Option<BoardResult> {
+        use BoardResult::*;
+        let pos: &Position = self.as_ref();
+        let rules = pos.rules();
+        if let Some(result) = rules.result(&self.move_state) {
+            Some(result)
+        } else if !self.can_move() {
+            Some(rules.no_moves_result(self.turn(), self.move_state.is_check()))
+        } else if repetitions >= 3 {
+            Some(Repetition)
+        } else if pos.moves_since_progress() == FIFTY_MOVE_PLIES {
+            Some(FiftyMoves)
+        } else if rules.has_insufficient_material() && insufficient.is_draw(pos) {
+            Some(Insufficient)
+        } else {
+            None
+        }
+    }
+
+    fn can_move(&self) -> bool {
+        let pos: &Position = self.as_ref();
+        pos.ours().iter().any(|from| !self.legal_moves(from).destinations().is_empty())
+    }
+
+    /// Lists the draws the side to move could claim, given how often each
+    /// position has occurred.
+    fn draw_claims(&self, repetitions: &Repetitions) -> Vec<DrawClaim> {
+        let pos: &Position = self.as_ref();
+        let mut claims = Vec::new();
+        if repetitions.count(pos) >= 3 {
+            claims.push(DrawClaim::Repetition(None));
+        }
+        if pos.moves_since_progress() >= FIFTY_MOVE_PLIES {
+            claims.push(DrawClaim::FiftyMoves(None));
+        }
+        for mv in candidate_moves(&self.move_state) {
+            let Ok(legal_move) = self.validate_move(mv) else {
+                continue;
+            };
+            let mut next = pos.clone();
+            if next.apply_move(legal_move).is_err() || next.moves_since_progress() == 0 {
+                continue;
+            }
+            if repetitions.count(&next) >= 2 {
+                claims.push(DrawClaim::Repetition(Some(mv)));
+            }
+            if next.moves_since_progress() >= FIFTY_MOVE_PLIES {
+                claims.push(DrawClaim::FiftyMoves(Some(mv)));
+            }
+        }
+        claims
+    }
+
+    /// Fails unless `claim` is one of `claims` and the game is still on.
+    fn check_claim(&self, claim: DrawClaim, result: Option<PlayResult>, repetitions: &Repetitions) -> Result<()> {
+        if let Some(result) = result {
+            return Err(ChessError::GameOver(result));
+        }
+        if !self.draw_claims(repetitions).contains(&claim) {
+            return Err(DrawClaimError::NotClaimable(claim).into());
+        }
+        Ok(())
+    }
+}
+
 impl<T> LegalMoves for PlayState<T> {}
 
 impl PreMoves for PlayState<PlayerMode> {}
@@ -164,16 +577,112 @@ impl Review for PlayState<PlayerMode> {
 }
 
 impl ReviewMut for PlayState<PlayerMode> {
-    fn set_offset(&mut self, offset: MoveId) {
-        self.mode.review.set_offset(offset);
+    fn set_offset(&mut self, offset: MoveId) -> Result<(), ReviewError> {
+        self.mode.review.set_offset(offset)
+    }
+}
+
+impl PlayState<AnalysisMode> {
+    /// Plays `mv` for the side to move in the reviewed position. It
+    /// extends the line if nothing followed, selects the existing line if
+    /// `mv` was played before, and starts a variation otherwise.
+    pub fn submit_move(&mut self, mv: Move) -> Result<MoveId> {
+        let mv = self.validate_move(mv)?;
+        let at = *self.mode.review.offset();
+        self.mode.review.add_variation(at, mv)?;
+        Ok(self.play(mv)?)
+    }
+
+    /// Deletes the last `plies` moves leading to the reviewed position,
+    /// along with everything that followed them.
+    pub fn take_back(&mut self, plies: usize) -> Result<(), TakebackError> {
+        let played = self.history.len();
+        if plies > played {
+            return Err(TakebackError::NotEnoughMoves { requested: plies, played });
+        }
+        for _ in 0..plies {
+            // positions after the start are never on the game line
+            let _ = self.mode.review.delete_variation();
+        }
+        self.sync();
+        Ok(())
+    }
+
+    /// Starts over from `position`, dropping every move and annotation.
+    pub fn set_position(&mut self, position: Position) {
+        *self = Self::new(position);
+    }
+
+    pub fn next_variation(&mut self) -> Result<(), ReviewError> {
+        self.mode.review.next_variation()?;
+        self.sync();
+        Ok(())
+    }
+
+    pub fn prev_variation(&mut self) -> Result<(), ReviewError> {
+        self.mode.review.prev_variation()?;
+        self.sync();
+        Ok(())
+    }
+
+    pub fn promote_variation(&mut self) -> Result<(), ReviewError> {
+        self.mode.review.promote_variation()
+    }
+
+    pub(crate) fn review_state(&self) -> &ReviewState {
+        &self.mode.review
+    }
+
+    pub(crate) fn review_state_mut(&mut self) -> &mut ReviewState {
+        &mut self.mode.review
+    }
+
+    /// Replays the current line up to the reviewed position, so that the
+    /// position, history and captured pieces follow the review.
+    fn sync(&mut self) {
+        let moves = self.mode.review.moves_to_current();
+        self.move_state = MoveState::new(self.start.clone());
+        self.history.clear();
+        self.captured = Pair::default();
+        for mv in moves {
+            // every move in the review was legal when it was added
+            let _ = self.play(mv);
+        }
+    }
+}
+
+impl Review for PlayState<AnalysisMode> {
+    fn len(&self) -> usize {
+        self.mode.review.len()
+    }
+    fn offset(&self) -> &MoveId {
+        self.mode.review.offset()
+    }
+    fn get(&self, offset: &MoveId) -> Option<&Position> {
+        self.mode.review.get(offset)
+    }
+}
+
+impl ReviewMut for PlayState<AnalysisMode> {
+    fn set_offset(&mut self, offset: MoveId) -> Result<(), ReviewError> {
+        self.mode.review.set_offset(offset)?;
+        self.sync();
+        Ok(())
     }
 }
 
 impl PlayState<EngineMode> {
+    /// Plays `mv` for the side to move. Once the game has a result this
+    /// fails with `ChessError::GameOver` unless post-game analysis is
+    /// allowed.
     pub fn submit_move(&mut self, mv: Move) -> Result<MoveId> {
+        if let Some(result) = self.mode.board_result {
+            if !self.mode.analysis {
+                return Err(ChessError::GameOver(result.into()));
+            }
+        }
         let mv = self.validate_move(mv)?;
-        let move_id = self.move_state.apply_move(mv);
-        self.history.push(mv);
+        let move_id = self.play(mv)?;
         self.update_result();
         Ok(move_id)
     }
@@ -182,69 +691,132 @@ impl PlayState<EngineMode> {
         self.mode.board_result
     }
 
-    fn update_result(&mut self) {
-        use BoardResult::*;
-        let repetitions = self.update_repetitions();
-        let pos: &Position = self.as_ref();
-        self.mode.board_result = if !self.can_move() {
-            if self.move_state.is_check() {
-                Some(CheckMate(!self.turn()))
-            } else {
-                Some(StaleMate)
-            }
-        } else if repetitions >= 3 {
-            Some(Repetition)
-        } else if pos.moves_since_progress() == 100 {
-            Some(FiftyMoves)
-        } else if self.is_insufficient() {
-            Some(Insufficient)
-        } else {
-            None
-        }
+    /// Returns how many times the current position has occurred.
+    pub fn repetition_count(&self) -> usize {
+        self.mode.repetitions.count(self.as_ref()) as usize
     }
-    fn update_repetitions(&mut self) -> u8 {
-        let pos: &Position = self.as_ref();
-        if pos.moves_since_progress() == 0 {
-            // This is an optimization: moving a pawn or capturing a piece
-            // is a trap-door event... no future position could be the same as
-            // any position prior to the move
-            self.mode.repetitions.clear();
+
+    /// Counts `prior`, the positions that came before the starting
+    /// position (oldest first), towards repetitions, e.g. for a game
+    /// imported mid-stream. Only positions since the last capture or pawn
+    /// move matter. Moves already played are recounted, but the result
+    /// isn't re-evaluated, so this is best called before play starts.
+    pub fn set_prior_positions(&mut self, prior: Vec<PositionKey>) {
+        self.mode.repetitions = self.count_repetitions(&prior);
+        self.mode.prior = prior;
+    }
+
+    /// Returns the number of moves (plies) played so far.
+    pub fn plies(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Undoes the last `plies` moves. Since positions aren't kept in
+    /// engine mode, the remaining moves are replayed from the starting
+    /// position, which also rebuilds the repetition counts.
+    pub fn take_back(&mut self, plies: usize) -> Result<(), TakebackError> {
+        let played = self.history.len();
+        if plies > played {
+            return Err(TakebackError::NotEnoughMoves { requested: plies, played });
         }
-        let pos: &Position = self.as_ref();
-        let key = pos.key();
-        let count = self.mode.repetitions.entry(key).or_insert(0);
-        *count += 1;
-        *count
+        let mut replay = Self::new(self.start.clone());
+        replay.mode.repetitions = Repetitions::new(&self.mode.prior, &self.start);
+        replay.mode.prior = self.mode.prior.clone();
+        replay.mode.policy = self.mode.policy.clone();
+        replay.mode.insufficient = self.mode.insufficient;
+        replay.mode.analysis = self.mode.analysis;
+        replay.mode.armageddon = self.mode.armageddon;
+        for mv in &self.history[..played - plies] {
+            replay.play(*mv)?;
+            replay.update_result();
+        }
+        *self = replay;
+        Ok(())
     }
 
-    fn can_move(&self) -> bool {
-        let pos: &Position = self.as_ref();
-        for from in pos.ours().iter() {
-            let destinations = self.legal_moves(from).destinations();
-            if !destinations.is_empty() {
-                return true;
-            }
+    /// Lists the draws the side to move could claim now (see `DrawClaim`),
+    /// or none once the game is over.
+    pub fn claimable_draws(&self) -> Vec<DrawClaim> {
+        match self.mode.board_result {
+            Some(_) => Vec::new(),
+            None => self.draw_claims(&self.mode.repetitions),
         }
-        false
     }
-    fn is_insufficient(&self) -> bool {
-        use MatingMaterial::*;
+
+    /// Fails unless the side to move may make `claim` now. The board
+    /// plays the claim's move, if any, and then calls `end_by_claim`.
+    pub(crate) fn check_draw_claim(&self, claim: DrawClaim) -> Result<()> {
+        self.check_claim(claim, self.mode.board_result.map(PlayResult::from), &self.mode.repetitions)
+    }
+
+    /// Sets the result of an accepted claim, unless its move already
+    /// ended the game.
+    pub(crate) fn end_by_claim(&mut self, claim: DrawClaim) -> BoardResult {
+        *self.mode.board_result.get_or_insert(claim.result())
+    }
+
+    /// Sets the policy consulted for custom results, or restores the
+    /// standard rules if `None`. Only later moves are affected.
+    pub fn set_result_policy(&mut self, policy: Option<Arc<dyn ResultPolicy>>) {
+        self.mode.policy = policy;
+    }
+
+    /// Selects the rules deciding when the game is drawn for lack of
+    /// material. Only later moves are affected.
+    pub fn set_insufficient_material(&mut self, policy: InsufficientMaterial) {
+        self.mode.insufficient = policy;
+    }
+
+    pub fn insufficient_material(&self) -> InsufficientMaterial {
+        self.mode.insufficient
+    }
+
+    /// Returns true if `color` wins when their opponent runs out of time
+    /// in the current position, under the insufficient material rules.
+    pub fn can_win_on_time(&self, color: Color) -> bool {
         let pos: &Position = self.as_ref();
-        match pos.our_mating_material() {
-            Sufficient => false,
-            ours => match (ours, pos.their_mating_material()) {
-                (_, Sufficient) => false,
-                (LoneKing, _) => true,
-                (_, LoneKing) => true,
-                (TwoKnights, _) => false,
-                (_, TwoKnights) => false,
-                _ => true,
-            }
+        !pos.rules().has_insufficient_material() || self.mode.insufficient.can_win_on_time(pos, color)
+    }
+
+    /// Marks the game as an Armageddon game, in which Black wins if the
+    /// game is drawn. The board still reports draws as such; the game
+    /// layer turns them into wins (see `GameResult::armageddon`).
+    pub fn set_armageddon(&mut self, armageddon: bool) {
+        self.mode.armageddon = armageddon;
+    }
+
+    pub fn is_armageddon(&self) -> bool {
+        self.mode.armageddon
+    }
+
+    /// Allows (or forbids) moves after the game has ended, e.g. to
+    /// analyse the final position. Such moves never change the result.
+    pub fn set_post_game_analysis(&mut self, allowed: bool) {
+        self.mode.analysis = allowed;
+    }
+
+    pub fn post_game_analysis(&self) -> bool {
+        self.mode.analysis
+    }
+
+    fn update_result(&mut self) {
+        let repetitions = self.mode.repetitions.record(self.move_state.as_ref());
+        if self.mode.board_result.is_some() {
+            return;
         }
+        let custom = self.mode.policy.as_ref()
+            .and_then(|policy| policy.board_result(&self.move_state, &self.history));
+        self.mode.board_result = custom
+            .or_else(|| self.standard_result(repetitions, self.mode.insufficient));
     }
 }
 
 impl PlayState<PlayerMode> {
+    #[inline]
+    pub fn side(&self) -> Color {
+        self.mode.side
+    }
+
     #[inline]
     pub fn our_turn(&self) -> bool {
         self.turn() == self.mode.side
@@ -254,6 +826,124 @@ impl PlayState<PlayerMode> {
     pub fn their_turn(&self) -> bool {
         self.turn() != self.mode.side
     }
+
+    /// Returns the result of the game under the standard rules, as the
+    /// server (an `EngineBoard`) would see it: checkmate, stalemate,
+    /// threefold repetition, the fifty-move rule or insufficient material.
+    pub fn board_result(&self) -> Option<BoardResult> {
+        self.mode.board_result
+    }
+
+    /// Records the result announced by the server, which may end the game
+    /// for reasons the board can't see, e.g. a resignation or timeout.
+    pub fn set_result(&mut self, result: impl Into<PlayResult>) {
+        self.mode.result = Some(result.into());
+    }
+
+    /// Returns the result announced by the server, or else the one the
+    /// board reached by itself.
+    pub fn result(&self) -> Option<PlayResult> {
+        self.mode.result.or_else(|| self.mode.board_result.map(PlayResult::from))
+    }
+
+    /// Returns a free analysis board for the finished game, sharing its
+    /// review: moves for either side are played from the reviewed position
+    /// into the variation tree. Fails with `ChessError::GameInProgress`
+    /// until the game has a result.
+    pub fn to_analysis(&self) -> Result<PlayState<AnalysisMode>> {
+        if self.result().is_none() {
+            return Err(ChessError::GameInProgress);
+        }
+        let mut analysis = PlayState {
+            mode: AnalysisMode { review: self.mode.review.clone() },
+            start: self.start.clone(),
+            move_state: MoveState::new(self.start.clone()),
+            history: Vec::new(),
+            captured: Pair::default(),
+        };
+        analysis.sync();
+        Ok(analysis)
+    }
+
+    /// Returns how many times the current position has occurred.
+    pub fn repetition_count(&self) -> usize {
+        self.mode.repetitions.count(self.as_ref()) as usize
+    }
+
+    /// Counts `prior`, the positions that came before the starting
+    /// position (oldest first), towards repetitions, e.g. for a game
+    /// imported mid-stream. Only positions since the last capture or pawn
+    /// move matter. Moves already played are recounted, but the result
+    /// isn't re-evaluated, so this is best called before play starts.
+    pub fn set_prior_positions(&mut self, prior: Vec<PositionKey>) {
+        self.mode.repetitions = self.count_repetitions(&prior);
+    }
+
+    /// Selects the rules deciding when the game is drawn for lack of
+    /// material; they should match the server's.
+    pub fn set_insufficient_material(&mut self, policy: InsufficientMaterial) {
+        self.mode.insufficient = policy;
+    }
+
+    pub fn set_promotion_policy(&mut self, policy: PromotionPolicy) {
+        self.mode.promotion = policy;
+    }
+
+    pub fn promotion_policy(&self) -> PromotionPolicy {
+        self.mode.promotion
+    }
+
+    /// Applies the promotion policy to `mv` if it's a legal (pre-)move of
+    /// a pawn onto the last rank without a promotion piece.
+    fn complete_promotion(&self, mv: Move) -> Result<Move> {
+        let pos = if self.our_turn() { self.as_ref() } else { self.preview() };
+        let promotes = mv.promotion.is_none()
+            && pos.contents(mv.from).is_some_and(|material| {
+                material.color() == self.mode.side
+                    && material.piece() == Piece::Pawn
+                    && mv.to.rank().is_promotion_rank(material.color())
+            });
+        if !promotes {
+            return Ok(mv);
+        }
+        let queen = Move::new(mv.from, mv.to, Some(Promotion::Queen));
+        if self.our_turn() {
+            self.validate_move(queen)?;
+        } else {
+            pos.validate_pre_move(queen)?;
+        }
+        match self.mode.promotion {
+            PromotionPolicy::AutoQueen => Ok(queen),
+            PromotionPolicy::Prompt => Err(ChessError::NeedsPromotion(PromotionPrompt {
+                from: mv.from,
+                to: mv.to,
+                choices: pos.rules().promotions().to_vec(),
+            })),
+        }
+    }
+
+    /// Lists the draws the side to move could claim now, or none once the
+    /// game is over.
+    pub fn claimable_draws(&self) -> Vec<DrawClaim> {
+        match self.result() {
+            Some(_) => Vec::new(),
+            None => self.draw_claims(&self.mode.repetitions),
+        }
+    }
+
+    /// Fails unless the side to move may make `claim` now. The board
+    /// plays the claim's move, if any, and then calls `end_by_claim`.
+    pub(crate) fn check_draw_claim(&self, claim: DrawClaim) -> Result<()> {
+        if claim.mv().is_some() && self.their_turn() {
+            return Err(MoveError::WrongTurn.into());
+        }
+        self.check_claim(claim, self.result(), &self.mode.repetitions)
+    }
+
+    pub(crate) fn end_by_claim(&mut self, claim: DrawClaim) -> BoardResult {
+        *self.mode.board_result.get_or_insert(claim.result())
+    }
+
     pub fn move_destinations(&self, from: Square) -> Mask {
         if self.our_turn() {
             self.legal_moves(from).destinations()
@@ -262,12 +952,37 @@ impl PlayState<PlayerMode> {
         }
     }
 
+    /// Returns `move_destinations` for every square at once, indexed by
+    /// `Square::to_index`. Only our pieces' squares can be non-empty.
+    pub fn all_move_destinations(&self) -> [Mask; 64] {
+        let mut destinations = [Mask::empty(); 64];
+        let pos: &Position = self.as_ref();
+        let ours = match self.mode.side {
+            Color::White => pos.white(),
+            Color::Black => pos.black(),
+        };
+        for from in ours.iter() {
+            destinations[from.to_index()] = self.move_destinations(from);
+        }
+        destinations
+    }
+
+    /// Plays `mv` if it's our turn or queues it as a pre-move otherwise.
+    /// Once the game has a result this fails with `ChessError::GameOver`
+    /// (see `to_analysis`). A promotion without a piece is handled by the
+    /// `PromotionPolicy`.
     pub fn submit_our_move(&mut self, mv: Move) -> Result<()> {
+        if let Some(result) = self.result() {
+            return Err(ChessError::GameOver(result));
+        }
+        let mv = self.complete_promotion(mv)?;
         if self.our_turn() {
-            self.submit_legal_move(self.validate_move(mv)?);
+            self.submit_legal_move(self.validate_move(mv)?)?;
         } else {
-            let pre_move = self.validate_pre_move(mv)?;
-            self.preview_mut().apply_pre_move(pre_move);
+            // Validate against the preview so that pre-moves can build
+            // on previously queued pre-moves
+            let pre_move = self.preview().validate_pre_move(mv)?;
+            self.preview_mut().apply_pre_move(pre_move)?;
             self.mode.pre_moves.push(mv);
         }
         Ok(())
@@ -284,22 +999,15 @@ impl PlayState<PlayerMode> {
     /// # Returns
     ///
     /// - `Ok(())` if the opponent's move was successfully applied.
+    /// - `MoveError::WrongTurn` if it is our turn.
     /// - An error otherwise.
-    ///
-    /// # Panics
-    /// 
-    /// Panics in debug mode if this method is invoked when it is our turn.
-    /// 
-    /// # Safety
-    /// 
-    /// The caller must ensure that it only uses this method when it is
-    /// the opponent's turn.
     pub fn submit_their_move(&mut self, mv: Move) -> Result<()> {
-        debug_assert!(self.their_turn());
+        if self.our_turn() {
+            return Err(MoveError::WrongTurn.into());
+        }
         let mv = self.validate_move(mv)?;
         let pre_moves = self.rollback_pre_moves();
-        self.submit_legal_move(mv);
-        debug_assert!(self.our_turn());
+        self.submit_legal_move(mv)?;
 
         // Resubmit pre-moves. Only the first one has a chance of being 
         // applied. If it's applied, the remaining pre-moves will be pushed 
@@ -312,36 +1020,103 @@ impl PlayState<PlayerMode> {
         Ok(())
     }
 
+    /// Simulates the queued pre-moves against hypothesized opponent replies
+    /// without modifying the board. The reply at index `i` is applied before
+    /// attempting the pre-move at index `i`, mirroring `submit_their_move`.
+    ///
+    /// Returns the first point at which the chain breaks, or `None` if every
+    /// pre-move covered by `replies` would be applied. Pre-moves beyond the
+    /// last reply are not evaluated.
+    pub fn preview_premove_chain(&self, replies: &[Move]) -> Option<PreMoveBreak> {
+        let mut state = self.move_state.clone();
+        for (index, (reply, pre_move)) in replies.iter()
+            .zip(self.mode.pre_moves.iter())
+            .enumerate()
+        {
+            let reply = state.validate_move(*reply)
+                .and_then(|reply| Ok(state.apply_move(reply)?));
+            if reply.is_err() {
+                return Some(PreMoveBreak::IllegalReply(index));
+            }
+            let pre_move = state.validate_move(*pre_move)
+                .and_then(|pre_move| Ok(state.apply_move(pre_move)?));
+            if pre_move.is_err() {
+                return Some(PreMoveBreak::Discarded(index));
+            }
+        }
+        None
+    }
+
+    /// Adds `mv`, played from the reviewed position at `at`, as a
+    /// variation and switches to it.
+    pub fn add_variation(&mut self, at: MoveId, mv: Move) -> Result<()> {
+        let state = self.mode.review.state_at(&at)
+            .ok_or(ReviewError::OutOfRange(at))?;
+        let mv = state.validate_move(mv)?;
+        self.mode.review.add_variation(at, mv)?;
+        Ok(())
+    }
+
+    /// Returns the annotation of the reviewed position at `at`, if any.
+    pub fn annotation(&self, at: &MoveId) -> Option<&Annotation> {
+        self.mode.review.annotation(at)
+    }
+
+    pub fn annotation_mut(&mut self, at: &MoveId) -> Result<&mut Annotation, ReviewError> {
+        self.mode.review.annotation_mut(at)
+    }
+
+    /// Selects which positions are kept for review (see
+    /// `HistoryRetention`).
+    pub fn set_history_retention(&mut self, retention: HistoryRetention) {
+        self.mode.review.set_retention(retention);
+    }
+
+    pub(crate) fn review_state(&self) -> &ReviewState {
+        &self.mode.review
+    }
+
+    pub(crate) fn review_state_mut(&mut self) -> &mut ReviewState {
+        &mut self.mode.review
+    }
+
+    pub fn queued_pre_moves(&self) -> &[Move] {
+        &self.mode.pre_moves
+    }
+
     pub fn cancel_pre_moves(&mut self) {
         let _ = self.rollback_pre_moves();
     }
 
-    fn submit_legal_move(&mut self, mv: LegalMove) {
+    fn submit_legal_move(&mut self, mv: LegalMove) -> Result<(), MoveError> {
         // Pre-condition: no pre-moves in the queue
         debug_assert!(self.mode.pre_moves.is_empty());
         debug_assert!(self.mode.preview.is_none());
-        self.move_state.apply_move(mv);
-        self.history.push(mv);
-        self.mode.review.push(self.move_state.clone());
+        self.play(mv)?;
+        let pos: &Position = self.move_state.as_ref();
+        self.mode.review.push(mv, pos.clone());
+        let repetitions = self.mode.repetitions.record(self.move_state.as_ref());
+        if self.mode.board_result.is_none() {
+            self.mode.board_result = self.standard_result(repetitions, self.mode.insufficient);
+        }
+        Ok(())
     }
 
     pub fn view(&self) -> &Position {
-        if !self.mode.review.at_end() {
+        if !self.mode.review.is_live() {
             return self.mode.review.as_ref()
         }
         self.preview()
     }
 
-    fn preview(&self) -> &Position {
+    /// Returns the current position with any queued pre-moves applied.
+    pub(crate) fn preview(&self) -> &Position {
         self.mode.preview.as_ref().unwrap_or(self.as_ref())
     }
 
     fn preview_mut(&mut self) -> &mut Position {
-        if self.mode.preview.is_none() {
-            let pos: &Position = self.as_ref();
-            self.mode.preview = Some(pos.clone());
-        }
-        self.mode.preview.as_mut().unwrap()
+        let pos: &Position = self.move_state.as_ref();
+        self.mode.preview.get_or_insert_with(|| pos.clone())
     }
 
     fn rollback_pre_moves(&mut self) -> Vec<Move> {
@@ -351,3 +1126,462 @@ impl PlayState<PlayerMode> {
 
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use Square::*;
+
+    fn mv(from: Square, to: Square) -> Move {
+        Move::new(from, to, None)
+    }
+
+    #[test]
+    fn test_captured_pieces_and_material_balance() {
+        let mut board = EngineBoard::standard();
+        // 1. e4 d5 2. exd5 Qxd5 3. Nc3 Qxa2 4. Rxa2
+        let moves = [
+            mv(E2, E4), mv(D7, D5), mv(E4, D5), mv(D8, D5),
+            mv(B1, C3), mv(D5, A2), mv(A1, A2),
+        ];
+        for mv in moves {
+            board.submit_move(mv).unwrap();
+        }
+        assert_eq!(board.captured(Color::White), vec![Piece::Pawn, Piece::Queen]);
+        assert_eq!(board.captured(Color::Black), vec![Piece::Pawn, Piece::Pawn]);
+        assert_eq!(board.material_balance(), 8);
+
+        board.take_back(1).unwrap();
+        assert_eq!(board.captured(Color::White), vec![Piece::Pawn]);
+        assert_eq!(board.material_balance(), -1);
+
+        let mut board = PlayerBoard::plays_black(None);
+        board.submit_their_move(mv(E2, E4)).unwrap();
+        board.submit_our_move(mv(D7, D5)).unwrap();
+        board.submit_their_move(mv(E4, D5)).unwrap();
+        assert_eq!(board.captured(Color::White), vec![Piece::Pawn]);
+        assert!(board.captured(Color::Black).is_empty());
+        assert_eq!(board.material_balance(), 1);
+    }
+
+    #[test]
+    fn test_progress_to_draw() {
+        let mut board = EngineBoard::standard();
+        assert_eq!(board.repetition_count(), 1);
+        assert_eq!(board.plies_until_fifty_move_draw(), 100);
+        for mv in [mv(G1, F3), mv(G8, F6), mv(F3, G1), mv(F6, G8), mv(G1, F3)] {
+            board.submit_move(mv).unwrap();
+        }
+        assert_eq!(board.repetition_count(), 2);
+        assert_eq!(board.moves_since_progress(), 5);
+        assert_eq!(board.plies_until_fifty_move_draw(), 95);
+
+        let position = Position::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 98 80").unwrap();
+        let mut board = EngineBoard::plays_both_from(position);
+        assert_eq!(board.plies_until_fifty_move_draw(), 2);
+        board.submit_move(mv(A1, A2)).unwrap();
+        board.submit_move(mv(E8, D8)).unwrap();
+        assert_eq!(board.plies_until_fifty_move_draw(), 0);
+        assert_eq!(board.board_result(), Some(BoardResult::FiftyMoves));
+
+        let mut board = PlayerBoard::plays_white(None);
+        for mv in [mv(G1, F3), mv(G8, F6), mv(F3, G1), mv(F6, G8), mv(G1, F3)] {
+            match board.our_turn() {
+                true => board.submit_our_move(mv).unwrap(),
+                false => board.submit_their_move(mv).unwrap(),
+            }
+        }
+        assert_eq!(board.repetition_count(), 2);
+    }
+
+    #[test]
+    fn test_repetitions_count_start_and_prior_positions() {
+        let shuffle = [mv(G1, F3), mv(G8, F6), mv(F3, G1), mv(F6, G8)];
+        let start = Position::default().key();
+        // the game was imported after 1. Nf3 Nf6 2. Ng1 Ng8, so the
+        // position has been seen once before
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 4 3";
+        let imported = Position::from_fen(fen).unwrap();
+        let mut engine = EngineBoard::plays_both_from(imported.clone());
+        engine.set_prior_positions(vec![start]);
+        assert_eq!(engine.repetition_count(), 2);
+        for mv in shuffle {
+            engine.submit_move(mv).unwrap();
+        }
+        assert_eq!(engine.board_result(), Some(BoardResult::Repetition));
+        // the prior positions survive a take back
+        engine.take_back(1).unwrap();
+        assert_eq!(engine.board_result(), None);
+        engine.submit_move(shuffle[3]).unwrap();
+        assert_eq!(engine.repetition_count(), 3);
+
+        let mut player = PlayerBoard::plays_from(Color::White, imported);
+        player.submit_our_move(shuffle[0]).unwrap();
+        player.set_prior_positions(vec![start]);
+        assert_eq!(player.repetition_count(), 1);
+        for mv in &shuffle[1..] {
+            match player.our_turn() {
+                true => player.submit_our_move(*mv).unwrap(),
+                false => player.submit_their_move(*mv).unwrap(),
+            }
+        }
+        assert_eq!(player.repetition_count(), 3);
+        assert_eq!(player.board_result(), Some(BoardResult::Repetition));
+    }
+
+    #[test]
+    fn test_take_back_restores_position_and_repetitions() {
+        let mut board = EngineBoard::standard();
+        let moves = [mv(G1, F3), mv(G8, F6), mv(F3, G1), mv(F6, G8)];
+        for mv in moves {
+            board.submit_move(mv).unwrap();
+        }
+        let after_two = {
+            let mut board = EngineBoard::standard();
+            board.submit_move(moves[0]).unwrap();
+            board.submit_move(moves[1]).unwrap();
+            AsRef::<Position>::as_ref(&board).key()
+        };
+        board.take_back(2).unwrap();
+        assert_eq!(board.plies(), 2);
+        assert_eq!(AsRef::<Position>::as_ref(&board).key(), after_two);
+        // replaying the shuffle twice more only reaches a threefold
+        // repetition if the taken back moves no longer count
+        for mv in moves.iter().cycle().skip(2).take(5) {
+            board.submit_move(*mv).unwrap();
+        }
+        assert_eq!(board.board_result(), None);
+        let expected = TakebackError::NotEnoughMoves { requested: 8, played: 7 };
+        assert_eq!(board.take_back(8), Err(expected));
+    }
+    #[test]
+    fn test_result_policy_is_consulted_first() {
+        let mut board = EngineBoard::standard();
+        // the first side to give check wins
+        board.set_result_policy(|state: &MoveState, _: &[LegalMove]| {
+            state.is_check().then(|| BoardResult::Adjudicated(Some(!state.turn())))
+        });
+        for mv in [mv(E2, E4), mv(E7, E6), mv(D2, D4)] {
+            board.submit_move(mv).unwrap();
+        }
+        assert_eq!(board.board_result(), None);
+        board.submit_move(mv(F8, B4)).unwrap();
+        assert_eq!(board.board_result(), Some(BoardResult::Adjudicated(Some(Color::Black))));
+        // the policy survives a take back
+        board.take_back(1).unwrap();
+        assert_eq!(board.board_result(), None);
+        board.submit_move(mv(F8, B4)).unwrap();
+        assert!(board.board_result().is_some());
+
+        board.take_back(1).unwrap();
+        board.clear_result_policy();
+        board.submit_move(mv(F8, B4)).unwrap();
+        assert_eq!(board.board_result(), None);
+    }
+    #[test]
+    fn test_moves_rejected_after_game_over() {
+        let shuffle = [mv(G1, F3), mv(G8, F6), mv(F3, G1), mv(F6, G8)];
+        let mut board = EngineBoard::standard();
+        for mv in shuffle.iter().cycle().take(8) {
+            board.submit_move(*mv).unwrap();
+        }
+        let result = BoardResult::Repetition;
+        assert_eq!(board.board_result(), Some(result));
+        assert_eq!(board.submit_move(mv(E2, E4)), Err(ChessError::GameOver(result.into())));
+
+        // analysis moves are allowed but don't change the result
+        board.set_post_game_analysis(true);
+        board.submit_move(mv(E2, E4)).unwrap();
+        assert_eq!(board.board_result(), Some(result));
+        board.set_post_game_analysis(false);
+        assert!(board.submit_move(mv(E7, E5)).is_err());
+        board.take_back(2).unwrap();
+        assert_eq!(board.board_result(), None);
+    }
+    #[test]
+    fn test_player_board_detects_repetition() {
+        let shuffle = [mv(G1, F3), mv(G8, F6), mv(F3, G1), mv(F6, G8)];
+        let mut board = PlayerBoard::plays_white(None);
+        // the starting position counts, so its third occurrence ends the game
+        for (i, mv) in shuffle.iter().cycle().take(8).enumerate() {
+            assert_eq!(board.board_result(), None);
+            if i % 2 == 0 {
+                board.submit_our_move(*mv).unwrap();
+            } else {
+                board.submit_their_move(*mv).unwrap();
+            }
+        }
+        assert_eq!(board.board_result(), Some(BoardResult::Repetition));
+    }
+    #[test]
+    fn test_all_move_destinations() {
+        let mut board = PlayerBoard::plays_black(None);
+        let expected: Vec<Mask> = (0..64).map(|index| board.move_destinations(Square::from_index(index))).collect();
+        assert_eq!(board.all_move_destinations().to_vec(), expected);
+        assert!(board.all_move_destinations()[E2.to_index()].is_empty());
+        assert!(board.all_move_destinations()[G8.to_index()].contains(F6));
+        board.submit_their_move(mv(E2, E4)).unwrap();
+        let expected: Vec<Mask> = (0..64).map(|index| board.move_destinations(Square::from_index(index))).collect();
+        assert_eq!(board.all_move_destinations().to_vec(), expected);
+    }
+    #[test]
+    fn test_promotion_policy() {
+        let position = Position::from_fen("7k/P7/8/8/8/8/p7/K7 w - - 0 1").unwrap();
+        let mut board = PlayerBoard::plays_from(Color::White, position.clone());
+        let prompt = match board.submit_our_move(mv(A7, A8)) {
+            Err(ChessError::NeedsPromotion(prompt)) => prompt,
+            other => panic!("expected a prompt, got {other:?}"),
+        };
+        assert_eq!(prompt.choices, vec![Promotion::Queen, Promotion::Rook, Promotion::Bishop, Promotion::Knight]);
+        board.submit_our_move(prompt.choose(Promotion::Knight)).unwrap();
+        assert_eq!(board.current()[A8], Some(Material::new(Color::White, Piece::Knight)));
+        // illegal moves are still rejected rather than prompted for
+        assert_eq!(board.submit_our_move(mv(B7, B8)), Err(ChessError::Move(MoveError::InvalidMove)));
+
+        // pre-moves are completed the same way
+        let mut board = PlayerBoard::plays_from(Color::Black, position);
+        board.set_promotion_policy(PromotionPolicy::AutoQueen);
+        board.submit_our_move(mv(A2, B1)).unwrap();
+        assert_eq!(board.queued_pre_moves(), &[Move::new(A2, B1, Some(Promotion::Queen))]);
+    }
+    #[test]
+    fn test_promotion_pre_moves_several_plies_ahead() {
+        let position = Position::from_fen("7k/8/8/8/p7/8/8/1N5K w - - 0 1").unwrap();
+        let mut board = PlayerBoard::plays_from(Color::Black, position.clone());
+        board.submit_our_move(mv(A4, A3)).unwrap();
+        board.submit_our_move(mv(A3, A2)).unwrap();
+        // the knight may still be on b1 by then, so capturing it is queued
+        assert!(matches!(board.submit_our_move(mv(A2, B1)), Err(ChessError::NeedsPromotion(..))));
+        board.submit_our_move(Move::new(A2, B1, Some(Promotion::Knight))).unwrap();
+        assert_eq!(board.submit_our_move(Move::new(A2, A1, Some(Promotion::King))),
+            Err(ChessError::Move(MoveError::InvalidMove)));
+        for reply in [mv(H1, G1), mv(G1, H1), mv(H1, G1)] {
+            board.submit_their_move(reply).unwrap();
+        }
+        assert!(board.queued_pre_moves().is_empty());
+        assert_eq!(board.current()[B1], Some(Material::new(Color::Black, Piece::Knight)));
+
+        // a capture promotion is discarded if the piece has moved away
+        let mut board = PlayerBoard::plays_from(Color::Black, position);
+        board.submit_our_move(mv(A4, A3)).unwrap();
+        board.submit_our_move(mv(A3, A2)).unwrap();
+        board.submit_our_move(Move::new(A2, B1, Some(Promotion::Queen))).unwrap();
+        for reply in [mv(H1, G1), mv(B1, C3), mv(G1, H1)] {
+            board.submit_their_move(reply).unwrap();
+        }
+        assert!(board.queued_pre_moves().is_empty());
+        assert_eq!(board.current()[A2], Some(Material::new(Color::Black, Piece::Pawn)));
+    }
+    #[test]
+    fn test_player_board_result() {
+        let mut board = PlayerBoard::plays_black(None);
+        assert_eq!(board.result(), None);
+        for (i, mv) in [mv(F2, F3), mv(E7, E5), mv(G2, G4), mv(D8, H4)].into_iter().enumerate() {
+            if i % 2 == 0 {
+                board.submit_their_move(mv).unwrap();
+            } else {
+                board.submit_our_move(mv).unwrap();
+            }
+        }
+        assert_eq!(board.result(), Some(PlayResult::Board(BoardResult::CheckMate(Color::Black))));
+
+        // the server knows about endings the board can't see
+        let mut board = PlayerBoard::plays_white(None);
+        board.set_result(GameResult::Win(Color::White, WinReason::Resigned));
+        assert_eq!(board.board_result(), None);
+        assert_eq!(board.result(), Some(PlayResult::Announced(Some(Color::White))));
+        assert_eq!(board.submit_our_move(mv(E2, E4)), Err(ChessError::GameOver(PlayResult::Announced(Some(Color::White)))));
+    }
+    #[test]
+    fn test_analysis_after_game_over() {
+        let mut board = PlayerBoard::plays_white(None);
+        assert_eq!(board.enter_analysis_mode().err(), Some(ChessError::GameInProgress));
+        board.submit_our_move(mv(E2, E4)).unwrap();
+        // a pre-move is dropped when the game ends
+        board.submit_our_move(mv(G1, F3)).unwrap();
+        assert_eq!(board.queued_pre_moves().len(), 1);
+        let resigned = PlayResult::Announced(Some(Color::White));
+        board.set_result(GameResult::Win(Color::White, WinReason::Resigned));
+        assert!(board.queued_pre_moves().is_empty());
+        assert_eq!(board.submit_our_move(mv(G1, F3)), Err(ChessError::GameOver(resigned)));
+        assert!(board.claimable_draws().is_empty());
+
+        let mut analysis = board.enter_analysis_mode().unwrap();
+        assert_eq!(analysis.offset(), &(MoveId::START + 1usize));
+        // either side may move, from whichever position is reviewed
+        analysis.submit_move(mv(E7, E5)).unwrap();
+        analysis.submit_move(mv(G1, F3)).unwrap();
+        assert_eq!(analysis.offset(), &(MoveId::START + 3usize));
+        analysis.set_offset(MoveId::START + 1usize).unwrap();
+        analysis.submit_move(mv(C7, C5)).unwrap();
+        assert_eq!(analysis.variations(), 2);
+        // the finished game is left as it was
+        assert_eq!(board.offset(), &(MoveId::START + 1usize));
+        assert_eq!(board.result(), Some(resigned));
+    }
+    #[test]
+    fn test_analysis_board() {
+        let mut board = AnalysisBoard::analyse(None);
+        for mv in [mv(E2, E4), mv(E7, E5), mv(G1, F3)] {
+            board.submit_move(mv).unwrap();
+        }
+        assert_eq!(board.offset(), &(MoveId::START + 3usize));
+        assert_eq!(board.turn(), Color::Black);
+
+        // branching off earlier makes the reviewed position current
+        board.set_offset(MoveId::START + 1usize).unwrap();
+        assert_eq!(board.turn(), Color::Black);
+        board.submit_move(mv(C7, C5)).unwrap();
+        assert_eq!(board.variations(), 2);
+        board.submit_move(mv(G1, F3)).unwrap();
+        board.submit_move(mv(D7, D6)).unwrap();
+        board.prev_variation().unwrap_err();
+        board.set_offset(MoveId::START + 2usize).unwrap();
+        board.prev_variation().unwrap();
+        assert_eq!(board.len(), 4);
+        assert_eq!(board.current()[E5], Some(Material::new(Color::Black, Piece::Pawn)));
+        assert!(board.move_destinations(G1).contains(F3));
+
+        // taking back deletes the moves
+        board.next_variation().unwrap();
+        board.skip_to_end();
+        board.take_back(2).unwrap();
+        assert_eq!(board.offset(), &(MoveId::START + 2usize));
+        assert_eq!(board.len(), 3);
+        assert_eq!(board.take_back(3), Err(TakebackError::NotEnoughMoves { requested: 3, played: 2 }));
+
+        board.set_eval(&(MoveId::START + 2usize), Eval::Centipawns(35)).unwrap();
+        assert_eq!(board.annotations().len(), 1);
+
+        let position = Position::from_fen("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1").unwrap();
+        board.set_position(position);
+        assert_eq!(board.len(), 1);
+        board.submit_move(mv(E8, D7)).unwrap();
+        board.submit_move(mv(E2, E4)).unwrap();
+        assert_eq!(board.current().to_fen(), "8/3k4/8/8/4P3/8/8/4K3 b - e3 0 2");
+    }
+    #[test]
+    fn test_claim_draw_before_repeating_move() {
+        let shuffle = [mv(G1, F3), mv(G8, F6), mv(F3, G1), mv(F6, G8)];
+        let claim = DrawClaim::Repetition(Some(mv(F6, G8)));
+        let mut engine = EngineBoard::standard();
+        let mut player = PlayerBoard::plays_black(None);
+        for (i, mv) in shuffle.iter().cycle().take(7).enumerate() {
+            assert_eq!(engine.claimable_draws(), []);
+            engine.submit_move(*mv).unwrap();
+            if i % 2 == 0 {
+                player.submit_their_move(*mv).unwrap();
+            } else {
+                player.submit_our_move(*mv).unwrap();
+            }
+        }
+        assert_eq!(engine.claimable_draws(), [claim]);
+        assert_eq!(player.claimable_draws(), [claim]);
+        let invalid = DrawClaim::FiftyMoves(None);
+        assert_eq!(engine.claim_draw(invalid), Err(DrawClaimError::NotClaimable(invalid).into()));
+
+        assert_eq!(engine.claim_draw(claim), Ok(BoardResult::Repetition));
+        assert_eq!(engine.plies(), 8);
+        assert_eq!(engine.claimable_draws(), []);
+        assert_eq!(player.claim_draw(claim), Ok(BoardResult::Repetition));
+        assert_eq!(player.board_result(), Some(BoardResult::Repetition));
+    }
+    #[test]
+    fn test_replay_reports_failing_move() {
+        let moves = [mv(E2, E4), mv(E7, E5), mv(G1, F3)];
+        let board = EngineBoard::replay(None, &moves).unwrap();
+        assert_eq!(board.plies(), 3);
+        let board = PlayerBoard::replay(Color::Black, None, &moves).unwrap();
+        assert_eq!(board.len(), 4);
+        assert!(board.our_turn());
+
+        let failure = |moves: &[Move]| EngineBoard::replay(None, moves).err().unwrap();
+        let error = failure(&[mv(E2, E4), mv(E4, E5)]);
+        assert_eq!((error.index, error.reason), (1, ReplayFailure::WrongTurn(E4)));
+        assert_eq!(failure(&[mv(E3, E4)]).reason, ReplayFailure::EmptySquare(E3));
+        assert_eq!(failure(&[mv(E2, E5)]).reason, ReplayFailure::Illegal);
+        let promotion = Move::new(E2, E4, Some(Promotion::Queen));
+        assert_eq!(failure(&[promotion]).reason, ReplayFailure::MalformedPromotion);
+
+        // 1. h4 g5 2. hxg5 Nf6 3. gxf6 Rg8 4. fxe7 Rg7 5. exd8
+        let moves: Vec<Move> = ["h2h4", "g7g5", "h4g5", "g8f6", "g5f6", "h8g8", "f6e7", "g8g7", "e7d8"]
+            .iter()
+            .map(|uci| uci.parse().unwrap())
+            .collect();
+        let (board, error) = PlayerBoard::replay_partial(Color::White, None, &moves);
+        let error = error.unwrap();
+        assert_eq!((error.index, error.reason), (8, ReplayFailure::MalformedPromotion));
+        assert_eq!(board.len(), 9);
+        assert!(board.view().contents(E7).is_some());
+    }
+    #[test]
+    fn test_rebuild_from_fen_and_pgn() {
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 b - - 3 40";
+        let board = EngineBoard::from_fen_and_moves(fen, &[mv(E8, D7), mv(E2, E4)]).unwrap();
+        let pos: &Position = board.as_ref();
+        assert_eq!(pos.to_fen(), "8/3k4/8/8/4P3/8/8/4K3 b - e3 0 41");
+
+        let error = EngineBoard::from_fen_and_moves(fen, &[mv(E8, D7), mv(E1, E3)]).err().unwrap();
+        let replay = ReplayError { index: 1, mv: mv(E1, E3), reason: ReplayFailure::Illegal };
+        assert_eq!(error, ChessError::Replay(replay));
+        let error = EngineBoard::from_fen_and_moves("4k3/8/8/8 b - - 0 1", &[]).err().unwrap();
+        assert!(matches!(error, ChessError::Parse(ParseError::InvalidFen(..))));
+
+        let pgn = format!("[FEN \"{fen}\"]\n\n40... Kd7 41. e4 *");
+        let board = EngineBoard::from_pgn(&pgn).unwrap();
+        assert_eq!(AsRef::<Position>::as_ref(&board).key(), pos.key());
+        let board = EngineBoard::from_pgn("1. f3 e5 2. g4 Qh4# 0-1").unwrap();
+        assert_eq!(board.board_result(), Some(BoardResult::CheckMate(Color::Black)));
+        let error = EngineBoard::from_pgn("1. e4 e5 2. Nf3 Nc6 3. Bb5 Ke6").err().unwrap();
+        assert!(matches!(error, ChessError::Pgn(PgnError::InvalidMove { index: 5, ref token, .. }) if token == "Ke6"));
+        let error = EngineBoard::from_pgn("[FEN \"4k3/8/8/8 b - - 0 1\"]\n\n*").err().unwrap();
+        assert!(matches!(error, ChessError::Pgn(PgnError::InvalidFen(ParseError::InvalidFen(..)))));
+    }
+    #[test]
+    fn test_premove_chain_survives() {
+        let mut board = PlayerBoard::plays_black(None);
+        board.submit_our_move(mv(E7, E5)).unwrap();
+        board.submit_our_move(mv(G8, F6)).unwrap();
+        let replies = [mv(E2, E4), mv(D2, D3)];
+        assert_eq!(board.preview_premove_chain(&replies), None);
+        assert_eq!(board.queued_pre_moves().len(), 2);
+    }
+    #[test]
+    fn test_premove_chain_discarded() {
+        let mut board = PlayerBoard::plays_black(None);
+        board.submit_our_move(mv(D7, D5)).unwrap();
+        board.submit_our_move(mv(D5, E4)).unwrap();
+        let replies = [mv(E2, E4), mv(E4, D5)];
+        let expected = Some(PreMoveBreak::Discarded(1));
+        assert_eq!(board.preview_premove_chain(&replies), expected);
+        board.cancel_pre_moves();
+        board.submit_our_move(mv(D7, C6)).unwrap();
+        let replies = [mv(E2, E4)];
+        let expected = Some(PreMoveBreak::Discarded(0));
+        assert_eq!(board.preview_premove_chain(&replies), expected);
+    }
+    #[test]
+    fn test_premove_chain_illegal_reply() {
+        let mut board = PlayerBoard::plays_black(None);
+        board.submit_our_move(mv(E7, E5)).unwrap();
+        let replies = [mv(E2, E5)];
+        let expected = Some(PreMoveBreak::IllegalReply(0));
+        assert_eq!(board.preview_premove_chain(&replies), expected);
+    }
+    #[test]
+    fn test_their_move_on_our_turn_is_rejected() {
+        let mut board = PlayerBoard::plays_white(None);
+        let error = board.submit_their_move(mv(E2, E4)).unwrap_err();
+        assert_eq!(error, ChessError::Move(MoveError::WrongTurn));
+    }
+    #[test]
+    fn test_review_navigation_is_bounded() {
+        let mut board = PlayerBoard::plays_white(None);
+        assert_eq!(board.back(), Err(ReviewError::AtStart));
+        assert_eq!(board.forward(), Err(ReviewError::AtEnd));
+        board.submit_our_move(mv(E2, E4)).unwrap();
+        assert_eq!(board.back(), Ok(()));
+        assert_eq!(board.forward(), Ok(()));
+        let offset = MoveId::START + 5usize;
+        assert_eq!(board.set_offset(offset), Err(ReviewError::OutOfRange(offset)));
+    }
+}
@@ -12,29 +12,45 @@
 //    See the License for the specific language governing permissions and
 //    limitations under the License.
 
+use std::collections::HashMap;
 use std::hash::Hash;
 use std::ops::{Add, AddAssign, Sub, SubAssign, Index, IndexMut};
-use std::collections::HashSet;
 use serde::ser::SerializeTuple;
 use strum::IntoEnumIterator;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 use super::backrank::{BackRank, BackRanks, BackRankId};
 use super::castling::{
-    CastlingRights, 
+    infer_castling, CastlingRights, 
     Castling, CastlingMut, 
     CastlingRightsRef, CastlingRightsMut
 };
 use super::square::{Square, File, Rank, Mask, Direction};
 use super::material::{Material, Piece, Color, Pair};
-use super::moves::{LegalMove, PreMove};
+use super::moves::{LegalMove, PreMove, PreMoves, MoveError};
+use super::rules::Rules;
+use super::variant::Variant;
 use super::Turn;
 
 use Rank::*;
 use Piece::*;
 use Color::*;
 
+/// The plies without a capture or pawn move that end a game as a draw
+/// under the fifty-move rule.
+pub const FIFTY_MOVE_PLIES: usize = 100;
+/// Leads every serialized `Position` so the layout can change without
+/// relying on self-describing formats
+const SERIALIZED_VERSION: u8 = 1;
+
+/// Identifies a position of a game, and the move played from it, by the
+/// number of plies played before it: `START` is the starting position,
+/// with White to move, and each move advances the id by one.
+///
+/// Arithmetic panics on overflow or underflow rather than wrapping; use
+/// the `checked_*` methods when the result may be out of range.
 #[derive(
     Debug, Serialize, Deserialize, Clone, Copy, 
     PartialEq, Eq, PartialOrd, Ord, Hash
@@ -43,45 +59,95 @@ pub struct MoveId(u16);
 
 impl MoveId {
     pub const START: MoveId = MoveId(0);
+    pub const MAX: MoveId = MoveId(u16::MAX);
 
+    /// Returns the id after `move_count` full moves with `turn` to move.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the id is out of range.
     #[inline]
     pub fn new(move_count: u16, turn: Color) -> Self {
-        match turn {
-            White => Self(move_count*2),
-            Black => Self(move_count*2 + 1),
-        }
+        Self::from_ply(2 * move_count as usize + turn.to_index())
+            .expect("MoveId out of range")
+    }
+    /// Returns the id after `ply` plies, if it's in range.
+    #[inline]
+    pub fn from_ply(ply: usize) -> Option<Self> {
+        u16::try_from(ply).ok().map(Self)
     }
+    /// Returns the id of move `move_number` (counting from 1, as in a
+    /// game record) by `color`, if it's in range.
+    #[inline]
+    pub fn from_fullmove(move_number: usize, color: Color) -> Option<Self> {
+        let move_count = move_number.checked_sub(1)?;
+        Self::from_ply(move_count.checked_mul(2)?.checked_add(color.to_index())?)
+    }
+    /// Returns the number of plies played before this position.
+    #[inline]
+    pub fn ply(&self) -> usize {
+        self.0 as usize
+    }
+    /// Returns the number of the move in a game record, counting from 1.
+    #[inline]
+    pub fn fullmove(&self) -> usize {
+        1 + self.ply() / 2
+    }
+    /// Returns the side to move in this position.
+    #[inline]
+    pub fn color(&self) -> Color {
+        if self.0.is_multiple_of(2) { White } else { Black }
+    }
+    /// Same as `color`.
     #[inline]
     pub fn turn(&self) -> Color {
-        const TURNS: [Color; 2] = [White, Black];
-        let index = self.value() % 2;
-        TURNS[index]
+        self.color()
     }
+    /// Same as `ply`.
     #[inline]
     pub fn value(&self) -> usize {
-        self.0 as usize
+        self.ply()
     }
+    /// Returns the number of full moves played before this position.
     #[inline]
     pub fn move_count(&self) -> usize {
-        self.value() / 2
+        self.ply() / 2
     }
+    /// Same as `fullmove`.
     #[inline]
     pub fn move_number(&self) -> usize {
-        1 + self.move_count()
+        self.fullmove()
     }
     #[inline]
     pub fn at_start(&self) -> bool {
         self.0 == 0
     }
+    /// Returns the next id, or `MAX` at `MAX`.
     #[inline]
     pub fn next(self) -> Self {
-        Self(self.0 + 1)
+        self.checked_next().unwrap_or(Self::MAX)
     }
+    /// Returns the previous id, or `START` at `START`.
     #[inline]
     pub fn prev(self) -> Self {
-        Self(self.0 - 1)
+        self.checked_prev().unwrap_or(Self::START)
+    }
+    #[inline]
+    pub fn checked_next(self) -> Option<Self> {
+        self.0.checked_add(1).map(Self)
+    }
+    #[inline]
+    pub fn checked_prev(self) -> Option<Self> {
+        self.0.checked_sub(1).map(Self)
+    }
+    #[inline]
+    pub fn checked_add(self, plies: usize) -> Option<Self> {
+        Self::from_ply(self.ply().checked_add(plies)?)
+    }
+    #[inline]
+    pub fn checked_sub(self, plies: usize) -> Option<Self> {
+        Self::from_ply(self.ply().checked_sub(plies)?)
     }
-
 }
 
 impl Default for MoveId {
@@ -91,35 +157,44 @@ impl Default for MoveId {
     }
 }
 
+impl TryFrom<usize> for MoveId {
+    type Error = std::num::TryFromIntError;
+    #[inline]
+    fn try_from(ply: usize) -> Result<Self, Self::Error> {
+        u16::try_from(ply).map(Self)
+    }
+}
+
+/// Returns the number of plies from `rhs` to `self`.
 impl Sub for MoveId {
     type Output = usize;
     #[inline]
     fn sub(self, rhs: Self) -> Self::Output {
-        self.value() - rhs.value()
+        self.ply().checked_sub(rhs.ply()).expect("MoveId underflow")
     }
 }
 
 impl<T: Into<usize>> Add<T> for MoveId {
     type Output = MoveId;
     fn add(self, rhs: T) -> Self::Output {
-        Self(self.0 + rhs.into() as u16)
+        self.checked_add(rhs.into()).expect("MoveId overflow")
     }
 }
 impl<T: Into<usize>> AddAssign<T> for MoveId {
     fn add_assign(&mut self, rhs: T) {
-        self.0 += rhs.into() as u16;
+        *self = *self + rhs;
     }
 }
 
 impl<T: Into<usize>> Sub<T> for MoveId {
     type Output = MoveId;
     fn sub(self, rhs: T) -> Self::Output {
-        Self(self.0 - rhs.into() as u16)
+        self.checked_sub(rhs.into()).expect("MoveId underflow")
     }
 }
 impl<T: Into<usize>> SubAssign<T> for MoveId {
     fn sub_assign(&mut self, rhs: T) {
-        self.0 -= rhs.into() as u16;
+        *self = *self - rhs;
     }
 }
 
@@ -214,6 +289,45 @@ impl From<&Squares> for Masks {
 }
 
 impl Masks {
+    /// Checks that colors and piece kinds don't overlap, that each side
+    /// has exactly one king (if `variant` has a royal king), and that
+    /// there are no pawns on either back rank.
+    fn validate(&self, variant: Variant) -> Result<(), InvariantError> {
+        self.validate_structure()?;
+        for color in Color::iter() {
+            if variant.rules().has_royal_king() && (self.pieces[color] & self.kings).len() != 1 {
+                return Err(InvariantError::KingCount(color));
+            }
+        }
+        let back_ranks = Rank1.to_mask() | Rank8.to_mask();
+        if let Some(square) = (self.pawns & back_ranks).iter().next() {
+            return Err(InvariantError::PawnOnBackRank(square));
+        }
+        Ok(())
+    }
+
+    fn validate_structure(&self) -> Result<(), InvariantError> {
+        if let Some(square) = (self.pieces[White] & self.pieces[Black]).iter().next() {
+            return Err(InvariantError::ColorOverlap(square));
+        }
+        let kinds = [
+            self.kings, self.queens, self.rooks,
+            self.bishops, self.knights, self.pawns,
+        ];
+        let mut union = Mask::empty();
+        for kind in kinds {
+            if let Some(square) = (union & kind).iter().next() {
+                return Err(InvariantError::PieceOverlap(square));
+            }
+            union |= kind;
+        }
+        let occupied = self.pieces[White] | self.pieces[Black];
+        if let Some(square) = ((union - occupied) | (occupied - union)).iter().next() {
+            return Err(InvariantError::MaskMismatch(square));
+        }
+        Ok(())
+    }
+
     fn empty() -> Self {
         Self {
             pieces: Pair::new(Mask::empty(), Mask::empty()),
@@ -227,6 +341,44 @@ impl Masks {
     }
 }
 
+/// A change to the contents of the board between two positions, as
+/// reported by `Position::diff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SquareChange {
+    /// `material` moved from one square to another (captures are reported
+    /// as a `Removed` of the captured piece plus a `Moved`).
+    Moved { from: Square, to: Square, material: Material },
+    Removed { square: Square, material: Material },
+    /// `material` appeared without a matching removal, e.g. the piece a
+    /// pawn promoted to (the pawn itself is reported as `Removed`).
+    Added { square: Square, material: Material },
+}
+
+/// Describes the first inconsistency found by `Position::validate`.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvariantError {
+    #[error("{0} is occupied by both colors")]
+    ColorOverlap(Square),
+    #[error("{0} is occupied by more than one piece")]
+    PieceOverlap(Square),
+    #[error("piece and color masks disagree on {0}")]
+    MaskMismatch(Square),
+    #[error("squares and masks disagree on {0}")]
+    SquareMismatch(Square),
+    #[error("{0} must have exactly one king")]
+    KingCount(Color),
+    #[error("pawn on back rank at {0}")]
+    PawnOnBackRank(Square),
+    #[error("{0} has castling rights without king and rook in place")]
+    Castling(Color),
+    #[error("en passant target {0} is implausible")]
+    EnPassant(Square),
+    #[error("fullmove number must be at least 1")]
+    FullmoveNumber,
+    #[error("attack tables are stale")]
+    StaleAttacks,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct PositionKey {
     turn: Color,
@@ -240,10 +392,12 @@ pub struct Position {
     squares: Squares,
     masks: Masks,
     backrank: &'static BackRank,
+    variant: Variant,
     castling: Pair<CastlingRights>,
     en_passant: Option<Square>,
     next_move_id: MoveId,
     moves_since_progress: u8,
+    fullmove_number: u16,
 }
 
 impl Default for Position {
@@ -257,13 +411,16 @@ impl Serialize for Position {
         where
             S: serde::Serializer 
     {
-        let mut tuple = serializer.serialize_tuple(6)?;
+        let mut tuple = serializer.serialize_tuple(9)?;
+        tuple.serialize_element(&SERIALIZED_VERSION)?;
         tuple.serialize_element(&self.masks)?;
         tuple.serialize_element(&self.backrank.id())?;
         tuple.serialize_element(&self.castling)?;
         tuple.serialize_element(&self.en_passant)?;
         tuple.serialize_element(&self.next_move_id)?;
         tuple.serialize_element(&self.moves_since_progress)?;
+        tuple.serialize_element(&self.fullmove_number)?;
+        tuple.serialize_element(&self.variant)?;
         tuple.end()
     }
 }
@@ -275,14 +432,20 @@ impl<'de> Deserialize<'de> for Position {
     {
         struct PositionVisitor;
         impl<'de> serde::de::Visitor<'de> for PositionVisitor {
-            type Value = (Masks, BackRankId, Pair<CastlingRights>, Option<Square>, MoveId, u8);
+            type Value = (Masks, BackRankId, Pair<CastlingRights>, Option<Square>, MoveId, u8, u16, Variant);
             fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                formatter.write_str("a Position struct condensed into a 6-element tuple")
+                formatter.write_str("a Position struct condensed into a versioned 9-element tuple")
             }
             fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
                 where
                     A: serde::de::SeqAccess<'de>, 
             {
+                let version: u8 = seq.next_element()?.ok_or_else(
+                    || serde::de::Error::custom("Missing elements")
+                )?;
+                if version != SERIALIZED_VERSION {
+                    return Err(serde::de::Error::custom(format!("Unsupported Position version {version}")));
+                }
                 let masks = seq.next_element()?.ok_or_else(
                     || serde::de::Error::custom("Missing elements")
                 )?;
@@ -301,13 +464,21 @@ impl<'de> Deserialize<'de> for Position {
                 let moves_since_progress = seq.next_element()?.ok_or_else(
                     || serde::de::Error::custom("Missing elements")
                 )?;
+                let fullmove_number = seq.next_element()?.ok_or_else(
+                    || serde::de::Error::custom("Missing elements")
+                )?;
+                let variant = seq.next_element()?.ok_or_else(
+                    || serde::de::Error::custom("Missing elements")
+                )?;
                 Ok((
                     masks, 
                     backrank_id, 
                     castling, 
                     en_passant, 
                     next_move_id, 
-                    moves_since_progress
+                    moves_since_progress,
+                    fullmove_number,
+                    variant,
                 ))
             }
         }
@@ -317,37 +488,196 @@ impl<'de> Deserialize<'de> for Position {
             castling, 
             en_passant, 
             next_move_id, 
-            moves_since_progress
-        ) = deserializer.deserialize_tuple(7, PositionVisitor)?;
+            moves_since_progress,
+            fullmove_number,
+            variant,
+        ) = deserializer.deserialize_tuple(9, PositionVisitor)?;
         let squares = (&masks).into();
         let backrank = BackRank::lookup(backrank_id);
-        Ok(Position {
+        let position = Position {
             squares,
             masks,
             backrank,
+            variant,
             castling,
             en_passant,
             next_move_id,
             moves_since_progress,
-        })
+            fullmove_number,
+        };
+        position.validate().map_err(serde::de::Error::custom)?;
+        Ok(position)
     }
 }
 
 impl Position {
 
     pub fn new(backrank: &'static BackRank) -> Self {
+        Self::new_variant(backrank, Variant::Standard)
+    }
+
+    /// Returns the starting position of `backrank` for a game of
+    /// `variant`. Castling rights are dropped if the variant doesn't allow
+    /// castling.
+    pub fn new_variant(backrank: &'static BackRank, variant: Variant) -> Self {
+        let mut castling = Pair::<CastlingRights>::default();
+        if !variant.rules().allows_castling() {
+            castling[White].clear();
+            castling[Black].clear();
+        }
         let position = Self {
             squares: Squares::empty(),
             masks: Masks::empty(),
             backrank,
-            castling: Pair::default(),
+            variant,
+            castling,
             en_passant: None,
             next_move_id: MoveId(0),
             moves_since_progress: 0,
+            fullmove_number: 1,
         };
         position.init()
     }
 
+    /// Builds a position from its parts, e.g. when reading a FEN. The
+    /// position is checked with `validate`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn from_parts(
+        contents: impl IntoIterator<Item = (Square, Material)>,
+        backrank: &'static BackRank,
+        variant: Variant,
+        castling: Pair<CastlingRights>,
+        en_passant: Option<Square>,
+        turn: Color,
+        halfmove_clock: u8,
+        fullmove_number: u16,
+    ) -> Result<Self, InvariantError> {
+        let mut squares = Squares::empty();
+        for (square, material) in contents {
+            squares[square] = Some(material);
+        }
+        let position = Self {
+            masks: (&squares).into(),
+            squares,
+            backrank,
+            variant,
+            castling,
+            en_passant,
+            // move ids count from the start of this game's record
+            next_move_id: MoveId(turn.to_index() as u16),
+            moves_since_progress: halfmove_clock,
+            fullmove_number,
+        };
+        position.validate()?;
+        Ok(position)
+    }
+
+    /// Replaces the back rank and castling rights with every right the
+    /// placement allows (see `infer_castling`), e.g. for a position set up
+    /// in an editor. Variants without castling keep no rights.
+    pub fn with_inferred_castling(mut self) -> Self {
+        if !self.variant.rules().allows_castling() {
+            return self;
+        }
+        let contents: Vec<(Square, Material)> = Square::iter()
+            .filter_map(|square| self.squares[square].map(|material| (square, material)))
+            .collect();
+        (self.backrank, self.castling) = infer_castling(&contents);
+        self
+    }
+
+    /// Returns the position mirrored left to right, so the a-file and the
+    /// h-file swap. Castling rights are kept by mirroring the back rank,
+    /// which makes a Chess960 position of a standard one: the king still
+    /// castles onto the c- or g-file, so castling isn't mirrored exactly.
+    pub fn mirror_horizontal(&self) -> Self {
+        let mut pieces = [King; 8];
+        for file in File::iter() {
+            pieces[file.mirror().to_index()] = self.backrank[file];
+        }
+        let id = BackRank::identify(&pieces).expect("a mirrored Chess960 back rank is one too");
+        let mirrored = |color: Color| CastlingRights::new(color, self.castling[color].ooo(), self.castling[color].oo());
+        let castling = Pair::new(mirrored(White), mirrored(Black));
+        let en_passant = self.en_passant.map(|square| square.mirror_horizontal());
+        self.transform(Square::mirror_horizontal, false, BackRank::lookup(id), castling, en_passant, self.next_move_id)
+    }
+
+    /// Returns the position mirrored top to bottom, so the first rank and
+    /// the eighth swap, with the same pieces and side to move. Each side's
+    /// pieces end up facing the wrong way, so castling rights and the en
+    /// passant square are dropped.
+    pub fn mirror_vertical(&self) -> Self {
+        let mut castling = self.castling;
+        castling[White].clear();
+        castling[Black].clear();
+        self.transform(Square::mirror_vertical, false, self.backrank, castling, None, self.next_move_id)
+    }
+
+    /// Returns the same position from the other side's point of view: the
+    /// board mirrored top to bottom with the colors of the pieces, the
+    /// castling rights and the side to move swapped. A position and its
+    /// swap are equally good for opposite sides.
+    pub fn swap_colors(&self) -> Self {
+        let castling = Pair::new(
+            CastlingRights::new(White, self.castling[Black].oo(), self.castling[Black].ooo()),
+            CastlingRights::new(Black, self.castling[White].oo(), self.castling[White].ooo()),
+        );
+        let en_passant = self.en_passant.map(|square| square.mirror_vertical());
+        let next_move_id = match self.turn() {
+            White => self.next_move_id + 1usize,
+            Black => self.next_move_id - 1usize,
+        };
+        self.transform(Square::mirror_vertical, true, self.backrank, castling, en_passant, next_move_id)
+    }
+
+    /// Moves every piece to `square(from)`, swapping its color if
+    /// `swap_colors`, for the transforms above.
+    fn transform(
+        &self,
+        square: impl Fn(&Square) -> Square,
+        swap_colors: bool,
+        backrank: &'static BackRank,
+        castling: Pair<CastlingRights>,
+        en_passant: Option<Square>,
+        next_move_id: MoveId,
+    ) -> Self {
+        let mut squares = Squares::empty();
+        for (from, material) in self.placements() {
+            let color = match swap_colors {
+                true => !material.color(),
+                false => material.color(),
+            };
+            squares[square(&from)] = Some(Material::new(color, material.piece()));
+        }
+        let position = Self {
+            masks: (&squares).into(),
+            squares,
+            backrank,
+            variant: self.variant,
+            castling,
+            en_passant,
+            next_move_id,
+            moves_since_progress: self.moves_since_progress,
+            fullmove_number: self.fullmove_number,
+        };
+        position.debug_validate();
+        position
+    }
+
+    /// Returns the starting position for `id`, shared and cached so that
+    /// previews of many 960 starts don't need a board or position each.
+    /// All 960 positions are built on first use.
+    pub fn starting(id: BackRankId) -> &'static Position {
+        &STARTING_POSITIONS[id.to_index()]
+    }
+
+    /// Returns the back rank whose starting position is `fen`, if any.
+    /// Chess960 FENs write castling rights in several ways, so only the
+    /// placement and the side to move are compared.
+    pub fn starting_id(fen: &str) -> Option<BackRankId> {
+        STARTING_IDS.get(&starting_key(fen)).copied()
+    }
+
     fn init(mut self) -> Self {
         self.init_file(self.backrank.king(), King);
         self.init_file(self.backrank.queen(), Queen);
@@ -389,6 +719,116 @@ impl Position {
         &self.squares
     }
 
+    /// Reports the changes that turn `self` into `other`: pieces that
+    /// moved, disappeared or appeared. A disappearing piece is paired
+    /// with the nearest appearing piece of the same material to form a
+    /// move, so castling yields two moves and an en passant capture a
+    /// move plus a removal. Changes are listed as moves (ordered by
+    /// destination), then removals, then additions, each in square order
+    /// (`A8` to `H1`).
+    pub fn diff(&self, other: &Position) -> Vec<SquareChange> {
+        let mut removed: Vec<(Square, Material)> = Vec::new();
+        let mut added: Vec<(Square, Material)> = Vec::new();
+        for square in Square::iter() {
+            let (before, after) = (self.squares[square], other.squares[square]);
+            if before == after {
+                continue;
+            }
+            if let Some(material) = before {
+                removed.push((square, material));
+            }
+            if let Some(material) = after {
+                added.push((square, material));
+            }
+        }
+        let distance = |a: Square, b: Square| {
+            a.file_index().abs_diff(b.file_index())
+                .max(a.rank_index().abs_diff(b.rank_index()))
+        };
+        let mut changes = Vec::new();
+        added.retain(|&(to, material)| {
+            let nearest = removed.iter()
+                .enumerate()
+                .filter(|(_, (_, removed))| *removed == material)
+                .min_by_key(|(_, (from, _))| distance(*from, to))
+                .map(|(index, _)| index);
+            match nearest {
+                Some(index) => {
+                    let (from, _) = removed.remove(index);
+                    changes.push(SquareChange::Moved { from, to, material });
+                    false
+                },
+                None => true,
+            }
+        });
+        changes.extend(removed.into_iter()
+            .map(|(square, material)| SquareChange::Removed { square, material }));
+        changes.extend(added.into_iter()
+            .map(|(square, material)| SquareChange::Added { square, material }));
+        changes
+    }
+
+    /// Verifies the internal consistency of the position: squares agree
+    /// with masks, each side has exactly one king (unless the variant's
+    /// king isn't royal), castling rights are
+    /// backed by a king and rook on their starting squares, and the en
+    /// passant target could have been produced by the previous move.
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        self.validate_squares()?;
+        self.masks.validate(self.variant)?;
+        for color in Color::iter() {
+            let castling = CastlingRightsRef::new(&self.castling[color], self.backrank);
+            let king = Material::new(color, King);
+            let rook = Some(Material::new(color, Rook));
+            let king_ok = self.squares[castling.king_src()] == Some(king);
+            let oo_ok = !castling.oo() || (king_ok && self.squares[castling.oo_rook_src()] == rook);
+            let ooo_ok = !castling.ooo() || (king_ok && self.squares[castling.ooo_rook_src()] == rook);
+            if !oo_ok || !ooo_ok {
+                return Err(InvariantError::Castling(color));
+            }
+        }
+        if self.fullmove_number == 0 {
+            return Err(InvariantError::FullmoveNumber);
+        }
+        if let Some(target) = self.en_passant {
+            // the side that just moved double advanced a pawn across `target`
+            let mover = !self.turn();
+            let (target_rank, pawn_offset) = match mover {
+                White => (Rank3, -1),
+                Black => (Rank6, 1),
+            };
+            let pawn = (target.rank() + pawn_offset).map(|rank| Square::new(target.file(), rank));
+            let plausible = target.rank() == target_rank
+                && self.squares[target].is_none()
+                && pawn.is_some_and(|pawn| self.squares[pawn] == Some(Material::new(mover, Pawn)));
+            if !plausible {
+                return Err(InvariantError::EnPassant(target));
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `validate` in debug builds and panics on failure. This is a
+    /// no-op in release builds.
+    #[inline]
+    pub fn debug_validate(&self) {
+        #[cfg(debug_assertions)]
+        if let Err(error) = self.validate() {
+            panic!("invalid position: {error}");
+        }
+    }
+
+    fn validate_squares(&self) -> Result<(), InvariantError> {
+        self.masks.validate_structure()?;
+        let expected: Squares = (&self.masks).into();
+        for square in Square::iter() {
+            if expected[square] != self.squares[square] {
+                return Err(InvariantError::SquareMismatch(square));
+            }
+        }
+        Ok(())
+    }
+
     pub fn masks(&self) -> &Masks {
         &self.masks
     }
@@ -397,6 +837,16 @@ impl Position {
         self.backrank
     }
 
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn rules(&self) -> &'static dyn Rules {
+        self.variant.rules()
+    }
+
+    /// Returns the number of the current move counted from the start of
+    /// this game's record (see `fullmove_number` for the FEN field).
     pub fn move_number(&self) -> usize {
         self.next_move_id.move_number()
     }
@@ -405,10 +855,41 @@ impl Position {
         self.moves_since_progress as usize
     }
 
+    /// Returns how many more plies without a capture or pawn move end the
+    /// game under the fifty-move rule, or zero if the limit is reached.
+    pub fn plies_until_fifty_move_draw(&self) -> usize {
+        FIFTY_MOVE_PLIES.saturating_sub(self.moves_since_progress())
+    }
+
+    /// Returns the FEN halfmove clock: plies since the last capture or
+    /// pawn move.
+    pub fn halfmove_clock(&self) -> usize {
+        self.moves_since_progress()
+    }
+
+    /// Returns the FEN fullmove number, which starts at 1 and increases
+    /// after each move by Black. Unlike `move_number`, it doesn't depend
+    /// on `MoveId`, so it stays correct for games that began from a
+    /// position reached in another game.
+    pub fn fullmove_number(&self) -> usize {
+        self.fullmove_number as usize
+    }
+
     pub fn en_passant(&self) -> Option<Square> {
         self.en_passant
     }
 
+    /// Returns White's material minus Black's in conventional points
+    /// (see `Piece::value`), so positive when White is ahead.
+    pub fn material_balance(&self) -> i32 {
+        self.placements()
+            .map(|(_, material)| match material.color() {
+                Color::White => material.piece().value(),
+                Color::Black => -material.piece().value(),
+            })
+            .sum()
+    }
+
     pub fn our_mating_material(&self) -> MatingMaterial {
         self.mating_material(self.turn())
     }
@@ -417,7 +898,7 @@ impl Position {
         self.mating_material(!self.turn())
     }
 
-    fn mating_material(&self, side: Color) -> MatingMaterial {
+    pub fn mating_material(&self, side: Color) -> MatingMaterial {
         let pieces = self.masks.pieces[side] & !self.masks.kings;
         let pawns = pieces & self.masks.pawns;
         if !pawns.is_empty() {
@@ -449,8 +930,20 @@ impl Position {
         MatingMaterial::LoneKing
     }
     
-    pub fn apply_move(&mut self, mv: LegalMove) -> MoveId {
-        self.moves_since_progress += 1;
+    /// Applies a legal move and toggles the turn.
+    ///
+    /// The move is checked against the contents of the board before any
+    /// changes are made, so an error leaves the position untouched.
+    pub fn apply_move(&mut self, mv: LegalMove) -> Result<MoveId, MoveError> {
+        self.check_move(mv)?;
+        let move_id = self.next_move_id;
+        let next_move_id = move_id.checked_next().ok_or(MoveError::MoveLimit)?;
+        self.moves_since_progress = self.moves_since_progress.saturating_add(1);
+        if self.turn() == Black {
+            self.fullmove_number = self.fullmove_number.saturating_add(1);
+        }
+        // Safety: `check_move` ensures the squares we remove from are
+        // occupied, so the `unwrap` calls below cannot fail
         match mv {
             LegalMove::Standard(from, to) => {
                 let material = self.remove(from).unwrap();
@@ -503,14 +996,20 @@ impl Position {
                 self.en_passant = None;
             },
         };
-        let move_id = self.next_move_id;
-        self.next_move_id = move_id.next();
-        move_id
+        self.next_move_id = next_move_id;
+        self.debug_validate();
+        Ok(move_id)
     }
 
-    pub fn apply_pre_move(&mut self, mv: PreMove) {
+    /// Applies a pre-move for the side that is not on turn. The turn is
+    /// not toggled. As with `apply_move`, an error leaves the position
+    /// untouched.
+    pub fn apply_pre_move(&mut self, mv: PreMove) -> Result<(), MoveError> {
+        self.check_pre_move(mv)?;
         // Note: it's not "our" turn, so we use "their" to refer to
         // the side performing the pre-move, and vise versa.
+        // Safety: `check_pre_move` ensures the squares we remove from are
+        // occupied, so the `unwrap` calls below cannot fail
         match mv {
             PreMove::Standard(from, to) => {
                 let material = self.remove(from).unwrap();
@@ -539,6 +1038,63 @@ impl Position {
                 self.their_castling_mut().clear();
             },
         }
+        // Pre-move previews aren't reachable positions (the turn doesn't
+        // toggle and pieces may land on their own side's pieces), so only
+        // the structure of the board is checked
+        debug_assert_eq!(self.validate_squares(), Ok(()));
+        Ok(())
+    }
+
+    fn require(&self, square: Square, color: Color) -> Result<(), MoveError> {
+        match self.squares[square] {
+            Some(material) if material.color() == color => Ok(()),
+            _ => Err(MoveError::EmptySquare(square)),
+        }
+    }
+
+    fn check_move(&self, mv: LegalMove) -> Result<(), MoveError> {
+        let turn = self.turn();
+        match mv {
+            LegalMove::Standard(from, _) | LegalMove::Promoting(from, _, _) => {
+                self.require(from, turn)
+            },
+            LegalMove::EnPassant(from, to) => {
+                self.require(from, turn)?;
+                self.require(Square::new(to.file(), from.rank()), !turn)
+            },
+            LegalMove::DoubleAdvance(from, to) => {
+                self.require(from, turn)?;
+                if between(from, to).is_empty() {
+                    return Err(MoveError::InvalidMove);
+                }
+                Ok(())
+            },
+            LegalMove::ShortCastle => {
+                self.require(self.our_king_src(), turn)?;
+                self.require(self.our_oo_rook_src(), turn)
+            },
+            LegalMove::LongCastle => {
+                self.require(self.our_king_src(), turn)?;
+                self.require(self.our_ooo_rook_src(), turn)
+            },
+        }
+    }
+
+    fn check_pre_move(&self, mv: PreMove) -> Result<(), MoveError> {
+        let side = !self.turn();
+        match mv {
+            PreMove::Standard(from, _) | PreMove::Promoting(from, _, _) => {
+                self.require(from, side)
+            },
+            PreMove::ShortCastle => {
+                self.require(self.their_king_src(), side)?;
+                self.require(self.their_oo_rook_src(), side)
+            },
+            PreMove::LongCastle => {
+                self.require(self.their_king_src(), side)?;
+                self.require(self.their_ooo_rook_src(), side)
+            },
+        }
     }
 
     fn place(&mut self, square: Square, material: Material) -> Option<Material> {
@@ -605,6 +1161,8 @@ impl BackRanks for Position {}
 
 impl Pos for Position {}
 
+impl PreMoves for Position {}
+
 
 impl Position {
     #[inline]
@@ -664,23 +1222,23 @@ impl Position {
         self.their_castling().ooo_rook_dest()
     }
     #[inline]
-    pub fn our_castling(&self) -> CastlingRightsRef {
+    pub fn our_castling(&self) -> CastlingRightsRef<'_> {
         let turn = self.turn();
         CastlingRightsRef::new(&self.castling[turn], self.backrank)
     }
     #[inline]
-    pub fn their_castling(&self) -> CastlingRightsRef {
+    pub fn their_castling(&self) -> CastlingRightsRef<'_> {
         let turn = self.turn();
         CastlingRightsRef::new(&self.castling[!turn], self.backrank)
     }
     
     #[inline]
-    pub fn our_castling_mut(&mut self) -> CastlingRightsMut {
+    pub fn our_castling_mut(&mut self) -> CastlingRightsMut<'_> {
         let turn = self.turn();
         CastlingRightsMut::new(&mut self.castling[turn], self.backrank)
     }
     #[inline]
-    pub fn their_castling_mut(&mut self) -> CastlingRightsMut {
+    pub fn their_castling_mut(&mut self) -> CastlingRightsMut<'_> {
         let turn = self.turn();
         CastlingRightsMut::new(&mut self.castling[!turn], self.backrank)
     }
@@ -857,119 +1415,101 @@ pub(super) fn between(from: Square, to: Square) -> Mask {
     SQUARES_BETWEEN[index]
 }
 
-pub(super) static SQUARES_BETWEEN: Lazy<[Mask; 64 * 64]> = Lazy::new(|| {
-
-    // Returns a mask of squares between `start` and `end` (exclusive of both)
-    // if they are not equal and in a line. Otherwise returns an empty mask.
-    fn squares_between(start: Square, end: Square) -> Mask {
-        let mut mask = Mask::empty();
-        if let Some(step) = (end - start).to_unit() {
-            if let Some(mut start) = start + step {
-                while start != end {
-                    mask |= start.to_mask();
-                    // Safety: calling `unwrap` here is safe because
-                    // `step` is a unit from start to end and we'll
-                    // always hit `end` before dropping off the edge
-                    start = (start + step).unwrap();
-                }
-            }
-        }
-        mask
-    }
-
-    let mut array = [Mask::empty(); 64 * 64];
-    let mut visited = HashSet::new();
-    for start in Square::iter() {
-        let start_index = start.to_index();
-        for end in Square::iter() {
-            if start == end { continue; }
-            let end_index = end.to_index();
-            let index1 = start_index * 64 + end_index;
-            let index2: usize = end_index * 64 + start_index;
-            if !visited.contains(&index1) {
-                visited.insert(index1);
-                visited.insert(index2);
-                if ALL_LINES[start_index].contains(end) {
-                    // Safety: `start` and `end` are in a line with each 
-                    // other and are not equal
-                    let mask = squares_between(start, end);
-                    array[index1] = mask;
-                    array[index2] = mask;
-                }
-            }
-        }
-    }
-    array
+static STARTING_POSITIONS: Lazy<Vec<Position>> = Lazy::new(|| {
+    (0..960usize)
+        .map(|index| Position::new(BackRank::lookup(BackRankId::try_from(index).unwrap())))
+        .collect()
 });
 
-pub(super) static SQUARES_SHIELDED: Lazy<[Mask; 64 * 64]> = Lazy::new(|| {
-
-    // Returns a mask of squares between `end` (exclusive) and the edge of
-    // the board if we draw a line from `start` through `end`. Returns `None`
-    // if `start` and `end` are equal or not in a line.
-    fn squares_shielded(start: Square, end: Square) -> Mask {
-        let mut mask = Mask::empty();
-        if let Some(step) = (end - start).to_unit() {
-            let mut next = end + step;
-            while next.is_some() {
-                let square = next.unwrap();
-                mask |= square.to_mask();
-                next = square + step;
-            }
-        }
-        mask
-    }
-
-    let mut array = [Mask::empty(); 64 * 64];
-    for start in Square::iter() {
-        let start_index = start.to_index();
-        for end in Square::iter() {
-            if start == end { continue; }
-            let end_index = end.to_index();
-            let index = start_index * 64 + end_index;
-            if ALL_LINES[start_index].contains(end) {
-                // Safety: `start` and `end` are in a line with each 
-                // other and are not equal
-                let mask = squares_shielded(start, end);
-                array[index] = mask;
-            }
-        }
-    }
-    array
+/// Looks up starting positions by `starting_key`.
+static STARTING_IDS: Lazy<HashMap<String, BackRankId>> = Lazy::new(|| {
+    STARTING_POSITIONS.iter()
+        .map(|pos| (starting_key(&pos.to_fen()), pos.backrank.id()))
+        .collect()
 });
 
-pub(super) static HORIZONTALS: Lazy<[Mask; 64]> = Lazy::new(|| {
-    let mut array = [Mask::default(); 64];
-    for square in Square::iter() {
-        let mask = square.file().to_mask() | square.rank().to_mask();
-        array[square.to_index()] = mask;
-    }
-    array
-});
+/// Returns the placement and side to move fields of `fen`.
+fn starting_key(fen: &str) -> String {
+    fen.split_whitespace().take(2).collect::<Vec<_>>().join(" ")
+}
 
-pub(super) static DIAGONALS: Lazy<[Mask; 64]> = Lazy::new(|| {
-    let mut array = [Mask::default(); 64];
-    for square in Square::iter() {
-        let mut mask = square.to_mask();
-        Direction::diagonals().for_each(|dir| {
-            let mut next = square + dir;
-            loop {
-                let Some(sq) = next else { break };
-                mask |= sq.to_mask();
-                next = sq + dir;
-            }
-        });
-        array[square.to_index()] = mask;
-    }
-    array
-});
+/// Returns the direction from `start` to `end` if they're different
+/// squares on a line.
+const fn direction_between(start: Square, end: Square) -> Option<Direction> {
+    let files = end.file_index() as isize - start.file_index() as isize;
+    let ranks = end.rank_index() as isize - start.rank_index() as isize;
+    if (files == 0 && ranks == 0) || (files != 0 && ranks != 0 && files.abs() != ranks.abs()) {
+        return None;
+    }
+    // rank indexes grow downwards, from rank 8 to rank 1
+    Some(match (files.signum(), ranks.signum()) {
+        (-1, -1) => Direction::UpLeft,
+        (0, -1) => Direction::Up,
+        (1, -1) => Direction::UpRight,
+        (-1, 0) => Direction::Left,
+        (1, 0) => Direction::Right,
+        (-1, 1) => Direction::DownLeft,
+        (0, 1) => Direction::Down,
+        _ => Direction::DownRight,
+    })
+}
 
-pub(super) static ALL_LINES: Lazy<[Mask; 64]> = Lazy::new(|| {
-    let mut array = [Mask::default(); 64];
-    for square in Square::iter() {
-        array[square] = HORIZONTALS[square] | DIAGONALS[square];
+/// Builds a table indexed by `start * 64 + end` from the direction of each
+/// line; squares not on a line map to an empty mask.
+const fn line_table(f: LineMask) -> [Mask; 64 * 64] {
+    let mut table = [Mask::empty(); 64 * 64];
+    let mut index = 0;
+    while index < 64 * 64 {
+        let start = Square::from_index(index / 64);
+        let end = Square::from_index(index % 64);
+        if let Some(direction) = direction_between(start, end) {
+            let from_start = start.to_mask().fill(direction).inner();
+            let from_end = end.to_mask().fill(direction).inner();
+            table[index] = Mask::new(match f {
+                // Squares between `start` and `end` (exclusive of both)
+                LineMask::Between => from_start & !from_end & !start.to_mask().inner(),
+                // Squares between `end` (exclusive) and the edge of the
+                // board on a line drawn from `start` through `end`
+                LineMask::Shielded => from_end & !end.to_mask().inner(),
+            });
+        }
+        index += 1;
     }
-    array
+    table
+}
+
+#[derive(Clone, Copy)]
+enum LineMask {
+    Between,
+    Shielded,
+}
+
+pub(super) static SQUARES_BETWEEN: [Mask; 64 * 64] = line_table(LineMask::Between);
+
+pub(super) static SQUARES_SHIELDED: [Mask; 64 * 64] = line_table(LineMask::Shielded);
+
+/// Returns the rank and file of `square`, including `square` itself.
+const fn horizontals(square: Square) -> Mask {
+    Mask::union(&[square.file().to_mask(), square.rank().to_mask()])
+}
+
+/// Returns the diagonals through `square`, including `square` itself.
+const fn diagonals(square: Square) -> Mask {
+    let square = square.to_mask();
+    Mask::union(&[
+        square.fill(Direction::UpLeft),
+        square.fill(Direction::UpRight),
+        square.fill(Direction::DownLeft),
+        square.fill(Direction::DownRight),
+    ])
+}
+
+pub(super) static HORIZONTALS: [Mask; 64] = square_table!(|square| horizontals(square));
+
+pub(super) static DIAGONALS: [Mask; 64] = square_table!(|square| diagonals(square));
+
+pub(super) static ALL_LINES: [Mask; 64] = square_table!(|square| {
+    Mask::union(&[horizontals(square), diagonals(square)])
 });
 
 #[cfg(test)]
@@ -1011,6 +1551,10 @@ impl Position {
         self.moves_since_progress = value;
         self
     }
+    pub fn set_fullmove_number(mut self, value: u16) -> Self {
+        self.fullmove_number = value;
+        self
+    }
 }
 
 #[cfg(test)]
@@ -1018,6 +1562,230 @@ mod tests {
     use super::*;
     use Square::*;
 
+    #[test]
+    fn test_move_id_conversions() {
+        let id = MoveId::from_fullmove(3, Black).unwrap();
+        assert_eq!((id.ply(), id.fullmove(), id.color()), (5, 3, Black));
+        assert_eq!(id, MoveId::new(2, Black));
+        assert_eq!(MoveId::from_fullmove(1, White), Some(MoveId::START));
+        assert_eq!(MoveId::from_fullmove(0, White), None);
+        assert_eq!(MoveId::from_fullmove(40000, White), None);
+        assert_eq!(MoveId::try_from(5usize), Ok(id));
+        assert!(MoveId::try_from(70000usize).is_err());
+    }
+
+    #[test]
+    fn test_move_id_navigation_is_checked() {
+        assert_eq!(MoveId::START.checked_prev(), None);
+        assert_eq!(MoveId::START.prev(), MoveId::START);
+        assert_eq!(MoveId::MAX.checked_next(), None);
+        assert_eq!(MoveId::MAX.next(), MoveId::MAX);
+        assert_eq!(MoveId::START.checked_add(70000usize), None);
+        assert_eq!(MoveId::MAX.checked_sub(u16::MAX as usize), Some(MoveId::START));
+        assert_eq!(MoveId::START + 4usize - 1usize, MoveId::from_ply(3).unwrap());
+        let overflow = std::panic::catch_unwind(|| MoveId::MAX + 1usize);
+        assert!(overflow.is_err());
+    }
+
+    #[test]
+    fn test_apply_move_from_empty_square_is_rejected() {
+        let mut position = Position::default();
+        let key = position.key();
+        let result = position.apply_move(LegalMove::Standard(E3, E4));
+        assert_eq!(result, Err(MoveError::EmptySquare(E3)));
+        let result = position.apply_move(LegalMove::Standard(E7, E5));
+        assert_eq!(result, Err(MoveError::EmptySquare(E7)));
+        let result = position.apply_move(LegalMove::EnPassant(E2, D3));
+        assert_eq!(result, Err(MoveError::EmptySquare(D2)));
+        assert_eq!(position.key(), key);
+    }
+    #[test]
+    fn test_apply_pre_move_from_empty_square_is_rejected() {
+        let mut position = Position::default();
+        let result = position.apply_pre_move(PreMove::Standard(E2, E4));
+        assert_eq!(result, Err(MoveError::EmptySquare(E2)));
+        assert!(position.apply_pre_move(PreMove::Standard(E7, E5)).is_ok());
+    }
+    #[test]
+    fn test_moves_since_progress_saturates() {
+        let mut position = Position::default()
+            .set_moves_since_progress(u8::MAX);
+        assert!(position.apply_move(LegalMove::Standard(G1, F3)).is_ok());
+        assert_eq!(position.moves_since_progress(), u8::MAX as usize);
+    }
+    #[test]
+    fn test_move_counters() {
+        let mut position = Position::default();
+        assert_eq!((position.halfmove_clock(), position.fullmove_number()), (0, 1));
+        position.apply_move(LegalMove::Standard(G1, F3)).unwrap();
+        assert_eq!((position.halfmove_clock(), position.fullmove_number()), (1, 1));
+        position.apply_move(LegalMove::Standard(G8, F6)).unwrap();
+        assert_eq!((position.halfmove_clock(), position.fullmove_number()), (2, 2));
+
+        // a game continuing from move 40 of another game
+        let mut position = Position::default().set_fullmove_number(40);
+        position.apply_move(LegalMove::DoubleAdvance(E2, E4)).unwrap();
+        position.apply_move(LegalMove::DoubleAdvance(E7, E5)).unwrap();
+        assert_eq!(position.fullmove_number(), 41);
+        assert_eq!(position.move_number(), 2);
+        let json = serde_json::to_string(&position).unwrap();
+        let restored: Position = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.fullmove_number(), 41);
+
+        // the layout is versioned rather than inferred from its length
+        assert!(json.starts_with("[1,"));
+        let future = json.replacen("[1,", "[2,", 1);
+        assert!(serde_json::from_str::<Position>(&future).is_err());
+        let truncated = json.replace(r#",41,"Standard"]"#, "]");
+        assert!(serde_json::from_str::<Position>(&truncated).is_err());
+        let zero = json.replace(",41,", ",0,");
+        assert!(serde_json::from_str::<Position>(&zero).is_err());
+    }
+    #[test]
+    fn test_starting_positions_are_shared() {
+        let standard = Position::starting(BackRankId::STANDARD);
+        assert!(std::ptr::eq(standard, Position::starting(BackRankId::STANDARD)));
+        assert_eq!(standard.key(), Position::new(BackRank::lookup(BackRankId::STANDARD)).key());
+        for index in [0usize, 959] {
+            let id = BackRankId::try_from(index).unwrap();
+            let backrank: &BackRank = Position::starting(id).as_ref();
+            assert_eq!(backrank.id(), id);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_round_trip() {
+        let position = Position::default();
+        let json = serde_json::to_string(&position).unwrap();
+        let result: Position = serde_json::from_str(&json).unwrap();
+        assert_eq!(result.key(), position.key());
+    }
+    #[test]
+    fn test_deserialize_rejects_malformed_positions() {
+        let missing_king = Position::default().set_contents(E8, None);
+        let json = serde_json::to_string(&missing_king).unwrap();
+        assert!(serde_json::from_str::<Position>(&json).is_err());
+
+        let pawn_on_back_rank = Position::default().set_contents(A8, Some(Material::WP));
+        let json = serde_json::to_string(&pawn_on_back_rank).unwrap();
+        assert!(serde_json::from_str::<Position>(&json).is_err());
+
+        let json = serde_json::to_string(&Position::default()).unwrap();
+        let bad_backrank = json.replacen("518", "960", 1);
+        assert!(serde_json::from_str::<Position>(&bad_backrank).is_err());
+    }
+    #[test]
+    fn test_diff() {
+        let before = Position::default();
+        assert!(before.diff(&before).is_empty());
+
+        let mut after = before.clone();
+        after.apply_move(LegalMove::DoubleAdvance(E2, E4)).unwrap();
+        after.apply_move(LegalMove::DoubleAdvance(D7, D5)).unwrap();
+        let mut capture = after.clone();
+        capture.apply_move(LegalMove::Standard(E4, D5)).unwrap();
+        assert_eq!(after.diff(&capture), vec![
+            SquareChange::Moved { from: E4, to: D5, material: Material::WP },
+            SquareChange::Removed { square: D5, material: Material::BP },
+        ]);
+        // and back again, the captured pawn reappears
+        assert_eq!(capture.diff(&after), vec![
+            SquareChange::Moved { from: D5, to: E4, material: Material::WP },
+            SquareChange::Added { square: D5, material: Material::BP },
+        ]);
+    }
+    #[test]
+    fn test_diff_castling_and_promotion() {
+        let before = Position::default()
+            .set_contents(F1, None)
+            .set_contents(G1, None)
+            .set_contents(B7, Some(Material::WP))
+            .set_contents(B8, None);
+        let mut after = before.clone();
+        after.apply_move(LegalMove::ShortCastle).unwrap();
+        assert_eq!(before.diff(&after), vec![
+            SquareChange::Moved { from: H1, to: F1, material: Material::WR },
+            SquareChange::Moved { from: E1, to: G1, material: Material::WK },
+        ]);
+        let mut after = before.clone();
+        after.apply_move(LegalMove::Promoting(B7, B8, crate::Promotion::Queen)).unwrap();
+        assert_eq!(before.diff(&after), vec![
+            SquareChange::Removed { square: B7, material: Material::WP },
+            SquareChange::Added { square: B8, material: Material::WQ },
+        ]);
+    }
+    #[test]
+    fn test_validate_castling_rights() {
+        assert_eq!(Position::default().validate(), Ok(()));
+        let position = Position::default().set_contents(H1, None);
+        assert_eq!(position.validate(), Err(InvariantError::Castling(White)));
+        let position = Position::default().set_contents(H1, None).clear_white_oo();
+        assert_eq!(position.validate(), Ok(()));
+        let position = Position::default().set_contents(A8, Some(Material::BN));
+        assert_eq!(position.validate(), Err(InvariantError::Castling(Black)));
+    }
+
+    #[test]
+    fn test_inferred_castling() {
+        let inferred = |fen: &str| Position::from_fen(fen).unwrap().with_inferred_castling();
+        let pos = inferred("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1");
+        assert_eq!(pos.backrank().id(), BackRankId::STANDARD);
+        assert_eq!(pos.to_fen(), "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        // Chess960 rooks, with an inner rook that doesn't get the rights
+        let pos = inferred("1r2k1r1/8/8/8/8/8/8/1RR1K1R1 w - - 0 1");
+        assert_eq!(pos.to_fen(), "1r2k1r1/8/8/8/8/8/8/1RR1K1R1 w GBgb - 0 1");
+        assert_eq!(pos.validate(), Ok(()));
+        // the kings disagree, so only one side keeps its rights
+        let pos = inferred("r2k3r/8/8/8/8/8/8/R3K2R b - - 0 1");
+        assert_eq!(pos.to_fen(), "r2k3r/8/8/8/8/8/8/R3K2R b KQ - 0 1");
+        let pos = inferred("4k3/8/8/8/8/8/4K3/R6R w - - 0 1");
+        assert_eq!(pos.to_fen(), "4k3/8/8/8/8/8/4K3/R6R w - - 0 1");
+        // a Chess960 starting position keeps its own back rank
+        let id = BackRankId::try_from(518usize + 1).unwrap();
+        let start = Position::starting(id).clone().clear_white_oo().clear_white_ooo();
+        assert_eq!(start.with_inferred_castling().to_fen(), Position::starting(id).to_fen());
+    }
+    #[test]
+    fn test_mirror_and_swap_colors() {
+        let mut position = Position::default();
+        position.apply_move(LegalMove::DoubleAdvance(E2, E4)).unwrap();
+
+        let swapped = position.swap_colors();
+        assert_eq!(swapped.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/8/8/PPPPPPPP/RNBQKBNR w KQkq e6 0 1");
+        assert_eq!(swapped.validate(), Ok(()));
+        assert_eq!(swapped.swap_colors().key(), position.key());
+        assert_eq!(swapped.swap_colors().turn(), Black);
+
+        let mirrored = position.mirror_horizontal();
+        assert_eq!(mirrored.to_fen(), "rnbkqbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBKQBNR b HAha d3 0 1");
+        assert_eq!(mirrored.validate(), Ok(()));
+        let oo_rook = CastlingRightsRef::new(&mirrored.castling[White], mirrored.backrank).oo_rook_src();
+        assert_eq!(oo_rook, H1);
+        assert_eq!(mirrored.mirror_horizontal().key(), position.key());
+        assert_eq!(mirrored.mirror_horizontal().backrank().id(), BackRankId::STANDARD);
+
+        let flipped = position.mirror_vertical();
+        assert_eq!(flipped.to_fen(), "RNBQKBNR/PPPP1PPP/8/4P3/8/8/pppppppp/rnbqkbnr b - - 0 1");
+        assert_eq!(flipped.validate(), Ok(()));
+    }
+    #[test]
+    fn test_validate_en_passant() {
+        let mut position = Position::default();
+        position.apply_move(LegalMove::DoubleAdvance(E2, E4)).unwrap();
+        assert_eq!(position.en_passant(), Some(E3));
+        assert_eq!(position.validate(), Ok(()));
+        let position = position.set_en_passant(Some(D3));
+        assert_eq!(position.validate(), Err(InvariantError::EnPassant(D3)));
+        let position = Position::default().set_en_passant(Some(E6));
+        assert_eq!(position.validate(), Err(InvariantError::EnPassant(E6)));
+    }
+    #[test]
+    fn test_validate_squares_match_masks() {
+        let mut position = Position::default();
+        position.squares[E4] = Some(Material::WQ);
+        assert_eq!(position.validate(), Err(InvariantError::SquareMismatch(E4)));
+    }
+
     #[test] 
     fn test_diagonals() {
         let mask = DIAGONALS[C5];
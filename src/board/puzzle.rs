@@ -0,0 +1,302 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Tactics puzzles: a position, the line that solves it and checking a
+//! solver's attempt against that line.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::material::Color;
+use super::moves::{LegalMoves, Move, MoveState};
+use super::play::{check_replay_move, replay_moves, ReplayError};
+use super::position::Position;
+use super::square::ParseError;
+use super::strategy::candidate_moves;
+use super::Turn;
+
+/// Alternate mates are only searched for while the solver has at most
+/// this many moves left, which keeps `verify` fast.
+const MATE_SEARCH_MOVES: usize = 2;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PuzzleError {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error("The puzzle has no solution")]
+    NoSolution,
+    /// A move of the solution isn't legal.
+    #[error(transparent)]
+    Replay(#[from] ReplayError),
+}
+
+/// How far an attempt at a puzzle got.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PuzzleVerdict {
+    /// The attempt completes the solution or mates.
+    Solved,
+    /// Every move so far is correct but the puzzle isn't solved yet.
+    InProgress,
+    /// The move at `index` (counting from 0) of the attempt is wrong.
+    Wrong { index: usize },
+}
+
+/// A position with a line of moves that solves it. The solver moves
+/// first and the solution alternates between the solver's moves and the
+/// opponent's replies, ending with a move of the solver.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Puzzle {
+    pub fen: String,
+    pub solution: Vec<Move>,
+    /// Free-form tags such as `"mateIn2"` or `"fork"`.
+    pub themes: Vec<String>,
+}
+
+impl Puzzle {
+    /// Creates a puzzle, failing if `fen` can't be read or the solution
+    /// is empty or illegal.
+    pub fn new(fen: &str, solution: Vec<Move>, themes: Vec<String>) -> Result<Self, PuzzleError> {
+        let puzzle = Self { fen: fen.to_string(), solution, themes };
+        puzzle.solution_states()?;
+        Ok(puzzle)
+    }
+
+    /// Creates a puzzle from the fields of the Lichess puzzle database,
+    /// where `fen` is the position before the opponent's move that sets
+    /// up the puzzle and `moves` lists that move followed by the solution,
+    /// in UCI notation separated by spaces. `themes` are separated by
+    /// spaces too.
+    pub fn from_lichess(fen: &str, moves: &str, themes: &str) -> Result<Self, PuzzleError> {
+        let mut moves = moves.split_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<Move>, _>>()?;
+        if moves.is_empty() {
+            return Err(PuzzleError::NoSolution);
+        }
+        let setup = moves.remove(0);
+        let mut state = MoveState::new(Position::from_fen(fen)?);
+        replay_moves(&mut state, &[setup])?;
+        let pos: &Position = state.as_ref();
+        let themes = themes.split_whitespace().map(str::to_string).collect();
+        Self::new(&pos.to_fen(), moves, themes)
+    }
+
+    /// Returns the position the puzzle starts from.
+    pub fn position(&self) -> Result<Position, PuzzleError> {
+        Ok(Position::from_fen(&self.fen)?)
+    }
+
+    /// Returns the side solving the puzzle (the side to move at the start).
+    pub fn solver(&self) -> Result<Color, PuzzleError> {
+        Ok(self.position()?.turn())
+    }
+
+    /// Returns true if the solution ends in checkmate, in which case any
+    /// mate the solver finds within as many moves is accepted.
+    pub fn is_mate(&self) -> Result<bool, PuzzleError> {
+        let states = self.solution_states()?;
+        Ok(states.last().is_some_and(is_checkmate))
+    }
+
+    /// Checks the moves of an attempt, alternating between the solver's
+    /// moves and the opponent's replies like the solution (e.g.
+    /// `PlayerBoard::moves` of a board set up with `Board::plays_from`).
+    ///
+    /// A move is correct if it's the move of the solution or if it reaches
+    /// the same position (a transposition). In a mate puzzle the solver may
+    /// also deviate with another mate, or with a move that still forces
+    /// mate within the remaining moves; the opponent may then defend in
+    /// any way. Outside of mate puzzles the opponent's replies must keep
+    /// to the solution, as played by `reply`.
+    pub fn verify(&self, attempt: &[Move]) -> Result<PuzzleVerdict, PuzzleError> {
+        Ok(self.follow(attempt)?.0)
+    }
+
+    /// Returns the opponent's reply to an attempt that's in progress and
+    /// ends with a move of the solver: the solution's move if the attempt
+    /// kept to it and otherwise a defence against the solver's mate.
+    pub fn reply(&self, attempt: &[Move]) -> Result<Option<Move>, PuzzleError> {
+        let (verdict, state, on_line) = self.follow(attempt)?;
+        if verdict != PuzzleVerdict::InProgress || attempt.len().is_multiple_of(2) {
+            return Ok(None);
+        }
+        if on_line {
+            return Ok(self.solution.get(attempt.len()).copied());
+        }
+        Ok(candidate_moves(&state).into_iter().next())
+    }
+
+    /// Returns the states reached by the solution, one per move.
+    fn solution_states(&self) -> Result<Vec<MoveState>, PuzzleError> {
+        if self.solution.is_empty() {
+            return Err(PuzzleError::NoSolution);
+        }
+        let mut state = MoveState::new(self.position()?);
+        let played = replay_moves(&mut state.clone(), &self.solution)?;
+        let mut states = Vec::with_capacity(played.len());
+        for legal in played {
+            // replay_moves played every move
+            let _ = state.apply_move(legal);
+            states.push(state.clone());
+        }
+        Ok(states)
+    }
+
+    /// Plays `attempt`, returning the verdict, the state reached and
+    /// whether the attempt is still on the solution's line.
+    fn follow(&self, attempt: &[Move]) -> Result<(PuzzleVerdict, MoveState, bool), PuzzleError> {
+        let solution = self.solution_states()?;
+        let mate = solution.last().is_some_and(is_checkmate);
+        let mut state = MoveState::new(self.position()?);
+        let mut on_line = true;
+        for (index, &mv) in attempt.iter().enumerate() {
+            let wrong = PuzzleVerdict::Wrong { index };
+            if index >= solution.len() || check_replay_move(&state, index, mv).is_err() {
+                return Ok((wrong, state, on_line));
+            }
+            let Ok(legal) = state.validate_move(mv) else {
+                return Ok((wrong, state, on_line));
+            };
+            if state.apply_move(legal).is_err() {
+                return Ok((wrong, state, on_line));
+            }
+            let pos: &Position = state.as_ref();
+            on_line = on_line && pos.key() == AsRef::<Position>::as_ref(&solution[index]).key();
+            let solvers_move = index.is_multiple_of(2);
+            if on_line || (mate && !solvers_move) {
+                continue;
+            }
+            if !mate {
+                return Ok((wrong, state, on_line));
+            }
+            if is_checkmate(&state) {
+                return Ok((PuzzleVerdict::Solved, state, on_line));
+            }
+            // The solver's remaining moves, not counting this one
+            let remaining = (solution.len() - index) / 2;
+            if remaining == 0 || remaining > MATE_SEARCH_MOVES || !all_replies_mated(&state, remaining) {
+                return Ok((wrong, state, on_line));
+            }
+        }
+        let verdict = match attempt.len() == solution.len() && on_line {
+            true => PuzzleVerdict::Solved,
+            false => PuzzleVerdict::InProgress,
+        };
+        Ok((verdict, state, on_line))
+    }
+}
+
+fn is_checkmate(state: &MoveState) -> bool {
+    state.is_check() && candidate_moves(state).is_empty()
+}
+
+/// Returns true if the side to move can mate within `moves` moves
+/// whatever the opponent does.
+fn forces_mate(state: &MoveState, moves: usize) -> bool {
+    candidate_moves(state).into_iter().any(|mv| {
+        let mut next = state.clone();
+        let Ok(legal) = next.validate_move(mv) else { return false };
+        let _ = next.apply_move(legal);
+        is_checkmate(&next) || (moves > 1 && all_replies_mated(&next, moves - 1))
+    })
+}
+
+/// Returns true if every reply of the side to move (who must have one)
+/// allows the opponent to mate within `moves` moves.
+fn all_replies_mated(state: &MoveState, moves: usize) -> bool {
+    let replies = candidate_moves(state);
+    !replies.is_empty() && replies.into_iter().all(|mv| {
+        let mut next = state.clone();
+        let Ok(legal) = next.validate_move(mv) else { return false };
+        let _ = next.apply_move(legal);
+        forces_mate(&next, moves)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn moves(uci: &str) -> Vec<Move> {
+        uci.split_whitespace().map(|mv| mv.parse().unwrap()).collect()
+    }
+
+    // Mate in 2: 1. Kc7 Ka7 2. Ra1#, but also 1. Kb6 Kb8 2. Rh8#
+    const MATE_IN_TWO: &str = "k7/8/2K5/8/8/8/8/7R w - - 0 1";
+
+    #[test]
+    fn test_mate_puzzle() {
+        let puzzle = Puzzle::new(MATE_IN_TWO, moves("c6c7 a8a7 h1a1"), vec!["mateIn2".into()]).unwrap();
+        assert_eq!(puzzle.solver(), Ok(Color::White));
+        assert_eq!(puzzle.is_mate(), Ok(true));
+        assert_eq!(puzzle.verify(&[]), Ok(PuzzleVerdict::InProgress));
+        assert_eq!(puzzle.verify(&moves("c6c7")), Ok(PuzzleVerdict::InProgress));
+        assert_eq!(puzzle.reply(&moves("c6c7")), Ok(Some("a8a7".parse().unwrap())));
+        assert_eq!(puzzle.verify(&moves("c6c7 a8a7 h1a1")), Ok(PuzzleVerdict::Solved));
+
+        // A different mating plan
+        assert_eq!(puzzle.verify(&moves("c6b6")), Ok(PuzzleVerdict::InProgress));
+        assert_eq!(puzzle.reply(&moves("c6b6")), Ok(Some("a8b8".parse().unwrap())));
+        assert_eq!(puzzle.verify(&moves("c6b6 a8b8 h1h8")), Ok(PuzzleVerdict::Solved));
+        assert_eq!(puzzle.verify(&moves("c6b6 a8b8 h1h7")), Ok(PuzzleVerdict::Wrong { index: 2 }));
+
+        // Moves that don't force mate, illegal moves and moves past the end
+        assert_eq!(puzzle.verify(&moves("h1h2")), Ok(PuzzleVerdict::Wrong { index: 0 }));
+        assert_eq!(puzzle.verify(&moves("c6a6")), Ok(PuzzleVerdict::Wrong { index: 0 }));
+        assert_eq!(puzzle.verify(&moves("c6c7 a8a7 h1a1 a7a6")), Ok(PuzzleVerdict::Wrong { index: 3 }));
+        assert_eq!(puzzle.reply(&moves("h1h2")), Ok(None));
+    }
+
+    #[test]
+    fn test_transposition() {
+        // 1. Nf3 Nf6 2. Nc3 Nc6 and 1. Nc3 Nc6 2. Nf3 Nf6 reach the same position
+        let fen = Position::starting(BackRankId::default()).to_fen();
+        let puzzle = Puzzle::new(&fen, moves("g1f3 g8f6 b1c3 b8c6 e2e4"), Vec::new()).unwrap();
+        assert_eq!(puzzle.is_mate(), Ok(false));
+        assert_eq!(puzzle.verify(&moves("g1f3 g8f6 b1c3 b8c6 e2e4")), Ok(PuzzleVerdict::Solved));
+        assert_eq!(puzzle.verify(&moves("b1c3")), Ok(PuzzleVerdict::Wrong { index: 0 }));
+        assert_eq!(puzzle.verify(&moves("g1f3 b8c6")), Ok(PuzzleVerdict::Wrong { index: 1 }));
+        assert_eq!(puzzle.reply(&moves("g1f3 g8f6 b1c3")), Ok(Some("b8c6".parse().unwrap())));
+    }
+
+    #[test]
+    fn test_from_lichess() {
+        // 1. e4 f6 2. d4 g5 3. Qh5#
+        let puzzle = Puzzle::from_lichess(
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+            "f7f6 d2d4 g7g5 d1h5",
+            "mateIn2 short",
+        ).unwrap();
+        assert_eq!(puzzle.fen, "rnbqkbnr/ppppp1pp/5p2/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2");
+        assert_eq!(puzzle.themes, vec!["mateIn2", "short"]);
+        assert_eq!(puzzle.solver(), Ok(Color::White));
+        assert_eq!(puzzle.verify(&moves("d2d4 g7g5 d1h5")), Ok(PuzzleVerdict::Solved));
+
+        assert!(Puzzle::from_lichess("8/8/8/8/8/8/8/8 w - - 0 1", "e2e4", "").is_err());
+        assert_eq!(Puzzle::new(MATE_IN_TWO, Vec::new(), Vec::new()), Err(PuzzleError::NoSolution));
+        assert!(matches!(Puzzle::new(MATE_IN_TWO, moves("h1g2"), Vec::new()), Err(PuzzleError::Replay(_))));
+    }
+
+    #[test]
+    fn test_trainer_board() {
+        let puzzle = Puzzle::new(MATE_IN_TWO, moves("c6c7 a8a7 h1a1"), Vec::new()).unwrap();
+        let mut board = PlayerBoard::plays_from(puzzle.solver().unwrap(), puzzle.position().unwrap());
+        board.submit_our_move("c6b6".parse().unwrap()).unwrap();
+        let reply = puzzle.reply(&board.moves()).unwrap().unwrap();
+        board.submit_their_move(reply).unwrap();
+        board.submit_our_move("h1h8".parse().unwrap()).unwrap();
+        assert_eq!(puzzle.verify(&board.moves()), Ok(PuzzleVerdict::Solved));
+        assert_eq!(board.board_result(), Some(BoardResult::CheckMate(Color::White)));
+    }
+}
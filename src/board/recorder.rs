@@ -0,0 +1,246 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Durable game logs. A `GameRecorder` is a `BoardListener` that turns
+//! every move applied to a board (and the result) into a `GameRecord` and
+//! hands it to a `RecordSink`: a callback, a channel or, with the `jsonl`
+//! feature, a writer receiving one JSON object per line.
+
+use std::io;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+use super::listener::BoardListener;
+use super::material::Color;
+use super::moves::{LegalMove, MoveState};
+use super::play::BoardResult;
+use super::position::Position;
+use super::Turn;
+
+/// One entry of a game log. Timestamps are milliseconds since the Unix
+/// epoch, taken when the board applied the move.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GameRecord {
+    Move {
+        /// The FEN fullmove number of the move.
+        fullmove: usize,
+        color: Color,
+        uci: String,
+        san: String,
+        /// The position after the move.
+        fen: String,
+        timestamp_ms: u64,
+    },
+    Result {
+        result: BoardResult,
+        timestamp_ms: u64,
+    },
+}
+
+/// Where a `GameRecorder` sends its records.
+pub trait RecordSink: Send {
+    fn write(&mut self, record: &GameRecord) -> io::Result<()>;
+}
+
+impl<F: FnMut(&GameRecord) + Send> RecordSink for F {
+    fn write(&mut self, record: &GameRecord) -> io::Result<()> {
+        self(record);
+        Ok(())
+    }
+}
+
+/// Fails once the receiver has been dropped.
+impl RecordSink for mpsc::Sender<GameRecord> {
+    fn write(&mut self, record: &GameRecord) -> io::Result<()> {
+        self.send(record.clone()).map_err(|error| io::Error::new(io::ErrorKind::BrokenPipe, error))
+    }
+}
+
+/// Writes each record as a line of JSON, flushing after every record so
+/// the log survives a crash.
+#[cfg(feature = "jsonl")]
+#[derive(Debug)]
+pub struct JsonLines<W>(pub W);
+
+#[cfg(feature = "jsonl")]
+impl<W: io::Write + Send> RecordSink for JsonLines<W> {
+    fn write(&mut self, record: &GameRecord) -> io::Result<()> {
+        serde_json::to_writer(&mut self.0, record)?;
+        self.0.write_all(b"\n")?;
+        self.0.flush()
+    }
+}
+
+struct Recording {
+    sink: Box<dyn RecordSink>,
+    error: Option<io::ErrorKind>,
+}
+
+/// Records the moves of the boards it's added to (see
+/// `Board::add_listener`). Clones share the sink, so a clone kept by the
+/// caller can check for write errors. After a failed write the recorder
+/// stops writing, since the log would have a gap.
+#[derive(Clone)]
+pub struct GameRecorder {
+    recording: Arc<Mutex<Recording>>,
+    clock: fn() -> SystemTime,
+}
+
+impl GameRecorder {
+    pub fn new<S: RecordSink + 'static>(sink: S) -> Self {
+        let recording = Recording { sink: Box::new(sink), error: None };
+        Self { recording: Arc::new(Mutex::new(recording)), clock: SystemTime::now }
+    }
+
+    /// Replaces the source of timestamps, e.g. for tests.
+    pub fn with_clock(mut self, clock: fn() -> SystemTime) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns the kind of the write error that stopped the recorder, if
+    /// any.
+    pub fn error(&self) -> Option<io::ErrorKind> {
+        self.recording.lock().unwrap().error
+    }
+
+    fn timestamp_ms(&self) -> u64 {
+        (self.clock)().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+    }
+
+    fn record(&self, record: GameRecord) {
+        let mut recording = self.recording.lock().unwrap();
+        if recording.error.is_none() {
+            if let Err(error) = recording.sink.write(&record) {
+                recording.error = Some(error.kind());
+            }
+        }
+    }
+}
+
+impl BoardListener for GameRecorder {
+    fn on_move_played(&mut self, before: &MoveState, mv: LegalMove, after: &MoveState) {
+        let pos: &Position = before.as_ref();
+        let after: &Position = after.as_ref();
+        self.record(GameRecord::Move {
+            fullmove: pos.fullmove_number(),
+            color: pos.turn(),
+            uci: mv.to_move(pos).to_string(),
+            san: before.to_san(mv),
+            fen: after.to_fen(),
+            timestamp_ms: self.timestamp_ms(),
+        });
+    }
+
+    fn on_result(&mut self, result: BoardResult) {
+        self.record(GameRecord::Result { result, timestamp_ms: self.timestamp_ms() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::mpsc;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use crate::*;
+
+    fn mv(uci: &str) -> Move {
+        uci.parse().unwrap()
+    }
+
+    fn fixed_time() -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)
+    }
+
+    #[test]
+    fn test_records_moves_and_result() {
+        let (sender, receiver) = mpsc::channel();
+        let mut board = EngineBoard::standard();
+        board.add_listener(GameRecorder::new(sender).with_clock(fixed_time));
+        for uci in ["f2f3", "e7e5", "g2g4", "d8h4"] {
+            board.submit_move(mv(uci)).unwrap();
+        }
+        let records: Vec<GameRecord> = receiver.try_iter().collect();
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[1], GameRecord::Move {
+            fullmove: 1,
+            color: Color::Black,
+            uci: "e7e5".to_string(),
+            san: "e5".to_string(),
+            fen: "rnbqkbnr/pppp1ppp/8/4p3/8/5P2/PPPPP1PP/RNBQKBNR w KQkq e6 0 2".to_string(),
+            timestamp_ms: 1_700_000_000_123,
+        });
+        assert!(matches!(&records[3], GameRecord::Move { san, .. } if san == "Qh4#"));
+        assert_eq!(records[4], GameRecord::Result {
+            result: BoardResult::CheckMate(Color::Black),
+            timestamp_ms: 1_700_000_000_123,
+        });
+        let json = serde_json::to_string(&records[4]).unwrap();
+        assert!(json.starts_with(r#"{"type":"result","#));
+    }
+
+    #[test]
+    fn test_failed_sink_stops_recording() {
+        let (sender, receiver) = mpsc::channel();
+        let recorder = GameRecorder::new(sender);
+        let mut board = PlayerBoard::plays_white(None);
+        board.add_listener(recorder.clone());
+        board.submit_our_move(mv("e2e4")).unwrap();
+        assert_eq!(receiver.try_iter().count(), 1);
+        drop(receiver);
+        board.submit_their_move(mv("e7e5")).unwrap();
+        assert_eq!(recorder.error(), Some(io::ErrorKind::BrokenPipe));
+
+        let sans = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let callback = {
+            let sans = sans.clone();
+            move |record: &GameRecord| if let GameRecord::Move { san, .. } = record {
+                sans.lock().unwrap().push(san.clone());
+            }
+        };
+        let mut board = EngineBoard::standard();
+        board.add_listener(GameRecorder::new(callback));
+        board.submit_move(mv("g1f3")).unwrap();
+        assert_eq!(*sans.lock().unwrap(), vec!["Nf3".to_string()]);
+
+        // sinks are only ever used behind the recorder's lock
+        let count = std::cell::Cell::new(0);
+        let recorder = GameRecorder::new(move |_: &GameRecord| count.set(count.get() + 1));
+        EngineBoard::standard().add_listener(recorder);
+    }
+
+    #[cfg(feature = "jsonl")]
+    #[test]
+    fn test_json_lines() {
+        let log = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        struct Shared(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+        impl io::Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut board = EngineBoard::standard();
+        board.add_listener(GameRecorder::new(JsonLines(Shared(log.clone()))));
+        board.submit_move(mv("e2e4")).unwrap();
+        board.submit_move(mv("c7c5")).unwrap();
+        let log = String::from_utf8(log.lock().unwrap().clone()).unwrap();
+        let records: Vec<GameRecord> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert!(matches!(&records[1], GameRecord::Move { san, .. } if san == "c5"));
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! A slow but obviously correct move generator, used to check the
+//! table-driven generator in `moves.rs`. Every pseudo-legal move is
+//! played on a copy of the position and kept only if it doesn't leave
+//! the mover's king attacked; attacks are found by walking the board
+//! square by square rather than with masks.
+//!
+//! With the `cross-check` feature, every `legal_moves` call is checked
+//! against this generator and panics on a mismatch.
+
+use super::castling::Castling;
+use super::material::{Color, Piece};
+use super::moves::{LegalMove, Promotion};
+use super::position::{Pos, Position};
+use super::square::{Direction, Offset, Rank, Square};
+use super::Turn;
+use strum::IntoEnumIterator;
+
+use Piece::*;
+
+const KNIGHT_OFFSETS: [Offset; 8] = [
+    Offset::new(1, 2), Offset::new(2, 1), Offset::new(2, -1), Offset::new(1, -2),
+    Offset::new(-1, -2), Offset::new(-2, -1), Offset::new(-2, 1), Offset::new(-1, 2),
+];
+
+/// Returns the legal moves of the piece on `from`, in the form returned
+/// by `LegalMoves::legal_moves`: pawn moves onto the back rank are
+/// `Standard` (the promotion is chosen when the move is validated) and
+/// castling moves are listed for the king.
+pub fn reference_moves(pos: &Position, from: Square) -> Vec<LegalMove> {
+    let Some(material) = *pos.contents(from) else { return Vec::new() };
+    if material.color() != pos.turn() {
+        return Vec::new();
+    }
+    let mut moves = match material.piece() {
+        King => steps(pos, from, Direction::iter().map(Offset::from)),
+        Queen => slides(pos, from, Direction::iter()),
+        Rook => slides(pos, from, Direction::horizontals()),
+        Bishop => slides(pos, from, Direction::diagonals()),
+        Knight => steps(pos, from, KNIGHT_OFFSETS.into_iter()),
+        Pawn => pawn_moves(pos, from),
+    };
+    moves.retain(|&mv| !exposes_king(pos, mv));
+    if material.piece() == King {
+        moves.extend(castle_moves(pos));
+    }
+    moves
+}
+
+/// Returns true if a piece of `side` attacks `square`.
+pub fn is_attacked_by(pos: &Position, square: Square, side: Color) -> bool {
+    let holds = |square: Option<Square>, pieces: &[Piece]| {
+        square.and_then(|square| *pos.contents(square))
+            .is_some_and(|material| material.color() == side && pieces.contains(&material.piece()))
+    };
+    // a pawn attacks diagonally forwards, so look diagonally backwards
+    let backwards = match side {
+        Color::White => 1,
+        Color::Black => -1,
+    };
+    let line_attack = |direction: Direction, pieces: &[Piece]| {
+        let mut next = square + direction;
+        while let Some(square) = next {
+            if pos.contents(square).is_some() {
+                return holds(Some(square), pieces);
+            }
+            next = square + direction;
+        }
+        false
+    };
+    KNIGHT_OFFSETS.iter().any(|&offset| holds(square + offset, &[Knight]))
+        || Direction::iter().any(|direction| holds(square + direction, &[King]))
+        || [-1, 1].iter().any(|&x| holds(square + Offset::new(x, backwards), &[Pawn]))
+        || Direction::horizontals().any(|direction| line_attack(direction, &[Queen, Rook]))
+        || Direction::diagonals().any(|direction| line_attack(direction, &[Queen, Bishop]))
+}
+
+/// Panics if `moves`, the result of `legal_moves` for `from`, differs
+/// from the reference moves.
+#[cfg(feature = "cross-check")]
+pub(crate) fn cross_check(pos: &Position, from: Square, moves: &super::moves::MoveSet<LegalMove>) {
+    use std::collections::HashSet;
+
+    let generated: HashSet<LegalMove> = moves.values().copied().collect();
+    let reference: HashSet<LegalMove> = reference_moves(pos, from).into_iter().collect();
+    if generated != reference {
+        let missing: Vec<_> = reference.difference(&generated).collect();
+        let extra: Vec<_> = generated.difference(&reference).collect();
+        panic!(
+            "legal moves from {} in {} disagree with the reference: missing {:?}, extra {:?}",
+            from, pos.to_fen(), missing, extra,
+        );
+    }
+}
+
+fn steps(pos: &Position, from: Square, offsets: impl Iterator<Item = Offset>) -> Vec<LegalMove> {
+    offsets.filter_map(|offset| from + offset)
+        .filter(|&to| !is_ours(pos, to))
+        .map(|to| LegalMove::Standard(from, to))
+        .collect()
+}
+
+fn slides(pos: &Position, from: Square, directions: impl Iterator<Item = Direction>) -> Vec<LegalMove> {
+    let mut moves = Vec::new();
+    for direction in directions {
+        let mut next = from + direction;
+        while let Some(to) = next {
+            if is_ours(pos, to) {
+                break;
+            }
+            moves.push(LegalMove::Standard(from, to));
+            if pos.contents(to).is_some() {
+                break;
+            }
+            next = to + direction;
+        }
+    }
+    moves
+}
+
+fn pawn_moves(pos: &Position, from: Square) -> Vec<LegalMove> {
+    let (forwards, start) = match pos.turn() {
+        Color::White => (-1, Rank::Rank2),
+        Color::Black => (1, Rank::Rank7),
+    };
+    let mut moves = Vec::new();
+    if let Some(to) = from + Offset::new(0, forwards) {
+        if pos.contents(to).is_none() {
+            moves.push(LegalMove::Standard(from, to));
+            if let Some(double) = to + Offset::new(0, forwards) {
+                if from.rank() == start && pos.contents(double).is_none() {
+                    moves.push(LegalMove::DoubleAdvance(from, double));
+                }
+            }
+        }
+    }
+    for x in [-1, 1] {
+        if let Some(to) = from + Offset::new(x, forwards) {
+            if pos.contents(to).is_some_and(|material| material.color() != pos.turn()) {
+                moves.push(LegalMove::Standard(from, to));
+            } else if pos.en_passant() == Some(to) {
+                moves.push(LegalMove::EnPassant(from, to));
+            }
+        }
+    }
+    moves
+}
+
+fn castle_moves(pos: &Position) -> Vec<LegalMove> {
+    let castling = pos.our_castling();
+    let king = castling.king_src();
+    let mut moves = Vec::new();
+    let sides = [
+        (castling.oo(), LegalMove::ShortCastle, castling.oo_rook_src(),
+            castling.oo_king_dest(), castling.oo_rook_dest()),
+        (castling.ooo(), LegalMove::LongCastle, castling.ooo_rook_src(),
+            castling.ooo_king_dest(), castling.ooo_rook_dest()),
+    ];
+    for (allowed, mv, rook, king_dest, rook_dest) in sides {
+        if !allowed {
+            continue;
+        }
+        // every square either piece crosses or lands on must be empty,
+        // apart from the king and the castling rook themselves
+        let clear = span(king, king_dest).chain(span(rook, rook_dest))
+            .all(|square| square == king || square == rook || pos.contents(square).is_none());
+        // the king may not castle out of, through or into check
+        let safe = span(king, king_dest)
+            .all(|square| !is_attacked_by(pos, square, !pos.turn()));
+        if clear && safe && !exposes_king(pos, mv) {
+            moves.push(mv);
+        }
+    }
+    moves
+}
+
+/// Returns true if playing `mv` leaves the mover's king attacked.
+fn exposes_king(pos: &Position, mv: LegalMove) -> bool {
+    let side = pos.turn();
+    // promote to a queen so the position stays valid; the choice of
+    // piece can't affect the safety of our king
+    let mv = match mv {
+        LegalMove::Standard(from, to) if pos.contents(from)
+            .is_some_and(|material| material.piece() == Pawn) && to.rank().is_promotion_rank(side) =>
+        {
+            LegalMove::Promoting(from, to, Promotion::Queen)
+        },
+        mv => mv,
+    };
+    let mut next = pos.clone();
+    if next.apply_move(mv).is_err() {
+        return true;
+    }
+    // after the move it's their turn, so our king is "their" king
+    let king = (next.kings() & next.occupied_by(side)).iter().next();
+    king.is_none_or(|king| is_attacked_by(&next, king, !side))
+}
+
+fn is_ours(pos: &Position, square: Square) -> bool {
+    pos.contents(square).is_some_and(|material| material.color() == pos.turn())
+}
+
+/// Returns the squares from `start` to `end` inclusive, on a rank.
+fn span(start: Square, end: Square) -> impl Iterator<Item = Square> {
+    let (low, high) = if start.file_index() <= end.file_index() { (start, end) } else { (end, start) };
+    (low.file_index()..=high.file_index())
+        .map(move |file| Square::from_index(low.rank_index() * 8 + file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use Square::*;
+
+    #[test]
+    fn test_reference_moves_from_start() {
+        let pos = Position::default();
+        let moves: usize = Square::iter().map(|from| reference_moves(&pos, from).len()).sum();
+        assert_eq!(moves, 20);
+        assert_eq!(reference_moves(&pos, G1), [
+            LegalMove::Standard(G1, H3), LegalMove::Standard(G1, F3),
+        ]);
+        assert!(is_attacked_by(&pos, F3, Color::White));
+        assert!(!is_attacked_by(&pos, F4, Color::White));
+    }
+}
@@ -13,15 +13,69 @@
 //    limitations under the License.
 
 
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::ops::Index;
+use thiserror::Error;
 
+use super::annotation::{Annotation, Arrow, Highlight};
 use super::backrank::BackRank;
-use super::square::Square;
+use super::square::{Rank, Square};
 use super::material::{Material, Color};
-use super::moves::MoveState;
+use super::moves::{LegalMove, MoveError, MoveState};
 use super::position::{MoveId, Pos, Position};
+use super::strategy::material_score;
 use super::Turn;
 
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReviewError {
+    #[error("Already at the starting position")]
+    AtStart,
+    #[error("Already at the latest position")]
+    AtEnd,
+    #[error("Review offset {0:?} is out of range")]
+    OutOfRange(MoveId),
+    #[error("The position at {0:?} is no longer kept")]
+    NotRetained(MoveId),
+    #[error("No such variation")]
+    NoVariation,
+    #[error("Positions reached in the game cannot be deleted")]
+    GameLine,
+    #[error(transparent)]
+    Move(#[from] MoveError),
+}
+
+/// Why a position was picked as a keyframe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyframeKind {
+    /// Both sides have developed their minor pieces (or the opening ply
+    /// limit was reached).
+    OpeningEnd,
+    /// The first position in which a piece has been captured.
+    FirstCapture,
+    /// The material balance changed sharply over the preceding two plies.
+    Swing,
+    /// The latest position in the game.
+    Final,
+}
+
+/// A representative position of a game, e.g. for a preview strip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Keyframe {
+    pub offset: MoveId,
+    pub kind: KeyframeKind,
+}
+
+/// The opening is considered over after this many plies at the latest.
+const OPENING_PLIES: usize = 20;
+/// Minimum change in material balance over two plies to count as a swing
+/// (so that even trades aren't reported).
+const SWING_THRESHOLD: i32 = 2;
+
+/// Read access to the positions of a game. Implementors must hold at
+/// least one position (the starting position) and keep `offset` within
+/// bounds, which makes `first`, `last` and `current` infallible.
 #[allow(clippy::len_without_is_empty)]
 pub trait Review {
     fn len(&self) -> usize;
@@ -34,7 +88,7 @@ pub trait Review {
     }
     #[inline]
     fn at_end(&self) -> bool {
-        self.offset().value() == self.len() - 1
+        self.offset().value() + 1 >= self.len()
     }
     #[inline]
     fn first(&self) -> &Position {
@@ -50,58 +104,607 @@ pub trait Review {
     fn current(&self) -> &Position {
         self.get(self.offset()).expect("Review::current - out of bounds")
     }
+
+    /// Iterates over the positions from the start, with their offsets.
+    fn iter(&self) -> ReviewIter<'_, Self> {
+        ReviewIter { review: self, next: 0 }
+    }
+
+    /// Returns a small set of representative positions ordered by offset:
+    /// the end of the opening, the first capture, up to `max_swings` of
+    /// the largest material swings, and the final position. A position
+    /// picked for more than one reason is reported once, under the
+    /// earliest applicable kind in that list.
+    fn keyframes(&self, max_swings: usize) -> Vec<Keyframe> {
+        let (offsets, positions): (Vec<MoveId>, Vec<&Position>) = self.iter().unzip();
+        let keyframe = |index: usize, kind| Keyframe { offset: offsets[index], kind };
+        let mut result = Vec::new();
+
+        let last = positions.len() - 1;
+        let opening_end = positions.iter()
+            .position(|pos| is_developed(pos, Color::White) && is_developed(pos, Color::Black))
+            .unwrap_or(last)
+            .min(OPENING_PLIES)
+            .min(last);
+        result.push(keyframe(opening_end, KeyframeKind::OpeningEnd));
+
+        let first_capture = positions.windows(2)
+            .position(|pair| pair[1].occupied().len() < pair[0].occupied().len());
+        if let Some(index) = first_capture {
+            result.push(keyframe(index + 1, KeyframeKind::FirstCapture));
+        }
+
+        let scores: Vec<i32> = positions.iter()
+            .map(|pos| material_score(pos, Color::White))
+            .collect();
+        let mut swings: Vec<(usize, i32)> = (2..scores.len())
+            .map(|index| (index, (scores[index] - scores[index - 2]).abs()))
+            .filter(|(_, swing)| *swing >= SWING_THRESHOLD)
+            .collect();
+        // a capture and its recapture overlap two windows, so only the
+        // larger of two adjacent swings is kept
+        swings.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let mut picked: Vec<usize> = Vec::new();
+        for (index, _) in swings {
+            if picked.len() == max_swings {
+                break;
+            }
+            if picked.iter().all(|other| other.abs_diff(index) > 1) {
+                picked.push(index);
+                result.push(keyframe(index, KeyframeKind::Swing));
+            }
+        }
+
+        result.push(keyframe(last, KeyframeKind::Final));
+
+        let mut keyframes: Vec<Keyframe> = Vec::with_capacity(result.len());
+        for candidate in result {
+            if keyframes.iter().all(|kf| kf.offset != candidate.offset) {
+                keyframes.push(candidate);
+            }
+        }
+        keyframes.sort_by_key(|kf| kf.offset.value());
+        keyframes
+    }
+}
+
+/// Iterator over the positions of a `Review` (see `Review::iter`).
+pub struct ReviewIter<'a, R: ?Sized> {
+    review: &'a R,
+    next: usize,
+}
+
+impl<'a, R: Review + ?Sized> Iterator for ReviewIter<'a, R> {
+    type Item = (MoveId, &'a Position);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // positions that are no longer kept are skipped
+        while self.next < self.review.len() {
+            let offset = MoveId::from_ply(self.next)?;
+            self.next += 1;
+            if let Some(pos) = self.review.get(&offset) {
+                return Some((offset, pos));
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // counting the kept positions keeps the iterator exact-size
+        let remaining = (self.next..self.review.len())
+            .filter_map(MoveId::from_ply)
+            .filter(|offset| self.review.get(offset).is_some())
+            .count();
+        (remaining, Some(remaining))
+    }
+}
+
+impl<R: Review + ?Sized> ExactSizeIterator for ReviewIter<'_, R> {}
+
+/// Returns true once `color` has at most one minor piece left on its
+/// back rank.
+fn is_developed(pos: &Position, color: Color) -> bool {
+    let minors = (pos.knights() | pos.bishops()) & pos.occupied_by(color);
+    (minors & Rank::back_rank(color).to_mask()).len() <= 1
 }
 
 pub trait ReviewMut: Review {
-    fn set_offset(&mut self, offset: MoveId);
+    /// Moves to the position at `offset`, failing if it is out of range.
+    fn set_offset(&mut self, offset: MoveId) -> Result<(), ReviewError>;
 
     #[inline]
-    fn forward(&mut self) {
-        debug_assert!(self.offset().value() < self.len() - 1);
-        self.set_offset(self.offset().next());
+    fn forward(&mut self) -> Result<(), ReviewError> {
+        if self.at_end() {
+            return Err(ReviewError::AtEnd);
+        }
+        self.set_offset(self.offset().next())
     }
     #[inline]
-    fn back(&mut self) {
-        debug_assert!(self.offset().value() > 0);
-        self.set_offset(self.offset().prev());
+    fn back(&mut self) -> Result<(), ReviewError> {
+        let offset = self.offset().checked_prev().ok_or(ReviewError::AtStart)?;
+        self.set_offset(offset)
     }
     #[inline]
     fn skip_to_start(&mut self) {
-        let offset = MoveId::START;
-        self.set_offset(offset);
+        // the starting position is always present
+        let _ = self.set_offset(MoveId::START);
     }
     #[inline]
     fn skip_to_end(&mut self) {
+        // `len` is at least one, so the last offset is in range
         let offset: MoveId = MoveId::START + (self.len() - 1);
-        self.set_offset(offset);
+        let _ = self.set_offset(offset);
     }
 }
 
+/// Which positions a `ReviewState` keeps in memory. Moves and annotations
+/// are always kept; dropping positions saves memory on constrained
+/// clients such as mobile or WASM.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum HistoryRetention {
+    /// Every position is kept.
+    #[default]
+    All,
+    /// Only the starting position and this many of the latest positions
+    /// can be reviewed; older ones are dropped.
+    Last(usize),
+    /// Every position can be reviewed, but only the starting position,
+    /// every this many plies and the latest one are kept. The others are
+    /// rebuilt from the moves when reviewed, and stay cached until the
+    /// next `compact`.
+    Keyframes(usize),
+}
+
+/// A node of the variation tree: a position, the move leading to it and
+/// any notes on that move. Only the position is kept; the attack tables
+/// of a `MoveState` are rebuilt when needed (see `state_at`), which keeps
+/// long games small. The position itself may be dropped too, depending on
+/// the `HistoryRetention`; the root's never is.
+#[derive(Debug, Clone)]
+struct Node {
+    position: OnceCell<Position>,
+    ply: usize,
+    mv: Option<LegalMove>,
+    annotation: Annotation,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+/// The positions of a game arranged as a variation tree. The moves
+/// actually played (see `push`) form the game line; any other line is a
+/// variation added for analysis. The first child of a node continues its
+/// main line, the others are alternatives.
+///
+/// `Review` navigates the current line, which initially is the game line.
+/// Switching to a variation replaces the part of the current line below
+/// the branching position with that variation followed by its main line.
 #[derive(Debug, Clone)]
 pub struct ReviewState {
     offset: MoveId,
-    history: Vec<MoveState>,
+    nodes: Vec<Node>,
+    line: Vec<usize>,
+    game: Vec<usize>,
+    retention: HistoryRetention,
 }
 
 impl ReviewState {
     pub fn new(backrank: &'static BackRank) -> Self {
-        let initial_state = MoveState::new(Position::new(backrank));
+        Self::from_position(Position::new(backrank))
+    }
+
+    /// Starts reviewing from `position` rather than from the starting
+    /// position of a back rank.
+    pub fn from_position(position: Position) -> Self {
+        let root = Node {
+            position: OnceCell::with_value(position),
+            ply: 0,
+            mv: None,
+            annotation: Annotation::default(),
+            parent: None,
+            children: Vec::new(),
+        };
         Self {
             offset: MoveId::START,
-            history: vec![initial_state],
+            nodes: vec![root],
+            line: vec![0],
+            game: vec![0],
+            retention: HistoryRetention::default(),
         }
     }
 
-    pub fn push(&mut self, state: MoveState) {
-        if self.at_end() {
-            self.offset = self.offset.next();
+    pub fn retention(&self) -> HistoryRetention {
+        self.retention
+    }
+
+    /// Changes which positions are kept and drops the others right away
+    /// (see `compact`).
+    pub fn set_retention(&mut self, retention: HistoryRetention) {
+        self.retention = retention;
+        self.compact();
+    }
+
+    /// Drops every position the `HistoryRetention` doesn't keep, including
+    /// positions rebuilt for review since the last call. If the current
+    /// position can no longer be reviewed, the offset moves to the oldest
+    /// one that can.
+    pub fn compact(&mut self) {
+        for node in 0..self.nodes.len() {
+            if !self.keeps(node) {
+                self.nodes[node].position.take();
+            }
+        }
+        self.leave_dropped_position();
+    }
+
+    /// Appends a position reached in the game by playing `mv`. If the
+    /// current line is the game line it grows too, and if the latest
+    /// position was being viewed the offset moves on with it.
+    pub fn push(&mut self, mv: LegalMove, position: Position) {
+        let parent = self.game[self.game.len() - 1];
+        let follows_game = self.line.last() == Some(&parent);
+        let at_end = self.at_end();
+        let node = self.add_node(parent, mv, position);
+        self.game.push(node);
+        if follows_game {
+            self.line.push(node);
+            if at_end {
+                self.offset = self.offset.next();
+            }
+        }
+        // Drop the game positions no longer kept now that the game has
+        // moved on; positions of variations are left to `compact`
+        let latest = self.game.len() - 1;
+        let stale = match self.retention {
+            HistoryRetention::All => None,
+            HistoryRetention::Last(plies) => latest.checked_sub(plies.max(1)),
+            HistoryRetention::Keyframes(_) => Some(latest - 1),
+        };
+        if let Some(ply) = stale.filter(|&ply| ply > 0) {
+            let node = self.game[ply];
+            if !self.keeps(node) {
+                self.nodes[node].position.take();
+            }
         }
-        self.history.push(state);
+        self.leave_dropped_position();
     }
+
+    /// Discards every position (in the game and all variations) after the
+    /// current one.
     pub fn truncate(&mut self) {
-        self.history.truncate(self.offset.value() + 1);
+        let index = self.offset.value();
+        let node = self.line[index];
+        self.nodes[node].children.clear();
+        self.line.truncate(index + 1);
+        if self.game.get(index) == Some(&node) {
+            self.game.truncate(index + 1);
+        }
+    }
+
+    /// Returns true if the latest position of the game is being viewed.
+    pub fn is_live(&self) -> bool {
+        self.line[self.offset.value()] == self.game[self.game.len() - 1]
+    }
+
+    /// Returns true if the current position was reached in the game
+    /// rather than in a variation.
+    pub fn on_game_line(&self) -> bool {
+        let index = self.offset.value();
+        self.game.get(index) == Some(&self.line[index])
+    }
+
+    /// Plays `mv` from the position at offset `at` of the current line and
+    /// switches to the resulting line. If `mv` was already played from
+    /// that position, the existing line is selected instead of adding a
+    /// duplicate.
+    pub fn add_variation(&mut self, at: MoveId, mv: LegalMove) -> Result<(), ReviewError> {
+        let index = at.value();
+        let parent = *self.line.get(index).ok_or(ReviewError::OutOfRange(at))?;
+        if !self.reviewable(parent) {
+            return Err(ReviewError::NotRetained(at));
+        }
+        let existing = self.nodes[parent].children.iter()
+            .copied()
+            .find(|&child| self.nodes[child].mv == Some(mv));
+        let node = match existing {
+            Some(node) => node,
+            None => {
+                let mut position = self.position(parent).clone();
+                position.apply_move(mv)?;
+                self.add_node(parent, mv, position)
+            },
+        };
+        self.select(index + 1, node);
+        Ok(())
+    }
+
+    /// Returns the number of alternatives (including the current one) for
+    /// the move leading to the current position.
+    pub fn variations(&self) -> usize {
+        match self.nodes[self.current_node()].parent {
+            Some(parent) => self.nodes[parent].children.len(),
+            None => 1,
+        }
+    }
+
+    /// Switches to the next alternative for the move leading to the
+    /// current position.
+    pub fn next_variation(&mut self) -> Result<(), ReviewError> {
+        self.switch_variation(1)
+    }
+
+    /// Switches to the previous alternative for the move leading to the
+    /// current position.
+    pub fn prev_variation(&mut self) -> Result<(), ReviewError> {
+        self.switch_variation(-1)
     }
 
+    /// Makes the line through the current position the main line at its
+    /// branching point.
+    pub fn promote_variation(&mut self) -> Result<(), ReviewError> {
+        let node = self.current_node();
+        let parent = self.nodes[node].parent.ok_or(ReviewError::NoVariation)?;
+        let children = &mut self.nodes[parent].children;
+        let position = children.iter().position(|&child| child == node)
+            .ok_or(ReviewError::NoVariation)?;
+        if position == 0 {
+            return Err(ReviewError::NoVariation);
+        }
+        let node = children.remove(position);
+        children.insert(0, node);
+        Ok(())
+    }
+
+    /// Deletes the current position and everything following it, then
+    /// steps back to the previous position. Positions reached in the game
+    /// can't be deleted.
+    pub fn delete_variation(&mut self) -> Result<(), ReviewError> {
+        if self.at_start() {
+            return Err(ReviewError::AtStart);
+        }
+        if self.on_game_line() {
+            return Err(ReviewError::GameLine);
+        }
+        let index = self.offset.value();
+        let node = self.line[index];
+        // Detached nodes stay allocated until the review state is dropped
+        if let Some(parent) = self.nodes[node].parent {
+            self.nodes[parent].children.retain(|&child| child != node);
+        }
+        self.line.truncate(index);
+        self.offset = self.offset.prev();
+        self.extend_line();
+        Ok(())
+    }
+
+    /// Returns the state at `offset` of the current line.
+    pub fn state_at(&self, offset: &MoveId) -> Option<MoveState> {
+        let node = *self.line.get(offset.value())?;
+        if !self.reviewable(node) {
+            return None;
+        }
+        Some(MoveState::new(self.owned_position(node)))
+    }
+
+    /// Returns the state at `offset` of the game line, whether or not its
+    /// position can be reviewed.
+    pub(crate) fn game_state_at(&self, offset: &MoveId) -> Option<MoveState> {
+        let node = *self.game.get(offset.value())?;
+        Some(MoveState::new(self.owned_position(node)))
+    }
+
+    /// Iterates over the positions of the current line like `iter`, along
+    /// with the SAN of the move leading to each (`None` for the starting
+    /// position), e.g. to build a move list.
+    pub fn iter_san(&self) -> impl Iterator<Item = (MoveId, &Position, Option<String>)> + '_ {
+        self.line.iter()
+            .enumerate()
+            .filter(|(_, &node)| self.reviewable(node))
+            .map(|(index, &node)| {
+                let Node { mv, parent, .. } = &self.nodes[node];
+                let san = mv.zip(*parent).map(|(mv, parent)| {
+                    MoveState::new(self.owned_position(parent)).to_san(mv)
+                });
+                (MoveId::START + index, self.position(node), san)
+            })
+    }
+
+    /// Returns the annotation of the position at `offset` of the current
+    /// line, if it has one.
+    pub fn annotation(&self, offset: &MoveId) -> Option<&Annotation> {
+        let node = *self.line.get(offset.value())?;
+        let annotation = &self.nodes[node].annotation;
+        (!annotation.is_empty()).then_some(annotation)
+    }
+
+    /// Returns the annotation of the position at `offset` of the current
+    /// line for editing. Annotations belong to the position in the tree,
+    /// so switching variations shows the notes made on that line.
+    pub fn annotation_mut(&mut self, offset: &MoveId) -> Result<&mut Annotation, ReviewError> {
+        let node = *self.line.get(offset.value()).ok_or(ReviewError::OutOfRange(*offset))?;
+        Ok(&mut self.nodes[node].annotation)
+    }
+
+    /// Draws `arrow` on the position being reviewed, or takes it off (see
+    /// `Annotation::toggle_arrow`), e.g. while authoring a lesson. Like
+    /// other annotations it's shown whenever that position is reviewed.
+    pub fn toggle_arrow(&mut self, arrow: Arrow) {
+        self.current_annotation_mut().toggle_arrow(arrow);
+    }
+
+    /// Like `toggle_arrow`, for a highlighted square.
+    pub fn toggle_highlight(&mut self, highlight: Highlight) {
+        self.current_annotation_mut().toggle_highlight(highlight);
+    }
+
+    /// Removes the arrows and highlights of the position being reviewed.
+    pub fn clear_marks(&mut self) {
+        self.current_annotation_mut().clear_marks();
+    }
+
+    fn current_annotation_mut(&mut self) -> &mut Annotation {
+        let node = self.line[self.offset.value()];
+        &mut self.nodes[node].annotation
+    }
+
+    /// Returns the annotated positions of the current line, e.g. to save
+    /// them alongside the moves.
+    pub fn annotations(&self) -> BTreeMap<MoveId, Annotation> {
+        self.line.iter()
+            .enumerate()
+            .filter(|(_, &node)| !self.nodes[node].annotation.is_empty())
+            .map(|(index, &node)| (MoveId::START + index, self.nodes[node].annotation.clone()))
+            .collect()
+    }
+
+    /// Replaces the annotations of the current line with `annotations`
+    /// (as returned by `annotations`). Nothing is changed if any offset is
+    /// out of range.
+    pub fn set_annotations(&mut self, annotations: BTreeMap<MoveId, Annotation>) -> Result<(), ReviewError> {
+        if let Some((offset, _)) = annotations.last_key_value() {
+            if offset.value() >= self.line.len() {
+                return Err(ReviewError::OutOfRange(*offset));
+            }
+        }
+        for &node in &self.line {
+            self.nodes[node].annotation = Annotation::default();
+        }
+        for (offset, annotation) in annotations {
+            self.nodes[self.line[offset.value()]].annotation = annotation;
+        }
+        Ok(())
+    }
+
+    /// Returns the moves of the whole current line.
+    pub(crate) fn line_moves(&self) -> Vec<LegalMove> {
+        self.line[1..].iter()
+            .filter_map(|&node| self.nodes[node].mv)
+            .collect()
+    }
+
+    /// Returns the moves of the current line leading to the current
+    /// position.
+    pub(crate) fn moves_to_current(&self) -> Vec<LegalMove> {
+        self.line[1..=self.offset.value()].iter()
+            .filter_map(|&node| self.nodes[node].mv)
+            .collect()
+    }
+
+    fn current_node(&self) -> usize {
+        self.line[self.offset.value()]
+    }
+
+    /// Moves from a position that can no longer be reviewed to the oldest
+    /// one that can.
+    fn leave_dropped_position(&mut self) {
+        if !self.reviewable(self.current_node()) {
+            let oldest = (1..self.line.len())
+                .find(|&index| self.reviewable(self.line[index]))
+                .unwrap_or(0);
+            self.offset = MoveId::START + oldest;
+        }
+    }
+
+    /// Returns true if `node`'s position is kept in memory under the
+    /// retention policy.
+    fn keeps(&self, node: usize) -> bool {
+        let ply = self.nodes[node].ply;
+        match self.retention {
+            HistoryRetention::All => true,
+            HistoryRetention::Last(_) => self.reviewable(node),
+            HistoryRetention::Keyframes(plies) => {
+                ply.is_multiple_of(plies.max(1)) || self.game.last() == Some(&node)
+            },
+        }
+    }
+
+    /// Returns true if `node`'s position can be reviewed.
+    fn reviewable(&self, node: usize) -> bool {
+        let ply = self.nodes[node].ply;
+        match self.retention {
+            HistoryRetention::Last(plies) => ply == 0 || ply + plies.max(1) >= self.game.len(),
+            HistoryRetention::All | HistoryRetention::Keyframes(_) => true,
+        }
+    }
+
+    /// Returns `node`'s position, rebuilding and caching it if it was
+    /// dropped.
+    fn position(&self, node: usize) -> &Position {
+        self.nodes[node].position.get_or_init(|| self.rebuild(node))
+    }
+
+    /// Returns a copy of `node`'s position without caching it.
+    fn owned_position(&self, node: usize) -> Position {
+        match self.nodes[node].position.get() {
+            Some(position) => position.clone(),
+            None => self.rebuild(node),
+        }
+    }
+
+    /// Replays the moves leading to `node` from the closest ancestor whose
+    /// position is kept.
+    fn rebuild(&self, node: usize) -> Position {
+        let mut moves = Vec::new();
+        let mut current = node;
+        let mut position = loop {
+            let Node { position, mv, parent, .. } = &self.nodes[current];
+            if let Some(position) = position.get() {
+                break position.clone();
+            }
+            // the root's position is never dropped, so every other
+            // node has a move and a parent
+            moves.push(mv.unwrap());
+            current = parent.unwrap();
+        };
+        for mv in moves.into_iter().rev() {
+            // these moves were applied to the same positions before
+            position.apply_move(mv).expect("ReviewState::rebuild - illegal move");
+        }
+        position
+    }
+
+    fn add_node(&mut self, parent: usize, mv: LegalMove, position: Position) -> usize {
+        let node = self.nodes.len();
+        let ply = self.nodes[parent].ply + 1;
+        self.nodes.push(Node {
+            position: OnceCell::with_value(position),
+            ply,
+            mv: Some(mv),
+            annotation: Annotation::default(),
+            parent: Some(parent),
+            children: Vec::new(),
+        });
+        self.nodes[parent].children.push(node);
+        node
+    }
+
+    fn switch_variation(&mut self, step: isize) -> Result<(), ReviewError> {
+        let node = self.current_node();
+        let parent = self.nodes[node].parent.ok_or(ReviewError::NoVariation)?;
+        let children = &self.nodes[parent].children;
+        let sibling = children.iter().position(|&child| child == node)
+            .and_then(|position| position.checked_add_signed(step))
+            .and_then(|position| children.get(position).copied())
+            .ok_or(ReviewError::NoVariation)?;
+        self.select(self.offset.value(), sibling);
+        Ok(())
+    }
+
+    /// Makes `node` the position at `index` of the current line, followed
+    /// by its main line, and moves to it.
+    fn select(&mut self, index: usize, node: usize) {
+        self.line.truncate(index);
+        self.line.push(node);
+        self.offset = MoveId::START + index;
+        self.extend_line();
+    }
+
+    fn extend_line(&mut self) {
+        let mut node = self.line[self.line.len() - 1];
+        while let Some(&child) = self.nodes[node].children.first() {
+            self.line.push(child);
+            node = child;
+        }
+    }
 }
 
 impl Turn for ReviewState {
@@ -113,7 +716,7 @@ impl Turn for ReviewState {
 impl Review for ReviewState {
     #[inline]
     fn len(&self) -> usize {
-        self.history.len()
+        self.line.len()
     }
     #[inline]
     fn offset(&self) -> &MoveId {
@@ -121,29 +724,38 @@ impl Review for ReviewState {
     }
     #[inline]
     fn get(&self, offset: &MoveId) -> Option<&Position> {
-        let index: usize = offset.value();
-        self.history.get(index).map(|state| state.as_ref())
+        let node = *self.line.get(offset.value())?;
+        self.reviewable(node).then(|| self.position(node))
     }
 }
 
 impl ReviewMut for ReviewState {
     #[inline]
-    fn set_offset(&mut self, offset: MoveId) {
+    fn set_offset(&mut self, offset: MoveId) -> Result<(), ReviewError> {
+        if offset.value() >= self.len() {
+            return Err(ReviewError::OutOfRange(offset));
+        }
+        if !self.reviewable(self.line[offset.value()]) {
+            return Err(ReviewError::NotRetained(offset));
+        }
         self.offset = offset;
+        Ok(())
     }
 }
 
+/// Indexes the positions reached in the game (regardless of the line
+/// currently being reviewed).
 impl Index<MoveId> for ReviewState {
-    type Output = MoveState;
+    type Output = Position;
     fn index(&self, index: MoveId) -> &Self::Output {
-        self.history.index(index.value())
+        self.position(self.game[index.value()])
     }
 }
 
 impl Index<&MoveId> for ReviewState {
-    type Output = MoveState;
+    type Output = Position;
     fn index(&self, index: &MoveId) -> &Self::Output {
-        self.history.index(index.value())
+        self.position(self.game[index.value()])
     }
 }
 
@@ -162,3 +774,256 @@ impl AsRef<Position> for ReviewState {
 
 impl Pos for ReviewState {}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{BackRankId, LegalMove, LegalMoves};
+    use Square::*;
+
+    fn play(moves: &[LegalMove]) -> ReviewState {
+        play_keeping(HistoryRetention::All, moves)
+    }
+
+    fn play_keeping(retention: HistoryRetention, moves: &[LegalMove]) -> ReviewState {
+        let mut review = ReviewState::new(BackRank::lookup(BackRankId::STANDARD));
+        review.set_retention(retention);
+        let mut position = review[MoveId::START].clone();
+        for mv in moves {
+            position.apply_move(*mv).unwrap();
+            review.push(*mv, position.clone());
+        }
+        review
+    }
+
+    #[test]
+    fn test_keyframes_of_starting_position() {
+        let review = play(&[]);
+        let keyframes = review.keyframes(3);
+        assert_eq!(keyframes, vec![
+            Keyframe { offset: MoveId::START, kind: KeyframeKind::OpeningEnd },
+        ]);
+    }
+    #[test]
+    fn test_keyframes_capture_and_swing() {
+        use LegalMove::*;
+        // 1. e4 d5 2. exd5 Qxd5 3. Nc3 Qxg2 4. Bxg2 Nf6 5. Nf3 Nc6 6. d3 Bf5
+        let review = play(&[
+            DoubleAdvance(E2, E4), DoubleAdvance(D7, D5),
+            Standard(E4, D5), Standard(D8, D5),
+            Standard(B1, C3), Standard(D5, G2),
+            Standard(F1, G2), Standard(G8, F6),
+            Standard(G1, F3), Standard(B8, C6),
+            Standard(D2, D3), Standard(C8, F5),
+        ]);
+        let keyframes = review.keyframes(1);
+        let kinds: Vec<(usize, KeyframeKind)> = keyframes.iter()
+            .map(|kf| (kf.offset.value(), kf.kind))
+            .collect();
+        // the opening ends with the final position, which is reported once
+        assert_eq!(kinds, vec![
+            (3, KeyframeKind::FirstCapture),
+            (8, KeyframeKind::Swing),
+            (12, KeyframeKind::OpeningEnd),
+        ]);
+        let keyframes = review.keyframes(0);
+        assert!(keyframes.iter().all(|kf| kf.kind != KeyframeKind::Swing));
+    }
+
+    #[test]
+    fn test_variations() {
+        use LegalMove::*;
+        // 1. e4 e5 2. Nf3
+        let mut review = play(&[
+            DoubleAdvance(E2, E4), DoubleAdvance(E7, E5), Standard(G1, F3),
+        ]);
+        let game_key = review.last().key();
+
+        // 1... c5 instead of 1... e5
+        review.add_variation(MoveId::START + 1usize, DoubleAdvance(C7, C5)).unwrap();
+        assert_eq!(review.offset().value(), 2);
+        assert_eq!(review.len(), 3);
+        assert!(review.current().contents(C5).is_some());
+        assert!(!review.on_game_line());
+        assert_eq!(review.variations(), 2);
+        // the game itself is unaffected
+        let game: &Position = review[MoveId::START + 3usize].as_ref();
+        assert_eq!(game.key(), game_key);
+
+        // continue the variation with 2. Nf3
+        review.add_variation(MoveId::START + 2usize, Standard(G1, F3)).unwrap();
+        assert_eq!(review.len(), 4);
+        assert_eq!(review.variations(), 1);
+
+        review.set_offset(MoveId::START + 2usize).unwrap();
+        review.prev_variation().unwrap();
+        assert!(review.on_game_line());
+        assert_eq!(review.last().key(), game_key);
+        assert_eq!(review.prev_variation(), Err(ReviewError::NoVariation));
+        review.next_variation().unwrap();
+        assert_eq!(review.next_variation(), Err(ReviewError::NoVariation));
+
+        // replaying an existing move selects it rather than duplicating it
+        review.add_variation(MoveId::START + 1usize, DoubleAdvance(C7, C5)).unwrap();
+        assert_eq!(review.variations(), 2);
+
+        review.promote_variation().unwrap();
+        assert_eq!(review.promote_variation(), Err(ReviewError::NoVariation));
+
+        review.set_offset(MoveId::START + 2usize).unwrap();
+        assert!(!review.on_game_line());
+        review.delete_variation().unwrap();
+        assert_eq!(review.offset().value(), 1);
+        assert_eq!(review.variations(), 1);
+        assert_eq!(review.last().key(), game_key);
+        review.set_offset(MoveId::START + 2usize).unwrap();
+        assert_eq!(review.delete_variation(), Err(ReviewError::GameLine));
+    }
+
+    #[test]
+    fn test_state_is_rebuilt_from_position() {
+        use LegalMove::*;
+        let review = play(&[Standard(E2, E3), Standard(F7, F6), Standard(D1, H5)]);
+        let state = review.state_at(&(MoveId::START + 3usize)).unwrap();
+        assert!(state.is_check());
+        assert!(state.legal_moves(E8).destinations().is_empty());
+        assert!(!review.state_at(&(MoveId::START + 2usize)).unwrap().is_check());
+        assert!(review.state_at(&(MoveId::START + 4usize)).is_none());
+    }
+
+    fn kept(review: &ReviewState) -> usize {
+        review.nodes.iter().filter(|node| node.position.get().is_some()).count()
+    }
+
+    const OPENING: [LegalMove; 6] = [
+        LegalMove::DoubleAdvance(E2, E4), LegalMove::DoubleAdvance(E7, E5),
+        LegalMove::Standard(G1, F3), LegalMove::Standard(B8, C6),
+        LegalMove::Standard(F1, B5), LegalMove::Standard(A7, A6),
+    ];
+
+    #[test]
+    fn test_keep_last_positions() {
+        let mut review = play_keeping(HistoryRetention::Last(2), &OPENING);
+        let offsets: Vec<usize> = review.iter().map(|(offset, _)| offset.ply()).collect();
+        assert_eq!(offsets, [0, 5, 6]);
+        assert_eq!(review.iter().len(), 3);
+        assert_eq!(kept(&review), 3);
+        let old = MoveId::START + 3usize;
+        assert!(review.get(&old).is_none() && review.state_at(&old).is_none());
+        assert_eq!(review.set_offset(old), Err(ReviewError::NotRetained(old)));
+        review.set_offset(MoveId::START).unwrap();
+        assert_eq!(review.last().key(), play(&OPENING).last().key());
+
+        // positions being viewed move on once they're dropped
+        review.set_offset(MoveId::START + 5usize).unwrap();
+        let mut position = review.last().clone();
+        position.apply_move(LegalMove::Standard(B5, A4)).unwrap();
+        review.push(LegalMove::Standard(B5, A4), position);
+        assert_eq!(review.offset().ply(), 6);
+    }
+
+    #[test]
+    fn test_keyframes_rebuild_positions() {
+        let full = play(&OPENING);
+        let mut review = play_keeping(HistoryRetention::Keyframes(4), &OPENING);
+        // the start, ply 4 and the latest
+        assert_eq!(kept(&review), 3);
+        let offset = MoveId::START + 3usize;
+        assert_eq!(review.get(&offset).unwrap().key(), full.get(&offset).unwrap().key());
+        assert_eq!(review.iter().count(), 7);
+        assert_eq!(kept(&review), 7);
+        review.compact();
+        assert_eq!(kept(&review), 3);
+
+        review.set_retention(HistoryRetention::All);
+        assert_eq!(review.iter_san().last().unwrap().2, Some("a6".to_string()));
+        assert_eq!(kept(&review), 7);
+    }
+
+    #[test]
+    fn test_iter_positions_and_san() {
+        use LegalMove::*;
+        let mut review = play(&[DoubleAdvance(E2, E4), DoubleAdvance(E7, E5), Standard(G1, F3)]);
+        let offsets: Vec<usize> = review.iter().map(|(offset, _)| offset.ply()).collect();
+        assert_eq!(offsets, [0, 1, 2, 3]);
+        assert_eq!(review.iter().count(), 4);
+        let (offset, pos) = review.iter().last().unwrap();
+        assert_eq!((offset, pos.key()), (MoveId::START + 3usize, review.last().key()));
+
+        review.add_variation(MoveId::START + 1usize, DoubleAdvance(C7, C5)).unwrap();
+        let sans: Vec<Option<String>> = review.iter_san().map(|(_, _, san)| san).collect();
+        assert_eq!(sans, [None, Some("e4".to_string()), Some("c5".to_string())]);
+    }
+
+    #[test]
+    fn test_annotations_follow_the_line() {
+        use crate::board::Nag;
+        use LegalMove::*;
+        let mut review = play(&[DoubleAdvance(E2, E4), DoubleAdvance(E7, E5)]);
+        let reply = MoveId::START + 2usize;
+        review.annotation_mut(&reply).unwrap().add_nag(Nag::Good);
+        assert!(review.annotation(&MoveId::START).is_none());
+        assert!(review.annotation_mut(&(MoveId::START + 3usize)).is_err());
+
+        review.add_variation(MoveId::START + 1usize, DoubleAdvance(C7, C5)).unwrap();
+        assert!(review.annotation(&reply).is_none());
+        review.annotation_mut(&reply).unwrap().comment = "Sicilian".to_string();
+        assert_eq!(review.annotation(&reply).unwrap().to_pgn(), "{Sicilian}");
+
+        review.prev_variation().unwrap();
+        assert_eq!(review.annotation(&reply).unwrap().nags, vec![Nag::Good]);
+
+        let saved = review.annotations();
+        assert_eq!(saved.keys().copied().collect::<Vec<_>>(), vec![reply]);
+        let json = serde_json::to_string(&saved).unwrap();
+        let mut restored = play(&[DoubleAdvance(E2, E4), DoubleAdvance(E7, E5)]);
+        restored.set_annotations(serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(restored.annotations(), saved);
+        assert!(play(&[]).set_annotations(saved).is_err());
+    }
+
+    #[test]
+    fn test_marks_on_reviewed_positions() {
+        use crate::board::{MarkColor, PgnGame, PgnOptions};
+        use LegalMove::*;
+        let mut review = play(&[DoubleAdvance(E2, E4), DoubleAdvance(E7, E5)]);
+        review.set_offset(MoveId::START + 1usize).unwrap();
+        review.toggle_arrow(Arrow::new(E7, E5));
+        review.toggle_highlight(Highlight::colored(E5, MarkColor::Red));
+        review.skip_to_end();
+        review.toggle_arrow(Arrow::colored(G1, F3, MarkColor::Blue));
+        review.toggle_arrow(Arrow::colored(G1, F3, MarkColor::Blue));
+        assert!(review.annotation(review.offset()).is_none());
+
+        review.back().unwrap();
+        let annotation = review.annotation(review.offset()).unwrap();
+        assert_eq!(annotation.arrows, vec![Arrow::new(E7, E5)]);
+        let moves = vec!["e2e4".parse().unwrap(), "e7e5".parse().unwrap()];
+        let game = PgnGame::new(BackRankId::STANDARD, moves).with_annotations(review.annotations());
+        let pgn = game.to_pgn(&PgnOptions::default()).unwrap();
+        assert!(pgn.contains("1. e4 {[%csl Re5][%cal Ge7e5]} 1... e5"), "{pgn}");
+        let parsed = PgnGame::parse_all(&pgn).unwrap().remove(0);
+        assert_eq!(parsed.annotations, review.annotations());
+
+        review.clear_marks();
+        assert!(review.annotations().is_empty());
+    }
+
+    #[test]
+    fn test_game_continues_while_viewing_variation() {
+        let mut board = crate::PlayerBoard::plays_white(None);
+        let mv = |uci: &str| -> crate::Move { uci.parse().unwrap() };
+        board.submit_our_move(mv("e2e4")).unwrap();
+        board.submit_their_move(mv("e7e5")).unwrap();
+        board.add_variation(MoveId::START + 1usize, mv("c7c5")).unwrap();
+        assert!(board.view().contents(C5).is_some());
+        board.submit_our_move(mv("g1f3")).unwrap();
+        // still viewing the variation
+        assert!(board.view().contents(C5).is_some());
+        assert_eq!(board.len(), 3);
+        board.prev_variation().unwrap();
+        assert_eq!(board.len(), 4);
+        board.skip_to_end();
+        assert!(board.view().contents(F3).is_some());
+        assert!(board.add_variation(MoveId::START + 1usize, mv("e2e4")).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! The rules that differ between variants. `MoveState` generates moves
+//! the same way for every variant and consults the position's `Rules`
+//! where they differ: whether the king is royal (which brings checks and
+//! pins), which moves remain legal, how pawns promote and how the game
+//! ends. A new variant implements `Rules` and is registered as a
+//! `Variant` (see `Variant::register`) rather than getting a generator of
+//! its own.
+
+use std::fmt;
+
+use super::material::Color;
+use super::moves::{LegalMove, MoveSet, MoveState, Promotion};
+use super::play::BoardResult;
+
+const STANDARD_PROMOTIONS: [Promotion; 4] = [
+    Promotion::Queen, Promotion::Rook, Promotion::Bishop, Promotion::Knight,
+];
+
+/// The rules of a variant, as far as they differ from standard chess.
+/// Every method defaults to the standard rules.
+pub trait Rules: fmt::Debug + Send + Sync {
+    /// Returns true if the king may never be left attacked, which is what
+    /// makes checks, pins and checkmate matter. Otherwise kings move like
+    /// any other piece, sides may have any number of them and the
+    /// variant must end the game some other way.
+    fn has_royal_king(&self) -> bool {
+        true
+    }
+
+    fn allows_castling(&self) -> bool {
+        true
+    }
+
+    /// Returns true if the game is drawn when neither side can mate (see
+    /// `InsufficientMaterial`).
+    fn has_insufficient_material(&self) -> bool {
+        true
+    }
+
+    /// Returns the pieces a pawn may promote to.
+    fn promotions(&self) -> &'static [Promotion] {
+        &STANDARD_PROMOTIONS
+    }
+
+    /// Narrows down `moves`, the moves the generator found for one piece
+    /// in `state`, e.g. to force captures.
+    fn restrict_moves(&self, _state: &MoveState, moves: MoveSet<LegalMove>) -> MoveSet<LegalMove> {
+        moves
+    }
+
+    /// Returns the result reached in `state` by a rule of the variant,
+    /// checked after every move before the others.
+    fn result(&self, _state: &MoveState) -> Option<BoardResult> {
+        None
+    }
+
+    /// Returns the result when `color`, the side to move, has no legal
+    /// moves.
+    fn no_moves_result(&self, color: Color, in_check: bool) -> BoardResult {
+        if in_check {
+            BoardResult::CheckMate(!color)
+        } else {
+            BoardResult::StaleMate
+        }
+    }
+}
+
+/// The rules of standard chess, which Chess960 shares: it only changes
+/// the starting position (see `BackRank`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct StandardRules;
+
+impl Rules for StandardRules {}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_variant_rules() {
+        let standard = Variant::Standard.rules();
+        assert!(standard.has_royal_king() && standard.allows_castling());
+        assert!(!standard.promotions().contains(&Promotion::King));
+        assert_eq!(standard.no_moves_result(Color::White, true), BoardResult::CheckMate(Color::Black));
+        assert_eq!(standard.no_moves_result(Color::White, false), BoardResult::StaleMate);
+
+        let antichess = Variant::Antichess.rules();
+        assert!(!antichess.has_royal_king() && !antichess.has_insufficient_material());
+        assert_eq!(antichess.no_moves_result(Color::White, false), BoardResult::VariantWin(Color::White));
+        let pos = Position::new_variant(BackRankId::default().into(), Variant::Antichess);
+        assert!(pos.rules().promotions().contains(&Promotion::King));
+        assert!(!MoveState::new(pos).can_capture());
+    }
+
+    #[derive(Debug)]
+    struct NoCastlingRules;
+    impl Rules for NoCastlingRules {
+        fn allows_castling(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_registered_rules() {
+        let variant = Variant::register("No castling", &NoCastlingRules).unwrap();
+        assert_eq!(Variant::register("No castling", &StandardRules), Err(VariantError::NameTaken("No castling".to_string())));
+        assert!(Variant::register("Antichess", &NoCastlingRules).is_err());
+        assert_eq!(Variant::from_name("No castling"), Some(variant));
+        assert_eq!(variant.name(), "No castling");
+
+        let pos = Position::new_variant(BackRankId::default().into(), variant);
+        assert!(!pos.rules().allows_castling());
+        assert!(pos.to_fen().contains(" w - - "));
+        let json = serde_json::to_string(&pos).unwrap();
+        let restored: Position = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.variant(), variant);
+        assert!(serde_json::from_str::<Variant>(r#"{"Custom":"Atomic"}"#).is_err());
+    }
+}
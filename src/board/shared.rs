@@ -0,0 +1,182 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use std::sync::{Arc, PoisonError, RwLock};
+
+use super::moves::MoveState;
+use super::position::Position;
+use super::play::EngineMode;
+use super::{Board, EngineBoard, PlayerBoard};
+
+// Boards only hold owned data and `&'static BackRank` references, so they
+// are `Send` and `Sync` without any unsafe code. These assertions keep it
+// that way.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Position>();
+    assert_send_sync::<MoveState>();
+    assert_send_sync::<EngineBoard>();
+    assert_send_sync::<PlayerBoard>();
+    assert_send_sync::<ArcBoard<EngineMode>>();
+};
+
+/// A cheaply cloneable handle to a board shared between threads or tasks.
+///
+/// Access goes through closures so a lock can never be held across an
+/// `.await` point. Many readers may inspect the board concurrently while
+/// writers are serialized.
+///
+/// The lock's poisoned flag is ignored, so other handles keep working
+/// after a closure passed to `write` panics. Such a panic can leave the
+/// board half updated, e.g. when it happens in the middle of applying a
+/// move, and later readers would see an inconsistent board. Check
+/// `is_poisoned` after a writer panicked and discard the board if set.
+pub struct ArcBoard<T> {
+    inner: Arc<RwLock<Board<T>>>,
+}
+
+impl<T> ArcBoard<T> {
+    pub fn new(board: Board<T>) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(board)),
+        }
+    }
+
+    /// Runs `f` with shared access to the board.
+    pub fn read<R>(&self, f: impl FnOnce(&Board<T>) -> R) -> R {
+        let board = self.inner.read().unwrap_or_else(PoisonError::into_inner);
+        f(&board)
+    }
+
+    /// Runs `f` with exclusive access to the board.
+    pub fn write<R>(&self, f: impl FnOnce(&mut Board<T>) -> R) -> R {
+        let mut board = self.inner.write().unwrap_or_else(PoisonError::into_inner);
+        f(&mut board)
+    }
+
+    /// Returns true if a closure passed to `write` panicked, in which case
+    /// the board may be inconsistent.
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+    }
+
+    /// Returns the board if this is the last handle to it.
+    pub fn try_unwrap(self) -> Result<Board<T>, Self> {
+        Arc::try_unwrap(self.inner)
+            .map(|lock| lock.into_inner().unwrap_or_else(PoisonError::into_inner))
+            .map_err(|inner| Self { inner })
+    }
+}
+
+impl<T> Clone for ArcBoard<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<T> From<Board<T>> for ArcBoard<T> {
+    fn from(board: Board<T>) -> Self {
+        Self::new(board)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use crate::board::{Color, Move, Turn};
+
+    fn moves(uci: &[&str]) -> Vec<Move> {
+        uci.iter().map(|mv| mv.parse().unwrap()).collect()
+    }
+
+    fn play_side(board: ArcBoard<EngineMode>, color: Color, moves: Vec<Move>) {
+        let mut moves = moves.into_iter().peekable();
+        while moves.peek().is_some() {
+            let submitted = board.write(|board| {
+                if board.turn() != color {
+                    return false;
+                }
+                let mv = moves.next().unwrap();
+                board.submit_move(mv).unwrap();
+                true
+            });
+            if !submitted {
+                thread::yield_now();
+            }
+        }
+    }
+
+    #[test]
+    fn test_players_on_separate_threads() {
+        let board = ArcBoard::new(EngineBoard::standard());
+        let white = moves(&["e2e4", "g1f3", "f1c4", "e1g1"]);
+        let black = moves(&["e7e5", "b8c6", "g8f6", "f8c5"]);
+        let handles = [
+            thread::spawn({
+                let board = board.clone();
+                move || play_side(board, Color::White, white)
+            }),
+            thread::spawn({
+                let board = board.clone();
+                move || play_side(board, Color::Black, black)
+            }),
+        ];
+        let readers: Vec<_> = (0..4).map(|_| {
+            let board = board.clone();
+            thread::spawn(move || {
+                let mut last = 0;
+                for _ in 0..100 {
+                    let move_number = board.read(|board| {
+                        let position: &Position = board.as_ref();
+                        position.move_number()
+                    });
+                    assert!(move_number >= last);
+                    last = move_number;
+                }
+            })
+        }).collect();
+        for handle in handles.into_iter().chain(readers) {
+            handle.join().unwrap();
+        }
+        let board = board.try_unwrap().ok().unwrap();
+        let position: &Position = board.as_ref();
+        assert_eq!(position.move_number(), 5);
+        assert_eq!(board.turn(), Color::White);
+        assert_eq!(board.board_result(), None);
+    }
+
+    #[test]
+    fn test_try_unwrap_requires_last_handle() {
+        let board = ArcBoard::new(EngineBoard::standard());
+        let other = board.clone();
+        let board = board.try_unwrap().err().unwrap();
+        drop(other);
+        assert!(board.try_unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_panicking_writer_poisons_the_board() {
+        let board = ArcBoard::new(EngineBoard::standard());
+        assert!(!board.is_poisoned());
+        let writer = board.clone();
+        let panicked = thread::spawn(move || writer.write(|_| panic!("mid-move"))).join();
+        assert!(panicked.is_err());
+        assert!(board.is_poisoned());
+        // the board is still reachable
+        assert_eq!(board.read(|board| board.turn()), Color::White);
+    }
+}
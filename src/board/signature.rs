@@ -0,0 +1,173 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Piece counts and material signatures, e.g. to classify endgames or
+//! decide whether a position is small enough for a tablebase.
+
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+use super::material::{Color, Material, Piece};
+use super::position::{Pos, Position};
+use super::square::ParseError;
+
+use Color::{White, Black};
+use Piece::{Pawn, Knight, Bishop, Rook, Queen, King};
+
+/// The order pieces are listed in a signature, as in tablebase names.
+const SIGNATURE_ORDER: [Piece; 6] = [King, Queen, Rook, Bishop, Knight, Pawn];
+
+/// The number of pieces of each kind on the board, packed four bits per
+/// color and piece. It's written like tablebase names, White's pieces in
+/// upper case then Black's in lower case, e.g. "KRPkr".
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MaterialSignature(u64);
+
+impl MaterialSignature {
+    fn shift(color: Color, piece: Piece) -> u32 {
+        ((color.to_index() * 6 + piece.to_index()) * 4) as u32
+    }
+
+    pub fn count(&self, color: Color, piece: Piece) -> usize {
+        ((self.0 >> Self::shift(color, piece)) & 0xf) as usize
+    }
+
+    /// Returns the number of pieces on the board, kings included.
+    pub fn pieces(&self) -> usize {
+        (0..12).map(|nibble| ((self.0 >> (nibble * 4)) & 0xf) as usize).sum()
+    }
+
+    /// Returns the signature with the colors swapped, so "KRPkr" becomes
+    /// "KRkrp".
+    pub fn flipped(&self) -> Self {
+        Self((self.0 >> 24) | ((self.0 & 0xff_ffff) << 24))
+    }
+
+    pub fn to_bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Adds one piece, failing if there would be more than fifteen of a
+    /// kind.
+    fn add(&mut self, color: Color, piece: Piece) -> Option<()> {
+        if self.count(color, piece) == 0xf {
+            return None;
+        }
+        self.0 += 1 << Self::shift(color, piece);
+        Some(())
+    }
+}
+
+impl fmt::Display for MaterialSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for color in [White, Black] {
+            for piece in SIGNATURE_ORDER {
+                let c = Material::new(color, piece).to_char();
+                for _ in 0..self.count(color, piece) {
+                    write!(f, "{c}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for MaterialSignature {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut signature = Self::default();
+        for c in s.chars() {
+            Material::from_char(c)
+                .and_then(|material| signature.add(material.color(), material.piece()))
+                .ok_or_else(|| ParseError::InvalidSignature(s.to_string()))?;
+        }
+        Ok(signature)
+    }
+}
+
+impl Position {
+    pub fn piece_count(&self, color: Color, piece: Piece) -> usize {
+        let side = match color {
+            White => self.white(),
+            Black => self.black(),
+        };
+        let pieces = match piece {
+            King => self.kings(),
+            Queen => self.queens(),
+            Rook => self.rooks(),
+            Bishop => self.bishops(),
+            Knight => self.knights(),
+            Pawn => self.pawns(),
+        };
+        (side & pieces).len()
+    }
+
+    pub fn material_signature(&self) -> MaterialSignature {
+        let mut signature = MaterialSignature::default();
+        for (_, material) in self.placements() {
+            // a legal position has at most ten pieces of a kind per side
+            let _ = signature.add(material.color(), material.piece());
+        }
+        signature
+    }
+
+    /// Returns true if the position looks like an endgame: each side
+    /// either has no queen and at most 13 points of pieces (say two rooks
+    /// and a minor piece), or has a queen and at most one minor piece
+    /// besides. Pawns don't count.
+    pub fn is_endgame(&self) -> bool {
+        [White, Black].into_iter().all(|color| {
+            let count = |piece| self.piece_count(color, piece);
+            let minors = count(Bishop) + count(Knight);
+            match count(Queen) {
+                0 => count(Rook) * 5 + minors * 3 <= 13,
+                1 => count(Rook) == 0 && minors <= 1,
+                _ => false,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_piece_counts() {
+        let pos = Position::default();
+        assert_eq!(pos.piece_count(Color::White, Piece::Pawn), 8);
+        assert_eq!(pos.piece_count(Color::Black, Piece::Queen), 1);
+        assert_eq!(pos.material_signature().to_string(), "KQRRBBNNPPPPPPPPkqrrbbnnpppppppp");
+        assert_eq!(pos.material_signature().pieces(), 32);
+        assert!(!pos.is_endgame());
+
+        let pos = Position::from_fen("8/8/4k3/3r4/8/3PK3/8/5R2 w - - 0 1").unwrap();
+        let signature = pos.material_signature();
+        assert_eq!(signature.to_string(), "KRPkr");
+        assert_eq!(signature.pieces(), 5);
+        assert_eq!(signature.flipped().to_string(), "KRkrp");
+        assert_eq!("KRPkr".parse::<MaterialSignature>().unwrap(), signature);
+        assert_eq!("PRKrk".parse::<MaterialSignature>().unwrap(), signature);
+        assert!(pos.is_endgame());
+        assert!("KXk".parse::<MaterialSignature>().is_err());
+        assert!("KPPPPPPPPPPPPPPPPk".parse::<MaterialSignature>().is_err());
+
+        // a queen with a rook is still a middlegame
+        let pos = Position::from_fen("4k3/8/8/8/8/8/R2Q4/4K3 w - - 0 1").unwrap();
+        assert!(!pos.is_endgame());
+        let pos = Position::from_fen("4k3/8/8/8/8/8/N2Q4/4K3 w - - 0 1").unwrap();
+        assert!(pos.is_endgame());
+    }
+}
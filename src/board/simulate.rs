@@ -0,0 +1,208 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Plays many complete games between strategies on an `EngineBoard`.
+//! Useful for load testing servers and for shaking out rare move
+//! generation failures: every game is reproducible from its seed.
+
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::backrank::BackRankId;
+use super::play::BoardResult;
+use super::strategy::{MoveWeights, Strategy, WeightedRandomMover};
+use super::EngineBoard;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SimulationConfig {
+    /// Number of games to play
+    pub games: usize,
+    /// Games reaching this many plies without a result are abandoned
+    pub max_plies: usize,
+    /// Seed for both the movers and the Chess960 start positions
+    pub seed: u64,
+    /// Play Chess960 starts instead of the standard back rank
+    pub shuffled: bool,
+    pub weights: MoveWeights,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            games: 1000,
+            max_plies: 500,
+            seed: 0,
+            shuffled: false,
+            weights: MoveWeights::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SimulationReport {
+    pub games: usize,
+    pub plies: usize,
+    pub results: HashMap<BoardResult, usize>,
+    /// Games that reached `max_plies` without a result
+    pub unfinished: usize,
+    /// Moves chosen by a strategy but rejected by the board. Anything
+    /// other than zero indicates a move generation bug.
+    pub rejected: usize,
+    /// Games aborted by a panic in the board
+    pub panics: usize,
+    /// The panicked games played by `simulate`, with what it takes to
+    /// reproduce them
+    pub panicked: Vec<PanickedGame>,
+    pub elapsed: Duration,
+}
+
+/// A simulated game aborted by a panic. Replaying it on
+/// `EngineBoard::plays_both(Some(backrank))` with a
+/// `WeightedRandomMover::seeded(seed, weights)` for both sides, using the
+/// weights of the simulation, reproduces the panic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PanickedGame {
+    pub backrank: BackRankId,
+    pub seed: u64,
+}
+
+impl SimulationReport {
+    pub fn count(&self, result: BoardResult) -> usize {
+        self.results.get(&result).copied().unwrap_or(0)
+    }
+    pub fn games_per_second(&self) -> f64 {
+        self.games as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+    pub fn plies_per_second(&self) -> f64 {
+        self.plies as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Plays `config.games` games using seeded `WeightedRandomMover`s for
+/// both sides.
+pub fn simulate(config: SimulationConfig) -> SimulationReport {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut report = SimulationReport::default();
+    let start = Instant::now();
+    for _ in 0..config.games {
+        let backrank = if config.shuffled {
+            BackRankId::shuffled_with(&mut rng)
+        } else {
+            BackRankId::default()
+        };
+        let seed = rng.gen();
+        let mut mover = WeightedRandomMover::seeded(seed, config.weights);
+        let board = EngineBoard::plays_both(Some(backrank));
+        if !play_game(&mut report, board, &mut mover, config.max_plies) {
+            report.panicked.push(PanickedGame { backrank, seed });
+        }
+    }
+    report.elapsed = start.elapsed();
+    report
+}
+
+/// Plays `games` games from the standard position using `strategy` for
+/// both sides.
+pub fn simulate_with<S: Strategy>(
+    games: usize,
+    max_plies: usize,
+    strategy: &mut S
+) -> SimulationReport {
+    let mut report = SimulationReport::default();
+    let start = Instant::now();
+    for _ in 0..games {
+        play_game(&mut report, EngineBoard::standard(), strategy, max_plies);
+    }
+    report.elapsed = start.elapsed();
+    report
+}
+
+// Returns false if the game panicked
+fn play_game<S: Strategy>(
+    report: &mut SimulationReport,
+    board: EngineBoard,
+    strategy: &mut S,
+    max_plies: usize
+) -> bool {
+    report.games += 1;
+    let mut plies = 0;
+    let outcome = catch_unwind(AssertUnwindSafe(|| {
+        play_to_end(board, strategy, max_plies, &mut plies)
+    }));
+    report.plies += plies;
+    match outcome {
+        Ok(GameEnd::Finished(result)) => *report.results.entry(result).or_insert(0) += 1,
+        Ok(GameEnd::Unfinished) => report.unfinished += 1,
+        Ok(GameEnd::Rejected) => report.rejected += 1,
+        Err(_) => report.panics += 1,
+    }
+    outcome.is_ok()
+}
+
+enum GameEnd {
+    Finished(BoardResult),
+    Unfinished,
+    Rejected,
+}
+
+fn play_to_end<S: Strategy>(
+    mut board: EngineBoard,
+    strategy: &mut S,
+    max_plies: usize,
+    plies: &mut usize
+) -> GameEnd {
+    for _ in 0..max_plies {
+        if board.board_result().is_some() {
+            break;
+        }
+        let Some(mv) = board.choose_move(strategy) else { break };
+        if board.submit_move(mv).is_err() {
+            return GameEnd::Rejected;
+        }
+        *plies += 1;
+    }
+    match board.board_result() {
+        Some(result) => GameEnd::Finished(result),
+        None => GameEnd::Unfinished,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulation_is_deterministic() {
+        let config = SimulationConfig { games: 10, max_plies: 300, seed: 7, ..Default::default() };
+        let first = simulate(config);
+        let second = simulate(config);
+        assert_eq!(first.plies, second.plies);
+        assert_eq!(first.results, second.results);
+        assert_eq!(first.unfinished, second.unfinished);
+        assert_eq!(first.panicked, second.panicked);
+    }
+    #[test]
+    fn test_simulation_accounts_for_every_game() {
+        let config = SimulationConfig { games: 40, max_plies: 400, seed: 1, shuffled: true, ..Default::default() };
+        let report = simulate(config);
+        assert_eq!(report.games, 40);
+        let finished: usize = report.results.values().sum();
+        let total = finished + report.unfinished + report.rejected + report.panics;
+        assert_eq!(total, report.games);
+        assert_eq!(report.panicked.len(), report.panics);
+    }
+}
@@ -14,8 +14,10 @@
 
 use strum_macros::EnumIter;
 use strum::IntoEnumIterator;
+use thiserror::Error;
 use std::fmt;
-use std::ops::{Add, Sub, Not, BitOr, BitAnd, BitOrAssign, BitAndAssign, Deref};
+use std::str::FromStr;
+use std::ops::{Add, Sub, Not, BitOr, BitAnd, BitXor, BitOrAssign, BitAndAssign, BitXorAssign, Deref};
 use std::ops::{Index, IndexMut};
 use serde::{Deserialize, Serialize};
 
@@ -23,6 +25,46 @@ use super::material::Color;
 
 use Color::*;
 
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("Invalid square: {0:?}")]
+    InvalidSquare(String),
+    #[error("Invalid file: {0:?}")]
+    InvalidFile(String),
+    #[error("Invalid rank: {0:?}")]
+    InvalidRank(String),
+    #[error("Invalid material: {0:?}")]
+    InvalidMaterial(String),
+    #[error("Invalid promotion: {0:?}")]
+    InvalidPromotion(String),
+    #[error("Invalid move: {0:?}")]
+    InvalidMove(String),
+    #[error("Invalid annotation glyph: {0:?}")]
+    InvalidNag(String),
+    #[error("Invalid evaluation: {0:?}")]
+    InvalidEval(String),
+    #[error("Invalid clock time: {0:?}")]
+    InvalidClock(String),
+    #[error("Invalid FEN {0:?}: {1}")]
+    InvalidFen(String, String),
+    #[error("Invalid or illegal SAN move: {0:?}")]
+    InvalidSan(String),
+    #[error("Ambiguous SAN move: {0:?}")]
+    AmbiguousSan(String),
+    #[error("Invalid material signature: {0:?}")]
+    InvalidSignature(String),
+    #[error("Invalid game id: {0:?}")]
+    InvalidGameId(String),
+}
+
+/// The color of a square on the board, as opposed to the `Color` of a
+/// piece.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, EnumIter)]
+pub enum SquareColor {
+    Light,
+    Dark,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, EnumIter)]
 pub enum Square {
     A8, B8, C8, D8, E8, F8, G8, H8,
@@ -113,11 +155,64 @@ impl Square {
     pub const fn rank(&self) -> Rank {
         Rank::from_index(self.rank_index())
     }
+    /// Returns the color of the square on the board (a1 is dark).
+    #[inline]
+    pub const fn color(&self) -> SquareColor {
+        if (self.file_index() + self.rank_index()).is_multiple_of(2) {
+            SquareColor::Light
+        } else {
+            SquareColor::Dark
+        }
+    }
+    /// Returns the number of king moves from this square to `other`.
+    #[inline]
+    pub const fn distance(&self, other: Square) -> usize {
+        let files = self.file_index().abs_diff(other.file_index());
+        let ranks = self.rank_index().abs_diff(other.rank_index());
+        if files > ranks { files } else { ranks }
+    }
+    /// Returns the number of rook steps (one square at a time) from this
+    /// square to `other`.
+    #[inline]
+    pub const fn manhattan_distance(&self, other: Square) -> usize {
+        self.file_index().abs_diff(other.file_index()) + self.rank_index().abs_diff(other.rank_index())
+    }
+    /// Returns the square on the same file, mirrored across the middle of
+    /// the board (e.g. a1 and a8).
+    #[inline]
+    pub const fn mirror_vertical(&self) -> Self {
+        Self::new(self.file(), self.rank().mirror())
+    }
+    /// Returns the square on the same rank, mirrored across the middle of
+    /// the board (e.g. a1 and h1).
+    #[inline]
+    pub const fn mirror_horizontal(&self) -> Self {
+        Self::new(self.file().mirror(), self.rank())
+    }
  }
 
- impl fmt::Display for Square {
+/// Formats the square in standard notation (e.g. `e4`). The alternate
+/// form (`{:#}`) wraps the name in parentheses for debug output.
+impl fmt::Display for Square {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "({}{})", self.file(), self.rank())
+        let (file, rank) = (self.file().to_char(), self.rank().to_char());
+        if f.alternate() {
+            write!(f, "({}{})", file, rank)
+        } else {
+            write!(f, "{}{}", file, rank)
+        }
+    }
+}
+
+impl FromStr for Square {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseError::InvalidSquare(s.to_string());
+        let mut chars = s.chars();
+        let (Some(f), Some(r), None) = (chars.next(), chars.next(), chars.next()) else {
+            return Err(error());
+        };
+        Self::try_from_chars(f, r).ok_or_else(error)
     }
 }
 
@@ -181,6 +276,16 @@ impl File {
         Mask::new(u64::from_be_bytes([0x1 << (7 - self.to_index()); 8]))
     }
     #[inline]
+    pub const fn to_char(&self) -> char {
+        const VALUES: [char; 8] = ['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h'];
+        VALUES[self.to_index()]
+    }
+    /// Returns the file on the other side of the board (a and h).
+    #[inline]
+    pub const fn mirror(&self) -> Self {
+        Self::from_index(7 - self.to_index())
+    }
+    #[inline]
     pub fn range(start: File, end: File) -> impl Iterator<Item=File> {
         let start_index = start.to_index();
         let end_index = end.to_index();
@@ -190,8 +295,22 @@ impl File {
 
 impl fmt::Display for File {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        const VALUES: [char; 8] = ['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h'];
-        write!(f, "({})", VALUES[self.to_index()])
+        if f.alternate() {
+            write!(f, "({})", self.to_char())
+        } else {
+            write!(f, "{}", self.to_char())
+        }
+    }
+}
+
+impl FromStr for File {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Self::try_from_char(c),
+            _ => None,
+        }.ok_or_else(|| ParseError::InvalidFile(s.to_string()))
     }
 }
 
@@ -235,6 +354,21 @@ impl Rank {
             Black => Rank8,
         }
     }
+
+    /// Returns true if `color`'s pawns promote on this rank, which is the
+    /// opponent's back rank.
+    #[inline]
+    pub fn is_promotion_rank(&self, color: Color) -> bool {
+        Self::promotion_rank(color) == *self
+    }
+
+    #[inline]
+    pub const fn promotion_rank(color: Color) -> Self {
+        match color {
+            White => Rank8,
+            Black => Rank1,
+        }
+    }
     #[inline]
     pub const fn from_index(index: usize) -> Self {
         const VALUES: [Rank; 8] = [
@@ -277,11 +411,36 @@ impl Rank {
     pub const fn to_mask(&self) -> Mask {
         Mask::new(0xff << ((7 - self.to_index()) * 8))
     }
+    #[inline]
+    pub const fn to_char(&self) -> char {
+        const VALUES: [char; 8] = ['8', '7', '6', '5', '4', '3', '2', '1'];
+        VALUES[self.to_index()]
+    }
+    /// Returns the rank on the other side of the board (1 and 8).
+    #[inline]
+    pub const fn mirror(&self) -> Self {
+        Self::from_index(7 - self.to_index())
+    }
 }
 
 impl fmt::Display for Rank {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "({})", 8 - self.to_index())
+        if f.alternate() {
+            write!(f, "({})", self.to_char())
+        } else {
+            write!(f, "{}", self.to_char())
+        }
+    }
+}
+
+impl FromStr for Rank {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Self::try_from_char(c),
+            _ => None,
+        }.ok_or_else(|| ParseError::InvalidRank(s.to_string()))
     }
 }
 
@@ -474,6 +633,17 @@ impl Mask {
     pub(crate) const fn inner(&self) -> u64 {
         self.0
     }
+
+    /// Returns the union of `masks`; unlike `|`, usable in const context.
+    pub(crate) const fn union(masks: &[Mask]) -> Self {
+        let mut union = 0;
+        let mut index = 0;
+        while index < masks.len() {
+            union |= masks[index].0;
+            index += 1;
+        }
+        Self(union)
+    }
     
     #[inline]
     pub const fn is_empty(&self) -> bool {
@@ -524,6 +694,56 @@ impl Mask {
         MaskIter(self.0)
     }
 
+    /// Moves every square one step in `direction`, dropping the squares
+    /// that would leave the board.
+    pub const fn shift(self, direction: Direction) -> Self {
+        const NOT_FILE_A: u64 = !File::FileA.to_mask().0;
+        const NOT_FILE_H: u64 = !File::FileH.to_mask().0;
+        // Bit 63 is a8: moving right is a shift right by one, moving up
+        // a shift left by eight
+        Self(match direction {
+            UpLeft => (self.0 & NOT_FILE_A) << 9,
+            Up => self.0 << 8,
+            UpRight => (self.0 & NOT_FILE_H) << 7,
+            Left => (self.0 & NOT_FILE_A) << 1,
+            Right => (self.0 & NOT_FILE_H) >> 1,
+            DownLeft => (self.0 & NOT_FILE_A) >> 7,
+            Down => self.0 >> 8,
+            DownRight => (self.0 & NOT_FILE_H) >> 9,
+        })
+    }
+
+    /// Returns these squares along with every square beyond them in
+    /// `direction`, up to the edge of the board.
+    pub const fn fill(self, direction: Direction) -> Self {
+        let mut fill = self;
+        let mut ray = self.shift(direction);
+        while ray.0 != 0 {
+            fill.0 |= ray.0;
+            ray = ray.shift(direction);
+        }
+        fill
+    }
+
+    /// Returns every file with at least one of these squares.
+    pub const fn file_fill(self) -> Self {
+        Self(self.fill(Up).0 | self.fill(Down).0)
+    }
+
+    /// Returns the squares attacked by `color`'s pawns on these squares.
+    pub const fn pawn_attacks(self, color: Color) -> Self {
+        match color {
+            Color::White => Self(self.shift(UpLeft).0 | self.shift(UpRight).0),
+            Color::Black => Self(self.shift(DownLeft).0 | self.shift(DownRight).0),
+        }
+    }
+
+    /// Returns the squares next to `square`.
+    pub const fn king_ring(square: Square) -> Self {
+        let rank = Self(square.to_mask().0 | square.to_mask().shift(Left).0 | square.to_mask().shift(Right).0);
+        Self(rank.shift(Up).0 | rank.shift(Down).0 | (rank.0 & !square.to_mask().0))
+    }
+
 }
 
 impl fmt::Debug for Mask {
@@ -623,6 +843,58 @@ impl BitAndAssign<Square> for Mask {
     }
 }
 
+impl BitXor for Mask {
+    type Output = Self;
+
+    fn bitxor(self, rhs: Self) -> Self {
+        Self(self.0 ^ rhs.0)
+    }
+}
+
+impl BitXorAssign for Mask {
+    fn bitxor_assign(&mut self, rhs: Self) {
+        self.0 ^= rhs.0;
+    }
+}
+
+impl BitXor<Square> for Mask {
+    type Output = Self;
+
+    fn bitxor(self, rhs: Square) -> Self {
+        Self(self.0 ^ rhs.to_mask().0)
+    }
+}
+
+impl BitXorAssign<Square> for Mask {
+    fn bitxor_assign(&mut self, rhs: Square) {
+        self.0 ^= rhs.to_mask().0;
+    }
+}
+
+impl FromIterator<Square> for Mask {
+    fn from_iter<I: IntoIterator<Item = Square>>(iter: I) -> Self {
+        Self::from_squares(iter)
+    }
+}
+
+impl IntoIterator for Mask {
+    type Item = Square;
+    type IntoIter = MaskIter;
+
+    fn into_iter(self) -> MaskIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for &Mask {
+    type Item = Square;
+    type IntoIter = MaskIter;
+
+    fn into_iter(self) -> MaskIter {
+        self.iter()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct MaskIter(u64);
 
@@ -668,3 +940,116 @@ impl DoubleEndedIterator for MaskIter {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Material, Move, Promotion};
+
+    #[test]
+    fn test_mask_bitboard_ops() {
+        let mask: Mask = [A1, D4, H8].into_iter().collect();
+        assert_eq!(mask.shift(Up), Mask::from_squares([A2, D5]));
+        assert_eq!(mask.shift(Right), Mask::from_squares([B1, E4]));
+        assert_eq!(mask.shift(DownLeft), Mask::from_squares([C3, G7]));
+        assert_eq!(D4.to_mask().fill(UpRight), Mask::from_squares([D4, E5, F6, G7, H8]));
+        assert_eq!(B2.to_mask().file_fill(), File::FileB.to_mask());
+        assert_eq!(Mask::from_squares([A2, E2]).pawn_attacks(Color::White), Mask::from_squares([B3, D3, F3]));
+        assert_eq!(E7.to_mask().pawn_attacks(Color::Black), Mask::from_squares([D6, F6]));
+        assert_eq!(Mask::king_ring(A1), Mask::from_squares([A2, B2, B1]));
+        assert_eq!(Mask::king_ring(E4).len(), 8);
+        assert!(!Mask::king_ring(E4).contains(E4));
+        assert_eq!(mask ^ D4, Mask::from_squares([A1, H8]));
+        let squares: Vec<Square> = (&mask).into_iter().collect();
+        assert_eq!(squares, [H8, D4, A1]);
+        let mut count = 0;
+        for _ in mask {
+            count += 1;
+        }
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_geometry() {
+        assert_eq!((A1.color(), H1.color(), A8.color(), E4.color()),
+            (SquareColor::Dark, SquareColor::Light, SquareColor::Light, SquareColor::Light));
+        assert_eq!(A1.distance(H8), 7);
+        assert_eq!(E4.distance(F6), 2);
+        assert_eq!(A1.manhattan_distance(H8), 14);
+        assert_eq!(E4.manhattan_distance(E4), 0);
+        assert_eq!((C2.mirror_vertical(), C2.mirror_horizontal()), (C7, F2));
+        assert_eq!((File::FileB.mirror(), Rank::Rank3.mirror()), (File::FileG, Rank::Rank6));
+    }
+
+    #[test]
+    fn test_parse_square() {
+        assert_eq!("e4".parse::<Square>(), Ok(E4));
+        assert_eq!("H8".parse::<Square>(), Ok(H8));
+        assert!("e9".parse::<Square>().is_err());
+        assert!("e4x".parse::<Square>().is_err());
+        assert!("".parse::<Square>().is_err());
+    }
+    #[test]
+    fn test_parse_file_and_rank() {
+        assert_eq!("c".parse::<File>(), Ok(FileC));
+        assert_eq!("7".parse::<Rank>(), Ok(Rank7));
+        assert!("i".parse::<File>().is_err());
+        assert!("0".parse::<Rank>().is_err());
+    }
+    #[test]
+    fn test_display_round_trip() {
+        for square in Square::iter() {
+            assert_eq!(square.to_string().parse::<Square>(), Ok(square));
+        }
+        for file in File::iter() {
+            assert_eq!(file.to_string().parse::<File>(), Ok(file));
+        }
+        for rank in Rank::iter() {
+            assert_eq!(rank.to_string().parse::<Rank>(), Ok(rank));
+        }
+        for promotion in [Promotion::Queen, Promotion::Rook, Promotion::Bishop, Promotion::Knight] {
+            assert_eq!(promotion.to_string().parse::<Promotion>(), Ok(promotion));
+        }
+    }
+    #[test]
+    fn test_display_bare_and_decorated() {
+        assert_eq!(E4.to_string(), "e4");
+        assert_eq!(format!("{:#}", E4), "(e4)");
+        assert_eq!(FileA.to_string(), "a");
+        assert_eq!(format!("{:#}", Rank8), "(8)");
+        assert_eq!(Promotion::Queen.to_string(), "q");
+        assert_eq!(format!("{:#}", Promotion::Knight), "(n)");
+    }
+    #[test]
+    fn test_parse_material() {
+        assert_eq!("K".parse::<Material>(), Ok(Material::WK));
+        assert_eq!("q".parse::<Material>(), Ok(Material::BQ));
+        assert_eq!("♞".parse::<Material>(), Ok(Material::BN));
+        assert!("x".parse::<Material>().is_err());
+        for c in "KQRBNPkqrbnp".chars() {
+            assert_eq!(Material::from_char(c).map(|material| material.to_char()), Some(c));
+        }
+        assert_eq!(Material::WK.to_unicode(), '♔');
+        assert_eq!(Material::BP.to_unicode(), '♟');
+    }
+    #[test]
+    fn test_parse_move() {
+        assert_eq!("e2e4".parse::<Move>(), Ok(Move::new(E2, E4, None)));
+        let expected = Move::new(E7, E8, Some(Promotion::Queen));
+        assert_eq!("e7e8q".parse::<Move>(), Ok(expected));
+        assert!("e2".parse::<Move>().is_err());
+        assert!("e7e8x".parse::<Move>().is_err());
+        assert_eq!("e2-e4".parse::<Move>(), Ok(Move::new(E2, E4, None)));
+        assert_eq!("e7-e8=Q".parse::<Move>(), Ok(expected));
+        assert_eq!(Move::try_from("e7-e8q"), Ok(expected));
+        assert!("e7e8=q".parse::<Move>().is_err());
+        assert!("e2--e4".parse::<Move>().is_err());
+        assert_eq!(Promotion::try_from("N"), Ok(Promotion::Knight));
+        assert_eq!(Promotion::try_from("k"), Ok(Promotion::King));
+        assert!(Promotion::try_from("p").is_err());
+        for uci in ["e2e4", "e7e8q", "a2a1n"] {
+            assert_eq!(uci.parse::<Move>().unwrap().to_string(), uci);
+        }
+        assert_eq!(Move::new(E7, E8, Some(Promotion::Queen)).to_string(), "e7e8q");
+    }
+}
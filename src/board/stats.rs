@@ -0,0 +1,155 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Statistics over a finished (or ongoing) game for post-game summary
+//! screens. Plies are counted from zero, from the game's first move.
+
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use super::material::{Color, Pair, Piece};
+use super::moves::{LegalMove, MoveState};
+use super::position::Position;
+use super::strategy::candidate_moves;
+use super::Turn;
+
+/// When a side castled, and to which wing.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Castled {
+    pub ply: usize,
+    pub long: bool,
+}
+
+/// The time a side took over a move.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Think {
+    pub ply: usize,
+    pub time: Duration,
+}
+
+/// Counts and highlights of a game, per side. Each side's figures
+/// describe the moves that side played, e.g. `checks` are the checks it
+/// gave.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct GameStats {
+    pub plies: usize,
+    pub captures: Pair<usize>,
+    pub checks: Pair<usize>,
+    pub castled: Pair<Option<Castled>>,
+    /// The pieces each side promoted to, in order.
+    pub promotions: Pair<Vec<Piece>>,
+    /// The average number of legal moves a side had on its turns.
+    pub mobility: Pair<f64>,
+    /// Only known once clock readings are given (see `with_clocks`).
+    pub longest_think: Pair<Option<Think>>,
+    /// Whether the game started with Black to move, e.g. from a FEN.
+    black_first: bool,
+}
+
+impl GameStats {
+    /// Replays `history` from `start`, which every move must be legal in.
+    pub(crate) fn from_history(start: &Position, history: &[LegalMove]) -> Self {
+        let mut stats = Self {
+            plies: history.len(),
+            black_first: start.turn() == Color::Black,
+            ..Self::default()
+        };
+        let mut turns = Pair::new(0usize, 0usize);
+        let mut moves_available = Pair::new(0usize, 0usize);
+        let mut state = MoveState::new(start.clone());
+        for (ply, &mv) in history.iter().enumerate() {
+            let side = state.turn();
+            let pos: &Position = state.as_ref();
+            turns[side] += 1;
+            moves_available[side] += candidate_moves(&state).len();
+            if mv.is_capture(pos) {
+                stats.captures[side] += 1;
+            }
+            match mv {
+                LegalMove::ShortCastle | LegalMove::LongCastle => {
+                    stats.castled[side] = Some(Castled { ply, long: mv == LegalMove::LongCastle });
+                },
+                LegalMove::Promoting(_, _, promotion) => stats.promotions[side].push(promotion.into()),
+                _ => (),
+            }
+            // every move in the history was legal when it was played
+            let _ = state.apply_move(mv);
+            if state.is_check() {
+                stats.checks[side] += 1;
+            }
+        }
+        for side in [Color::White, Color::Black] {
+            if turns[side] > 0 {
+                stats.mobility[side] = moves_available[side] as f64 / turns[side] as f64;
+            }
+        }
+        stats
+    }
+
+    /// Fills in `longest_think` from clock readings as in PGN `[%clk]`
+    /// comments: `clocks[ply]` is the mover's remaining time after that
+    /// ply. Each side starts with `initial` and gains `increment` after
+    /// every move. Readings beyond the game's plies are ignored.
+    pub fn with_clocks(mut self, clocks: &[Duration], initial: Duration, increment: Duration) -> Self {
+        self.longest_think = Pair::default();
+        for (ply, &after) in clocks.iter().enumerate().take(self.plies) {
+            let before = match ply {
+                0 | 1 => initial,
+                _ => clocks[ply - 2],
+            };
+            let time = (before + increment).saturating_sub(after);
+            let side = match (ply % 2 == 0) != self.black_first {
+                true => Color::White,
+                false => Color::Black,
+            };
+            if self.longest_think[side].is_none_or(|think| time > think.time) {
+                self.longest_think[side] = Some(Think { ply, time });
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::*;
+
+    #[test]
+    fn test_game_stats() {
+        let moves: Vec<Move> = ["e2e4", "d7d5", "e4d5", "g8f6", "f1b5", "c7c6", "d5c6", "d8d7",
+            "c6b7", "d7b5", "b7a8q", "e8d8", "g1f3", "b5d7", "e1g1"]
+            .iter().map(|uci| uci.parse().unwrap()).collect();
+        let board = EngineBoard::replay(None, &moves).unwrap();
+        let stats = board.stats();
+        assert_eq!(stats.plies, 15);
+        assert_eq!(stats.captures, Pair::new(4, 1));
+        // only Bb5+; neither ...Qxb5 nor bxa8=Q gives check
+        assert_eq!(stats.checks, Pair::new(1, 0));
+        assert_eq!(stats.castled, Pair::new(Some(Castled { ply: 14, long: false }), None));
+        assert_eq!(stats.promotions, Pair::new(vec![Piece::Queen], vec![]));
+        assert!(*stats.mobility.white() > 20.0);
+        assert!(*stats.mobility.black() > 0.0);
+        assert_eq!(stats.longest_think, Pair::new(None, None));
+
+        let minute = Duration::from_secs(60);
+        let secs = Duration::from_secs;
+        let clocks = [secs(58), secs(59), secs(55), secs(30), secs(50)];
+        let stats = board.stats().with_clocks(&clocks, minute, secs(1));
+        assert_eq!(stats.longest_think.white().unwrap(), Think { ply: 4, time: secs(6) });
+        assert_eq!(stats.longest_think.black().unwrap(), Think { ply: 3, time: secs(30) });
+
+        assert_eq!(EngineBoard::standard().stats(), GameStats::default());
+    }
+}
@@ -0,0 +1,502 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Lightweight move selection strategies for scripted bots and test
+//! harnesses. None of these are meant to play well; they exist so that
+//! games can be driven without a full engine.
+
+use std::collections::HashMap;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::{StdRng, ThreadRng};
+use rand::seq::SliceRandom;
+use rand::{thread_rng, Rng, SeedableRng};
+
+use super::material::{Color, Piece};
+use super::moves::{LegalMove, LegalMoves, Move, MoveState};
+use super::position::{Pos, Position, PositionKey};
+use super::Turn;
+
+use Piece::*;
+
+pub trait Strategy {
+    /// Chooses a move for the side to play, or `None` if there are no
+    /// legal moves.
+    fn choose_move(&mut self, state: &MoveState) -> Option<Move>;
+}
+
+/// Returns every legal move for the side to play. Pawn moves onto the
+/// back rank are expanded into one move per promotion piece and each
+/// castling move is listed once.
+pub fn candidate_moves(state: &MoveState) -> Vec<Move> {
+    let promotions = AsRef::<Position>::as_ref(state).rules().promotions();
+    let mut result = Vec::new();
+    let mut castles = Vec::new();
+    for from in state.ours().iter() {
+        let promoting = state.contents(from)
+            .is_some_and(|material| material.piece() == Pawn);
+        let legal_moves = state.legal_moves(from);
+        for to in legal_moves.destinations().iter() {
+            let legal_move = legal_moves[to];
+            if legal_move.is_castle() {
+                // Castles are reachable from both the king's destination and
+                // the rook's square
+                if castles.contains(&legal_move) {
+                    continue;
+                }
+                castles.push(legal_move);
+            }
+            if promoting && to.rank().is_promotion_rank(state.turn()) {
+                for &promotion in promotions {
+                    result.push(Move::new(from, to, Some(promotion)));
+                }
+            } else {
+                result.push(Move::new(from, to, None));
+            }
+        }
+    }
+    result
+}
+
+/// Returns the material balance of `pos` from the perspective of `side`
+/// using conventional point values.
+pub fn material_score(pos: &Position, side: Color) -> i32 {
+    let mut score = 0;
+    for square in pos.occupied().iter() {
+        if let Some(material) = pos.contents(square) {
+            let value = material.piece().value();
+            score += if material.color() == side { value } else { -value };
+        }
+    }
+    score
+}
+
+/// Plays a uniformly random legal move.
+#[derive(Debug, Clone)]
+pub struct RandomMover<R: Rng = ThreadRng> {
+    rng: R,
+}
+
+impl RandomMover {
+    pub fn new() -> Self {
+        Self::with_rng(thread_rng())
+    }
+}
+
+impl Default for RandomMover {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RandomMover<StdRng> {
+    /// Creates a deterministic mover that replays the same choices for
+    /// the same seed and sequence of positions.
+    pub fn seeded(seed: u64) -> Self {
+        Self::with_rng(StdRng::seed_from_u64(seed))
+    }
+}
+
+impl<R: Rng> RandomMover<R> {
+    pub fn with_rng(rng: R) -> Self {
+        Self { rng }
+    }
+}
+
+impl<R: Rng> Strategy for RandomMover<R> {
+    fn choose_move(&mut self, state: &MoveState) -> Option<Move> {
+        candidate_moves(state).choose(&mut self.rng).copied()
+    }
+}
+
+/// Relative weights used by `WeightedRandomMover` for each kind of move.
+/// A weight of zero excludes that kind of move unless nothing else is
+/// available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MoveWeights {
+    pub quiet: u32,
+    pub capture: u32,
+    pub promotion: u32,
+    pub castle: u32,
+}
+
+impl Default for MoveWeights {
+    fn default() -> Self {
+        Self { quiet: 1, capture: 4, promotion: 8, castle: 2 }
+    }
+}
+
+impl MoveWeights {
+    fn weigh(&self, state: &MoveState, mv: &Move) -> u32 {
+        let pos: &Position = state.as_ref();
+        match state.validate_move(*mv) {
+            Ok(LegalMove::Promoting(..)) => self.promotion,
+            Ok(LegalMove::ShortCastle | LegalMove::LongCastle) => self.castle,
+            Ok(LegalMove::EnPassant(..)) => self.capture,
+            _ if pos.theirs().contains(mv.to) => self.capture,
+            _ => self.quiet,
+        }
+    }
+}
+
+/// Plays a random legal move, biased by move kind according to
+/// `MoveWeights`. Seeded instances are fully deterministic, which makes
+/// them suitable for load tests and for reproducing rare failures.
+#[derive(Debug, Clone)]
+pub struct WeightedRandomMover<R: Rng = StdRng> {
+    weights: MoveWeights,
+    rng: R,
+}
+
+impl WeightedRandomMover<StdRng> {
+    pub fn seeded(seed: u64, weights: MoveWeights) -> Self {
+        Self::with_rng(StdRng::seed_from_u64(seed), weights)
+    }
+}
+
+impl<R: Rng> WeightedRandomMover<R> {
+    pub fn with_rng(rng: R, weights: MoveWeights) -> Self {
+        Self { weights, rng }
+    }
+    pub fn weights(&self) -> MoveWeights {
+        self.weights
+    }
+}
+
+impl<R: Rng> Strategy for WeightedRandomMover<R> {
+    fn choose_move(&mut self, state: &MoveState) -> Option<Move> {
+        let candidates = candidate_moves(state);
+        let weights = candidates.iter().map(|mv| self.weights.weigh(state, mv));
+        match WeightedIndex::new(weights) {
+            Ok(distribution) => Some(candidates[distribution.sample(&mut self.rng)]),
+            // all weights are zero (or there are no candidates)
+            Err(_) => candidates.choose(&mut self.rng).copied(),
+        }
+    }
+}
+
+/// Plays a random capture if one is available and a random legal move
+/// otherwise.
+#[derive(Debug, Clone)]
+pub struct CapturePreferring<R: Rng = ThreadRng> {
+    rng: R,
+}
+
+impl CapturePreferring {
+    pub fn new() -> Self {
+        Self::with_rng(thread_rng())
+    }
+}
+
+impl Default for CapturePreferring {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: Rng> CapturePreferring<R> {
+    pub fn with_rng(rng: R) -> Self {
+        Self { rng }
+    }
+}
+
+impl<R: Rng> Strategy for CapturePreferring<R> {
+    fn choose_move(&mut self, state: &MoveState) -> Option<Move> {
+        let candidates = candidate_moves(state);
+        let pos: &Position = state.as_ref();
+        let captures: Vec<Move> = candidates.iter()
+            .filter(|mv| {
+                let target = state.theirs().contains(mv.to);
+                let en_passant = pos.en_passant() == Some(mv.to)
+                    && state.our_pawns().contains(mv.from);
+                target || en_passant
+            })
+            .copied()
+            .collect();
+        if captures.is_empty() {
+            candidates.choose(&mut self.rng).copied()
+        } else {
+            captures.choose(&mut self.rng).copied()
+        }
+    }
+}
+
+/// Looks one ply ahead and plays the move that leaves the best material
+/// balance, preferring checkmate and breaking ties at random.
+#[derive(Debug, Clone)]
+pub struct GreedyMaterial<R: Rng = ThreadRng> {
+    rng: R,
+}
+
+impl GreedyMaterial {
+    pub fn new() -> Self {
+        Self::with_rng(thread_rng())
+    }
+}
+
+impl Default for GreedyMaterial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: Rng> GreedyMaterial<R> {
+    pub fn with_rng(rng: R) -> Self {
+        Self { rng }
+    }
+}
+
+impl<R: Rng> Strategy for GreedyMaterial<R> {
+    fn choose_move(&mut self, state: &MoveState) -> Option<Move> {
+        const MATE: i32 = 1000;
+        let side = state.turn();
+        let mut best = Vec::new();
+        let mut best_score = i32::MIN;
+        for mv in candidate_moves(state) {
+            let Ok(legal_move) = state.validate_move(mv) else { continue };
+            let mut next = state.clone();
+            if next.apply_move(legal_move).is_err() {
+                continue;
+            }
+            let pos: &Position = next.as_ref();
+            let mut score = material_score(pos, side);
+            if next.is_check() && candidate_moves(&next).is_empty() {
+                score += MATE;
+            }
+            if score > best_score {
+                best_score = score;
+                best.clear();
+            }
+            if score == best_score {
+                best.push(mv);
+            }
+        }
+        best.choose(&mut self.rng).copied()
+    }
+}
+
+/// A simple opening book mapping positions to candidate moves.
+#[derive(Debug, Clone, Default)]
+pub struct Book {
+    entries: HashMap<PositionKey, Vec<Move>>,
+}
+
+impl Book {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn insert(&mut self, pos: &Position, mv: Move) {
+        self.insert_key(pos.key(), mv);
+    }
+    pub(crate) fn insert_key(&mut self, key: PositionKey, mv: Move) {
+        self.entries.entry(key).or_default().push(mv);
+    }
+    pub fn get(&self, pos: &Position) -> &[Move] {
+        self.entries.get(&pos.key()).map(Vec::as_slice).unwrap_or(&[])
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Plays a random (legal) book move while the game is in book and defers
+/// to `fallback` afterwards.
+#[derive(Debug, Clone)]
+pub struct BookThen<S, R: Rng = ThreadRng> {
+    book: Book,
+    fallback: S,
+    rng: R,
+}
+
+impl<S: Strategy> BookThen<S> {
+    pub fn new(book: Book, fallback: S) -> Self {
+        Self::with_rng(book, fallback, thread_rng())
+    }
+}
+
+impl<S: Strategy, R: Rng> BookThen<S, R> {
+    pub fn with_rng(book: Book, fallback: S, rng: R) -> Self {
+        Self { book, fallback, rng }
+    }
+}
+
+impl<S: Strategy, R: Rng> Strategy for BookThen<S, R> {
+    fn choose_move(&mut self, state: &MoveState) -> Option<Move> {
+        let pos: &Position = state.as_ref();
+        let moves: Vec<Move> = self.book.get(pos).iter()
+            .filter(|mv| state.validate_move(**mv).is_ok())
+            .copied()
+            .collect();
+        match moves.choose(&mut self.rng) {
+            Some(mv) => Some(*mv),
+            None => self.fallback.choose_move(state),
+        }
+    }
+}
+
+/// Suggests candidate moves for the side to play, e.g. for a "hint"
+/// button in a casual client.
+pub trait HintStrategy {
+    /// Returns up to `max_n` moves, best first.
+    fn hints(&mut self, state: &MoveState, max_n: usize) -> Vec<Move>;
+}
+
+/// The default hint selection: book moves while the game is in book,
+/// otherwise the moves that `evaluator` scores highest one ply ahead,
+/// otherwise checks and then captures (most valuable first).
+#[derive(Debug, Clone, Default)]
+pub struct Hints {
+    book: Book,
+    evaluator: Option<fn(&Position, Color) -> i32>,
+}
+
+impl Hints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_book(mut self, book: Book) -> Self {
+        self.book = book;
+        self
+    }
+    /// Ranks moves by `evaluator`, which scores a position from the
+    /// perspective of the given side (`material_score` for instance).
+    pub fn with_evaluator(mut self, evaluator: fn(&Position, Color) -> i32) -> Self {
+        self.evaluator = Some(evaluator);
+        self
+    }
+
+    /// Orders moves with checks first, then captures by the value of the
+    /// captured piece, then everything else.
+    fn tactical_order(state: &MoveState, candidates: &mut [(Move, MoveState)]) {
+        let pos: &Position = state.as_ref();
+        let priority = |(mv, next): &(Move, MoveState)| {
+            let captured = match pos.contents(mv.to) {
+                Some(material) => 1 + material.piece().value(),
+                None if pos.en_passant() == Some(mv.to) && state.our_pawns().contains(mv.from) => {
+                    1 + Pawn.value()
+                },
+                None => 0,
+            };
+            (next.is_check(), captured)
+        };
+        candidates.sort_by_key(|candidate| std::cmp::Reverse(priority(candidate)));
+    }
+}
+
+impl HintStrategy for Hints {
+    fn hints(&mut self, state: &MoveState, max_n: usize) -> Vec<Move> {
+        let pos: &Position = state.as_ref();
+        let book: Vec<Move> = self.book.get(pos).iter()
+            .filter(|mv| state.validate_move(**mv).is_ok())
+            .take(max_n)
+            .copied()
+            .collect();
+        if !book.is_empty() {
+            return book;
+        }
+        let mut candidates: Vec<(Move, MoveState)> = candidate_moves(state).into_iter()
+            .filter_map(|mv| {
+                let legal_move = state.validate_move(mv).ok()?;
+                let mut next = state.clone();
+                next.apply_move(legal_move).ok()?;
+                Some((mv, next))
+            })
+            .collect();
+        Self::tactical_order(state, &mut candidates);
+        if let Some(evaluator) = self.evaluator {
+            // the sort is stable, so equally scored moves stay in
+            // tactical order
+            let side = state.turn();
+            candidates.sort_by_key(|(_, next)| std::cmp::Reverse(evaluator(next.as_ref(), side)));
+        }
+        candidates.into_iter()
+            .take(max_n)
+            .map(|(mv, _)| mv)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use Square::*;
+
+    #[test]
+    fn test_candidate_moves_from_start() {
+        let state = MoveState::default();
+        assert_eq!(candidate_moves(&state).len(), 20);
+    }
+    #[test]
+    fn test_candidate_moves_expand_promotions() {
+        let position = Position::default()
+            .set_contents(B7, Some(Material::WP))
+            .set_contents(B8, None);
+        let state = MoveState::new(position);
+        let promotions = candidate_moves(&state).into_iter()
+            .filter(|mv| mv.promotion.is_some())
+            .count();
+        // b7-b8, b7-a8 and b7-c8, four promotion pieces each
+        assert_eq!(promotions, 12);
+    }
+    #[test]
+    fn test_greedy_takes_free_queen() {
+        let position = Position::default()
+            .set_contents(E3, Some(Material::BQ));
+        let state = MoveState::new(position);
+        let mv = GreedyMaterial::new().choose_move(&state).unwrap();
+        assert_eq!(mv.to, E3);
+    }
+    #[test]
+    fn test_hints() {
+        let mut book = Book::new();
+        let state = MoveState::default();
+        book.insert(state.as_ref(), Move::new(D2, D4, None));
+        book.insert(state.as_ref(), Move::new(C2, C4, None));
+        let mut hints = Hints::new().with_book(book);
+        assert_eq!(hints.hints(&state, 1), vec![Move::new(D2, D4, None)]);
+        assert_eq!(hints.hints(&state, 5).len(), 2);
+
+        // out of book: checks come first, then captures
+        let position = Position::default()
+            .set_contents(E3, Some(Material::BQ))
+            .set_contents(F7, None)
+            .set_contents(D1, None)
+            .set_contents(H3, Some(Material::WQ));
+        let state = MoveState::new(position);
+        let mut hints = Hints::new();
+        let moves = hints.hints(&state, 3);
+        assert_eq!(moves[..2], [Move::new(H3, D7, None), Move::new(H3, H5, None)]);
+        assert_eq!(moves[2].to, E3);
+
+        let mut board = EngineBoard::standard();
+        assert_eq!(board.hints(4).len(), 4);
+        board.submit_move(Move::new(E2, E4, None)).unwrap();
+        let mut hints = Hints::new().with_evaluator(material_score);
+        assert_eq!(board.hints_with(&mut hints, 100).len(), 20);
+    }
+    #[test]
+    fn test_book_then_fallback() {
+        let mut book = Book::new();
+        let state = MoveState::default();
+        let pos: &Position = state.as_ref();
+        book.insert(pos, Move::new(D2, D4, None));
+        let mut strategy = BookThen::new(book, RandomMover::new());
+        assert_eq!(strategy.choose_move(&state), Some(Move::new(D2, D4, None)));
+        let mut board = EngineBoard::standard();
+        board.submit_move(Move::new(E2, E4, None)).unwrap();
+        assert!(board.choose_move(&mut strategy).is_some());
+    }
+}
@@ -0,0 +1,410 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Studies: documents of chapters for trainers and lessons. A chapter is
+//! a line of moves from a start position, annotated like a reviewed game,
+//! with checkpoints where the reader has to find the next move. Chapters
+//! are authored on an `AnalysisBoard` and worked through as a `Lesson`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+use super::annotation::{Annotation, PgnOptions};
+use super::moves::{Move, MoveState};
+use super::pgn::{PgnError, PgnGame};
+use super::play::{replay_moves, ReplayError};
+use super::position::{MoveId, Position};
+use super::review::{Review, ReviewMut};
+use super::square::ParseError;
+use super::AnalysisBoard;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum StudyError {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    /// A move of the chapter's line, or an attempt at a checkpoint, isn't
+    /// legal.
+    #[error(transparent)]
+    Replay(#[from] ReplayError),
+    #[error(transparent)]
+    Pgn(#[from] PgnError),
+    /// A checkpoint must come before one of the line's moves.
+    #[error("No move at ply {0} to make a checkpoint of")]
+    CheckpointOutOfRange(usize),
+    /// A move was attempted where the lesson doesn't ask for one.
+    #[error("The lesson isn't waiting for a move")]
+    NoCheckpoint,
+}
+
+/// A place in a chapter's line where the reader plays the next move
+/// themselves instead of being shown it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// The number of moves played before the checkpoint, so the move to
+    /// find is `moves[ply]`.
+    pub ply: usize,
+    /// The question put to the reader, e.g. "How does White win a piece?".
+    pub prompt: String,
+    /// Other moves accepted as correct, e.g. an equally good move order.
+    #[serde(default)]
+    pub alternatives: Vec<Move>,
+}
+
+impl Checkpoint {
+    pub fn new(ply: usize, prompt: &str) -> Self {
+        Self { ply, prompt: prompt.to_string(), alternatives: Vec::new() }
+    }
+}
+
+/// The verdict on a move played at a checkpoint.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CheckpointVerdict {
+    /// The move of the line, one reaching the same position (such as
+    /// castling entered by dropping the king onto its rook), or one of
+    /// the checkpoint's alternatives.
+    Correct,
+    Wrong,
+}
+
+/// A chapter of a study: a line of moves from `fen`, with annotations
+/// keyed as by `ReviewState::annotations` (the n-th move's at
+/// `MoveId::START + n`) and checkpoints in the order of their plies.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    pub name: String,
+    pub fen: String,
+    pub moves: Vec<Move>,
+    #[serde(default)]
+    pub annotations: BTreeMap<MoveId, Annotation>,
+    #[serde(default)]
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Chapter {
+    /// Creates a chapter, failing if `fen` can't be read or a move of the
+    /// line is illegal. `Lesson::new` checks the checkpoints against the
+    /// line again, since `moves` may change after they were added.
+    pub fn new(name: &str, fen: &str, moves: Vec<Move>) -> Result<Self, StudyError> {
+        let chapter = Self {
+            name: name.to_string(),
+            fen: fen.to_string(),
+            moves,
+            annotations: BTreeMap::new(),
+            checkpoints: Vec::new(),
+        };
+        chapter.replay()?;
+        Ok(chapter)
+    }
+
+    /// Creates a chapter from the current line of `board` and its
+    /// annotations.
+    pub fn from_analysis(name: &str, board: &AnalysisBoard) -> Self {
+        let review = board.state.review_state();
+        let start = review.first().clone();
+        let mut state = MoveState::new(start.clone());
+        let moves = review.line_moves().into_iter()
+            .map(|legal| {
+                let mv = legal.to_move(state.as_ref());
+                // the line was played on the board, so every move is legal
+                let _ = state.apply_move(legal);
+                mv
+            })
+            .collect();
+        Self {
+            name: name.to_string(),
+            fen: start.to_fen(),
+            moves,
+            annotations: review.annotations(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    /// Sets the chapter up on an `AnalysisBoard` to carry on authoring
+    /// it, at the start of the line.
+    pub fn to_analysis(&self) -> Result<AnalysisBoard, StudyError> {
+        let position = self.position()?;
+        replay_moves(&mut MoveState::new(position.clone()), &self.moves)?;
+        let mut board = AnalysisBoard::analyse_from(position);
+        for &mv in &self.moves {
+            // replay_moves played every move
+            let _ = board.submit_move(mv);
+        }
+        let review = board.state.review_state_mut();
+        // Annotations past the end of the line are dropped
+        let annotations = self.annotations.range(..MoveId::START + (self.moves.len() + 1))
+            .map(|(&at, annotation)| (at, annotation.clone()))
+            .collect();
+        let _ = review.set_annotations(annotations);
+        board.skip_to_start();
+        Ok(board)
+    }
+
+    pub fn position(&self) -> Result<Position, StudyError> {
+        Ok(Position::from_fen(&self.fen)?)
+    }
+
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Adds `checkpoint`, replacing any at the same ply.
+    pub fn add_checkpoint(&mut self, checkpoint: Checkpoint) -> Result<(), StudyError> {
+        if checkpoint.ply >= self.moves.len() {
+            return Err(StudyError::CheckpointOutOfRange(checkpoint.ply));
+        }
+        self.remove_checkpoint(checkpoint.ply);
+        let index = self.checkpoints.partition_point(|existing| existing.ply < checkpoint.ply);
+        self.checkpoints.insert(index, checkpoint);
+        Ok(())
+    }
+
+    pub fn remove_checkpoint(&mut self, ply: usize) -> Option<Checkpoint> {
+        let index = self.checkpoints.iter().position(|checkpoint| checkpoint.ply == ply)?;
+        Some(self.checkpoints.remove(index))
+    }
+
+    pub fn checkpoint(&self, ply: usize) -> Option<&Checkpoint> {
+        self.checkpoints.iter().find(|checkpoint| checkpoint.ply == ply)
+    }
+
+    /// Returns the chapter as a PGN game named after it, starting from its
+    /// position. Checkpoints aren't part of PGN and are left out.
+    pub fn to_pgn_game(&self) -> PgnGame {
+        let mut game = PgnGame { moves: self.moves.clone(), ..PgnGame::default() };
+        game.set_tag("Event", &self.name);
+        if self.fen != Position::default().to_fen() {
+            game.set_tag("SetUp", "1");
+            game.set_tag("FEN", &self.fen);
+        }
+        game.with_annotations(self.annotations.clone())
+    }
+
+    /// Returns the states before each move of the line, and after the
+    /// last. Fails if a checkpoint has no move, e.g. after the line was
+    /// cut short.
+    fn replay(&self) -> Result<Vec<MoveState>, StudyError> {
+        if let Some(checkpoint) = self.checkpoints.iter().find(|checkpoint| checkpoint.ply >= self.moves.len()) {
+            return Err(StudyError::CheckpointOutOfRange(checkpoint.ply));
+        }
+        let mut state = MoveState::new(self.position()?);
+        let played = replay_moves(&mut state.clone(), &self.moves)?;
+        let mut states = Vec::with_capacity(played.len() + 1);
+        for legal in played {
+            states.push(state.clone());
+            // replay_moves played every move
+            let _ = state.apply_move(legal);
+        }
+        states.push(state);
+        Ok(states)
+    }
+}
+
+/// A named collection of chapters.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct Study {
+    pub name: String,
+    pub chapters: Vec<Chapter>,
+}
+
+impl Study {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string(), chapters: Vec::new() }
+    }
+
+    /// Formats every chapter as a PGN game, one after another.
+    pub fn to_pgn(&self, options: &PgnOptions) -> Result<String, StudyError> {
+        let games = self.chapters.iter()
+            .map(|chapter| chapter.to_pgn_game().to_pgn(options))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(games.join("\n\n"))
+    }
+}
+
+/// Someone working through a chapter. The line is shown a move at a time
+/// with `advance` until a checkpoint, where the reader's move is checked
+/// with `attempt`; once they find it the lesson carries on along the
+/// line.
+#[derive(Debug, Clone)]
+pub struct Lesson {
+    chapter: Chapter,
+    states: Vec<MoveState>,
+    ply: usize,
+    mistakes: usize,
+}
+
+impl Lesson {
+    pub fn new(chapter: Chapter) -> Result<Self, StudyError> {
+        let states = chapter.replay()?;
+        Ok(Self { chapter, states, ply: 0, mistakes: 0 })
+    }
+
+    pub fn chapter(&self) -> &Chapter {
+        &self.chapter
+    }
+
+    /// Returns the number of moves of the line played so far.
+    pub fn ply(&self) -> usize {
+        self.ply
+    }
+
+    pub fn position(&self) -> &Position {
+        self.states[self.ply].as_ref()
+    }
+
+    /// Returns the annotation of the current position: the notes on the
+    /// last move played, or on the start position.
+    pub fn annotation(&self) -> Option<&Annotation> {
+        self.chapter.annotations.get(&(MoveId::START + self.ply))
+    }
+
+    /// Returns the checkpoint waiting for the reader's move, if any.
+    pub fn pending(&self) -> Option<&Checkpoint> {
+        self.chapter.checkpoint(self.ply)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.ply == self.chapter.moves.len()
+    }
+
+    /// Returns the number of wrong moves played at checkpoints so far.
+    pub fn mistakes(&self) -> usize {
+        self.mistakes
+    }
+
+    /// Plays the line's next move and returns it, or returns `None` at a
+    /// checkpoint or the end of the line.
+    pub fn advance(&mut self) -> Option<Move> {
+        if self.is_finished() || self.pending().is_some() {
+            return None;
+        }
+        self.ply += 1;
+        Some(self.chapter.moves[self.ply - 1])
+    }
+
+    /// Checks the reader's move at the pending checkpoint. A correct move
+    /// plays the line's move, so the lesson carries on along the line
+    /// even after an alternative; a wrong one counts as a mistake and the
+    /// reader can try again. Illegal moves fail without counting.
+    pub fn attempt(&mut self, mv: Move) -> Result<CheckpointVerdict, StudyError> {
+        let checkpoint = self.pending().ok_or(StudyError::NoCheckpoint)?;
+        let mut next = self.states[self.ply].clone();
+        replay_moves(&mut next, &[mv]).map_err(|error| ReplayError { index: self.ply, ..error })?;
+        let expected = self.chapter.moves[self.ply];
+        let correct = mv == expected || checkpoint.alternatives.contains(&mv) || {
+            let reached: &Position = next.as_ref();
+            let line: &Position = self.states[self.ply + 1].as_ref();
+            reached.key() == line.key()
+        };
+        if !correct {
+            self.mistakes += 1;
+            return Ok(CheckpointVerdict::Wrong);
+        }
+        self.ply += 1;
+        Ok(CheckpointVerdict::Correct)
+    }
+
+    /// Goes back to the start of the chapter.
+    pub fn restart(&mut self) {
+        self.ply = 0;
+        self.mistakes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn moves(uci: &str) -> Vec<Move> {
+        uci.split_whitespace().map(|mv| mv.parse().unwrap()).collect()
+    }
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    #[test]
+    fn test_lesson() {
+        // 1. e4 e5 2. Nf3 Nc6 3. Bb5
+        let mut chapter = Chapter::new("Ruy Lopez", START, moves("e2e4 e7e5 g1f3 b8c6 f1b5")).unwrap();
+        let mut checkpoint = Checkpoint::new(2, "Develop with an attack");
+        checkpoint.alternatives.push("b1c3".parse().unwrap());
+        chapter.add_checkpoint(checkpoint).unwrap();
+        chapter.add_checkpoint(Checkpoint::new(4, "Pin the knight?")).unwrap();
+        assert_eq!(chapter.add_checkpoint(Checkpoint::new(5, "")), Err(StudyError::CheckpointOutOfRange(5)));
+        chapter.annotations.entry(MoveId::START + 2usize).or_default().comment = "Open game".to_string();
+        let mut shortened = chapter.clone();
+        shortened.moves.truncate(4);
+        assert_eq!(Lesson::new(shortened).err(), Some(StudyError::CheckpointOutOfRange(4)));
+
+        let mut lesson = Lesson::new(chapter).unwrap();
+        assert_eq!(lesson.attempt("e2e4".parse().unwrap()), Err(StudyError::NoCheckpoint));
+        assert_eq!(lesson.advance(), Some("e2e4".parse().unwrap()));
+        assert_eq!(lesson.advance(), Some("e7e5".parse().unwrap()));
+        assert_eq!(lesson.annotation().unwrap().comment, "Open game");
+        assert_eq!(lesson.pending().unwrap().prompt, "Develop with an attack");
+        assert_eq!(lesson.advance(), None);
+
+        assert_eq!(lesson.attempt("d2d3".parse().unwrap()), Ok(CheckpointVerdict::Wrong));
+        assert!(matches!(lesson.attempt("e1e3".parse().unwrap()), Err(StudyError::Replay(_))));
+        assert_eq!(lesson.mistakes(), 1);
+        // an alternative is accepted, and the lesson follows the line
+        assert_eq!(lesson.attempt("b1c3".parse().unwrap()), Ok(CheckpointVerdict::Correct));
+        assert_eq!(lesson.position().contents(Square::F3), &Some(Material::new(Color::White, Piece::Knight)));
+        assert_eq!(lesson.advance(), Some("b8c6".parse().unwrap()));
+        assert_eq!(lesson.attempt("f1b5".parse().unwrap()), Ok(CheckpointVerdict::Correct));
+        assert!(lesson.is_finished());
+        assert_eq!(lesson.advance(), None);
+    }
+
+    #[test]
+    fn test_castling_onto_the_rook_at_checkpoint() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        let mut chapter = Chapter::new("Castling", fen, moves("e1g1")).unwrap();
+        chapter.add_checkpoint(Checkpoint::new(0, "Get the king safe")).unwrap();
+        let mut lesson = Lesson::new(chapter).unwrap();
+        assert_eq!(lesson.attempt("e1c1".parse().unwrap()), Ok(CheckpointVerdict::Wrong));
+        // the same castling move, entered by dropping the king on its rook
+        assert_eq!(lesson.attempt("e1h1".parse().unwrap()), Ok(CheckpointVerdict::Correct));
+        assert!(lesson.is_finished());
+        lesson.restart();
+        assert_eq!(lesson.ply(), 0);
+        assert_eq!(lesson.mistakes(), 0);
+    }
+
+    #[test]
+    fn test_authoring_on_an_analysis_board() {
+        let mut board = AnalysisBoard::analyse(None);
+        for mv in moves("d2d4 d7d5 c2c4") {
+            board.submit_move(mv).unwrap();
+        }
+        board.annotation_mut(&(MoveId::START + 3usize)).unwrap().add_nag(Nag::Good);
+        board.annotation_mut(&(MoveId::START + 3usize)).unwrap().toggle_arrow(Arrow::new(Square::C4, Square::D5));
+        let mut chapter = Chapter::from_analysis("Queen's Gambit", &board);
+        assert_eq!(chapter.moves, moves("d2d4 d7d5 c2c4"));
+        assert_eq!(chapter.fen, START);
+        chapter.add_checkpoint(Checkpoint::new(2, "Challenge the centre")).unwrap();
+
+        let board = chapter.to_analysis().unwrap();
+        assert_eq!(board.annotations(), chapter.annotations);
+        assert_eq!(*board.offset(), MoveId::START);
+
+        let mut study = Study::new("Openings");
+        study.chapters.push(chapter.clone());
+        let json = serde_json::to_string(&study).unwrap();
+        assert_eq!(serde_json::from_str::<Study>(&json).unwrap(), study);
+        let pgn = study.to_pgn(&PgnOptions::default()).unwrap();
+        assert!(pgn.starts_with("[Event \"Queen's Gambit\"]\n\n1. d4 d5 2. c4 $1 {[%cal Gc4d5]}"), "{pgn}");
+
+        assert!(matches!(Chapter::new("Bad", START, moves("e2e5")), Err(StudyError::Replay(_))));
+    }
+}
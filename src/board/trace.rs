@@ -0,0 +1,83 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Optional `tracing` instrumentation. With the `tracing` feature each
+//! board gets a `game` span, and every move is recorded inside it as a
+//! `DEBUG` event carrying its SAN and the resulting FEN (plus `INFO`
+//! events for results). Without the feature everything here compiles to
+//! nothing.
+
+use super::backrank::BackRankId;
+use super::moves::{LegalMove, Move, MoveState};
+use super::play::BoardResult;
+use super::position::{MoveId, Position};
+use crate::error::ChessError;
+
+#[derive(Debug, Clone)]
+pub(crate) struct GameSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+impl GameSpan {
+    /// Creates the span for a new game. `mode` is "engine" or "player".
+    pub fn new(mode: &'static str, id: BackRankId) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!("game", mode, backrank = id.to_index()),
+        }
+    }
+
+    /// Returns true if move events would be recorded, so callers can skip
+    /// the work of preparing them otherwise.
+    #[inline]
+    pub fn wants_moves(&self) -> bool {
+        #[cfg(feature = "tracing")]
+        {
+            tracing::enabled!(tracing::Level::DEBUG) && !self.span.is_disabled()
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            false
+        }
+    }
+
+    /// Records `mv`, played from `before`, which produced `after`.
+    pub fn move_applied(&self, before: &MoveState, mv: LegalMove, move_id: MoveId, after: &Position) {
+        #[cfg(feature = "tracing")]
+        self.span.in_scope(|| {
+            tracing::debug!(
+                ply = move_id.ply(),
+                san = %before.to_san(mv),
+                fen = %after.to_fen(),
+                "move applied",
+            );
+        });
+    }
+
+    pub fn move_rejected(&self, mv: Move, error: &ChessError) {
+        #[cfg(feature = "tracing")]
+        self.span.in_scope(|| {
+            tracing::debug!(uci = %mv, %error, "move rejected");
+        });
+    }
+
+    pub fn result(&self, result: BoardResult) {
+        #[cfg(feature = "tracing")]
+        self.span.in_scope(|| {
+            tracing::info!(%result, "game over");
+        });
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use std::fmt;
+use std::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::antichess::AntichessRules;
+use super::rules::{Rules, StandardRules};
+
+/// Names the rules a game is played by, e.g. when saving a position.
+/// Chess960 only changes the starting position (see `BackRank`), so it's
+/// played under `Standard` rules. Variants outside this crate are added
+/// with `Variant::register`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Variant {
+    #[default]
+    Standard,
+    /// Losing chess (see `AntichessRules`).
+    Antichess,
+    /// Rules registered with `Variant::register`.
+    Custom(CustomVariant),
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum VariantError {
+    #[error("A variant named {0:?} already exists")]
+    NameTaken(String),
+}
+
+/// A variant registered at runtime. It's saved by name, so a program
+/// reading a saved game must have registered the same name first.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomVariant(usize);
+
+static CUSTOM_VARIANTS: RwLock<Vec<(&str, &dyn Rules)>> = RwLock::new(Vec::new());
+
+impl CustomVariant {
+    fn get(&self) -> (&'static str, &'static dyn Rules) {
+        CUSTOM_VARIANTS.read().unwrap_or_else(|error| error.into_inner())[self.0]
+    }
+}
+
+impl fmt::Debug for CustomVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CustomVariant({:?})", self.get().0)
+    }
+}
+
+impl Serialize for CustomVariant {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.get().0)
+    }
+}
+
+impl<'de> Deserialize<'de> for CustomVariant {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        match Variant::from_name(&name) {
+            Some(Variant::Custom(custom)) => Ok(custom),
+            _ => Err(serde::de::Error::custom(format!("Unknown variant {name:?}"))),
+        }
+    }
+}
+
+impl Variant {
+    /// Makes `rules` available to positions under `name`, returning the
+    /// variant to create them with (see `Position::new_variant`). Fails
+    /// if the name is already in use.
+    pub fn register(name: &'static str, rules: &'static dyn Rules) -> Result<Self, VariantError> {
+        let mut variants = CUSTOM_VARIANTS.write().unwrap_or_else(|error| error.into_inner());
+        let taken = [Self::Standard, Self::Antichess].iter().any(|variant| variant.name() == name)
+            || variants.iter().any(|(registered, _)| *registered == name);
+        if taken {
+            return Err(VariantError::NameTaken(name.to_string()));
+        }
+        variants.push((name, rules));
+        Ok(Self::Custom(CustomVariant(variants.len() - 1)))
+    }
+
+    /// Returns the variant called `name`, including registered ones.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Standard" => Some(Self::Standard),
+            "Antichess" => Some(Self::Antichess),
+            _ => CUSTOM_VARIANTS.read().unwrap_or_else(|error| error.into_inner())
+                .iter()
+                .position(|(registered, _)| *registered == name)
+                .map(|index| Self::Custom(CustomVariant(index))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Standard => "Standard",
+            Self::Antichess => "Antichess",
+            Self::Custom(custom) => custom.get().0,
+        }
+    }
+
+    pub fn rules(&self) -> &'static dyn Rules {
+        match self {
+            Self::Standard => &StandardRules,
+            Self::Antichess => &AntichessRules,
+            Self::Custom(custom) => custom.get().1,
+        }
+    }
+}
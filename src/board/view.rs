@@ -0,0 +1,172 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use serde::{Deserialize, Serialize};
+
+use super::material::Color;
+use super::square::{File, Mask, Rank, Square};
+use super::PlayerBoard;
+
+/// Maps squares to screen cells for a board drawn from one side. Cells
+/// are numbered 0 to 63 row by row from the top left corner, so with
+/// White at the bottom cell 0 is a8, and with Black at the bottom it's h1.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BoardView {
+    orientation: Color,
+}
+
+impl BoardView {
+    /// Returns the view with `orientation`'s pieces at the bottom.
+    pub const fn new(orientation: Color) -> Self {
+        Self { orientation }
+    }
+
+    /// Returns the view from the side `board` plays.
+    pub fn for_player(board: &PlayerBoard) -> Self {
+        Self::new(board.side())
+    }
+
+    /// Returns the color at the bottom of the screen.
+    pub const fn orientation(&self) -> Color {
+        self.orientation
+    }
+
+    pub const fn flipped(&self) -> Self {
+        Self::new(match self.orientation {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        })
+    }
+
+    /// Returns the ranks from the top of the screen to the bottom.
+    pub fn ranks(&self) -> impl DoubleEndedIterator<Item = Rank> {
+        let flip = self.is_flipped();
+        (0..8).map(move |index| Rank::from_index(if flip { 7 - index } else { index }))
+    }
+
+    /// Returns the files from the left of the screen to the right.
+    pub fn files(&self) -> impl DoubleEndedIterator<Item = File> {
+        let flip = self.is_flipped();
+        (0..8).map(move |index| File::from_index(if flip { 7 - index } else { index }))
+    }
+
+    /// Returns the squares in cell order.
+    pub fn squares(&self) -> impl DoubleEndedIterator<Item = Square> {
+        let view = *self;
+        (0..64).map(move |cell| view.square(cell))
+    }
+
+    /// Returns the cell showing `square`.
+    pub const fn cell(&self, square: Square) -> usize {
+        match self.orientation {
+            Color::White => square.to_index(),
+            Color::Black => 63 - square.to_index(),
+        }
+    }
+
+    /// Returns the (row, column) of the cell showing `square`, counting
+    /// from the top left corner.
+    pub const fn row_column(&self, square: Square) -> (usize, usize) {
+        let cell = self.cell(square);
+        (cell / 8, cell % 8)
+    }
+
+    /// Returns the square shown in `cell`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cell` is 64 or more.
+    pub const fn square(&self, cell: usize) -> Square {
+        assert!(cell < 64, "BoardView::square - cell out of range");
+        match self.orientation {
+            Color::White => Square::from_index(cell),
+            Color::Black => Square::from_index(63 - cell),
+        }
+    }
+
+    /// Returns the square at (`row`, `column`) from the top left corner,
+    /// if it's on the board.
+    pub const fn square_at(&self, row: usize, column: usize) -> Option<Square> {
+        if row < 8 && column < 8 {
+            Some(self.square(row * 8 + column))
+        } else {
+            None
+        }
+    }
+
+    /// Returns `mask` with its bits in cell order: bit 63 (the most
+    /// significant) is cell 0, matching the bit order of a `Mask` viewed
+    /// from White.
+    pub fn cells(&self, mask: Mask) -> Mask {
+        match self.orientation {
+            Color::White => mask,
+            Color::Black => Mask::new(mask.reverse_bits()),
+        }
+    }
+
+    /// Returns the cells a piece shown in `cell` can move to on `board`,
+    /// as legal moves on our turn or pre-moves otherwise (see
+    /// `PlayerBoard::move_destinations`), in cell order.
+    pub fn move_destinations(&self, board: &PlayerBoard, cell: usize) -> Mask {
+        self.cells(board.move_destinations(self.square(cell)))
+    }
+
+    const fn is_flipped(&self) -> bool {
+        matches!(self.orientation, Color::Black)
+    }
+}
+
+impl From<Color> for BoardView {
+    fn from(orientation: Color) -> Self {
+        Self::new(orientation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use Square::*;
+
+    #[test]
+    fn test_orientation() {
+        let white = BoardView::new(Color::White);
+        let black = white.flipped();
+        assert_eq!((white.square(0), black.square(0)), (A8, H1));
+        assert_eq!((white.cell(E2), black.cell(E2)), (52, 11));
+        assert_eq!(black.row_column(E2), (1, 3));
+        assert_eq!(black.square_at(1, 3), Some(E2));
+        assert_eq!(black.square_at(8, 0), None);
+        assert_eq!(white.ranks().next(), Some(Rank::Rank8));
+        assert_eq!(black.ranks().next(), Some(Rank::Rank1));
+        assert_eq!(black.files().collect::<Vec<_>>()[..2], [File::FileH, File::FileG]);
+        assert!(black.squares().enumerate().all(|(cell, square)| black.cell(square) == cell));
+    }
+
+    #[test]
+    fn test_move_destinations_in_cell_order() {
+        let board = PlayerBoard::plays_black(None);
+        let view = BoardView::for_player(&board);
+        // the knight on g8 is shown in the bottom row, second column
+        let cell = view.cell(G8);
+        assert_eq!(cell, 57);
+        let cells: Vec<usize> = view.move_destinations(&board, cell).iter()
+            .map(|square| square.to_index())
+            .collect();
+        // pre-moves to h6, f6 and e7 (in case the pawn moves first)
+        assert_eq!(cells, [view.cell(H6), view.cell(F6), view.cell(E7)]);
+        let white = BoardView::new(Color::White);
+        assert_eq!(white.cells(board.move_destinations(G8)), board.move_destinations(G8));
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Partial views of a position for blindfold training and "fog of war"
+//! variants, where a player is only shown some of what's on the board.
+
+use serde::{Deserialize, Serialize};
+
+use super::material::{Color, Material};
+use super::moves::MoveState;
+use super::position::Pos;
+use super::square::{Direction, Mask, Rank, Square};
+use super::PlayerBoard;
+
+/// What a player is allowed to see of a position.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ViewFilter {
+    /// Only show their pieces on squares our pieces occupy, attack or can
+    /// push a pawn to.
+    pub fog_of_war: bool,
+    /// Show our pieces as occupied squares without saying what they are.
+    pub hide_ours: bool,
+    /// Show their (visible) pieces as occupied squares without saying
+    /// what they are.
+    pub hide_theirs: bool,
+}
+
+impl ViewFilter {
+    /// Everything is shown.
+    pub const FULL: Self = Self { fog_of_war: false, hide_ours: false, hide_theirs: false };
+    /// Every piece is shown as an occupied square of its color, e.g. to
+    /// practice blindfold play.
+    pub const BLINDFOLD: Self = Self { fog_of_war: false, hide_ours: true, hide_theirs: true };
+    /// Their pieces out of sight are hidden.
+    pub const FOG_OF_WAR: Self = Self { fog_of_war: true, hide_ours: false, hide_theirs: false };
+}
+
+/// What a player is shown of one square.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SquareView {
+    Empty,
+    Piece(Material),
+    /// A piece of this color whose identity is hidden.
+    Hidden(Color),
+    /// The square is out of sight.
+    Fog,
+}
+
+/// A position as shown to `viewer` through a `ViewFilter`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FilteredPosition {
+    viewer: Color,
+    visible: Mask,
+    /// Indexed by `Square::to_index`.
+    squares: Vec<SquareView>,
+}
+
+impl FilteredPosition {
+    /// Filters the position of `state` for `viewer`, whichever side is to
+    /// play.
+    pub fn new(state: &MoveState, viewer: Color, filter: ViewFilter) -> Self {
+        let visible = match filter.fog_of_war {
+            true => Self::sight(state, viewer),
+            false => Mask::all(),
+        };
+        let squares = (0..64).map(Square::from_index).map(|square| {
+            let Some(material) = *state.contents(square) else {
+                return match visible.contains(square) {
+                    true => SquareView::Empty,
+                    false => SquareView::Fog,
+                };
+            };
+            let ours = material.color() == viewer;
+            if !ours && !visible.contains(square) {
+                SquareView::Fog
+            } else if (ours && filter.hide_ours) || (!ours && filter.hide_theirs) {
+                SquareView::Hidden(material.color())
+            } else {
+                SquareView::Piece(material)
+            }
+        }).collect();
+        Self { viewer, visible, squares }
+    }
+
+    pub fn viewer(&self) -> Color {
+        self.viewer
+    }
+
+    /// Returns the squares the viewer can see; every square unless the
+    /// filter has fog of war.
+    pub fn visible(&self) -> Mask {
+        self.visible
+    }
+
+    pub fn get(&self, square: Square) -> SquareView {
+        self.squares[square.to_index()]
+    }
+
+    /// Returns the squares shown with a piece of `color`, whether or not
+    /// its identity is hidden.
+    pub fn occupied_by(&self, color: Color) -> Mask {
+        Mask::from_squares((0..64).map(Square::from_index).filter(|&square| match self.get(square) {
+            SquareView::Piece(material) => material.color() == color,
+            SquareView::Hidden(hidden) => hidden == color,
+            _ => false,
+        }))
+    }
+
+    /// Returns the squares `viewer` can see: those their own pieces occupy
+    /// or attack and those their pawns could advance to.
+    fn sight(state: &MoveState, viewer: Color) -> Mask {
+        let (forward, start) = match viewer {
+            Color::White => (Direction::Up, Rank::Rank3),
+            Color::Black => (Direction::Down, Rank::Rank6),
+        };
+        let pawns = state.pawns() & state.occupied_by(viewer);
+        let single = pawns.shift(forward) & state.vacant();
+        let double = (single & start.to_mask()).shift(forward) & state.vacant();
+        state.occupied_by(viewer) | state.attacked_squares(viewer) | single | double
+    }
+}
+
+impl PlayerBoard {
+    /// Returns the current position as our side is shown it through
+    /// `filter`.
+    pub fn filtered_view(&self, filter: ViewFilter) -> FilteredPosition {
+        FilteredPosition::new(self.state.as_ref(), self.side(), filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use Square::*;
+
+    #[test]
+    fn test_fog_of_war() {
+        let mut board = PlayerBoard::plays_white(None);
+        board.submit_our_move("e2e4".parse().unwrap()).unwrap();
+        board.submit_their_move("d7d5".parse().unwrap()).unwrap();
+        let view = board.filtered_view(ViewFilter::FOG_OF_WAR);
+        assert_eq!(view.viewer(), Color::White);
+        // the pawn on d5 is attacked and the e-pawn sees e5
+        assert_eq!(view.get(D5), SquareView::Piece(Material::BP));
+        assert_eq!(view.get(E5), SquareView::Empty);
+        assert_eq!(view.get(E8), SquareView::Fog);
+        assert_eq!(view.get(D6), SquareView::Fog);
+        // pawns see two squares ahead from the start, unless blocked
+        assert_eq!(view.get(A4), SquareView::Empty);
+        assert_eq!(view.get(E1), SquareView::Piece(Material::WK));
+        assert_eq!(view.occupied_by(Color::Black), D5.to_mask());
+        assert!(view.visible().contains(H5) && !view.visible().contains(H6));
+    }
+    #[test]
+    fn test_blocked_double_advance_stays_hidden() {
+        let position = Position::from_fen("4k3/8/8/8/n7/8/P7/4K3 w - - 0 1").unwrap();
+        let view = FilteredPosition::new(&MoveState::new(position), Color::White, ViewFilter::FOG_OF_WAR);
+        assert_eq!(view.get(A3), SquareView::Empty);
+        assert_eq!(view.get(A4), SquareView::Fog);
+    }
+
+    #[test]
+    fn test_hidden_identity() {
+        let board = PlayerBoard::plays_black(None);
+        let view = board.filtered_view(ViewFilter::BLINDFOLD);
+        assert_eq!(view.get(E1), SquareView::Hidden(Color::White));
+        assert_eq!(view.get(E8), SquareView::Hidden(Color::Black));
+        assert_eq!(view.get(E4), SquareView::Empty);
+        assert_eq!(view.visible(), Mask::all());
+
+        let filter = ViewFilter { hide_theirs: true, ..ViewFilter::FOG_OF_WAR };
+        let view = board.filtered_view(filter);
+        assert_eq!(view.get(E8), SquareView::Piece(Material::BK));
+        assert_eq!(view.get(E1), SquareView::Fog);
+        assert_eq!(board.filtered_view(ViewFilter::FULL).get(E1), SquareView::Piece(Material::WK));
+    }
+}
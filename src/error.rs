@@ -0,0 +1,87 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use thiserror::Error;
+
+use crate::board::{
+    BackRankError, DrawClaimError, InvariantError, MoveError, ParseError,
+    PgnError, PlayResult, PromotionPrompt, PuzzleError, RateLimited, ReplayError, ReviewError, StudyError,
+    TakebackError, VariantError,
+};
+use crate::game::{AbandonmentError, AbortError, CodecError, LobbyError, MatchError, SpectateError, TournamentError};
+use crate::interop::InteropError;
+
+/// Every failure reported by the public API, so callers can match on the
+/// kind of failure. Each variant wraps the more specific error returned
+/// by the module that detected it.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ChessError {
+    /// The move is illegal, out of turn or starts from an empty square.
+    #[error(transparent)]
+    Move(#[from] MoveError),
+    /// The game has already ended with this result.
+    #[error("The game is over: {0:?}")]
+    GameOver(PlayResult),
+    /// A pawn move onto the last rank was submitted without a promotion
+    /// piece (see `PromotionPolicy`).
+    #[error("Choose a piece to promote to")]
+    NeedsPromotion(PromotionPrompt),
+    /// The game hasn't ended yet, e.g. when asking to analyse it.
+    #[error("The game is still in progress")]
+    GameInProgress,
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    /// The back rank setup is invalid.
+    #[error(transparent)]
+    Setup(#[from] BackRankError),
+    /// A position is internally inconsistent.
+    #[error(transparent)]
+    Invariant(#[from] InvariantError),
+    #[error(transparent)]
+    Variant(#[from] VariantError),
+    #[error(transparent)]
+    Pgn(#[from] PgnError),
+    #[error(transparent)]
+    Puzzle(#[from] PuzzleError),
+    #[error(transparent)]
+    Study(#[from] StudyError),
+    #[error(transparent)]
+    Review(#[from] ReviewError),
+    #[error(transparent)]
+    Replay(#[from] ReplayError),
+    #[error(transparent)]
+    Takeback(#[from] TakebackError),
+    #[error(transparent)]
+    DrawClaim(#[from] DrawClaimError),
+    #[error(transparent)]
+    RateLimited(#[from] RateLimited),
+    #[error(transparent)]
+    Interop(#[from] InteropError),
+    #[error(transparent)]
+    Match(#[from] MatchError),
+    #[error(transparent)]
+    Tournament(#[from] TournamentError),
+    #[error(transparent)]
+    Spectate(#[from] SpectateError),
+    #[error(transparent)]
+    Abort(#[from] AbortError),
+    #[error(transparent)]
+    Abandonment(#[from] AbandonmentError),
+    #[error(transparent)]
+    Lobby(#[from] LobbyError),
+    #[error(transparent)]
+    Codec(#[from] CodecError),
+}
+
+pub type Result<T, E = ChessError> = std::result::Result<T, E>;
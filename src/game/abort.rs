@@ -0,0 +1,140 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Aborting games. An aborted game ends without a result, so unlike an
+//! abandoned one it isn't scored or rated. Either player may abort until
+//! both sides have moved; after that it takes both of them asking.
+
+use thiserror::Error;
+
+use crate::{Color, EngineBoard, Pair};
+use super::GameOutcome;
+
+/// The number of plies after which a game can only be aborted by
+/// agreement: one move by each side.
+const UNILATERAL_ABORT_PLIES: usize = 2;
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbortError {
+    #[error("The game is over")]
+    GameOver,
+    #[error("Both sides have moved, so aborting needs the opponent's agreement")]
+    Underway,
+}
+
+/// Returns true if either player could abort the game on `board` without
+/// the other's agreement.
+pub fn can_abort(board: &EngineBoard) -> bool {
+    board.board_result().is_none() && board.plies() < UNILATERAL_ABORT_PLIES
+}
+
+/// Aborts the game on `board` for one player, which is only allowed
+/// before both sides have moved (see `AbortRequests` for aborting by
+/// agreement).
+pub fn abort(board: &EngineBoard) -> Result<GameOutcome, AbortError> {
+    if board.board_result().is_some() {
+        return Err(AbortError::GameOver);
+    }
+    if !can_abort(board) {
+        return Err(AbortError::Underway);
+    }
+    Ok(GameOutcome::Aborted)
+}
+
+/// The players' requests to abort a game that's underway. A request
+/// lapses when the next move is played.
+#[derive(Debug, Clone, Default)]
+pub struct AbortRequests(Pair<bool>);
+
+impl AbortRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `color` wants to abort the game on `board`. Returns
+    /// `GameOutcome::Aborted` if the game is aborted now, because it's
+    /// early enough or the opponent asked too, or `None` while waiting
+    /// for the opponent.
+    pub fn request(&mut self, board: &EngineBoard, color: Color) -> Result<Option<GameOutcome>, AbortError> {
+        match abort(board) {
+            Err(AbortError::Underway) => (),
+            outcome => return outcome.map(Some),
+        }
+        self.0[color] = true;
+        Ok(self.0[!color].then_some(GameOutcome::Aborted))
+    }
+
+    pub fn withdraw(&mut self, color: Color) {
+        self.0[color] = false;
+    }
+
+    /// Returns the player waiting for the opponent to agree, if any.
+    pub fn pending(&self) -> Option<Color> {
+        [Color::White, Color::Black].into_iter().find(|&color| self.0[color])
+    }
+
+    /// Clears the requests once a move has been played.
+    pub fn on_move(&mut self) {
+        self.0 = Pair::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use Color::*;
+
+    fn mv(uci: &str) -> Move {
+        uci.parse().unwrap()
+    }
+
+    #[test]
+    fn test_abort_before_both_sides_move() {
+        let mut board = EngineBoard::standard();
+        assert_eq!(abort(&board), Ok(GameOutcome::Aborted));
+        board.submit_move(mv("e2e4")).unwrap();
+        assert!(can_abort(&board));
+        board.submit_move(mv("e7e5")).unwrap();
+        assert!(!can_abort(&board));
+        assert_eq!(abort(&board), Err(AbortError::Underway));
+        board.take_back(1).unwrap();
+        assert_eq!(abort(&board), Ok(GameOutcome::Aborted));
+
+        let mut board = EngineBoard::standard();
+        for uci in ["f2f3", "e7e5", "g2g4", "d8h4"] {
+            board.submit_move(mv(uci)).unwrap();
+        }
+        assert_eq!(abort(&board), Err(AbortError::GameOver));
+    }
+
+    #[test]
+    fn test_abort_by_agreement() {
+        let mut board = EngineBoard::standard();
+        let mut requests = AbortRequests::new();
+        assert_eq!(requests.request(&board, Black), Ok(Some(GameOutcome::Aborted)));
+
+        board.submit_move(mv("e2e4")).unwrap();
+        board.submit_move(mv("e7e5")).unwrap();
+        assert_eq!(requests.request(&board, White), Ok(None));
+        assert_eq!(requests.pending(), Some(White));
+        // the request lapses once a move is played
+        board.submit_move(mv("g1f3")).unwrap();
+        requests.on_move();
+        assert_eq!(requests.pending(), None);
+        assert_eq!(requests.request(&board, Black), Ok(None));
+        requests.withdraw(Black);
+        assert_eq!(requests.request(&board, White), Ok(None));
+        assert_eq!(requests.request(&board, Black), Ok(Some(GameOutcome::Aborted)));
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::{Color, EngineBoard, Pair};
+use super::{Clock, GameResult, TimeControl, WinReason};
+
+/// An Armageddon game always has a winner: White gets more time, but
+/// Black wins if the game is drawn.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ArmageddonRules {
+    pub white: TimeControl,
+    pub black: TimeControl,
+}
+
+impl ArmageddonRules {
+    pub const fn new(white: TimeControl, black: TimeControl) -> Self {
+        Self { white, black }
+    }
+
+    /// Returns a clock set up with each side's time control.
+    pub fn clock(&self) -> Clock {
+        Clock::asymmetric(Pair::new(self.white, self.black))
+    }
+
+    /// Marks `board` as an Armageddon game so that results reported for
+    /// it (see `GameResult::of_board`) follow these rules.
+    pub fn apply(&self, board: &mut EngineBoard) {
+        board.set_armageddon(true);
+    }
+}
+
+/// Five minutes for White against four for Black, without increment.
+impl Default for ArmageddonRules {
+    fn default() -> Self {
+        Self::new(
+            TimeControl::new(Duration::from_secs(300), Duration::ZERO),
+            TimeControl::new(Duration::from_secs(240), Duration::ZERO),
+        )
+    }
+}
+
+impl GameResult {
+    /// Applies Armageddon rules: a draw becomes a win for Black.
+    pub fn armageddon(self) -> Self {
+        match self {
+            Self::Draw(reason) => Self::Win(Color::Black, WinReason::Draw(reason)),
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_draws_become_black_wins() {
+        let draw = GameResult::Draw(DrawReason::StaleMate);
+        assert_eq!(draw.armageddon(), GameResult::Win(Color::Black, WinReason::Draw(DrawReason::StaleMate)));
+        let win = GameResult::Win(Color::White, WinReason::CheckMate);
+        assert_eq!(win.armageddon(), win);
+    }
+
+    #[test]
+    fn test_board_results() {
+        let rules = ArmageddonRules::default();
+        let mut board = EngineBoard::standard();
+        rules.apply(&mut board);
+        // Knights shuffle back and forth until threefold repetition
+        let moves = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        for mv in moves.iter().cycle().take(8) {
+            assert_eq!(GameResult::of_board(&board), None);
+            board.submit_move(mv.parse().unwrap()).unwrap();
+        }
+        assert_eq!(
+            GameResult::of_board(&board),
+            Some(GameResult::Win(Color::Black, WinReason::Draw(DrawReason::Repetition))),
+        );
+        assert_eq!(
+            GameResult::time_expired(&board, Color::Black),
+            GameResult::Win(Color::White, WinReason::TimeExpired),
+        );
+
+        let clock = rules.clock();
+        assert_eq!(clock.control(Color::White).initial(), Duration::from_secs(300));
+        assert_eq!(clock.control(Color::Black).initial(), Duration::from_secs(240));
+    }
+}
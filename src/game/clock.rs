@@ -0,0 +1,536 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+use crate::{Color, Pair};
+
+/// Initial time per side plus a per-move allowance in one of the usual
+/// forms: a Fischer increment added after each move, a Bronstein delay
+/// (the time used on a move, up to the delay, is given back) or a simple
+/// delay (the clock only starts counting down once the delay has passed).
+/// Bronstein and simple delay never add more time than was used, so the
+/// clock can't grow beyond its initial time.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(try_from = "TimeControlRepr", into = "TimeControlRepr")]
+pub enum TimeControl {
+    Fischer { initial: Duration, increment: Duration },
+    Bronstein { initial: Duration, delay: Duration },
+    SimpleDelay { initial: Duration, delay: Duration },
+}
+
+impl TimeControl {
+    /// Creates a time control with a Fischer increment.
+    pub const fn new(initial: Duration, increment: Duration) -> Self {
+        Self::Fischer { initial, increment }
+    }
+
+    pub const fn bronstein(initial: Duration, delay: Duration) -> Self {
+        Self::Bronstein { initial, delay }
+    }
+
+    pub const fn simple_delay(initial: Duration, delay: Duration) -> Self {
+        Self::SimpleDelay { initial, delay }
+    }
+
+    pub const fn initial(&self) -> Duration {
+        match *self {
+            Self::Fischer { initial, .. }
+            | Self::Bronstein { initial, .. }
+            | Self::SimpleDelay { initial, .. } => initial,
+        }
+    }
+
+    /// Returns the time charged for a turn that lasted `used`.
+    fn charged(&self, used: Duration) -> Duration {
+        match *self {
+            Self::SimpleDelay { delay, .. } => used.saturating_sub(delay),
+            _ => used,
+        }
+    }
+
+    /// Returns the time added back at the end of a turn that lasted
+    /// `used`.
+    fn bonus(&self, used: Duration) -> Duration {
+        match *self {
+            Self::Fischer { increment, .. } => increment,
+            Self::Bronstein { delay, .. } => used.min(delay),
+            Self::SimpleDelay { .. } => Duration::ZERO,
+        }
+    }
+
+    /// Returns how long a turn lasts before the clock starts counting
+    /// down.
+    fn grace(&self) -> Duration {
+        match *self {
+            Self::SimpleDelay { delay, .. } => delay,
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// Serialized form of a `TimeControl`: the initial time and increment
+/// written before delays were supported, plus the delay if there is one.
+#[derive(Serialize, Deserialize)]
+struct TimeControlRepr {
+    initial: Duration,
+    increment: Duration,
+    #[serde(default)]
+    delay: Option<DelayRepr>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum DelayRepr {
+    Bronstein(Duration),
+    Simple(Duration),
+}
+
+impl From<TimeControl> for TimeControlRepr {
+    fn from(control: TimeControl) -> Self {
+        let (increment, delay) = match control {
+            TimeControl::Fischer { increment, .. } => (increment, None),
+            TimeControl::Bronstein { delay, .. } => (Duration::ZERO, Some(DelayRepr::Bronstein(delay))),
+            TimeControl::SimpleDelay { delay, .. } => (Duration::ZERO, Some(DelayRepr::Simple(delay))),
+        };
+        Self { initial: control.initial(), increment, delay }
+    }
+}
+
+impl TryFrom<TimeControlRepr> for TimeControl {
+    type Error = &'static str;
+    fn try_from(repr: TimeControlRepr) -> Result<Self, Self::Error> {
+        let TimeControlRepr { initial, increment, delay } = repr;
+        match delay {
+            None => Ok(Self::new(initial, increment)),
+            Some(_) if !increment.is_zero() => Err("a time control has either an increment or a delay"),
+            Some(DelayRepr::Bronstein(delay)) => Ok(Self::bronstein(initial, delay)),
+            Some(DelayRepr::Simple(delay)) => Ok(Self::simple_delay(initial, delay)),
+        }
+    }
+}
+
+/// Lag compensation for networked play, modelled on Lichess: when a
+/// client reports the moment it made its move, the time spent in transit
+/// is credited back to the mover, up to `max_per_move` on each move and
+/// `quota` over the whole game. The cap keeps a client from claiming
+/// arbitrary thinking time by misreporting its timestamps.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LagCompensation {
+    pub max_per_move: Duration,
+    pub quota: Duration,
+}
+
+impl LagCompensation {
+    pub const fn new(max_per_move: Duration, quota: Duration) -> Self {
+        Self { max_per_move, quota }
+    }
+}
+
+impl Default for LagCompensation {
+    /// Up to half a second per move and ten seconds per game.
+    fn default() -> Self {
+        Self::new(Duration::from_millis(500), Duration::from_secs(10))
+    }
+}
+
+/// A serializable snapshot of a `Clock`, e.g. to persist a game or hand
+/// it to another server. `Instant`s can't be serialized, so the running
+/// side's time is charged up to the moment the snapshot was taken and
+/// `Clock::restore` starts it again from the moment of restoring.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ClockState {
+    pub controls: Pair<TimeControl>,
+    pub remaining: Pair<Duration>,
+    pub running: Option<Color>,
+    pub history: Vec<Pair<Duration>>,
+    pub lag: Option<LagCompensation>,
+    /// Lag each side may still be credited.
+    pub lag_quota: Pair<Duration>,
+}
+
+/// A chess clock. The clock never reads the system time itself; callers
+/// pass in `now` so the clock can be driven by any time source (and
+/// tested deterministically).
+///
+/// The clock records the remaining times at the start of every ply so
+/// that a takeback can restore them (see `rewind`).
+#[derive(Debug, Clone)]
+pub struct Clock {
+    controls: Pair<TimeControl>,
+    remaining: Pair<Duration>,
+    running: Option<(Color, Instant)>,
+    history: Vec<Pair<Duration>>,
+    lag: Option<LagCompensation>,
+    lag_quota: Pair<Duration>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        Self::asymmetric(Pair::new(control, control))
+    }
+
+    /// Creates a clock with a different time control for each side, e.g.
+    /// for Armageddon games.
+    pub fn asymmetric(controls: Pair<TimeControl>) -> Self {
+        let initial = Pair::new(controls[Color::White].initial(), controls[Color::Black].initial());
+        Self {
+            controls,
+            remaining: initial,
+            running: None,
+            history: vec![initial],
+            lag: None,
+            lag_quota: Pair::default(),
+        }
+    }
+
+    /// Enables (or, with `None`, disables) lag compensation for
+    /// `press_reported`, resetting both sides' quotas.
+    pub fn set_lag_compensation(&mut self, lag: Option<LagCompensation>) {
+        let quota = lag.map(|lag| lag.quota).unwrap_or_default();
+        self.lag = lag;
+        self.lag_quota = Pair::new(quota, quota);
+    }
+
+    pub fn lag_compensation(&self) -> Option<&LagCompensation> {
+        self.lag.as_ref()
+    }
+
+    /// Returns the lag `color` may still be credited this game.
+    pub fn lag_quota(&self, color: Color) -> Duration {
+        self.lag_quota[color]
+    }
+
+    pub fn control(&self, color: Color) -> &TimeControl {
+        &self.controls[color]
+    }
+
+    /// Returns the side whose time is running, if any.
+    pub fn running(&self) -> Option<Color> {
+        self.running.map(|(color, _)| color)
+    }
+
+    /// Starts (or restarts) the clock of `color`.
+    pub fn start(&mut self, color: Color, now: Instant) {
+        self.stop(now);
+        self.running = Some((color, now));
+    }
+
+    /// Stops the running clock, charging the time used so far.
+    pub fn stop(&mut self, now: Instant) {
+        if let Some((color, since)) = self.running.take() {
+            let used = self.controls[color].charged(now.saturating_duration_since(since));
+            let remaining = &mut self.remaining[color];
+            *remaining = remaining.saturating_sub(used);
+        }
+    }
+
+    /// Ends the running side's turn: charges its time, adds the increment
+    /// (or Bronstein delay) and starts the opponent's clock. Returns the mover's remaining time.
+    /// Does nothing if the clock isn't running.
+    pub fn press(&mut self, now: Instant) -> Option<Duration> {
+        self.press_at(now, now)
+    }
+
+    /// Like `press`, for a move the client reports making at `moved_at`
+    /// but which only reached us at `now`. With lag compensation enabled,
+    /// the mover is charged up to `moved_at` rather than `now`, within
+    /// the limits of `LagCompensation`; the opponent's clock starts at
+    /// `now` either way. Without lag compensation this is `press(now)`.
+    pub fn press_reported(&mut self, moved_at: Instant, now: Instant) -> Option<Duration> {
+        let (color, since) = self.running?;
+        let Some(lag) = self.lag else { return self.press(now) };
+        // a client can't have moved before its clock started, nor after
+        // we received the move
+        let moved_at = moved_at.max(since).min(now);
+        let credit = now.duration_since(moved_at)
+            .min(lag.max_per_move)
+            .min(self.lag_quota[color]);
+        self.lag_quota[color] -= credit;
+        self.press_at(now - credit, now)
+    }
+
+    /// Charges the running side up to `charged_at` and starts the
+    /// opponent's clock at `now`.
+    fn press_at(&mut self, charged_at: Instant, now: Instant) -> Option<Duration> {
+        let (color, since) = self.running?;
+        self.stop(charged_at);
+        let used = charged_at.saturating_duration_since(since);
+        // a flagged clock stays flagged
+        if !self.remaining[color].is_zero() {
+            self.remaining[color] += self.controls[color].bonus(used);
+        }
+        self.running = Some((!color, now));
+        self.history.push(self.remaining);
+        Some(self.remaining[color])
+    }
+
+    /// Returns the number of plies recorded by `press`.
+    pub fn plies(&self) -> usize {
+        self.history.len() - 1
+    }
+
+    /// Undoes the last `plies` presses: restores the times recorded at the
+    /// start of the rewound ply and, if the clock was running, restarts
+    /// it from `now` for the side to move at that ply. Returns the
+    /// restored times, or `None` (leaving the clock unchanged) if fewer
+    /// than `plies` presses were recorded.
+    pub fn rewind(&mut self, plies: usize, now: Instant) -> Option<Pair<Duration>> {
+        if plies > self.plies() {
+            return None;
+        }
+        let running = self.running();
+        self.running = None;
+        self.history.truncate(self.history.len() - plies);
+        self.remaining = *self.history.last()?;
+        if let Some(color) = running {
+            let color = if plies.is_multiple_of(2) { color } else { !color };
+            self.running = Some((color, now));
+        }
+        Some(self.remaining)
+    }
+
+    /// Returns the time `color` has left at `now`.
+    pub fn remaining(&self, color: Color, now: Instant) -> Duration {
+        match self.running {
+            Some((running, since)) if running == color => {
+                let used = self.controls[color].charged(now.saturating_duration_since(since));
+                self.remaining[color].saturating_sub(used)
+            },
+            _ => self.remaining[color],
+        }
+    }
+
+    /// Overrides the time left for `color` (the running clock restarts
+    /// counting from `now`).
+    pub fn set_remaining(&mut self, color: Color, remaining: Duration, now: Instant) {
+        if let Some((running, _)) = self.running {
+            if running == color {
+                self.running = Some((color, now));
+            }
+        }
+        self.remaining[color] = remaining;
+    }
+
+    /// Captures the clock's state at `now`.
+    pub fn snapshot(&self, now: Instant) -> ClockState {
+        ClockState {
+            controls: self.controls,
+            remaining: Pair::new(self.remaining(Color::White, now), self.remaining(Color::Black, now)),
+            running: self.running(),
+            history: self.history.clone(),
+            lag: self.lag,
+            lag_quota: self.lag_quota,
+        }
+    }
+
+    /// Recreates a clock from `state`, restarting the running side's time
+    /// from `now`.
+    pub fn restore(state: ClockState, now: Instant) -> Self {
+        let ClockState { controls, remaining, running, mut history, lag, lag_quota } = state;
+        if history.is_empty() {
+            history.push(Pair::new(controls[Color::White].initial(), controls[Color::Black].initial()));
+        }
+        Self {
+            controls,
+            remaining,
+            running: running.map(|color| (color, now)),
+            history,
+            lag,
+            lag_quota,
+        }
+    }
+
+    /// Returns the instant at which the running side runs out of time.
+    pub fn flag_time(&self) -> Option<(Color, Instant)> {
+        self.running.map(|(color, since)| {
+            (color, since + self.controls[color].grace() + self.remaining[color])
+        })
+    }
+
+    /// Returns the side that has run out of time at `now`, if any.
+    pub fn flagged(&self, now: Instant) -> Option<Color> {
+        self.flag_time()
+            .filter(|(_, deadline)| *deadline <= now)
+            .map(|(color, _)| color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Color::*;
+
+    fn secs(value: u64) -> Duration {
+        Duration::from_secs(value)
+    }
+
+    #[test]
+    fn test_press_charges_time_and_adds_increment() {
+        let start = Instant::now();
+        let mut clock = Clock::new(TimeControl::new(secs(60), secs(2)));
+        clock.start(White, start);
+        assert_eq!(clock.remaining(White, start + secs(10)), secs(50));
+        assert_eq!(clock.press(start + secs(10)), Some(secs(52)));
+        assert_eq!(clock.running(), Some(Black));
+        assert_eq!(clock.remaining(White, start + secs(30)), secs(52));
+        assert_eq!(clock.remaining(Black, start + secs(30)), secs(40));
+    }
+    #[test]
+    fn test_asymmetric_controls() {
+        let start = Instant::now();
+        let white = TimeControl::new(secs(300), secs(2));
+        let black = TimeControl::new(secs(240), secs(0));
+        let mut clock = Clock::asymmetric(Pair::new(white, black));
+        assert_eq!(clock.control(Black), &black);
+        assert_eq!(clock.remaining(White, start), secs(300));
+        assert_eq!(clock.remaining(Black, start), secs(240));
+        clock.start(White, start);
+        assert_eq!(clock.press(start + secs(10)), Some(secs(292)));
+        assert_eq!(clock.press(start + secs(20)), Some(secs(230)));
+    }
+    #[test]
+    fn test_rewind_restores_recorded_times() {
+        let start = Instant::now();
+        let mut clock = Clock::new(TimeControl::new(secs(60), secs(1)));
+        clock.start(White, start);
+        clock.press(start + secs(10));
+        clock.press(start + secs(30));
+        clock.press(start + secs(35));
+        assert_eq!(clock.plies(), 3);
+        assert_eq!(clock.rewind(4, start + secs(40)), None);
+        let restored = clock.rewind(2, start + secs(40)).unwrap();
+        assert_eq!(restored, Pair::new(secs(51), secs(60)));
+        assert_eq!(clock.running(), Some(Black));
+        assert_eq!(clock.remaining(Black, start + secs(45)), secs(55));
+        assert_eq!(clock.plies(), 1);
+    }
+    #[test]
+    fn test_flagging() {
+        let start = Instant::now();
+        let mut clock = Clock::new(TimeControl::new(secs(5), secs(0)));
+        assert_eq!(clock.flag_time(), None);
+        clock.start(White, start);
+        assert_eq!(clock.flag_time(), Some((White, start + secs(5))));
+        assert_eq!(clock.flagged(start + secs(4)), None);
+        assert_eq!(clock.flagged(start + secs(5)), Some(White));
+        assert_eq!(clock.remaining(White, start + secs(9)), secs(0));
+    }
+    #[test]
+    fn test_stop_and_set_remaining() {
+        let start = Instant::now();
+        let mut clock = Clock::new(TimeControl::new(secs(60), secs(0)));
+        assert_eq!(clock.press(start), None);
+        clock.start(Black, start);
+        clock.set_remaining(Black, secs(30), start + secs(10));
+        assert_eq!(clock.remaining(Black, start + secs(15)), secs(25));
+        clock.stop(start + secs(20));
+        assert_eq!(clock.running(), None);
+        assert_eq!(clock.remaining(Black, start + secs(100)), secs(20));
+    }
+    #[test]
+    fn test_bronstein_delay() {
+        let start = Instant::now();
+        let mut clock = Clock::new(TimeControl::bronstein(secs(60), secs(3)));
+        clock.start(White, start);
+        // a quick move costs nothing
+        assert_eq!(clock.press(start + secs(2)), Some(secs(60)));
+        // a slow one is only refunded the delay
+        assert_eq!(clock.press(start + secs(12)), Some(secs(53)));
+        assert_eq!(clock.remaining(White, start + secs(14)), secs(58));
+        assert_eq!(clock.flag_time(), Some((White, start + secs(72))));
+        // running out of time isn't refunded
+        assert_eq!(clock.press(start + secs(80)), Some(secs(0)));
+    }
+    #[test]
+    fn test_time_control_serde() {
+        for control in [
+            TimeControl::new(secs(180), secs(2)),
+            TimeControl::bronstein(secs(60), secs(3)),
+            TimeControl::simple_delay(secs(60), secs(5)),
+        ] {
+            let json = serde_json::to_string(&control).unwrap();
+            assert_eq!(serde_json::from_str::<TimeControl>(&json).unwrap(), control);
+        }
+        // time controls saved before delays were supported
+        let json = r#"{"initial":{"secs":180,"nanos":0},"increment":{"secs":2,"nanos":0}}"#;
+        assert_eq!(serde_json::from_str::<TimeControl>(json).unwrap(), TimeControl::new(secs(180), secs(2)));
+        let json = r#"{"initial":{"secs":60,"nanos":0},"increment":{"secs":2,"nanos":0},"delay":{"Simple":{"secs":5,"nanos":0}}}"#;
+        assert!(serde_json::from_str::<TimeControl>(json).is_err());
+    }
+    #[test]
+    fn test_simple_delay() {
+        let start = Instant::now();
+        let mut clock = Clock::new(TimeControl::simple_delay(secs(60), secs(5)));
+        assert_eq!(clock.control(White).initial(), secs(60));
+        clock.start(White, start);
+        assert_eq!(clock.remaining(White, start + secs(4)), secs(60));
+        assert_eq!(clock.remaining(White, start + secs(8)), secs(57));
+        assert_eq!(clock.flag_time(), Some((White, start + secs(65))));
+        assert_eq!(clock.press(start + secs(8)), Some(secs(57)));
+        assert_eq!(clock.press(start + secs(10)), Some(secs(60)));
+        clock.stop(start + secs(20));
+        assert_eq!(clock.remaining(White, start + secs(30)), secs(52));
+    }
+    #[test]
+    fn test_lag_compensation() {
+        let start = Instant::now();
+        let millis = Duration::from_millis;
+        let mut clock = Clock::new(TimeControl::new(secs(60), secs(0)));
+        clock.start(White, start);
+        // without compensation the reported time is ignored
+        assert_eq!(clock.press_reported(start + secs(9), start + secs(10)), Some(secs(50)));
+
+        clock.set_lag_compensation(Some(LagCompensation::new(millis(500), millis(600))));
+        assert_eq!(clock.lag_quota(Black), millis(600));
+        // black is credited the 300ms in transit
+        let now = start + secs(20);
+        assert_eq!(clock.press_reported(now - millis(300), now), Some(secs(50) + millis(300)));
+        assert_eq!(clock.lag_quota(Black), millis(300));
+        assert_eq!(clock.running(), Some(White));
+        assert_eq!(clock.remaining(White, now + secs(1)), secs(49));
+
+        // white's credit is capped per move
+        let now = start + secs(30);
+        assert_eq!(clock.press_reported(now - secs(3), now), Some(secs(40) + millis(500)));
+        // and black's by what's left of the quota
+        let now = start + secs(40);
+        assert_eq!(clock.press_reported(now - secs(3), now), Some(secs(40) + millis(600)));
+        assert_eq!(clock.lag_quota(Black), Duration::ZERO);
+        // a move can't be reported after it was received
+        let now = start + secs(41);
+        assert_eq!(clock.press_reported(now + secs(5), now), Some(secs(39) + millis(500)));
+    }
+    #[test]
+    fn test_snapshot_and_restore() {
+        let start = Instant::now();
+        let mut clock = Clock::new(TimeControl::new(secs(60), secs(2)));
+        clock.set_lag_compensation(Some(LagCompensation::default()));
+        clock.start(White, start);
+        clock.press(start + secs(10));
+        let state = clock.snapshot(start + secs(15));
+        assert_eq!(state.remaining, Pair::new(secs(52), secs(55)));
+        assert_eq!(state.running, Some(Black));
+
+        let json = serde_json::to_string(&state).unwrap();
+        let state: ClockState = serde_json::from_str(&json).unwrap();
+        let later = start + secs(100);
+        let mut restored = Clock::restore(state, later);
+        assert_eq!(restored.running(), Some(Black));
+        assert_eq!(restored.remaining(Black, later + secs(5)), secs(50));
+        assert_eq!(restored.lag_compensation(), Some(&LagCompensation::default()));
+        assert_eq!(restored.plies(), 1);
+        assert_eq!(restored.rewind(1, later), Some(Pair::new(secs(60), secs(60))));
+    }
+}
@@ -0,0 +1,534 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! A compact binary format for storing many games, e.g. in a database.
+//! A game takes two bytes per move, plus a few for its setup, result and
+//! clock readings, instead of the hundreds a PGN record needs.
+//!
+//! A record starts with the format version, then:
+//!
+//! * the variant, one byte;
+//! * the setup: `0` and the back rank id (two bytes, little endian), or
+//!   `1` and a length-prefixed FEN;
+//! * the result: `0` if unknown, else `1` (White wins), `2` (Black wins)
+//!   or `3` (draw), then the reason;
+//! * the moves, counted, then each as `from | to << 6 | promotion << 12`;
+//! * the clock readings, counted, then each in milliseconds.
+//!
+//! Counts and lengths are LEB128 varints. A stream of records (see
+//! `GameWriter` and `GameReader`) prefixes each with its length.
+
+use std::io;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{BackRankId, Color, Move, ParseError, Position, Promotion, Square, Variant};
+use super::{DrawReason, GameResult, WinReason};
+
+/// The version written at the start of every record.
+const FORMAT_VERSION: u8 = 1;
+
+/// No valid record comes close to this many bytes, so a longer one means
+/// the stream is corrupt (rather than a reason to allocate it).
+const MAX_RECORD_LEN: usize = 1 << 20;
+
+/// Where the codes of Armageddon draws start among the win reasons.
+const WIN_DRAW: u8 = 6;
+
+const PROMOTIONS: [Promotion; 5] = [
+    Promotion::Queen,
+    Promotion::Rook,
+    Promotion::Bishop,
+    Promotion::Knight,
+    Promotion::King,
+];
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CodecError {
+    #[error("Unsupported game format version {0}")]
+    UnsupportedVersion(u8),
+    #[error("The game record ends early")]
+    Truncated,
+    #[error("Invalid game record: {0}")]
+    Invalid(&'static str),
+    #[error("Cannot read or write games: {0}")]
+    Io(io::ErrorKind),
+}
+
+impl From<io::Error> for CodecError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => Self::Truncated,
+            kind => Self::Io(kind),
+        }
+    }
+}
+
+/// How a stored game starts.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum GameSetup {
+    /// The starting position of a back rank, e.g. `BackRankId::STANDARD`.
+    BackRank(BackRankId),
+    /// Any other position, e.g. a puzzle or an adjourned game.
+    Fen(String),
+}
+
+/// A game as stored by the codec.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct StoredGame {
+    pub variant: Variant,
+    pub setup: GameSetup,
+    pub moves: Vec<Move>,
+    /// The mover's remaining time after each ply, as in PGN `[%clk]`
+    /// comments, to the millisecond. It may stop short of the moves, or
+    /// be empty if the game wasn't timed.
+    pub clocks: Vec<Duration>,
+    pub result: Option<GameResult>,
+}
+
+impl StoredGame {
+    /// Creates a record of `moves` played from `start`, stored by its back
+    /// rank id if it's a starting position and as a FEN otherwise.
+    pub fn new(start: &Position, moves: Vec<Move>) -> Self {
+        let variant = start.variant();
+        let id = start.backrank().id();
+        let fen = start.to_fen();
+        let setup = match Position::new_variant(id.into(), variant).to_fen() == fen {
+            true => GameSetup::BackRank(id),
+            false => GameSetup::Fen(fen),
+        };
+        Self { variant, setup, moves, clocks: Vec::new(), result: None }
+    }
+
+    pub fn with_clocks(mut self, clocks: Vec<Duration>) -> Self {
+        self.clocks = clocks;
+        self
+    }
+
+    pub fn with_result(mut self, result: GameResult) -> Self {
+        self.result = Some(result);
+        self
+    }
+
+    /// Returns the position the game starts from. The moves aren't
+    /// checked, so replay them to be sure they're legal.
+    pub fn start_position(&self) -> Result<Position, ParseError> {
+        match &self.setup {
+            GameSetup::BackRank(id) => Ok(Position::new_variant((*id).into(), self.variant)),
+            GameSetup::Fen(fen) => Position::from_variant_fen(self.variant, fen),
+        }
+    }
+
+    /// Returns the game in the current format version.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + 2 * self.moves.len() + 3 * self.clocks.len());
+        bytes.push(FORMAT_VERSION);
+        match self.variant {
+            Variant::Standard => bytes.push(0),
+            Variant::Antichess => bytes.push(1),
+            // registered variants are stored by name
+            Variant::Custom(_) => {
+                let name = self.variant.name();
+                bytes.push(2);
+                write_varint(&mut bytes, name.len() as u64);
+                bytes.extend_from_slice(name.as_bytes());
+            },
+        }
+        match &self.setup {
+            GameSetup::BackRank(id) => {
+                bytes.push(0);
+                bytes.extend_from_slice(&(id.to_index() as u16).to_le_bytes());
+            },
+            GameSetup::Fen(fen) => {
+                bytes.push(1);
+                write_varint(&mut bytes, fen.len() as u64);
+                bytes.extend_from_slice(fen.as_bytes());
+            },
+        }
+        encode_result(&mut bytes, self.result);
+        write_varint(&mut bytes, self.moves.len() as u64);
+        for &mv in &self.moves {
+            bytes.extend_from_slice(&encode_move(mv).to_le_bytes());
+        }
+        write_varint(&mut bytes, self.clocks.len() as u64);
+        for clock in &self.clocks {
+            write_varint(&mut bytes, clock.as_millis() as u64);
+        }
+        bytes
+    }
+
+    /// Reads a game written by `encode`, failing on records of a newer
+    /// format version.
+    pub fn decode(bytes: &[u8]) -> Result<Self, CodecError> {
+        let mut input = Decoder { bytes };
+        match input.byte()? {
+            FORMAT_VERSION => (),
+            version => return Err(CodecError::UnsupportedVersion(version)),
+        }
+        let variant = match input.byte()? {
+            0 => Variant::Standard,
+            1 => Variant::Antichess,
+            2 => {
+                let len = input.len()?;
+                let name = std::str::from_utf8(input.take(len)?).map_err(|_| CodecError::Invalid("variant name isn't UTF-8"))?;
+                Variant::from_name(name).ok_or(CodecError::Invalid("unknown variant"))?
+            },
+            _ => return Err(CodecError::Invalid("unknown variant")),
+        };
+        let setup = match input.byte()? {
+            0 => {
+                let id = u16::from_le_bytes([input.byte()?, input.byte()?]);
+                let id = BackRankId::try_from(usize::from(id)).map_err(|_| CodecError::Invalid("back rank out of range"))?;
+                GameSetup::BackRank(id)
+            },
+            1 => {
+                let len = input.len()?;
+                let fen = std::str::from_utf8(input.take(len)?).map_err(|_| CodecError::Invalid("FEN isn't UTF-8"))?;
+                GameSetup::Fen(fen.to_string())
+            },
+            _ => return Err(CodecError::Invalid("unknown setup")),
+        };
+        let result = decode_result(&mut input)?;
+        let count = input.len()?;
+        let moves = input.take(2 * count)?
+            .chunks_exact(2)
+            .map(|code| decode_move(u16::from_le_bytes([code[0], code[1]])))
+            .collect::<Result<_, _>>()?;
+        let count = input.len()?;
+        // every reading takes at least a byte
+        if count > input.bytes.len() {
+            return Err(CodecError::Truncated);
+        }
+        let clocks = (0..count)
+            .map(|_| input.varint().map(Duration::from_millis))
+            .collect::<Result<_, _>>()?;
+        if !input.bytes.is_empty() {
+            return Err(CodecError::Invalid("trailing bytes"));
+        }
+        Ok(Self { variant, setup, moves, clocks, result })
+    }
+}
+
+/// Writes games to a stream, each prefixed with its length.
+#[derive(Debug)]
+pub struct GameWriter<W> {
+    writer: W,
+}
+
+impl<W: io::Write> GameWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn write(&mut self, game: &StoredGame) -> Result<(), CodecError> {
+        let bytes = game.encode();
+        let mut prefix = Vec::with_capacity(3);
+        write_varint(&mut prefix, bytes.len() as u64);
+        self.writer.write_all(&prefix)?;
+        self.writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), CodecError> {
+        Ok(self.writer.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads the games written by a `GameWriter`, one at a time. It stops
+/// after the first error, since the stream can't be resynchronized.
+#[derive(Debug)]
+pub struct GameReader<R> {
+    reader: R,
+    failed: bool,
+}
+
+impl<R: io::Read> GameReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, failed: false }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads the next game, or `None` at the end of the stream.
+    pub fn read(&mut self) -> Result<Option<StoredGame>, CodecError> {
+        let Some(len) = self.read_len()? else { return Ok(None) };
+        let mut bytes = vec![0; len];
+        self.reader.read_exact(&mut bytes)?;
+        StoredGame::decode(&bytes).map(Some)
+    }
+
+    /// Reads a record's length, or `None` if the stream ended before it.
+    fn read_len(&mut self) -> Result<Option<usize>, CodecError> {
+        let mut len = 0;
+        for shift in (0..28).step_by(7) {
+            let mut byte = [0];
+            if self.reader.read(&mut byte)? == 0 {
+                return match shift {
+                    0 => Ok(None),
+                    _ => Err(CodecError::Truncated),
+                };
+            }
+            len |= ((byte[0] & 0x7f) as usize) << shift;
+            if byte[0] & 0x80 == 0 {
+                return match len <= MAX_RECORD_LEN {
+                    true => Ok(Some(len)),
+                    false => Err(CodecError::Invalid("record too long")),
+                };
+            }
+        }
+        Err(CodecError::Invalid("record too long"))
+    }
+}
+
+impl<R: io::Read> Iterator for GameReader<R> {
+    type Item = Result<StoredGame, CodecError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let game = self.read().transpose();
+        self.failed = matches!(game, Some(Err(_)));
+        game
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CodecError> {
+        if len > self.bytes.len() {
+            return Err(CodecError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, CodecError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, CodecError> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(CodecError::Invalid("varint too long"))
+    }
+
+    /// Reads a count or length, which can't exceed the record's size.
+    fn len(&mut self) -> Result<usize, CodecError> {
+        match self.varint()? {
+            len if len as usize <= MAX_RECORD_LEN => Ok(len as usize),
+            _ => Err(CodecError::Truncated),
+        }
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn encode_move(mv: Move) -> u16 {
+    let promotion = mv.promotion
+        .and_then(|promotion| PROMOTIONS.iter().position(|&p| p == promotion))
+        .map_or(0, |index| index + 1);
+    (mv.from.to_index() | mv.to.to_index() << 6 | promotion << 12) as u16
+}
+
+fn decode_move(code: u16) -> Result<Move, CodecError> {
+    let square = |shift: u16| Square::from_index(((code >> shift) & 0x3f) as usize);
+    let promotion = match (code >> 12) as usize {
+        0 => None,
+        index => Some(*PROMOTIONS.get(index - 1).ok_or(CodecError::Invalid("unknown promotion"))?),
+    };
+    Ok(Move::new(square(0), square(6), promotion))
+}
+
+/// The code of a win's reason. An Armageddon draw, won by Black, is
+/// written after the wins as `WIN_DRAW` plus the draw's code.
+fn win_code(reason: WinReason) -> u8 {
+    match reason {
+        WinReason::CheckMate => 0,
+        WinReason::TimeExpired => 1,
+        WinReason::Resigned => 2,
+        WinReason::Abandoned => 3,
+        WinReason::Adjudicated => 4,
+        WinReason::VariantWin => 5,
+        WinReason::Draw(draw) => WIN_DRAW + draw_code(draw),
+    }
+}
+
+fn win_reason(code: u8) -> Option<WinReason> {
+    match code {
+        0 => Some(WinReason::CheckMate),
+        1 => Some(WinReason::TimeExpired),
+        2 => Some(WinReason::Resigned),
+        3 => Some(WinReason::Abandoned),
+        4 => Some(WinReason::Adjudicated),
+        5 => Some(WinReason::VariantWin),
+        code => draw_reason(code.checked_sub(WIN_DRAW)?).map(WinReason::Draw),
+    }
+}
+
+fn draw_code(reason: DrawReason) -> u8 {
+    match reason {
+        DrawReason::Agreed => 0,
+        DrawReason::StaleMate => 1,
+        DrawReason::Repetition => 2,
+        DrawReason::FiftyMoves => 3,
+        DrawReason::Insufficient => 4,
+        DrawReason::TimeoutVsInsufficient => 5,
+        DrawReason::Adjudicated => 6,
+    }
+}
+
+fn draw_reason(code: u8) -> Option<DrawReason> {
+    match code {
+        0 => Some(DrawReason::Agreed),
+        1 => Some(DrawReason::StaleMate),
+        2 => Some(DrawReason::Repetition),
+        3 => Some(DrawReason::FiftyMoves),
+        4 => Some(DrawReason::Insufficient),
+        5 => Some(DrawReason::TimeoutVsInsufficient),
+        6 => Some(DrawReason::Adjudicated),
+        _ => None,
+    }
+}
+
+fn encode_result(bytes: &mut Vec<u8>, result: Option<GameResult>) {
+    let (outcome, reason) = match result {
+        None => return bytes.push(0),
+        Some(GameResult::Win(winner, reason)) => (1 + winner.to_index() as u8, win_code(reason)),
+        Some(GameResult::Draw(reason)) => (3, draw_code(reason)),
+    };
+    bytes.extend_from_slice(&[outcome, reason]);
+}
+
+fn decode_result(input: &mut Decoder) -> Result<Option<GameResult>, CodecError> {
+    let invalid = || CodecError::Invalid("unknown result");
+    let outcome = input.byte()?;
+    if outcome == 0 {
+        return Ok(None);
+    }
+    let reason = input.byte()?;
+    let result = match outcome {
+        1 => GameResult::Win(Color::White, win_reason(reason).ok_or_else(invalid)?),
+        2 => GameResult::Win(Color::Black, win_reason(reason).ok_or_else(invalid)?),
+        3 => GameResult::Draw(draw_reason(reason).ok_or_else(invalid)?),
+        _ => return Err(invalid()),
+    };
+    Ok(Some(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::*;
+
+    fn moves(uci: &str) -> Vec<Move> {
+        uci.split_whitespace().map(|mv| mv.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        let game = StoredGame::new(&Position::default(), moves("e2e4 e7e5 g1f3 b8c6 f1b5 a7a6"))
+            .with_clocks(vec![Duration::from_millis(179_500), Duration::from_secs(178)])
+            .with_result(GameResult::Win(Color::White, WinReason::Resigned));
+        assert_eq!(game.setup, GameSetup::BackRank(BackRankId::STANDARD));
+        let bytes = game.encode();
+        // header, result, moves and clocks
+        assert_eq!(bytes.len(), 5 + 2 + 13 + 7);
+        assert_eq!(StoredGame::decode(&bytes), Ok(game));
+
+        let fen = "8/P3k3/8/8/8/8/8/4K3 w - - 0 1";
+        let game = StoredGame::new(&Position::from_fen(fen).unwrap(), moves("a7a8n"))
+            .with_result(GameResult::Win(Color::Black, WinReason::Draw(DrawReason::Agreed)));
+        assert_eq!(game.setup, GameSetup::Fen(fen.to_string()));
+        let decoded = StoredGame::decode(&game.encode()).unwrap();
+        assert_eq!(decoded, game);
+        assert_eq!(decoded.start_position().unwrap().to_fen(), fen);
+
+        let id = BackRankId::try_from(0usize).unwrap();
+        let start = Position::new_variant(id.into(), Variant::Antichess);
+        let game = StoredGame::new(&start, moves("b2b3"));
+        assert_eq!(game.setup, GameSetup::BackRank(id));
+        let decoded = StoredGame::decode(&game.encode()).unwrap();
+        assert_eq!(decoded.start_position().unwrap().to_fen(), start.to_fen());
+        assert_eq!(decoded.start_position().unwrap().variant(), Variant::Antichess);
+
+        let variant = Variant::register("Codec test", Variant::Standard.rules()).unwrap();
+        let game = StoredGame::new(&Position::new_variant(id.into(), variant), moves("b2b3"));
+        assert_eq!(StoredGame::decode(&game.encode()).unwrap().variant, variant);
+    }
+
+    #[test]
+    fn test_invalid_records() {
+        let bytes = StoredGame::new(&Position::default(), moves("e2e4")).encode();
+        assert_eq!(StoredGame::decode(&bytes[..bytes.len() - 1]), Err(CodecError::Truncated));
+        assert_eq!(StoredGame::decode(&[]), Err(CodecError::Truncated));
+        let mut newer = bytes.clone();
+        newer[0] = 2;
+        assert_eq!(StoredGame::decode(&newer), Err(CodecError::UnsupportedVersion(2)));
+        let mut setup = bytes.clone();
+        setup[4] = 0xff;
+        assert_eq!(StoredGame::decode(&setup), Err(CodecError::Invalid("back rank out of range")));
+        let mut trailing = bytes;
+        trailing.push(0);
+        assert_eq!(StoredGame::decode(&trailing), Err(CodecError::Invalid("trailing bytes")));
+    }
+
+    #[test]
+    fn test_streaming() {
+        let games = [
+            StoredGame::new(&Position::default(), moves("f2f3 e7e5 g2g4 d8h4"))
+                .with_result(GameResult::Win(Color::Black, WinReason::CheckMate)),
+            StoredGame::new(&Position::default(), Vec::new()),
+            StoredGame::new(&Position::default(), moves("d2d4"))
+                .with_result(GameResult::Draw(DrawReason::TimeoutVsInsufficient)),
+        ];
+        let mut writer = GameWriter::new(Vec::new());
+        for game in &games {
+            writer.write(game).unwrap();
+        }
+        let bytes = writer.into_inner();
+        let read: Vec<StoredGame> = GameReader::new(bytes.as_slice()).collect::<Result<_, _>>().unwrap();
+        assert_eq!(read, games);
+
+        // a stream cut short fails once, after the games before the cut
+        let mut reader = GameReader::new(&bytes[..bytes.len() - 1]);
+        assert_eq!(reader.next(), Some(Ok(games[0].clone())));
+        assert_eq!(reader.next(), Some(Ok(games[1].clone())));
+        assert_eq!(reader.next(), Some(Err(CodecError::Truncated)));
+        assert_eq!(reader.next(), None);
+    }
+}
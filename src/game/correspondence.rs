@@ -0,0 +1,177 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use std::time::{Duration, SystemTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{Color, EngineBoard, Pair};
+use super::GameResult;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A correspondence ("daily") time control: every move must be made
+/// within `per_move` of the opponent's. A player who takes longer draws
+/// the excess from their `vacation` bank, which lasts the whole game.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CorrespondenceControl {
+    pub per_move: Duration,
+    pub vacation: Duration,
+}
+
+impl CorrespondenceControl {
+    pub const fn new(per_move: Duration, vacation: Duration) -> Self {
+        Self { per_move, vacation }
+    }
+
+    /// `days` days per move, without a vacation bank.
+    pub const fn days(days: u64) -> Self {
+        Self::new(Duration::from_secs(days * DAY.as_secs()), Duration::ZERO)
+    }
+
+    pub const fn with_vacation_days(self, days: u64) -> Self {
+        Self::new(self.per_move, Duration::from_secs(days * DAY.as_secs()))
+    }
+}
+
+/// The clock of a correspondence game. Games last days or weeks, so unlike
+/// `Clock` this works in wall-clock time and can be serialized and stored
+/// with the game between moves. Like `Clock`, it never reads the time
+/// itself: callers pass in `now`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct CorrespondenceClock {
+    control: CorrespondenceControl,
+    to_move: Option<Color>,
+    turn_started: SystemTime,
+    vacation: Pair<Duration>,
+}
+
+impl CorrespondenceClock {
+    /// Creates a clock for a game that started at `now`, with White to
+    /// move.
+    pub fn new(control: CorrespondenceControl, now: SystemTime) -> Self {
+        Self {
+            control,
+            to_move: Some(Color::White),
+            turn_started: now,
+            vacation: Pair::new(control.vacation, control.vacation),
+        }
+    }
+
+    pub fn control(&self) -> &CorrespondenceControl {
+        &self.control
+    }
+
+    /// Returns the side whose move it is, or `None` once the clock has
+    /// been stopped.
+    pub fn to_move(&self) -> Option<Color> {
+        self.to_move
+    }
+
+    /// Returns what's left of `color`'s vacation bank, not counting any
+    /// overrun on the current move.
+    pub fn vacation_left(&self, color: Color) -> Duration {
+        self.vacation[color]
+    }
+
+    /// Records the move of the side to move at `now`, charging any time
+    /// beyond `per_move` to its vacation bank, and starts the opponent's
+    /// turn. Returns the mover's remaining vacation, or `None` if the
+    /// clock is stopped.
+    pub fn on_move(&mut self, now: SystemTime) -> Option<Duration> {
+        let color = self.to_move?;
+        let used = now.duration_since(self.turn_started).unwrap_or_default();
+        let vacation = &mut self.vacation[color];
+        *vacation = vacation.saturating_sub(used.saturating_sub(self.control.per_move));
+        self.to_move = Some(!color);
+        self.turn_started = now;
+        Some(*vacation)
+    }
+
+    /// Stops the clock, e.g. when the game ends.
+    pub fn stop(&mut self) {
+        self.to_move = None;
+    }
+
+    /// Returns when the current move is due, before any vacation is used.
+    pub fn move_deadline(&self) -> Option<(Color, SystemTime)> {
+        self.to_move.map(|color| (color, self.turn_started + self.control.per_move))
+    }
+
+    /// Returns when the side to move runs out of time, including its
+    /// vacation bank.
+    pub fn deadline(&self) -> Option<(Color, SystemTime)> {
+        self.move_deadline().map(|(color, due)| (color, due + self.vacation[color]))
+    }
+
+    /// Returns the side that has run out of time at `now`, if any.
+    pub fn expired(&self, now: SystemTime) -> Option<Color> {
+        self.deadline()
+            .filter(|(_, deadline)| *deadline <= now)
+            .map(|(color, _)| color)
+    }
+
+    /// Ends the game on `board` if the side to move has run out of time at
+    /// `now`, stopping the clock and returning the result.
+    pub fn expire(&mut self, board: &EngineBoard, now: SystemTime) -> Option<GameResult> {
+        let loser = self.expired(now)?;
+        self.stop();
+        Some(GameResult::time_expired(board, loser))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WinReason;
+    use Color::*;
+
+    fn hours(value: u64) -> Duration {
+        Duration::from_secs(value * 60 * 60)
+    }
+
+    #[test]
+    fn test_moves_and_vacation() {
+        let start = SystemTime::UNIX_EPOCH + DAY * 1000;
+        let control = CorrespondenceControl::days(1).with_vacation_days(2);
+        assert_eq!(control, CorrespondenceControl::new(hours(24), hours(48)));
+        let mut clock = CorrespondenceClock::new(control, start);
+        assert_eq!(clock.deadline(), Some((White, start + hours(72))));
+
+        // moving within the day costs no vacation
+        assert_eq!(clock.on_move(start + hours(20)), Some(hours(48)));
+        assert_eq!(clock.move_deadline(), Some((Black, start + hours(44))));
+        // overrunning by 10 hours does
+        assert_eq!(clock.on_move(start + hours(54)), Some(hours(38)));
+        assert_eq!(clock.vacation_left(Black), hours(38));
+        assert_eq!(clock.deadline(), Some((White, start + hours(126))));
+
+        let json = serde_json::to_string(&clock).unwrap();
+        assert_eq!(serde_json::from_str::<CorrespondenceClock>(&json).unwrap(), clock);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let start = SystemTime::UNIX_EPOCH + DAY * 1000;
+        let board = EngineBoard::standard();
+        let mut clock = CorrespondenceClock::new(CorrespondenceControl::days(3), start);
+        clock.on_move(start + hours(1));
+        assert_eq!(clock.expired(start + hours(72)), None);
+        assert_eq!(clock.expire(&board, start + hours(72)), None);
+        let result = clock.expire(&board, start + hours(73));
+        assert_eq!(result, Some(GameResult::Win(White, WinReason::TimeExpired)));
+        assert_eq!(clock.to_move(), None);
+        assert_eq!(clock.deadline(), None);
+        assert_eq!(clock.on_move(start + hours(80)), None);
+    }
+}
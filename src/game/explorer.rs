@@ -0,0 +1,304 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! An opening explorer: aggregates many games into the moves played from
+//! each position and how those games ended. Positions are keyed like the
+//! `Book`, so transpositions share their statistics.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::board::replay_moves;
+use crate::{Book, Color, LegalMoves, Move, MoveState, PgnError, PgnGame, Position, PositionKey, ReplayError};
+use super::GameResult;
+
+/// Only the opening is explored: moves after this many plies are left
+/// out unless `Explorer::with_max_plies` says otherwise.
+const DEFAULT_MAX_PLIES: usize = 30;
+
+/// How the games through a position or move ended.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ExplorerStats {
+    pub games: u32,
+    pub white_wins: u32,
+    pub draws: u32,
+    pub black_wins: u32,
+}
+
+impl ExplorerStats {
+    /// Returns the games with a known result.
+    pub fn decided(&self) -> u32 {
+        self.white_wins + self.draws + self.black_wins
+    }
+
+    /// Returns White's score over the games with a known result, draws
+    /// counting half, from 0 to 1.
+    pub fn white_score(&self) -> Option<f64> {
+        let decided = self.decided();
+        (decided > 0).then(|| (self.white_wins as f64 + self.draws as f64 / 2.0) / decided as f64)
+    }
+
+    /// Returns `color`'s score, as `white_score`.
+    pub fn score(&self, color: Color) -> Option<f64> {
+        self.white_score().map(|white| match color {
+            Color::White => white,
+            Color::Black => 1.0 - white,
+        })
+    }
+
+    fn record(&mut self, result: Option<GameResult>) {
+        self.games += 1;
+        match result {
+            Some(GameResult::Win(Color::White, _)) => self.white_wins += 1,
+            Some(GameResult::Win(Color::Black, _)) => self.black_wins += 1,
+            Some(GameResult::Draw(_)) => self.draws += 1,
+            None => (),
+        }
+    }
+}
+
+/// A move played from a position, with the games that continued with it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ExplorerMove {
+    pub mv: Move,
+    pub san: String,
+    pub stats: ExplorerStats,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ExplorerEntry {
+    stats: ExplorerStats,
+    /// In the order first played.
+    moves: Vec<(Move, ExplorerStats)>,
+}
+
+/// The statistics of every position reached in the opening of the games
+/// added.
+#[derive(Debug, Clone)]
+pub struct Explorer {
+    max_plies: usize,
+    /// Where each position is in `entries`.
+    index: HashMap<PositionKey, usize>,
+    /// In the order the positions were first reached, so the explorer is
+    /// walked the same way every time.
+    entries: Vec<(PositionKey, ExplorerEntry)>,
+    games: u32,
+}
+
+impl Default for Explorer {
+    fn default() -> Self {
+        Self::with_max_plies(DEFAULT_MAX_PLIES)
+    }
+}
+
+impl Explorer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an explorer that records the first `max_plies` moves of
+    /// each game.
+    pub fn with_max_plies(max_plies: usize) -> Self {
+        Self { max_plies, index: HashMap::new(), entries: Vec::new(), games: 0 }
+    }
+
+    /// Returns the number of games added.
+    pub fn games(&self) -> u32 {
+        self.games
+    }
+
+    /// Returns the number of distinct positions recorded.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds a game of `moves` played from `start`, which ended with
+    /// `result` (`None` if unfinished or unknown: it still counts towards
+    /// how often moves were played). Nothing is recorded if a move is
+    /// illegal. A position repeated within a game counts once.
+    pub fn add_game(&mut self, start: &Position, moves: &[Move], result: Option<GameResult>) -> Result<(), ReplayError> {
+        let played = replay_moves(&mut MoveState::new(start.clone()), &moves[..moves.len().min(self.max_plies)])?;
+        let mut pos = start.clone();
+        let mut path = Vec::with_capacity(played.len());
+        for legal in played {
+            // castling may be entered in more than one way
+            path.push((pos.key(), legal.to_move(&pos)));
+            // replay_moves played every move
+            let _ = pos.apply_move(legal);
+        }
+        let end = pos.key();
+
+        self.games += 1;
+        let mut seen = Vec::with_capacity(path.len() + 1);
+        for (key, mv) in path {
+            if seen.contains(&key) {
+                continue;
+            }
+            seen.push(key);
+            let entry = self.entry_mut(key);
+            entry.stats.record(result);
+            match entry.moves.iter_mut().find(|(played, _)| *played == mv) {
+                Some((_, stats)) => stats.record(result),
+                None => {
+                    let mut stats = ExplorerStats::default();
+                    stats.record(result);
+                    entry.moves.push((mv, stats));
+                },
+            }
+        }
+        if !seen.contains(&end) {
+            self.entry_mut(end).stats.record(result);
+        }
+        Ok(())
+    }
+
+    /// Adds a game read from PGN, taking its result from the `Result`
+    /// tag.
+    pub fn add_pgn(&mut self, game: &PgnGame) -> Result<(), PgnError> {
+        let start = game.start_position()?;
+        let result = game.tag("Result").and_then(GameResult::from_pgn);
+        self.add_game(&start, &game.moves, result)?;
+        Ok(())
+    }
+
+    /// Returns how the games reaching `pos` ended, if any did.
+    pub fn position(&self, pos: &Position) -> Option<ExplorerStats> {
+        self.entry(&pos.key()).map(|entry| entry.stats)
+    }
+
+    /// Returns the moves played from `pos`, most played first (ties in
+    /// the order they were first seen).
+    pub fn moves(&self, pos: &Position) -> Vec<ExplorerMove> {
+        let Some(entry) = self.entry(&pos.key()) else { return Vec::new() };
+        let state = MoveState::new(pos.clone());
+        let mut moves: Vec<ExplorerMove> = entry.moves.iter()
+            .filter_map(|&(mv, stats)| {
+                let legal = state.validate_move(mv).ok()?;
+                Some(ExplorerMove { mv, san: state.to_san(legal), stats })
+            })
+            .collect();
+        moves.sort_by_key(|mv| std::cmp::Reverse(mv.stats.games));
+        moves
+    }
+
+    /// Returns a `Book` of the moves played in at least `min_games` games,
+    /// e.g. for an engine to follow popular openings.
+    pub fn to_book(&self, min_games: u32) -> Book {
+        let mut book = Book::new();
+        for (key, entry) in &self.entries {
+            for &(mv, stats) in &entry.moves {
+                if stats.games >= min_games {
+                    book.insert_key(*key, mv);
+                }
+            }
+        }
+        book
+    }
+
+    fn entry(&self, key: &PositionKey) -> Option<&ExplorerEntry> {
+        self.index.get(key).map(|&index| &self.entries[index].1)
+    }
+
+    fn entry_mut(&mut self, key: PositionKey) -> &mut ExplorerEntry {
+        let index = *self.index.entry(key).or_insert_with(|| {
+            self.entries.push((key, ExplorerEntry::default()));
+            self.entries.len() - 1
+        });
+        &mut self.entries[index].1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn moves(uci: &str) -> Vec<Move> {
+        uci.split_whitespace().map(|mv| mv.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_explorer() {
+        let start = Position::default();
+        let white = Some(GameResult::Win(Color::White, WinReason::Resigned));
+        let black = Some(GameResult::Win(Color::Black, WinReason::CheckMate));
+        let mut explorer = Explorer::new();
+        explorer.add_game(&start, &moves("e2e4 e7e5 g1f3"), white).unwrap();
+        explorer.add_game(&start, &moves("e2e4 c7c5"), black).unwrap();
+        explorer.add_game(&start, &moves("d2d4 d7d5"), None).unwrap();
+        explorer.add_game(&start, &moves("g1f3 g8f6 e2e4"), white).unwrap();
+        assert!(explorer.add_game(&start, &moves("e2e4 e2e4"), white).is_err());
+        assert_eq!(explorer.games(), 4);
+
+        let stats = explorer.position(&start).unwrap();
+        assert_eq!(stats, ExplorerStats { games: 4, white_wins: 2, draws: 0, black_wins: 1 });
+        assert_eq!(stats.white_score(), Some(2.0 / 3.0));
+        let played = explorer.moves(&start);
+        let sans: Vec<&str> = played.iter().map(|mv| mv.san.as_str()).collect();
+        assert_eq!(sans, ["e4", "d4", "Nf3"]);
+        assert_eq!(played[0].stats.games, 2);
+        assert_eq!(played[0].stats.score(Color::Black), Some(0.5));
+        assert_eq!(played[1].stats.white_score(), None);
+
+        let mut board = EngineBoard::standard();
+        board.submit_move("e2e4".parse().unwrap()).unwrap();
+        let pos: &Position = board.as_ref();
+        let replies = explorer.moves(pos);
+        assert_eq!(replies.iter().map(|mv| mv.san.as_str()).collect::<Vec<_>>(), ["e5", "c5"]);
+        assert!(explorer.moves(&Position::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap()).is_empty());
+
+        let book = explorer.to_book(2);
+        assert_eq!(book.get(&start), &moves("e2e4")[..]);
+    }
+
+    #[test]
+    fn test_transpositions_and_limits() {
+        let start = Position::default();
+        let mut explorer = Explorer::with_max_plies(4);
+        explorer.add_game(&start, &moves("g1f3 d7d5 d2d4 g8f6"), None).unwrap();
+        explorer.add_game(&start, &moves("d2d4 d7d5 g1f3 g8f6 c2c4"), None).unwrap();
+        let mut state = MoveState::new(start.clone());
+        for mv in moves("d2d4 d7d5 g1f3 g8f6") {
+            state.apply_move(state.validate_move(mv).unwrap()).unwrap();
+        }
+        let pos: &Position = state.as_ref();
+        // both games reach the position, but c4 was past the limit
+        assert_eq!(explorer.position(pos).unwrap().games, 2);
+        assert!(explorer.moves(pos).is_empty());
+
+        // castling is recorded once however it was entered
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        let pos = Position::from_fen(fen).unwrap();
+        let mut explorer = Explorer::new();
+        explorer.add_game(&pos, &moves("e1g1"), None).unwrap();
+        explorer.add_game(&pos, &moves("e1h1"), None).unwrap();
+        let played = explorer.moves(&pos);
+        assert_eq!(played.len(), 1);
+        assert_eq!(played[0].san, "O-O");
+        assert_eq!(played[0].stats.games, 2);
+    }
+
+    #[test]
+    fn test_add_pgn() {
+        let mut explorer = Explorer::new();
+        for game in PgnGame::parse_all("[Result \"0-1\"]\n\n1. f3 e5 2. g4 Qh4# 0-1\n\n[Result \"1/2-1/2\"]\n\n1. f3 e5 1/2-1/2").unwrap() {
+            explorer.add_pgn(&game).unwrap();
+        }
+        let stats = explorer.position(&Position::default()).unwrap();
+        assert_eq!(stats, ExplorerStats { games: 2, white_wins: 0, draws: 1, black_wins: 1 });
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{Color, Pair};
+use super::{GameOutcome, GameResult, WinReason};
+
+/// How long players may stay idle before a game is aborted or forfeited.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InactivityRules {
+    /// Time each side has to make its first move: White from the start of
+    /// the game, Black from White's first move.
+    pub first_move: Duration,
+    /// Time a disconnected player has to reconnect once both sides have
+    /// moved.
+    pub disconnect_grace: Duration,
+    /// Time after which the opponent of a disconnected player may claim
+    /// victory (see `InactivityTimer::claim_victory`) rather than wait
+    /// out the grace period.
+    pub claim_after: Duration,
+}
+
+impl InactivityRules {
+    /// Creates rules under which victory can be claimed once the grace
+    /// period is over.
+    pub const fn new(first_move: Duration, disconnect_grace: Duration) -> Self {
+        Self { first_move, disconnect_grace, claim_after: disconnect_grace }
+    }
+
+    pub const fn with_claim_after(mut self, claim_after: Duration) -> Self {
+        self.claim_after = claim_after;
+        self
+    }
+}
+
+impl Default for InactivityRules {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(30), Duration::from_secs(60))
+            .with_claim_after(Duration::from_secs(30))
+    }
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbandonmentError {
+    #[error("Until both sides have moved, the game can only be aborted")]
+    NotUnderway,
+    #[error("The opponent is still connected")]
+    OpponentConnected,
+    #[error("The opponent still has {0:?} to reconnect")]
+    TooEarly(Duration),
+}
+
+/// What an `InactivityTimer` decided about an idle player.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Inactivity {
+    /// `color` didn't make its first move in time. The game should be
+    /// aborted without a result.
+    Aborted(Color),
+    /// `color` stayed disconnected past the grace period and loses.
+    Abandoned(Color),
+}
+
+impl Inactivity {
+    /// Returns the result of the game, or `None` if it was aborted.
+    pub fn result(&self) -> Option<GameResult> {
+        self.outcome().result()
+    }
+
+    pub fn outcome(&self) -> GameOutcome {
+        match self {
+            Inactivity::Aborted(_) => GameOutcome::Aborted,
+            Inactivity::Abandoned(color) => GameResult::Win(!*color, WinReason::Abandoned).into(),
+        }
+    }
+}
+
+/// Watches for idle and disconnected players. Like `Clock`, the timer
+/// never reads the system time itself: callers report moves and
+/// connection changes as they happen and call `tick` periodically (or at
+/// `deadline`), so it works with any runtime or event loop.
+#[derive(Debug, Clone)]
+pub struct InactivityTimer {
+    rules: InactivityRules,
+    started: Instant,
+    first_moves: Pair<Option<Instant>>,
+    disconnected: Pair<Option<Instant>>,
+}
+
+impl InactivityTimer {
+    /// Creates a timer for a game that started at `now`.
+    pub fn new(rules: InactivityRules, now: Instant) -> Self {
+        Self {
+            rules,
+            started: now,
+            first_moves: Pair::new(None, None),
+            disconnected: Pair::new(None, None),
+        }
+    }
+
+    pub fn rules(&self) -> &InactivityRules {
+        &self.rules
+    }
+
+    /// Records a move by `color` at `now`.
+    pub fn on_move(&mut self, color: Color, now: Instant) {
+        self.first_moves[color].get_or_insert(now);
+    }
+
+    /// Records that `color` lost its connection at `now`. Repeated
+    /// reports don't restart the grace period.
+    pub fn on_disconnect(&mut self, color: Color, now: Instant) {
+        self.disconnected[color].get_or_insert(now);
+    }
+
+    pub fn on_reconnect(&mut self, color: Color) {
+        self.disconnected[color] = None;
+    }
+
+    /// Returns true once both sides have made their first move.
+    pub fn is_underway(&self) -> bool {
+        self.first_moves[Color::White].is_some() && self.first_moves[Color::Black].is_some()
+    }
+
+    /// Returns the next instant at which `tick` could report inactivity
+    /// (assuming nothing else is reported before then), if any.
+    pub fn deadline(&self) -> Option<(Color, Instant)> {
+        if !self.is_underway() {
+            return self.first_move_deadline();
+        }
+        [Color::White, Color::Black].into_iter()
+            .filter_map(|color| {
+                self.disconnected[color].map(|since| (color, since + self.rules.disconnect_grace))
+            })
+            .min_by_key(|(_, deadline)| *deadline)
+    }
+
+    /// Returns when `claimant` may claim victory because their opponent
+    /// disconnected, if they have.
+    pub fn claim_time(&self, claimant: Color) -> Option<Instant> {
+        self.disconnected[!claimant].map(|since| since + self.rules.claim_after)
+    }
+
+    /// Ends the game in `claimant`'s favour because their opponent has
+    /// been disconnected for at least `claim_after`. Fails before both
+    /// sides have moved, since the game would be aborted instead.
+    pub fn claim_victory(&self, claimant: Color, now: Instant) -> Result<GameResult, AbandonmentError> {
+        if !self.is_underway() {
+            return Err(AbandonmentError::NotUnderway);
+        }
+        let claim_time = self.claim_time(claimant)
+            .ok_or(AbandonmentError::OpponentConnected)?;
+        if claim_time > now {
+            return Err(AbandonmentError::TooEarly(claim_time - now));
+        }
+        Ok(GameResult::Win(claimant, WinReason::Abandoned))
+    }
+
+    /// Checks the timers at `now`.
+    pub fn tick(&self, now: Instant) -> Option<Inactivity> {
+        let (color, deadline) = self.deadline()?;
+        if deadline > now {
+            return None;
+        }
+        if self.is_underway() {
+            Some(Inactivity::Abandoned(color))
+        } else {
+            Some(Inactivity::Aborted(color))
+        }
+    }
+
+    fn first_move_deadline(&self) -> Option<(Color, Instant)> {
+        match (self.first_moves[Color::White], self.first_moves[Color::Black]) {
+            (None, _) => Some((Color::White, self.started + self.rules.first_move)),
+            (Some(white), None) => Some((Color::Black, white + self.rules.first_move)),
+            (Some(_), Some(_)) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Color::*;
+
+    fn secs(value: u64) -> Duration {
+        Duration::from_secs(value)
+    }
+
+    #[test]
+    fn test_abort_without_first_move() {
+        let start = Instant::now();
+        let mut timer = InactivityTimer::new(InactivityRules::new(secs(20), secs(60)), start);
+        assert_eq!(timer.tick(start + secs(19)), None);
+        assert_eq!(timer.tick(start + secs(20)), Some(Inactivity::Aborted(White)));
+
+        timer.on_move(White, start + secs(10));
+        assert_eq!(timer.deadline(), Some((Black, start + secs(30))));
+        // disconnects don't matter until the game is underway
+        timer.on_disconnect(Black, start + secs(11));
+        assert_eq!(timer.tick(start + secs(29)), None);
+        let aborted = timer.tick(start + secs(30)).unwrap();
+        assert_eq!(aborted, Inactivity::Aborted(Black));
+        assert_eq!(aborted.result(), None);
+    }
+
+    #[test]
+    fn test_claim_victory() {
+        let start = Instant::now();
+        let rules = InactivityRules::new(secs(20), secs(120)).with_claim_after(secs(30));
+        let mut timer = InactivityTimer::new(rules, start);
+        timer.on_move(White, start + secs(1));
+        timer.on_disconnect(Black, start + secs(2));
+        assert_eq!(timer.claim_victory(White, start + secs(60)), Err(AbandonmentError::NotUnderway));
+
+        timer.on_move(Black, start + secs(3));
+        timer.on_reconnect(Black);
+        assert_eq!(timer.claim_victory(White, start + secs(60)), Err(AbandonmentError::OpponentConnected));
+        timer.on_disconnect(Black, start + secs(100));
+        assert_eq!(timer.claim_time(White), Some(start + secs(130)));
+        assert_eq!(timer.claim_time(Black), None);
+        assert_eq!(timer.claim_victory(White, start + secs(110)), Err(AbandonmentError::TooEarly(secs(20))));
+        assert_eq!(timer.claim_victory(Black, start + secs(130)), Err(AbandonmentError::OpponentConnected));
+        assert_eq!(timer.claim_victory(White, start + secs(130)), Ok(GameResult::Win(White, WinReason::Abandoned)));
+        // the automatic forfeit is still further off
+        assert_eq!(timer.tick(start + secs(130)), None);
+    }
+
+    #[test]
+    fn test_abandoned_after_grace_period() {
+        let start = Instant::now();
+        let mut timer = InactivityTimer::new(InactivityRules::new(secs(20), secs(60)), start);
+        timer.on_move(White, start + secs(1));
+        timer.on_move(Black, start + secs(2));
+        assert!(timer.is_underway());
+        assert_eq!(timer.deadline(), None);
+
+        timer.on_disconnect(White, start + secs(100));
+        timer.on_disconnect(White, start + secs(130));
+        assert_eq!(timer.tick(start + secs(159)), None);
+        timer.on_reconnect(White);
+        assert_eq!(timer.tick(start + secs(180)), None);
+
+        timer.on_disconnect(Black, start + secs(200));
+        timer.on_disconnect(White, start + secs(210));
+        let abandoned = timer.tick(start + secs(260)).unwrap();
+        assert_eq!(abandoned, Inactivity::Abandoned(Black));
+        assert_eq!(abandoned.result(), Some(GameResult::Win(White, WinReason::Abandoned)));
+    }
+}
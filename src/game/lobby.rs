@@ -0,0 +1,259 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Matchmaking: open seeks that any suitable player may accept, and
+//! challenges sent to one player. Both turn into a `Game` once accepted.
+
+use std::ops::RangeInclusive;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{BackRankId, Color, Variant};
+use super::{BackRankPolicy, Game, Player, PlayerId, Players, TimeControl};
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LobbyError {
+    #[error("Players can't accept their own seek or challenge")]
+    SamePlayer,
+    #[error("The player's rating is outside the range sought")]
+    OutOfRange,
+    #[error("The challenge is for another player")]
+    NotChallenged,
+    #[error("The seeks don't agree on the game to play")]
+    Incompatible,
+}
+
+/// The color a player asks to play.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ColorPreference {
+    White,
+    Black,
+    #[default]
+    Random,
+}
+
+impl ColorPreference {
+    /// Returns the color the first player gets when their preference meets
+    /// `other`'s, or `None` if both want the same color.
+    fn resolve<R: Rng + ?Sized>(self, other: Self, rng: &mut R) -> Option<Color> {
+        match (self, other) {
+            (Self::White, Self::White) | (Self::Black, Self::Black) => None,
+            (Self::White, _) | (_, Self::Black) => Some(Color::White),
+            (Self::Black, _) | (_, Self::White) => Some(Color::Black),
+            (Self::Random, Self::Random) => Some(if rng.gen() { Color::White } else { Color::Black }),
+        }
+    }
+}
+
+/// A player's open offer of a game.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Seek {
+    pub player: Player,
+    pub control: TimeControl,
+    pub variant: Variant,
+    pub policy: BackRankPolicy,
+    pub rated: bool,
+    pub color: ColorPreference,
+    /// The ratings of opponents wanted. Unrated players are only matched
+    /// if there's no range.
+    pub rating_range: Option<RangeInclusive<u32>>,
+}
+
+impl Seek {
+    /// Seeks a casual game of standard chess with either color against
+    /// anyone.
+    pub fn new(player: Player, control: TimeControl) -> Self {
+        Self {
+            player,
+            control,
+            variant: Variant::Standard,
+            policy: BackRankPolicy::Fixed(BackRankId::STANDARD),
+            rated: false,
+            color: ColorPreference::Random,
+            rating_range: None,
+        }
+    }
+
+    /// Returns true if `player` may accept the seek.
+    pub fn admits(&self, player: &Player) -> bool {
+        player.id != self.player.id && self.rating_range.as_ref().is_none_or(|range| {
+            player.rating.is_some_and(|rating| range.contains(&rating))
+        })
+    }
+
+    /// Returns true if the seeks ask for the same game and each player
+    /// suits the other, which also rules out pairing a player with
+    /// themselves.
+    pub fn is_compatible(&self, other: &Seek) -> bool {
+        self.control == other.control
+            && self.variant == other.variant
+            && self.policy == other.policy
+            && self.rated == other.rated
+            && (self.color != other.color || self.color == ColorPreference::Random)
+            && self.admits(&other.player)
+            && other.admits(&self.player)
+    }
+
+    pub fn accept(&self, player: Player) -> Result<Game, LobbyError> {
+        self.accept_with(player, &mut thread_rng())
+    }
+
+    /// Starts the game sought against `player`, who gets whichever color
+    /// the seeker didn't ask for.
+    pub fn accept_with<R: Rng + ?Sized>(&self, player: Player, rng: &mut R) -> Result<Game, LobbyError> {
+        if player.id == self.player.id {
+            return Err(LobbyError::SamePlayer);
+        }
+        if !self.admits(&player) {
+            return Err(LobbyError::OutOfRange);
+        }
+        Ok(self.start(player, ColorPreference::Random, rng))
+    }
+
+    pub fn pair(&self, other: &Seek) -> Result<Game, LobbyError> {
+        self.pair_with(other, &mut thread_rng())
+    }
+
+    /// Starts the game both seeks ask for.
+    pub fn pair_with<R: Rng + ?Sized>(&self, other: &Seek, rng: &mut R) -> Result<Game, LobbyError> {
+        if other.player.id == self.player.id {
+            return Err(LobbyError::SamePlayer);
+        }
+        if !self.is_compatible(other) {
+            return Err(LobbyError::Incompatible);
+        }
+        Ok(self.start(other.player.clone(), other.color, rng))
+    }
+
+    fn start<R: Rng + ?Sized>(&self, opponent: Player, wanted: ColorPreference, rng: &mut R) -> Game {
+        // compatible preferences always resolve
+        let players = match self.color.resolve(wanted, rng) {
+            Some(Color::Black) => Players::new(opponent, self.player.clone()),
+            _ => Players::new(self.player.clone(), opponent),
+        };
+        let mut game = Game::new_with(players, self.control, self.policy, rng);
+        game.variant = self.variant;
+        game.rated = self.rated;
+        game
+    }
+}
+
+/// An offer of a game to one player.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Challenge {
+    /// The game offered, with the challenger as the seeker. Its rating
+    /// range is ignored.
+    pub seek: Seek,
+    pub opponent: PlayerId,
+}
+
+impl Challenge {
+    pub fn new(seek: Seek, opponent: PlayerId) -> Self {
+        Self { seek, opponent }
+    }
+
+    pub fn accept(&self, player: Player) -> Result<Game, LobbyError> {
+        self.accept_with(player, &mut thread_rng())
+    }
+
+    pub fn accept_with<R: Rng + ?Sized>(&self, player: Player, rng: &mut R) -> Result<Game, LobbyError> {
+        if player.id == self.seek.player.id {
+            return Err(LobbyError::SamePlayer);
+        }
+        if player.id != self.opponent {
+            return Err(LobbyError::NotChallenged);
+        }
+        Ok(self.seek.start(player, ColorPreference::Random, rng))
+    }
+}
+
+/// Pairs up compatible seeks, oldest first: each seek in turn is matched
+/// with the earliest later one it's compatible with. Returns the indices
+/// of the paired seeks; unpaired seeks stay open.
+pub fn match_seeks(seeks: &[Seek]) -> Vec<(usize, usize)> {
+    let mut paired = vec![false; seeks.len()];
+    let mut pairs = Vec::new();
+    for i in 0..seeks.len() {
+        if paired[i] {
+            continue;
+        }
+        let found = (i + 1..seeks.len())
+            .find(|&j| !paired[j] && seeks[i].is_compatible(&seeks[j]));
+        if let Some(j) = found {
+            paired[i] = true;
+            paired[j] = true;
+            pairs.push((i, j));
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use rand::{rngs::StdRng, SeedableRng};
+    use crate::*;
+
+    fn player(id: u64, rating: Option<u32>) -> Player {
+        Player { id: PlayerId::new(id), name: format!("player{id}"), rating }
+    }
+
+    fn blitz(player: Player) -> Seek {
+        Seek::new(player, TimeControl::new(Duration::from_secs(300), Duration::from_secs(3)))
+    }
+
+    #[test]
+    fn test_seeks() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let seek = Seek { color: ColorPreference::Black, rated: true, ..blitz(player(1, Some(1500))) };
+        let game = seek.accept_with(player(2, None), &mut rng).unwrap();
+        assert_eq!(game.players.white().id, PlayerId::new(2));
+        assert_eq!(game.players.black().id, PlayerId::new(1));
+        assert!(game.rated);
+        assert_eq!(seek.accept_with(player(1, None), &mut rng).err(), Some(LobbyError::SamePlayer));
+
+        let picky = Seek { rating_range: Some(1400..=1600), ..blitz(player(3, Some(1500))) };
+        assert_eq!(picky.accept_with(player(4, None), &mut rng).err(), Some(LobbyError::OutOfRange));
+        assert_eq!(picky.accept_with(player(4, Some(1700)), &mut rng).err(), Some(LobbyError::OutOfRange));
+        assert!(picky.accept_with(player(4, Some(1600)), &mut rng).is_ok());
+
+        let white = Seek { color: ColorPreference::White, ..blitz(player(5, Some(1500))) };
+        let also_white = Seek { color: ColorPreference::White, ..blitz(player(6, Some(1500))) };
+        assert!(!white.is_compatible(&also_white));
+        assert_eq!(white.pair_with(&also_white, &mut rng).err(), Some(LobbyError::Incompatible));
+        let game = blitz(player(7, None)).pair_with(&white, &mut rng).unwrap();
+        assert_eq!(game.players.white().id, PlayerId::new(5));
+
+        let challenge = Challenge::new(blitz(player(1, None)), PlayerId::new(2));
+        assert_eq!(challenge.accept_with(player(3, None), &mut rng).err(), Some(LobbyError::NotChallenged));
+        let game = challenge.accept_with(player(2, None), &mut rng).unwrap();
+        assert!(game.players.color_of(PlayerId::new(2)).is_some());
+    }
+
+    #[test]
+    fn test_match_seeks() {
+        let seeks = vec![
+            Seek { rated: true, ..blitz(player(1, Some(1500))) },
+            blitz(player(2, Some(1500))),
+            Seek { rating_range: Some(1000..=1200), ..blitz(player(3, Some(1500))) },
+            Seek { rated: true, ..blitz(player(1, Some(1500))) },
+            Seek { rated: true, ..blitz(player(4, Some(1100))) },
+            blitz(player(5, Some(1100))),
+        ];
+        // the rated seeks pair up, skipping player 1's second seek; player
+        // 3 only wants weaker opponents, and the only one is taken first
+        assert_eq!(match_seeks(&seeks), vec![(0, 4), (1, 5)]);
+    }
+}
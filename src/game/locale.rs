@@ -0,0 +1,277 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use std::collections::HashMap;
+use std::fmt;
+use strum_macros::EnumIter;
+
+use crate::{BoardResult, Color};
+use super::{DrawReason, GameResult, WinReason};
+
+/// Identifies a translatable fragment of a result message. `Win`, `Draw`
+/// and `ArmageddonWin` are templates in which `{winner}` and `{reason}`
+/// are substituted; the remaining keys are plain fragments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter)]
+pub enum MessageKey {
+    White,
+    Black,
+    Win,
+    Draw,
+    ArmageddonWin,
+    CheckMate,
+    TimeExpired,
+    Resigned,
+    Abandoned,
+    Agreed,
+    StaleMate,
+    Repetition,
+    FiftyMoves,
+    Insufficient,
+    TimeoutVsInsufficient,
+    Adjudicated,
+    VariantWin,
+}
+
+pub trait Translations {
+    fn message(&self, key: MessageKey) -> &str;
+}
+
+/// Built-in English messages, used by the `Display` impls.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct English;
+
+impl Translations for English {
+    fn message(&self, key: MessageKey) -> &str {
+        use MessageKey::*;
+        match key {
+            White => "White",
+            Black => "Black",
+            Win => "{winner} wins {reason}",
+            Draw => "Draw {reason}",
+            ArmageddonWin => "{winner} wins (draw {reason})",
+            CheckMate => "by checkmate",
+            TimeExpired => "on time",
+            Resigned => "by resignation",
+            Abandoned => "by abandonment",
+            Agreed => "by agreement",
+            StaleMate => "by stalemate",
+            Repetition => "by repetition",
+            FiftyMoves => "by the fifty-move rule",
+            Insufficient => "by insufficient material",
+            TimeoutVsInsufficient => "by timeout vs insufficient material",
+            Adjudicated => "by adjudication",
+            VariantWin => "by the rules of the variant",
+        }
+    }
+}
+
+/// Built-in German messages.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct German;
+
+impl Translations for German {
+    fn message(&self, key: MessageKey) -> &str {
+        use MessageKey::*;
+        match key {
+            White => "Weiß",
+            Black => "Schwarz",
+            Win => "{winner} gewinnt {reason}",
+            Draw => "Remis {reason}",
+            ArmageddonWin => "{winner} gewinnt (Remis {reason})",
+            CheckMate => "durch Schachmatt",
+            TimeExpired => "durch Zeitüberschreitung",
+            Resigned => "durch Aufgabe",
+            Abandoned => "durch Verlassen der Partie",
+            Agreed => "durch Einigung",
+            StaleMate => "durch Patt",
+            Repetition => "durch Stellungswiederholung",
+            FiftyMoves => "durch die 50-Züge-Regel",
+            Insufficient => "durch ungenügendes Material",
+            TimeoutVsInsufficient => "durch Zeitüberschreitung bei ungenügendem Material",
+            Adjudicated => "durch Schiedsrichterentscheid",
+            VariantWin => "nach den Regeln der Variante",
+        }
+    }
+}
+
+/// A user supplied translation table. Keys that haven't been set fall
+/// back to the English messages.
+#[derive(Debug, Clone, Default)]
+pub struct TranslationTable {
+    messages: HashMap<MessageKey, String>,
+}
+
+impl TranslationTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn set(&mut self, key: MessageKey, message: impl Into<String>) {
+        self.messages.insert(key, message.into());
+    }
+    pub fn with(mut self, key: MessageKey, message: impl Into<String>) -> Self {
+        self.set(key, message);
+        self
+    }
+}
+
+impl Translations for TranslationTable {
+    fn message(&self, key: MessageKey) -> &str {
+        match self.messages.get(&key) {
+            Some(message) => message,
+            None => English.message(key),
+        }
+    }
+}
+
+/// Renders a value as a human readable message in a given language.
+pub trait Localize {
+    fn localize(&self, translations: &dyn Translations) -> String;
+}
+
+fn fill(template: &str, winner: &str, reason: &str) -> String {
+    template
+        .replace("{winner}", winner)
+        .replace("{reason}", reason)
+}
+
+impl Localize for Color {
+    fn localize(&self, translations: &dyn Translations) -> String {
+        let key = match self {
+            Color::White => MessageKey::White,
+            Color::Black => MessageKey::Black,
+        };
+        translations.message(key).to_string()
+    }
+}
+
+impl Localize for DrawReason {
+    fn localize(&self, translations: &dyn Translations) -> String {
+        let key = match self {
+            DrawReason::Agreed => MessageKey::Agreed,
+            DrawReason::StaleMate => MessageKey::StaleMate,
+            DrawReason::Repetition => MessageKey::Repetition,
+            DrawReason::FiftyMoves => MessageKey::FiftyMoves,
+            DrawReason::Insufficient => MessageKey::Insufficient,
+            DrawReason::TimeoutVsInsufficient => MessageKey::TimeoutVsInsufficient,
+            DrawReason::Adjudicated => MessageKey::Adjudicated,
+        };
+        translations.message(key).to_string()
+    }
+}
+
+impl Localize for WinReason {
+    fn localize(&self, translations: &dyn Translations) -> String {
+        let key = match self {
+            WinReason::CheckMate => MessageKey::CheckMate,
+            WinReason::TimeExpired => MessageKey::TimeExpired,
+            WinReason::Resigned => MessageKey::Resigned,
+            WinReason::Abandoned => MessageKey::Abandoned,
+            WinReason::Adjudicated => MessageKey::Adjudicated,
+            WinReason::VariantWin => MessageKey::VariantWin,
+            WinReason::Draw(reason) => return reason.localize(translations),
+        };
+        translations.message(key).to_string()
+    }
+}
+
+impl Localize for GameResult {
+    fn localize(&self, translations: &dyn Translations) -> String {
+        match self {
+            GameResult::Win(color, reason) => {
+                let template = match reason {
+                    WinReason::Draw(_) => MessageKey::ArmageddonWin,
+                    _ => MessageKey::Win,
+                };
+                fill(
+                    translations.message(template),
+                    &color.localize(translations),
+                    &reason.localize(translations),
+                )
+            },
+            GameResult::Draw(reason) => fill(
+                translations.message(MessageKey::Draw),
+                "",
+                &reason.localize(translations),
+            ),
+        }
+    }
+}
+
+impl Localize for BoardResult {
+    fn localize(&self, translations: &dyn Translations) -> String {
+        GameResult::from(*self).localize(translations)
+    }
+}
+
+impl fmt::Display for DrawReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.localize(&English))
+    }
+}
+
+impl fmt::Display for WinReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.localize(&English))
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.localize(&English))
+    }
+}
+
+impl fmt::Display for BoardResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.localize(&English))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn test_english_display() {
+        let result = GameResult::Win(Color::White, WinReason::TimeExpired);
+        assert_eq!(result.to_string(), "White wins on time");
+        let result = GameResult::Draw(DrawReason::FiftyMoves);
+        assert_eq!(result.to_string(), "Draw by the fifty-move rule");
+        let result = GameResult::Draw(DrawReason::TimeoutVsInsufficient);
+        assert_eq!(result.to_string(), "Draw by timeout vs insufficient material");
+        let result = GameResult::Win(Color::Black, WinReason::Draw(DrawReason::Repetition));
+        assert_eq!(result.to_string(), "Black wins (draw by repetition)");
+        assert_eq!(BoardResult::CheckMate(Color::Black).to_string(), "Black wins by checkmate");
+        assert_eq!(BoardResult::StaleMate.to_string(), "Draw by stalemate");
+        assert_eq!(GameResult::from(BoardResult::VariantWin(Color::White)).to_string(), "White wins by the rules of the variant");
+    }
+    #[test]
+    fn test_german() {
+        let result = GameResult::Win(Color::White, WinReason::TimeExpired);
+        assert_eq!(result.localize(&German), "Weiß gewinnt durch Zeitüberschreitung");
+        assert_eq!(BoardResult::Insufficient.localize(&German), "Remis durch ungenügendes Material");
+    }
+    #[test]
+    fn test_translation_table_falls_back_to_english() {
+        let table = TranslationTable::new()
+            .with(MessageKey::White, "Blanc")
+            .with(MessageKey::Win, "{winner} gagne {reason}");
+        let result = GameResult::Win(Color::White, WinReason::Resigned);
+        assert_eq!(result.localize(&table), "Blanc gagne by resignation");
+        for key in MessageKey::iter() {
+            assert!(!table.message(key).is_empty());
+        }
+    }
+}
@@ -12,12 +12,61 @@
 //    See the License for the specific language governing permissions and
 //    limitations under the License.
 
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 use serde::{Deserialize, Serialize};
 use rand::{thread_rng, Rng};
 
-use crate::Color;
+use crate::{BackRankId, BoardResult, Color, EngineBoard, ParseError, PlayResult, Position, Variant};
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+mod abort;
+mod armageddon;
+mod clock;
+mod codec;
+mod correspondence;
+mod explorer;
+mod inactivity;
+mod lobby;
+mod locale;
+mod player;
+mod rating;
+mod series;
+#[cfg(feature = "session")]
+mod session;
+mod spectate;
+mod tournament;
+
+pub use abort::*;
+pub use armageddon::*;
+pub use clock::*;
+pub use codec::*;
+pub use correspondence::*;
+pub use explorer::*;
+pub use inactivity::*;
+pub use lobby::*;
+pub use locale::*;
+pub use player::*;
+pub use rating::*;
+pub use series::*;
+#[cfg(feature = "session")]
+pub use session::*;
+pub use spectate::*;
+pub use tournament::*;
+
+/// The digits of a `GameId` as a string, in ASCII order so that ids sort
+/// the same as strings and as numbers.
+const BASE62: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Enough base62 digits for any `u64`.
+const GAME_ID_LEN: usize = 11;
+
+/// The low bits of a timestamped id that are drawn at random.
+const GAME_ID_RANDOM_BITS: u32 = 22;
+
+/// Identifies a game. It's written as eleven base62 digits for URLs, e.g.
+/// "0Fj3sQ9xKbT", which parse back with `FromStr`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct GameId(u64);
 
 impl GameId {
@@ -25,33 +74,345 @@ impl GameId {
         Self(value)
     }
     pub fn random() -> Self {
-        Self(thread_rng().gen())
+        Self::random_with(&mut thread_rng())
+    }
+    /// Draws an id from `rng`, e.g. a seeded one for reproducible tests.
+    pub fn random_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        Self(rng.gen())
+    }
+    /// Returns an id that sorts after those made earlier, like a ULID: the
+    /// milliseconds since the Unix epoch followed by 22 random bits, so a
+    /// collision needs two games created in the same millisecond.
+    pub fn timestamped() -> Self {
+        Self::timestamped_with(SystemTime::now(), &mut thread_rng())
+    }
+    pub fn timestamped_with<R: Rng + ?Sized>(now: SystemTime, rng: &mut R) -> Self {
+        let millis = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let random = rng.gen::<u64>() >> (64 - GAME_ID_RANDOM_BITS);
+        Self((millis << GAME_ID_RANDOM_BITS) | random)
+    }
+    pub fn to_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for GameId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut digits = [b'0'; GAME_ID_LEN];
+        let mut value = self.0;
+        for digit in digits.iter_mut().rev() {
+            *digit = BASE62[(value % 62) as usize];
+            value /= 62;
+        }
+        // the digits are all ASCII
+        f.write_str(std::str::from_utf8(&digits).unwrap_or_default())
+    }
+}
+
+impl FromStr for GameId {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseError::InvalidGameId(s.to_string());
+        if s.len() != GAME_ID_LEN {
+            return Err(invalid());
+        }
+        let mut value: u64 = 0;
+        for c in s.bytes() {
+            let digit = BASE62.iter().position(|&d| d == c).ok_or_else(invalid)?;
+            value = value.checked_mul(62)
+                .and_then(|value| value.checked_add(digit as u64))
+                .ok_or_else(invalid)?;
+        }
+        Ok(Self(value))
+    }
+}
+
+/// How the back rank of a game is chosen.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BackRankPolicy {
+    /// Every game starts from this layout, e.g. `BackRankId::STANDARD`.
+    Fixed(BackRankId),
+    /// Every game gets a fresh Chess960 shuffle, rematches included.
+    Shuffle,
+    /// A game gets a fresh Chess960 shuffle and its rematches keep it, so
+    /// both players get to play the layout from each side.
+    ShuffleOnce,
+}
+
+/// A game as a server tracks it: who plays which side, how it's set up
+/// and how it ended.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Game {
+    pub id: GameId,
+    pub players: Players,
+    pub control: TimeControl,
+    pub policy: BackRankPolicy,
+    /// The layout picked for this game under `policy`.
+    pub backrank: BackRankId,
+    pub variant: Variant,
+    pub rated: bool,
+    pub outcome: Option<GameOutcome>,
+    /// The game this one is a rematch of, so a chain of rematches can be
+    /// followed back to the first game.
+    pub rematch_of: Option<GameId>,
+}
+
+impl Game {
+    pub fn new(players: Players, control: TimeControl, policy: BackRankPolicy) -> Self {
+        Self::new_with(players, control, policy, &mut thread_rng())
+    }
+
+    /// Creates a casual game of standard chess, drawing the id and any
+    /// Chess960 shuffle from `rng`.
+    pub fn new_with<R: Rng + ?Sized>(
+        players: Players,
+        control: TimeControl,
+        policy: BackRankPolicy,
+        rng: &mut R,
+    ) -> Self {
+        let backrank = match policy {
+            BackRankPolicy::Fixed(id) => id,
+            BackRankPolicy::Shuffle | BackRankPolicy::ShuffleOnce => BackRankId::shuffled_with(rng),
+        };
+        Self {
+            id: GameId::random_with(rng),
+            players,
+            control,
+            policy,
+            backrank,
+            variant: Variant::Standard,
+            rated: false,
+            outcome: None,
+            rematch_of: None,
+        }
+    }
+
+    /// Returns a board to play the game on.
+    pub fn board(&self) -> EngineBoard {
+        EngineBoard::plays_both_from(Position::new_variant(self.backrank.into(), self.variant))
+    }
+
+    /// Returns the player who won, if the game has finished with a win.
+    pub fn winner(&self) -> Option<&Player> {
+        self.outcome?.result().and_then(|result| self.players.winner(result))
+    }
+
+    pub fn rematch(&self) -> Self {
+        self.rematch_with(&mut thread_rng())
+    }
+
+    /// Returns a new game between the same players with colors swapped,
+    /// the same time control and a back rank chosen by the same policy.
+    pub fn rematch_with<R: Rng + ?Sized>(&self, rng: &mut R) -> Self {
+        let mut game = Self::new_with(self.players.swapped(), self.control, self.policy, rng);
+        if self.policy == BackRankPolicy::ShuffleOnce {
+            game.backrank = self.backrank;
+        }
+        game.variant = self.variant;
+        game.rated = self.rated;
+        game.rematch_of = Some(self.id);
+        game
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum GameResult {
     Win(Color, WinReason),
     Draw(DrawReason),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl From<BoardResult> for GameResult {
+    fn from(value: BoardResult) -> Self {
+        match value {
+            BoardResult::CheckMate(winner) => Self::Win(winner, WinReason::CheckMate),
+            BoardResult::StaleMate => Self::Draw(DrawReason::StaleMate),
+            BoardResult::Insufficient => Self::Draw(DrawReason::Insufficient),
+            BoardResult::Repetition => Self::Draw(DrawReason::Repetition),
+            BoardResult::FiftyMoves => Self::Draw(DrawReason::FiftyMoves),
+            BoardResult::Adjudicated(Some(winner)) => Self::Win(winner, WinReason::Adjudicated),
+            BoardResult::Adjudicated(None) => Self::Draw(DrawReason::Adjudicated),
+            BoardResult::VariantWin(winner) => Self::Win(winner, WinReason::VariantWin),
+        }
+    }
+}
+
+impl From<GameResult> for PlayResult {
+    fn from(value: GameResult) -> Self {
+        match value {
+            GameResult::Win(winner, _) => Self::Announced(Some(winner)),
+            GameResult::Draw(_) => Self::Announced(None),
+        }
+    }
+}
+
+impl GameResult {
+    /// Returns the result when `loser` runs out of time on `board`: a win
+    /// for the opponent, unless the board's insufficient material rules
+    /// say they couldn't have won.
+    pub fn time_expired(board: &EngineBoard, loser: Color) -> Self {
+        let result = if board.can_win_on_time(!loser) {
+            Self::Win(!loser, WinReason::TimeExpired)
+        } else {
+            Self::Draw(DrawReason::TimeoutVsInsufficient)
+        };
+        result.for_board(board)
+    }
+
+    /// Returns the result as written in PGN: `1-0`, `0-1` or `1/2-1/2`.
+    pub fn to_pgn(&self) -> &'static str {
+        match self {
+            Self::Win(Color::White, _) => "1-0",
+            Self::Win(Color::Black, _) => "0-1",
+            Self::Draw(_) => "1/2-1/2",
+        }
+    }
+
+    /// Reads a result written in PGN, or returns `None` for `*` and
+    /// anything else. PGN doesn't say how a game was decided, so the
+    /// result is taken as adjudicated.
+    pub fn from_pgn(result: &str) -> Option<Self> {
+        match result {
+            "1-0" => Some(Self::Win(Color::White, WinReason::Adjudicated)),
+            "0-1" => Some(Self::Win(Color::Black, WinReason::Adjudicated)),
+            "1/2-1/2" => Some(Self::Draw(DrawReason::Adjudicated)),
+            _ => None,
+        }
+    }
+
+    /// Returns the result of the game on `board`, if it has ended.
+    pub fn of_board(board: &EngineBoard) -> Option<Self> {
+        board.board_result().map(|result| Self::from(result).for_board(board))
+    }
+
+    fn for_board(self, board: &EngineBoard) -> Self {
+        if board.is_armageddon() {
+            self.armageddon()
+        } else {
+            self
+        }
+    }
+}
+
+/// How a game ended: with a result, or called off without one (see
+/// `abort`).
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum GameOutcome {
+    Finished(GameResult),
+    Aborted,
+}
+
+impl From<GameResult> for GameOutcome {
+    fn from(value: GameResult) -> Self {
+        Self::Finished(value)
+    }
+}
+
+impl GameOutcome {
+    /// Returns the result, or `None` if the game was aborted.
+    pub fn result(&self) -> Option<GameResult> {
+        match self {
+            Self::Finished(result) => Some(*result),
+            Self::Aborted => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum WinReason {
     CheckMate,
     TimeExpired,
     Resigned,
     Abandoned,
+    /// Decided by a custom `ResultPolicy`.
+    Adjudicated,
+    /// Reached the goal of a variant (see `BoardResult::VariantWin`).
+    VariantWin,
     // In Armageddon Chess, there is no draw. So if a draw
     // state is reached, Black wins
     Draw(DrawReason),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum DrawReason {
     Agreed,
     StaleMate,
     Repetition,
     FiftyMoves,
     Insufficient,
+    /// A player ran out of time but their opponent didn't have enough
+    /// material to win.
+    TimeoutVsInsufficient,
+    /// Decided by a custom `ResultPolicy`.
+    Adjudicated,
 }
 
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use rand::{rngs::StdRng, SeedableRng};
+    use crate::*;
+
+    #[test]
+    fn test_rematch() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let alice = Player::new(PlayerId::new(1), "alice");
+        let bob = Player::new(PlayerId::new(2), "bob");
+        let players = Players::new(alice.clone(), bob.clone());
+        let control = TimeControl::new(Duration::from_secs(180), Duration::from_secs(2));
+        let game = Game::new_with(players, control, BackRankPolicy::Fixed(BackRankId::STANDARD), &mut rng);
+        let game = Game { variant: Variant::Antichess, rated: true, ..game };
+        let rematch = game.rematch_with(&mut rng);
+        assert_eq!(rematch.players.white(), &bob);
+        assert_eq!(rematch.players.black(), &alice);
+        assert_eq!(rematch.control, control);
+        assert_eq!(rematch.backrank, BackRankId::STANDARD);
+        assert_eq!(rematch.rematch_of, Some(game.id));
+        assert_eq!((rematch.variant, rematch.rated), (Variant::Antichess, true));
+        assert_ne!(rematch.id, game.id);
+        assert_eq!(rematch.outcome, None);
+        assert_eq!(rematch.winner(), None);
+        let finished = Game { outcome: Some(GameResult::Win(Color::Black, WinReason::Resigned).into()), ..rematch.clone() };
+        assert_eq!(finished.winner(), Some(&alice));
+        let again = rematch.rematch_with(&mut rng);
+        assert_eq!(again.players, game.players);
+        assert_eq!(again.rematch_of, Some(rematch.id));
+
+        let game = Game { policy: BackRankPolicy::ShuffleOnce, ..game };
+        assert_eq!(game.rematch_with(&mut rng).rematch_with(&mut rng).backrank, game.backrank);
+
+        let game = Game { policy: BackRankPolicy::Shuffle, ..game };
+        let shuffles: Vec<_> = (0..4).map(|_| game.rematch_with(&mut rng).backrank).collect();
+        assert!(shuffles.iter().any(|&id| id != game.backrank));
+    }
+
+    #[test]
+    fn test_game_id_strings() {
+        assert_eq!(GameId::new(0).to_string(), "00000000000");
+        assert_eq!(GameId::new(61).to_string(), "0000000000z");
+        assert_eq!(GameId::new(u64::MAX).to_string(), "LygHa16AHYF");
+        for value in [0, 1, 62, 123_456_789, u64::MAX] {
+            let id = GameId::new(value);
+            assert_eq!(id.to_string().parse::<GameId>().unwrap(), id);
+        }
+        // too long, too short, not base62, or beyond u64
+        for s in ["000000000000", "0000000000", "0000000000-", "zzzzzzzzzzz"] {
+            assert!(s.parse::<GameId>().is_err());
+        }
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let earlier = GameId::timestamped_with(UNIX_EPOCH + Duration::from_secs(1_700_000_000), &mut rng);
+        let later = GameId::timestamped_with(UNIX_EPOCH + Duration::from_secs(1_700_000_001), &mut rng);
+        assert!(earlier < later);
+        assert!(earlier.to_string() < later.to_string());
+        assert_eq!(earlier.to_u64() >> 22, 1_700_000_000_000);
+    }
+
+    #[test]
+    fn test_pgn_results() {
+        for result in [GameResult::Win(Color::White, WinReason::Resigned), GameResult::Win(Color::Black, WinReason::CheckMate), GameResult::Draw(DrawReason::Agreed)] {
+            assert_eq!(GameResult::from_pgn(result.to_pgn()).map(|parsed| parsed.to_pgn()), Some(result.to_pgn()));
+        }
+        assert_eq!(GameResult::from_pgn("*"), None);
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Who plays a game, so results can be attributed to players and
+//! exported to PGN tags.
+
+use std::fmt;
+use std::ops::Index;
+use serde::{Deserialize, Serialize};
+
+use crate::{Color, Pair, PgnGame};
+use super::GameResult;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PlayerId(u64);
+
+impl PlayerId {
+    pub const fn new(value: u64) -> Self {
+        Self(value)
+    }
+    pub fn to_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for PlayerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct Player {
+    pub id: PlayerId,
+    pub name: String,
+    /// The rating when the game started, if it's rated.
+    pub rating: Option<u32>,
+}
+
+impl Player {
+    pub fn new(id: PlayerId, name: &str) -> Self {
+        Self { id, name: name.to_string(), rating: None }
+    }
+
+    pub fn with_rating(mut self, rating: u32) -> Self {
+        self.rating = Some(rating);
+        self
+    }
+}
+
+/// The players of a game, by the color they play.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct Players(Pair<Player>);
+
+impl Players {
+    pub fn new(white: Player, black: Player) -> Self {
+        Self(Pair::new(white, black))
+    }
+
+    pub fn white(&self) -> &Player {
+        self.0.white()
+    }
+
+    pub fn black(&self) -> &Player {
+        self.0.black()
+    }
+
+    /// Returns the same players with colors swapped, e.g. for a rematch.
+    pub fn swapped(&self) -> Self {
+        Self::new(self.black().clone(), self.white().clone())
+    }
+
+    /// Returns the color `id` plays, if they play this game.
+    pub fn color_of(&self, id: PlayerId) -> Option<Color> {
+        [Color::White, Color::Black].into_iter().find(|&color| self[color].id == id)
+    }
+
+    /// Returns the winner of a game ending in `result`, or `None` for a
+    /// draw.
+    pub fn winner(&self, result: GameResult) -> Option<&Player> {
+        match result {
+            GameResult::Win(color, _) => Some(&self[color]),
+            GameResult::Draw(_) => None,
+        }
+    }
+
+    /// Sets the `White` and `Black` tags of `pgn`, and `WhiteElo` and
+    /// `BlackElo` for rated players.
+    pub fn set_pgn_tags(&self, pgn: &mut PgnGame) {
+        for (color, tag) in [(Color::White, "White"), (Color::Black, "Black")] {
+            let player = &self[color];
+            pgn.set_tag(tag, &player.name);
+            if let Some(rating) = player.rating {
+                pgn.set_tag(&format!("{tag}Elo"), &rating.to_string());
+            }
+        }
+    }
+}
+
+impl Index<Color> for Players {
+    type Output = Player;
+    fn index(&self, color: Color) -> &Self::Output {
+        &self.0[color]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_players() {
+        let alice = Player::new(PlayerId::new(1), "Alice").with_rating(1850);
+        let bob = Player::new(PlayerId::new(2), "Bob");
+        let players = Players::new(alice.clone(), bob.clone());
+        assert_eq!(players.color_of(PlayerId::new(2)), Some(Color::Black));
+        assert_eq!(players.color_of(PlayerId::new(3)), None);
+        assert_eq!(players.swapped().white(), &bob);
+        assert_eq!(players.winner(GameResult::Win(Color::White, WinReason::Resigned)), Some(&alice));
+        assert_eq!(players.winner(GameResult::Draw(DrawReason::Agreed)), None);
+
+        let mut pgn = PgnGame::new(BackRankId::default(), vec![]);
+        players.set_pgn_tags(&mut pgn);
+        assert_eq!(pgn.tag("White"), Some("Alice"));
+        assert_eq!(pgn.tag("WhiteElo"), Some("1850"));
+        assert_eq!(pgn.tag("Black"), Some("Bob"));
+        assert_eq!(pgn.tag("BlackElo"), None);
+    }
+}
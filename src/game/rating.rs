@@ -0,0 +1,350 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Elo and Glicko-2 rating updates from game results.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::hash::Hash;
+use serde::{Deserialize, Serialize};
+
+use crate::{Color, Pair};
+use super::GameResult;
+
+impl GameResult {
+    /// Returns the score of `color`: 1 for a win, 0.5 for a draw and 0
+    /// for a loss.
+    pub fn score(&self, color: Color) -> f64 {
+        match self {
+            GameResult::Win(winner, _) if *winner == color => 1.0,
+            GameResult::Win(_, _) => 0.0,
+            GameResult::Draw(_) => 0.5,
+        }
+    }
+}
+
+/// How much a single game can move an Elo rating.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum KFactor {
+    Fixed(f64),
+    /// FIDE's rules: 40 for a player's first 30 games, then 20 while
+    /// rated under 2400 and 10 from there on.
+    Fide,
+}
+
+impl KFactor {
+    pub fn value(&self, rating: &EloRating) -> f64 {
+        match self {
+            KFactor::Fixed(k) => *k,
+            KFactor::Fide if rating.games < 30 => 40.0,
+            KFactor::Fide if rating.rating < 2400.0 => 20.0,
+            KFactor::Fide => 10.0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct EloRating {
+    pub rating: f64,
+    /// Number of rated games played, used by `KFactor::Fide`.
+    pub games: u32,
+}
+
+impl EloRating {
+    pub const fn new(rating: f64) -> Self {
+        Self { rating, games: 0 }
+    }
+
+    /// Returns the expected score against `opponent`.
+    pub fn expected_score(&self, opponent: &EloRating) -> f64 {
+        1.0 / (1.0 + 10f64.powf((opponent.rating - self.rating) / 400.0))
+    }
+}
+
+impl Default for EloRating {
+    fn default() -> Self {
+        Self::new(1500.0)
+    }
+}
+
+/// Elo updates, applied after every game.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Elo {
+    pub k: KFactor,
+}
+
+impl Elo {
+    pub const fn new(k: KFactor) -> Self {
+        Self { k }
+    }
+
+    /// Returns the players' ratings after a game between them.
+    pub fn rate(&self, ratings: Pair<EloRating>, result: GameResult) -> Pair<EloRating> {
+        let update = |color: Color| {
+            let (player, opponent) = (ratings[color], ratings[!color]);
+            let change = self.k.value(&player) * (result.score(color) - player.expected_score(&opponent));
+            EloRating { rating: player.rating + change, games: player.games + 1 }
+        };
+        Pair::new(update(Color::White), update(Color::Black))
+    }
+
+    /// Applies a stream of results in order, starting unknown players at
+    /// the default rating.
+    pub fn rate_all<P, I>(&self, ratings: &mut HashMap<P, EloRating>, games: I)
+    where
+        P: Eq + Hash + Clone,
+        I: IntoIterator<Item = (P, P, GameResult)>,
+    {
+        for (white, black, result) in games {
+            let before = Pair::new(
+                *ratings.entry(white.clone()).or_default(),
+                *ratings.entry(black.clone()).or_default(),
+            );
+            let after = self.rate(before, result);
+            ratings.insert(white, *after.white());
+            ratings.insert(black, *after.black());
+        }
+    }
+}
+
+impl Default for Elo {
+    fn default() -> Self {
+        Self::new(KFactor::Fide)
+    }
+}
+
+/// A Glicko-2 rating, on the familiar Elo-like scale.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct GlickoRating {
+    pub rating: f64,
+    /// Rating deviation: how uncertain the rating is.
+    pub deviation: f64,
+    /// How erratic the player's results are.
+    pub volatility: f64,
+}
+
+impl GlickoRating {
+    pub const fn new(rating: f64, deviation: f64, volatility: f64) -> Self {
+        Self { rating, deviation, volatility }
+    }
+
+    // The Glicko-2 scale used internally
+    fn mu(&self) -> f64 {
+        (self.rating - 1500.0) / GLICKO_SCALE
+    }
+    fn phi(&self) -> f64 {
+        self.deviation / GLICKO_SCALE
+    }
+}
+
+impl Default for GlickoRating {
+    fn default() -> Self {
+        Self::new(1500.0, 350.0, 0.06)
+    }
+}
+
+const GLICKO_SCALE: f64 = 173.7178;
+const CONVERGENCE: f64 = 0.000001;
+
+/// Glicko-2 updates. Unlike Elo, ratings change once per rating period
+/// (e.g. a day or a tournament) from all the games played in it; see
+/// `RatingPeriod`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Glicko2 {
+    /// Constrains how quickly volatility changes, typically between 0.3
+    /// and 1.2.
+    pub tau: f64,
+}
+
+impl Glicko2 {
+    pub const fn new(tau: f64) -> Self {
+        Self { tau }
+    }
+
+    /// Returns `player`'s rating after a rating period in which they
+    /// scored `score` against each `opponent`. With no games, only the
+    /// deviation grows.
+    pub fn rate(&self, player: GlickoRating, games: &[(GlickoRating, f64)]) -> GlickoRating {
+        let (mu, phi, sigma) = (player.mu(), player.phi(), player.volatility);
+        if games.is_empty() {
+            let deviation = (phi * phi + sigma * sigma).sqrt() * GLICKO_SCALE;
+            return GlickoRating { deviation, ..player };
+        }
+        let g = |phi: f64| 1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt();
+        let expected = |opponent: &GlickoRating| {
+            1.0 / (1.0 + (-g(opponent.phi()) * (mu - opponent.mu())).exp())
+        };
+        let v = 1.0 / games.iter()
+            .map(|(opponent, _)| {
+                let e = expected(opponent);
+                g(opponent.phi()).powi(2) * e * (1.0 - e)
+            })
+            .sum::<f64>();
+        let improvement: f64 = games.iter()
+            .map(|(opponent, score)| g(opponent.phi()) * (score - expected(opponent)))
+            .sum();
+        let delta = v * improvement;
+
+        // New volatility, by the Illinois algorithm
+        let a = (sigma * sigma).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            let d = phi * phi + v + ex;
+            ex * (delta * delta - d) / (2.0 * d * d) - (x - a) / (self.tau * self.tau)
+        };
+        let mut x_a = a;
+        let mut x_b = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * self.tau) < 0.0 {
+                k += 1.0;
+            }
+            a - k * self.tau
+        };
+        let (mut f_a, mut f_b) = (f(x_a), f(x_b));
+        while (x_b - x_a).abs() > CONVERGENCE {
+            let c = x_a + (x_a - x_b) * f_a / (f_b - f_a);
+            let f_c = f(c);
+            if f_c * f_b <= 0.0 {
+                x_a = x_b;
+                f_a = f_b;
+            } else {
+                f_a /= 2.0;
+            }
+            x_b = c;
+            f_b = f_c;
+        }
+        let volatility = (x_a / 2.0).exp();
+
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let mu = mu + phi * phi * improvement;
+        GlickoRating {
+            rating: mu * GLICKO_SCALE + 1500.0,
+            deviation: phi * GLICKO_SCALE,
+            volatility,
+        }
+    }
+}
+
+impl Default for Glicko2 {
+    fn default() -> Self {
+        Self::new(0.5)
+    }
+}
+
+/// Collects the games of one Glicko-2 rating period.
+#[derive(Debug, Clone)]
+pub struct RatingPeriod<P> {
+    games: Vec<(P, P, GameResult)>,
+}
+
+impl<P: Eq + Hash + Clone> RatingPeriod<P> {
+    pub fn new() -> Self {
+        Self { games: Vec::new() }
+    }
+
+    pub fn record(&mut self, white: P, black: P, result: GameResult) {
+        self.games.push((white, black, result));
+    }
+
+    /// Ends the period, updating every player in `ratings` (including
+    /// those who didn't play) and adding new players at the default
+    /// rating. All games are rated against the opponents' ratings from
+    /// before the period.
+    pub fn finish(self, glicko: &Glicko2, ratings: &mut HashMap<P, GlickoRating>) {
+        for (white, black, _) in &self.games {
+            ratings.entry(white.clone()).or_default();
+            ratings.entry(black.clone()).or_default();
+        }
+        let mut games: HashMap<P, Vec<(GlickoRating, f64)>> = HashMap::new();
+        for (white, black, result) in &self.games {
+            let entry = (ratings[black], result.score(Color::White));
+            games.entry(white.clone()).or_default().push(entry);
+            let entry = (ratings[white], result.score(Color::Black));
+            games.entry(black.clone()).or_default().push(entry);
+        }
+        for (player, rating) in ratings.iter_mut() {
+            let games = games.get(player).map(Vec::as_slice).unwrap_or_default();
+            *rating = glicko.rate(*rating, games);
+        }
+    }
+}
+
+impl<P: Eq + Hash + Clone> Default for RatingPeriod<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() < tolerance
+    }
+
+    #[test]
+    fn test_elo() {
+        let elo = Elo::new(KFactor::Fixed(20.0));
+        let ratings = Pair::new(EloRating::new(1500.0), EloRating::new(1500.0));
+        let after = elo.rate(ratings, GameResult::Win(Color::White, WinReason::CheckMate));
+        assert_eq!(after.white().rating, 1510.0);
+        assert_eq!(after.black().rating, 1490.0);
+        assert_eq!(after.black().games, 1);
+
+        let strong = EloRating::new(1900.0);
+        assert!(close(strong.expected_score(&EloRating::new(1500.0)), 0.909, 0.001));
+        assert_eq!(KFactor::Fide.value(&EloRating { rating: 2500.0, games: 50 }), 10.0);
+        assert_eq!(KFactor::Fide.value(&EloRating { rating: 2500.0, games: 5 }), 40.0);
+
+        let mut ratings = HashMap::new();
+        let draw = GameResult::Draw(DrawReason::Agreed);
+        Elo::default().rate_all(&mut ratings, [("a", "b", draw), ("b", "c", draw)]);
+        assert_eq!(ratings.len(), 3);
+        assert_eq!(ratings["a"].rating, 1500.0);
+    }
+
+    #[test]
+    fn test_glicko2_example() {
+        // The worked example from Glickman's description of Glicko-2
+        let player = GlickoRating::new(1500.0, 200.0, 0.06);
+        let games = [
+            (GlickoRating::new(1400.0, 30.0, 0.06), 1.0),
+            (GlickoRating::new(1550.0, 100.0, 0.06), 0.0),
+            (GlickoRating::new(1700.0, 300.0, 0.06), 0.0),
+        ];
+        let after = Glicko2::new(0.5).rate(player, &games);
+        assert!(close(after.rating, 1464.06, 0.01), "{after:?}");
+        assert!(close(after.deviation, 151.52, 0.01), "{after:?}");
+        assert!(close(after.volatility, 0.05999, 0.00001), "{after:?}");
+    }
+
+    #[test]
+    fn test_rating_period() {
+        let mut ratings = HashMap::new();
+        ratings.insert("idle", GlickoRating::new(1500.0, 100.0, 0.06));
+        let mut period = RatingPeriod::new();
+        period.record("a", "b", GameResult::Win(Color::White, WinReason::Resigned));
+        period.finish(&Glicko2::default(), &mut ratings);
+        assert!(ratings["a"].rating > 1500.0);
+        assert!(ratings["b"].rating < 1500.0);
+        assert!(ratings["a"].deviation < 350.0);
+        assert!(ratings["idle"].deviation > 100.0);
+        assert_eq!(ratings["idle"].rating, 1500.0);
+    }
+}
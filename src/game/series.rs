@@ -0,0 +1,320 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+use std::ops::Not;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::Color;
+use super::{ArmageddonRules, GameResult};
+
+/// Identifies one of the two players of a `Match`. `First` has White in
+/// the first game.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MatchPlayer {
+    First,
+    Second,
+}
+
+impl Not for MatchPlayer {
+    type Output = Self;
+    fn not(self) -> Self::Output {
+        match self {
+            MatchPlayer::First => MatchPlayer::Second,
+            MatchPlayer::Second => MatchPlayer::First,
+        }
+    }
+}
+
+/// How many regular games a match lasts.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MatchFormat {
+    /// At most this many games, stopping as soon as the leader can no
+    /// longer be caught.
+    BestOf(u16),
+    /// Exactly this many games.
+    Fixed(u16),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MatchRules {
+    pub format: MatchFormat,
+    /// Number of tie-break games played if the regular games end level.
+    /// They stop early once the leader can no longer be caught.
+    pub tie_breaks: u16,
+    /// Settles a match still level after the tie-breaks with a single
+    /// Armageddon game, or leaves it drawn if `None`.
+    pub armageddon: Option<ArmageddonRules>,
+}
+
+impl MatchRules {
+    pub const fn new(format: MatchFormat) -> Self {
+        Self { format, tie_breaks: 0, armageddon: None }
+    }
+    pub const fn with_tie_breaks(mut self, games: u16) -> Self {
+        self.tie_breaks = games;
+        self
+    }
+    pub const fn with_armageddon(mut self, rules: ArmageddonRules) -> Self {
+        self.armageddon = Some(rules);
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MatchStage {
+    Regular,
+    TieBreak,
+    Armageddon,
+}
+
+/// A game of a match: who has White, at which stage, and its result once
+/// recorded.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct MatchGame {
+    pub white: MatchPlayer,
+    pub stage: MatchStage,
+    pub result: Option<GameResult>,
+}
+
+impl MatchGame {
+    pub fn player(&self, color: Color) -> MatchPlayer {
+        match color {
+            Color::White => self.white,
+            Color::Black => !self.white,
+        }
+    }
+}
+
+/// A player's results in a match.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Score {
+    pub wins: u16,
+    pub draws: u16,
+    pub losses: u16,
+}
+
+impl Score {
+    /// Returns the score counting draws as half a point.
+    pub fn half_points(&self) -> u32 {
+        2 * self.wins as u32 + self.draws as u32
+    }
+    pub fn points(&self) -> f32 {
+        self.half_points() as f32 / 2.0
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MatchOutcome {
+    Won(MatchPlayer),
+    Drawn,
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchError {
+    #[error("The match is over")]
+    Finished,
+}
+
+/// A series of games between two players. Colors alternate from game to
+/// game, and tie-breaks (ending with an optional Armageddon game) decide
+/// a level match. The whole state is serializable so a match can be
+/// saved and resumed.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Match {
+    rules: MatchRules,
+    games: Vec<MatchGame>,
+}
+
+impl Match {
+    pub fn new(rules: MatchRules) -> Self {
+        Self { rules, games: Vec::new() }
+    }
+
+    pub fn rules(&self) -> &MatchRules {
+        &self.rules
+    }
+
+    /// Returns the games played so far, followed by the one in progress
+    /// (if any), which has no result yet.
+    pub fn games(&self) -> &[MatchGame] {
+        &self.games
+    }
+
+    pub fn score(&self, player: MatchPlayer) -> Score {
+        let mut score = Score::default();
+        for game in &self.games {
+            match game.result {
+                Some(GameResult::Win(color, _)) if game.player(color) == player => score.wins += 1,
+                Some(GameResult::Win(_, _)) => score.losses += 1,
+                Some(GameResult::Draw(_)) => score.draws += 1,
+                None => {},
+            }
+        }
+        score
+    }
+
+    /// Returns the outcome once the match is over.
+    pub fn outcome(&self) -> Option<MatchOutcome> {
+        if self.games.last().is_some_and(|game| game.result.is_none()) {
+            return None;
+        }
+        match self.next_stage() {
+            Some(_) => None,
+            None => match self.leader() {
+                Some(player) => Some(MatchOutcome::Won(player)),
+                None => Some(MatchOutcome::Drawn),
+            },
+        }
+    }
+
+    /// Returns the game to be played next (or the one in progress),
+    /// starting it if needed, or `None` once the match is over.
+    pub fn next_game(&mut self) -> Option<MatchGame> {
+        if let Some(game) = self.games.last().filter(|game| game.result.is_none()) {
+            return Some(*game);
+        }
+        let stage = self.next_stage()?;
+        let white = if self.games.len().is_multiple_of(2) { MatchPlayer::First } else { MatchPlayer::Second };
+        let game = MatchGame { white, stage, result: None };
+        self.games.push(game);
+        Some(game)
+    }
+
+    /// Records the result of the current game. Armageddon games are
+    /// mapped with `GameResult::armageddon`, so a draw counts as a win
+    /// for Black.
+    pub fn record(&mut self, result: GameResult) -> Result<(), MatchError> {
+        self.next_game().ok_or(MatchError::Finished)?;
+        let game = self.games.last_mut().unwrap();
+        game.result = Some(match game.stage {
+            MatchStage::Armageddon => result.armageddon(),
+            _ => result,
+        });
+        Ok(())
+    }
+
+    fn leader(&self) -> Option<MatchPlayer> {
+        let first = self.score(MatchPlayer::First).half_points();
+        let second = self.score(MatchPlayer::Second).half_points();
+        match first.cmp(&second) {
+            std::cmp::Ordering::Greater => Some(MatchPlayer::First),
+            std::cmp::Ordering::Less => Some(MatchPlayer::Second),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+
+    /// Returns the stage of the next game, assuming the last one has a
+    /// result, or `None` if the match is decided.
+    fn next_stage(&self) -> Option<MatchStage> {
+        let played = |stage| self.games.iter().filter(|game| game.stage == stage).count();
+        let lead = self.score(MatchPlayer::First).half_points()
+            .abs_diff(self.score(MatchPlayer::Second).half_points());
+        // a player who leads by more than two half-points per remaining
+        // game can't be caught
+        let open = |remaining: usize| lead <= 2 * remaining as u32;
+
+        let regular_left = match self.rules.format {
+            MatchFormat::BestOf(games) => {
+                let left = games as usize - played(MatchStage::Regular).min(games as usize);
+                if open(left) { left } else { 0 }
+            },
+            MatchFormat::Fixed(games) => games as usize - played(MatchStage::Regular).min(games as usize),
+        };
+        if regular_left > 0 {
+            return Some(MatchStage::Regular);
+        }
+        let tie_breaks = self.rules.tie_breaks as usize;
+        let tie_breaks_played = played(MatchStage::TieBreak);
+        if tie_breaks_played == 0 && lead > 0 {
+            return None;
+        }
+        let tie_breaks_left = tie_breaks - tie_breaks_played.min(tie_breaks);
+        if tie_breaks_left > 0 && open(tie_breaks_left) {
+            return Some(MatchStage::TieBreak);
+        }
+        if lead > 0 {
+            return None;
+        }
+        if self.rules.armageddon.is_some() && played(MatchStage::Armageddon) == 0 {
+            return Some(MatchStage::Armageddon);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use MatchPlayer::*;
+
+    fn win(color: Color) -> GameResult {
+        GameResult::Win(color, WinReason::CheckMate)
+    }
+    const DRAW: GameResult = GameResult::Draw(DrawReason::Agreed);
+
+    #[test]
+    fn test_best_of_stops_when_decided() {
+        let mut series = Match::new(MatchRules::new(MatchFormat::BestOf(5)));
+        assert_eq!(series.next_game().unwrap().white, First);
+        series.record(win(Color::White)).unwrap();
+        assert_eq!(series.next_game().unwrap().white, Second);
+        series.record(win(Color::Black)).unwrap();
+        series.record(DRAW).unwrap();
+        assert_eq!(series.outcome(), None);
+        series.record(win(Color::Black)).unwrap();
+        // First leads 3.5 to 0.5 with one game left
+        assert_eq!(series.score(First), Score { wins: 3, draws: 1, losses: 0 });
+        assert_eq!(series.score(Second).points(), 0.5);
+        assert_eq!(series.outcome(), Some(MatchOutcome::Won(First)));
+        assert_eq!(series.next_game(), None);
+        assert_eq!(series.record(DRAW), Err(MatchError::Finished));
+    }
+
+    #[test]
+    fn test_tie_breaks_and_armageddon() {
+        let rules = MatchRules::new(MatchFormat::Fixed(2))
+            .with_tie_breaks(2)
+            .with_armageddon(ArmageddonRules::default());
+        let mut series = Match::new(rules);
+        series.record(DRAW).unwrap();
+        series.record(DRAW).unwrap();
+        // the tie-breaks continue after the first is won
+        series.record(win(Color::White)).unwrap();
+        assert_eq!(series.next_game().unwrap().stage, MatchStage::TieBreak);
+        series.record(win(Color::White)).unwrap();
+        let game = series.next_game().unwrap();
+        assert_eq!(game.stage, MatchStage::Armageddon);
+        assert_eq!(game.white, First);
+        series.record(GameResult::Draw(DrawReason::Repetition)).unwrap();
+        assert_eq!(series.outcome(), Some(MatchOutcome::Won(Second)));
+        let stages: Vec<MatchStage> = series.games().iter().map(|game| game.stage).collect();
+        assert_eq!(stages, [
+            MatchStage::Regular, MatchStage::Regular,
+            MatchStage::TieBreak, MatchStage::TieBreak,
+            MatchStage::Armageddon,
+        ]);
+    }
+
+    #[test]
+    fn test_fixed_match_can_be_drawn_and_resumed() {
+        let mut series = Match::new(MatchRules::new(MatchFormat::Fixed(2)));
+        series.record(win(Color::White)).unwrap();
+        let json = serde_json::to_string(&series).unwrap();
+        let mut series: Match = serde_json::from_str(&json).unwrap();
+        series.record(win(Color::White)).unwrap();
+        assert_eq!(series.outcome(), Some(MatchOutcome::Drawn));
+    }
+}
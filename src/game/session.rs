@@ -0,0 +1,555 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! An async hub for a single game: each player submits moves through
+//! their own channel, the session applies them to an `EngineBoard`, runs
+//! the clock and broadcasts everything that happens to subscribers.
+
+use std::future::pending;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::{interval, sleep_until, Instant, Interval};
+
+use crate::{Color, EngineBoard, Move, MoveId, Pair, Turn};
+use super::{AbortRequests, ArmageddonRules, Clock, GameOutcome, GameResult, LagCompensation, TimeControl, WinReason};
+
+use Color::*;
+
+/// Number of undelivered events kept for slow subscribers.
+const EVENT_CAPACITY: usize = 64;
+/// Number of commands a player can queue before sending waits.
+const COMMAND_CAPACITY: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionEvent {
+    MoveApplied {
+        color: Color,
+        mv: Move,
+        move_id: MoveId,
+        /// Time left for the mover, including the increment.
+        remaining: Duration,
+    },
+    MoveRejected {
+        color: Color,
+        mv: Move,
+        reason: String,
+    },
+    /// Periodic clock update, only sent if a tick interval is configured.
+    /// Also sent after a takeback to correct the displayed times.
+    Clock(Pair<Duration>),
+    TakebackOffered(Color),
+    TakebackDeclined(Color),
+    TakebackRejected {
+        color: Color,
+        reason: String,
+    },
+    /// The last `plies` moves were undone and the clock times recorded at
+    /// the start of the rewound ply restored.
+    TakebackApplied {
+        plies: usize,
+        remaining: Pair<Duration>,
+    },
+    /// A player asked to abort a game that's underway; it's aborted if
+    /// the opponent asks too before the next move.
+    AbortRequested(Color),
+    AbortRejected {
+        color: Color,
+        reason: String,
+    },
+    Finished(GameResult),
+    Aborted,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Command {
+    /// A move, with the time the client reports making it, if known.
+    Move(Move, Option<Instant>),
+    OfferTakeback,
+    AcceptTakeback,
+    DeclineTakeback,
+    RequestAbort,
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Game session has ended")]
+pub struct SessionClosed;
+
+/// One player's connection to a `GameSession`. Dropping it abandons the
+/// game.
+#[derive(Debug, Clone)]
+pub struct PlayerHandle {
+    color: Color,
+    commands: mpsc::Sender<Command>,
+    events: broadcast::Sender<SessionEvent>,
+}
+
+impl PlayerHandle {
+    pub fn color(&self) -> Color {
+        self.color
+    }
+    pub async fn submit(&self, mv: Move) -> Result<(), SessionClosed> {
+        self.send(Command::Move(mv, None)).await
+    }
+    /// Submits a move the client reports making at `moved_at` (already
+    /// translated to our time base). If the session has lag compensation
+    /// enabled, the time the move spent in transit is credited back.
+    pub async fn submit_at(&self, mv: Move, moved_at: Instant) -> Result<(), SessionClosed> {
+        self.send(Command::Move(mv, Some(moved_at))).await
+    }
+    /// Asks the opponent to undo our last move (and their reply, if they
+    /// already made one).
+    pub async fn offer_takeback(&self) -> Result<(), SessionClosed> {
+        self.send(Command::OfferTakeback).await
+    }
+    pub async fn accept_takeback(&self) -> Result<(), SessionClosed> {
+        self.send(Command::AcceptTakeback).await
+    }
+    pub async fn decline_takeback(&self) -> Result<(), SessionClosed> {
+        self.send(Command::DeclineTakeback).await
+    }
+    /// Aborts the game if neither side has moved yet, or otherwise asks
+    /// the opponent to agree to abort it.
+    pub async fn request_abort(&self) -> Result<(), SessionClosed> {
+        self.send(Command::RequestAbort).await
+    }
+    async fn send(&self, command: Command) -> Result<(), SessionClosed> {
+        self.commands.send(command).await.map_err(|_| SessionClosed)
+    }
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+}
+
+pub struct GameSession {
+    board: EngineBoard,
+    clock: Clock,
+    tick: Option<Duration>,
+    takeback: Option<(Color, usize)>,
+    aborts: AbortRequests,
+    white_commands: mpsc::Receiver<Command>,
+    black_commands: mpsc::Receiver<Command>,
+    events: broadcast::Sender<SessionEvent>,
+}
+
+impl GameSession {
+    /// Creates a session for `board` along with the handles for the white
+    /// and black players.
+    pub fn new(board: EngineBoard, control: TimeControl) -> (Self, Pair<PlayerHandle>) {
+        Self::with_clock(board, Clock::new(control))
+    }
+
+    /// Creates a session for an Armageddon game on `board`, with the
+    /// clock set up for `rules`.
+    pub fn armageddon(mut board: EngineBoard, rules: ArmageddonRules) -> (Self, Pair<PlayerHandle>) {
+        rules.apply(&mut board);
+        Self::with_clock(board, rules.clock())
+    }
+
+    fn with_clock(board: EngineBoard, clock: Clock) -> (Self, Pair<PlayerHandle>) {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let (white_tx, white_commands) = mpsc::channel(COMMAND_CAPACITY);
+        let (black_tx, black_commands) = mpsc::channel(COMMAND_CAPACITY);
+        let handle = |color, commands| PlayerHandle { color, commands, events: events.clone() };
+        let handles = Pair::new(handle(White, white_tx), handle(Black, black_tx));
+        let session = Self {
+            board,
+            clock,
+            tick: None,
+            takeback: None,
+            aborts: AbortRequests::new(),
+            white_commands,
+            black_commands,
+            events,
+        };
+        (session, handles)
+    }
+
+    /// Broadcasts `SessionEvent::Clock` every `period` while the game runs.
+    pub fn with_tick(mut self, period: Duration) -> Self {
+        self.tick = Some(period);
+        self
+    }
+
+    /// Credits network lag back to players who submit moves with
+    /// `PlayerHandle::submit_at`.
+    pub fn with_lag_compensation(mut self, lag: LagCompensation) -> Self {
+        self.clock.set_lag_compensation(Some(lag));
+        self
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    pub fn board(&self) -> &EngineBoard {
+        &self.board
+    }
+
+    /// Plays the game to completion and returns how it ended. White's
+    /// clock starts as soon as the session runs. Moves submitted out of
+    /// turn or rejected by the board are reported and otherwise ignored.
+    /// A player dropping their handle loses by abandonment. Pending
+    /// takeback offers and abort requests lapse when the next move is
+    /// played.
+    pub async fn run(mut self) -> GameOutcome {
+        self.clock.start(White, Instant::now().into_std());
+        let mut ticker = self.tick.map(interval);
+        loop {
+            let deadline = self.clock.flag_time()
+                .map(|(_, deadline)| Instant::from_std(deadline));
+            let (color, received) = tokio::select! {
+                command = self.white_commands.recv() => (White, command),
+                command = self.black_commands.recv() => (Black, command),
+                _ = next_tick(&mut ticker) => {
+                    self.broadcast_clock();
+                    continue;
+                },
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)) => {
+                    let result = GameResult::time_expired(&self.board, self.board.turn());
+                    return self.finish(result.into());
+                },
+            };
+            let Some(command) = received else {
+                return self.finish(GameResult::Win(!color, WinReason::Abandoned).into());
+            };
+            let result = match command {
+                Command::Move(mv, moved_at) => self.play(color, mv, moved_at),
+                Command::OfferTakeback => self.offer_takeback(color),
+                Command::AcceptTakeback => self.accept_takeback(color),
+                Command::DeclineTakeback => self.decline_takeback(color),
+                Command::RequestAbort => self.request_abort(color),
+            };
+            if let Some(outcome) = result {
+                return self.finish(outcome);
+            }
+        }
+    }
+
+    fn play(&mut self, color: Color, mv: Move, moved_at: Option<Instant>) -> Option<GameOutcome> {
+        if color != self.board.turn() {
+            let reason = "Not your turn".to_string();
+            let _ = self.events.send(SessionEvent::MoveRejected { color, mv, reason });
+            return None;
+        }
+        match self.board.submit_move(mv) {
+            Ok(move_id) => {
+                self.takeback = None;
+                self.aborts.on_move();
+                let now = Instant::now().into_std();
+                let remaining = match moved_at {
+                    Some(moved_at) => self.clock.press_reported(moved_at.into_std(), now),
+                    None => self.clock.press(now),
+                }.unwrap_or_default();
+                let event = SessionEvent::MoveApplied { color, mv, move_id, remaining };
+                let _ = self.events.send(event);
+                GameResult::of_board(&self.board).map(GameOutcome::from)
+            },
+            Err(error) => {
+                let reason = error.to_string();
+                let _ = self.events.send(SessionEvent::MoveRejected { color, mv, reason });
+                None
+            },
+        }
+    }
+
+    fn offer_takeback(&mut self, color: Color) -> Option<GameOutcome> {
+        // undo the opponent's reply as well if they've already made one
+        let plies = if color == self.board.turn() { 2 } else { 1 };
+        if plies > self.board.plies() {
+            self.reject_takeback(color, "No move to take back");
+        } else {
+            self.takeback = Some((color, plies));
+            let _ = self.events.send(SessionEvent::TakebackOffered(color));
+        }
+        None
+    }
+
+    fn accept_takeback(&mut self, color: Color) -> Option<GameOutcome> {
+        let plies = match self.takeback {
+            Some((offered_by, plies)) if offered_by != color => plies,
+            _ => {
+                self.reject_takeback(color, "No takeback offer to accept");
+                return None;
+            },
+        };
+        self.takeback = None;
+        if let Err(error) = self.board.take_back(plies) {
+            self.reject_takeback(color, &error.to_string());
+            return None;
+        }
+        let now = Instant::now().into_std();
+        // the clock records one entry per applied move, so this can't fail
+        let remaining = self.clock.rewind(plies, now).unwrap_or_default();
+        let _ = self.events.send(SessionEvent::TakebackApplied { plies, remaining });
+        let _ = self.events.send(SessionEvent::Clock(remaining));
+        None
+    }
+
+    fn decline_takeback(&mut self, color: Color) -> Option<GameOutcome> {
+        match self.takeback {
+            Some((offered_by, _)) if offered_by != color => {
+                self.takeback = None;
+                let _ = self.events.send(SessionEvent::TakebackDeclined(color));
+            },
+            _ => self.reject_takeback(color, "No takeback offer to decline"),
+        }
+        None
+    }
+
+    fn request_abort(&mut self, color: Color) -> Option<GameOutcome> {
+        match self.aborts.request(&self.board, color) {
+            Ok(None) => {
+                let _ = self.events.send(SessionEvent::AbortRequested(color));
+                None
+            },
+            Ok(outcome) => outcome,
+            Err(error) => {
+                let reason = error.to_string();
+                let _ = self.events.send(SessionEvent::AbortRejected { color, reason });
+                None
+            },
+        }
+    }
+
+    fn reject_takeback(&self, color: Color, reason: &str) {
+        let reason = reason.to_string();
+        let _ = self.events.send(SessionEvent::TakebackRejected { color, reason });
+    }
+
+    fn broadcast_clock(&self) {
+        let now = Instant::now().into_std();
+        let remaining = Pair::new(
+            self.clock.remaining(White, now),
+            self.clock.remaining(Black, now),
+        );
+        let _ = self.events.send(SessionEvent::Clock(remaining));
+    }
+
+    fn finish(mut self, outcome: GameOutcome) -> GameOutcome {
+        self.clock.stop(Instant::now().into_std());
+        let event = match outcome {
+            GameOutcome::Finished(result) => SessionEvent::Finished(result),
+            GameOutcome::Aborted => SessionEvent::Aborted,
+        };
+        let _ = self.events.send(event);
+        outcome
+    }
+}
+
+async fn next_tick(ticker: &mut Option<Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        },
+        None => pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(value: u64) -> Duration {
+        Duration::from_secs(value)
+    }
+
+    fn mv(uci: &str) -> Move {
+        uci.parse().unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_moves_are_applied_and_broadcast() {
+        let (session, players) = GameSession::new(EngineBoard::standard(), TimeControl::new(secs(60), secs(1)));
+        let mut events = session.subscribe();
+        let game = tokio::spawn(session.run());
+
+        tokio::time::sleep(secs(5)).await;
+        players.white().submit(mv("e2e4")).await.unwrap();
+        match events.recv().await.unwrap() {
+            SessionEvent::MoveApplied { color, remaining, .. } => {
+                assert_eq!(color, White);
+                assert_eq!(remaining, secs(56));
+            },
+            event => panic!("unexpected event {event:?}"),
+        }
+
+        players.white().submit(mv("d2d4")).await.unwrap();
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            SessionEvent::MoveRejected { color: White, .. }
+        ));
+
+        drop(players);
+        let result = game.await.unwrap().result();
+        assert!(matches!(result, Some(GameResult::Win(_, WinReason::Abandoned))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lag_compensation() {
+        let (session, players) = GameSession::new(EngineBoard::standard(), TimeControl::new(secs(60), secs(0)));
+        let lag = LagCompensation::new(Duration::from_millis(500), secs(5));
+        let session = session.with_lag_compensation(lag);
+        assert_eq!(session.clock().lag_compensation(), Some(&lag));
+        let mut events = session.subscribe();
+        let game = tokio::spawn(session.run());
+
+        tokio::time::sleep(secs(5)).await;
+        // the move reached us 300ms after the client made it
+        let moved_at = Instant::now() - Duration::from_millis(300);
+        players.white().submit_at(mv("e2e4"), moved_at).await.unwrap();
+        match events.recv().await.unwrap() {
+            SessionEvent::MoveApplied { remaining, .. } => assert_eq!(remaining, Duration::from_millis(55_300)),
+            event => panic!("unexpected event {event:?}"),
+        }
+        drop(players);
+        game.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_takeback_restores_clock() {
+        let (session, players) = GameSession::new(EngineBoard::standard(), TimeControl::new(secs(60), secs(0)));
+        let mut events = session.subscribe();
+        let game = tokio::spawn(session.run());
+
+        tokio::time::sleep(secs(5)).await;
+        players.white().submit(mv("e2e4")).await.unwrap();
+        tokio::time::sleep(secs(20)).await;
+        players.black().submit(mv("e7e5")).await.unwrap();
+        tokio::time::sleep(secs(10)).await;
+        players.black().offer_takeback().await.unwrap();
+        // the session reads both players' channels in no particular order,
+        // so wait for the offer before accepting it
+        while events.recv().await.unwrap() != SessionEvent::TakebackOffered(Black) {}
+        players.white().accept_takeback().await.unwrap();
+
+        let mut applied = None;
+        let mut corrected = None;
+        while applied.is_none() || corrected.is_none() {
+            match events.recv().await.unwrap() {
+                SessionEvent::TakebackApplied { plies, remaining } => applied = Some((plies, remaining)),
+                SessionEvent::Clock(remaining) => corrected = Some(remaining),
+                _ => {},
+            }
+        }
+        let restored = Pair::new(secs(55), secs(60));
+        assert_eq!(applied, Some((1, restored)));
+        assert_eq!(corrected, Some(restored));
+
+        // black is to move again with the restored time
+        players.black().submit(mv("c7c5")).await.unwrap();
+        loop {
+            if let SessionEvent::MoveApplied { color, remaining, .. } = events.recv().await.unwrap() {
+                assert_eq!(color, Black);
+                assert_eq!(remaining, secs(60));
+                break;
+            }
+        }
+        drop(players);
+        game.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_takeback_requires_offer() {
+        let (session, players) = GameSession::new(EngineBoard::standard(), TimeControl::new(secs(60), secs(0)));
+        let mut events = session.subscribe();
+        let game = tokio::spawn(session.run());
+        players.white().offer_takeback().await.unwrap();
+        players.black().accept_takeback().await.unwrap();
+        let mut rejected = Vec::new();
+        for _ in 0..2 {
+            match events.recv().await.unwrap() {
+                SessionEvent::TakebackRejected { color, .. } => rejected.push(color),
+                event => panic!("unexpected event {event:?}"),
+            }
+        }
+        assert!(rejected.contains(&White) && rejected.contains(&Black));
+        drop(players);
+        game.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_abort() {
+        let (session, players) = GameSession::new(EngineBoard::standard(), TimeControl::new(secs(60), secs(0)));
+        let game = tokio::spawn(session.run());
+        players.white().submit(mv("e2e4")).await.unwrap();
+        players.black().request_abort().await.unwrap();
+        assert_eq!(game.await.unwrap(), GameOutcome::Aborted);
+
+        let (session, players) = GameSession::new(EngineBoard::standard(), TimeControl::new(secs(60), secs(0)));
+        let mut events = session.subscribe();
+        let game = tokio::spawn(session.run());
+        // wait for each move so the abort request comes after both
+        for (player, uci) in [(players.white(), "e2e4"), (players.black(), "e7e5")] {
+            player.submit(mv(uci)).await.unwrap();
+            while !matches!(events.recv().await.unwrap(), SessionEvent::MoveApplied { .. }) {}
+        }
+        players.white().request_abort().await.unwrap();
+        while events.recv().await.unwrap() != SessionEvent::AbortRequested(White) {}
+        players.black().request_abort().await.unwrap();
+        assert_eq!(game.await.unwrap(), GameOutcome::Aborted);
+        let mut aborted = false;
+        while let Ok(event) = events.try_recv() {
+            aborted |= event == SessionEvent::Aborted;
+        }
+        assert!(aborted);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_flag_ends_game() {
+        let (session, players) = GameSession::new(EngineBoard::standard(), TimeControl::new(secs(10), secs(0)));
+        let session = session.with_tick(secs(4));
+        let mut events = session.subscribe();
+        let game = tokio::spawn(session.run());
+        players.white().submit(mv("e2e4")).await.unwrap();
+        let result = game.await.unwrap().result().unwrap();
+        assert_eq!(result, GameResult::Win(White, WinReason::TimeExpired));
+
+        let mut ticks = 0;
+        let mut finished = None;
+        while let Ok(event) = events.try_recv() {
+            match event {
+                SessionEvent::Clock(_) => ticks += 1,
+                SessionEvent::Finished(result) => finished = Some(result),
+                _ => {},
+            }
+        }
+        assert!(ticks >= 2);
+        assert_eq!(finished, Some(result));
+        drop(players);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_armageddon_clock() {
+        let rules = ArmageddonRules::default();
+        let (session, players) = GameSession::armageddon(EngineBoard::standard(), rules);
+        assert!(session.board().is_armageddon());
+        let session = session.with_tick(secs(1));
+        let mut events = session.subscribe();
+        let game = tokio::spawn(session.run());
+        loop {
+            if let SessionEvent::Clock(remaining) = events.recv().await.unwrap() {
+                assert_eq!(*remaining.black(), secs(240));
+                assert!(*remaining.white() > secs(240));
+                break;
+            }
+        }
+        drop(players);
+        game.await.unwrap();
+    }
+}
@@ -0,0 +1,286 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Broadcasting a live game to spectators. The server keeps a
+//! `SpectateFeed` next to the game: a spectator who starts watching is
+//! sent a `SpectateSnapshot`, then every `SpectateDelta` that follows.
+//! Each message carries a sequence number so a `SpectatorView` can tell
+//! when it missed one and needs a fresh snapshot.
+
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::error::Result;
+use crate::{Color, Move, Pair, PlayerBoard, Position, Turn};
+use super::{GameId, GameResult};
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpectateError {
+    /// Updates were missed; the spectator needs a new snapshot.
+    #[error("Expected spectator update {expected} but received {received}")]
+    OutOfSequence { expected: u64, received: u64 },
+    #[error("Snapshot is for another game")]
+    WrongGame,
+}
+
+/// The whole state of a game, as of update `seq`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SpectateSnapshot {
+    pub game: GameId,
+    pub seq: u64,
+    pub start: Position,
+    pub moves: Vec<Move>,
+    pub clock: Option<Pair<Duration>>,
+    pub result: Option<GameResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum SpectateUpdate {
+    /// A move, with the time the mover has left if the game is timed.
+    Move { mv: Move, remaining: Option<Duration> },
+    /// Corrected clock times, e.g. after a takeback or a periodic tick.
+    Clock(Pair<Duration>),
+    Finished(GameResult),
+}
+
+/// One update, numbered from 1 after the game starts.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct SpectateDelta {
+    pub game: GameId,
+    pub seq: u64,
+    pub update: SpectateUpdate,
+}
+
+/// The server side of a broadcast: records what happens in a game and
+/// numbers the updates. Moves are expected to have been validated by the
+/// game already.
+#[derive(Debug, Clone)]
+pub struct SpectateFeed {
+    snapshot: SpectateSnapshot,
+}
+
+impl SpectateFeed {
+    pub fn new(game: GameId, start: Position, clock: Option<Pair<Duration>>) -> Self {
+        let snapshot = SpectateSnapshot { game, seq: 0, start, moves: Vec::new(), clock, result: None };
+        Self { snapshot }
+    }
+
+    /// Returns the message for a spectator who starts watching now.
+    pub fn snapshot(&self) -> &SpectateSnapshot {
+        &self.snapshot
+    }
+
+    pub fn record_move(&mut self, mv: Move, remaining: Option<Duration>) -> SpectateDelta {
+        self.record(SpectateUpdate::Move { mv, remaining })
+    }
+
+    pub fn record_clock(&mut self, clock: Pair<Duration>) -> SpectateDelta {
+        self.record(SpectateUpdate::Clock(clock))
+    }
+
+    pub fn record_result(&mut self, result: GameResult) -> SpectateDelta {
+        self.record(SpectateUpdate::Finished(result))
+    }
+
+    fn record(&mut self, update: SpectateUpdate) -> SpectateDelta {
+        let snapshot = &mut self.snapshot;
+        match update {
+            SpectateUpdate::Move { mv, remaining } => {
+                // the mover is the side to move before this move
+                let turn = snapshot.start.turn();
+                let mover = if snapshot.moves.len().is_multiple_of(2) { turn } else { !turn };
+                snapshot.moves.push(mv);
+                if let (Some(clock), Some(remaining)) = (&mut snapshot.clock, remaining) {
+                    clock[mover] = remaining;
+                }
+            }
+            SpectateUpdate::Clock(clock) => snapshot.clock = Some(clock),
+            SpectateUpdate::Finished(result) => snapshot.result = Some(result),
+        }
+        snapshot.seq += 1;
+        SpectateDelta { game: snapshot.game, seq: snapshot.seq, update }
+    }
+}
+
+/// The spectator side of a broadcast: a read-only board rebuilt from a
+/// snapshot and kept up to date by applying deltas in order.
+pub struct SpectatorView {
+    game: GameId,
+    seq: u64,
+    board: PlayerBoard,
+    moves: Vec<Move>,
+    clock: Option<Pair<Duration>>,
+    result: Option<GameResult>,
+}
+
+impl SpectatorView {
+    pub fn new(snapshot: &SpectateSnapshot) -> Result<Self> {
+        let mut view = Self {
+            game: snapshot.game,
+            seq: snapshot.seq,
+            board: PlayerBoard::plays_from(Color::White, snapshot.start.clone()),
+            moves: Vec::new(),
+            clock: snapshot.clock,
+            result: snapshot.result,
+        };
+        for &mv in &snapshot.moves {
+            view.play(mv)?;
+        }
+        Ok(view)
+    }
+
+    /// Applies `delta`. Returns false if it was already applied (e.g. it
+    /// arrived again after a snapshot) and fails with
+    /// `SpectateError::OutOfSequence` if earlier updates were missed.
+    pub fn apply(&mut self, delta: &SpectateDelta) -> Result<bool> {
+        if delta.game != self.game {
+            return Err(SpectateError::WrongGame.into());
+        }
+        if delta.seq <= self.seq {
+            return Ok(false);
+        }
+        if delta.seq != self.seq + 1 {
+            let error = SpectateError::OutOfSequence { expected: self.seq + 1, received: delta.seq };
+            return Err(error.into());
+        }
+        match delta.update {
+            SpectateUpdate::Move { mv, remaining } => {
+                let mover = self.board.turn();
+                self.play(mv)?;
+                if let (Some(clock), Some(remaining)) = (&mut self.clock, remaining) {
+                    clock[mover] = remaining;
+                }
+            }
+            SpectateUpdate::Clock(clock) => self.clock = Some(clock),
+            SpectateUpdate::Finished(result) => self.result = Some(result),
+        }
+        self.seq = delta.seq;
+        Ok(true)
+    }
+
+    /// Replaces the view with `snapshot`, e.g. after falling behind.
+    pub fn resync(&mut self, snapshot: &SpectateSnapshot) -> Result<()> {
+        if snapshot.game != self.game {
+            return Err(SpectateError::WrongGame.into());
+        }
+        *self = Self::new(snapshot)?;
+        Ok(())
+    }
+
+    fn play(&mut self, mv: Move) -> Result<()> {
+        match self.board.our_turn() {
+            true => self.board.submit_our_move(mv)?,
+            false => self.board.submit_their_move(mv)?,
+        }
+        self.moves.push(mv);
+        Ok(())
+    }
+
+    pub fn game(&self) -> GameId {
+        self.game
+    }
+
+    /// Returns the sequence number of the last update applied.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Returns the board, seen from White's side. It can be reviewed but
+    /// not played on.
+    pub fn board(&self) -> &PlayerBoard {
+        &self.board
+    }
+
+    pub fn position(&self) -> &Position {
+        self.board.as_ref()
+    }
+
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    pub fn clock(&self) -> Option<Pair<Duration>> {
+        self.clock
+    }
+
+    pub fn result(&self) -> Option<GameResult> {
+        self.result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::*;
+
+    fn mv(uci: &str) -> Move {
+        uci.parse().unwrap()
+    }
+
+    fn secs(white: u64, black: u64) -> Pair<Duration> {
+        Pair::new(Duration::from_secs(white), Duration::from_secs(black))
+    }
+
+    #[test]
+    fn test_spectator_follows_feed() {
+        let game = GameId::new(7);
+        let mut feed = SpectateFeed::new(game, Position::default(), Some(secs(60, 60)));
+        let first = feed.record_move(mv("e2e4"), Some(Duration::from_secs(58)));
+        let mut view = SpectatorView::new(feed.snapshot()).unwrap();
+        assert_eq!(view.seq(), 1);
+        assert_eq!(view.clock(), Some(secs(58, 60)));
+        // a delta already covered by the snapshot is ignored
+        assert_eq!(view.apply(&first), Ok(false));
+
+        let deltas = [
+            feed.record_move(mv("e7e5"), Some(Duration::from_secs(55))),
+            feed.record_clock(secs(50, 55)),
+            feed.record_result(GameResult::Draw(DrawReason::Agreed)),
+        ];
+        for delta in &deltas {
+            let json = serde_json::to_string(delta).unwrap();
+            assert_eq!(view.apply(&serde_json::from_str(&json).unwrap()), Ok(true));
+        }
+        assert_eq!(view.moves(), &[mv("e2e4"), mv("e7e5")]);
+        assert_eq!(view.position().turn(), Color::White);
+        assert_eq!(view.clock(), Some(secs(50, 55)));
+        assert_eq!(view.result(), Some(GameResult::Draw(DrawReason::Agreed)));
+        let fresh = SpectatorView::new(feed.snapshot()).unwrap();
+        assert_eq!(view.position().key(), fresh.position().key());
+        assert_eq!(fresh.seq(), 4);
+    }
+
+    #[test]
+    fn test_spectator_gap_and_resync() {
+        let game = GameId::new(1);
+        let mut feed = SpectateFeed::new(game, Position::default(), None);
+        let mut view = SpectatorView::new(feed.snapshot()).unwrap();
+        feed.record_move(mv("d2d4"), None);
+        let missed = feed.record_move(mv("d7d5"), None);
+        let error = SpectateError::OutOfSequence { expected: 1, received: 2 };
+        assert_eq!(view.apply(&missed), Err(error.into()));
+        view.resync(feed.snapshot()).unwrap();
+        assert_eq!(view.moves().len(), 2);
+        assert_eq!(view.clock(), None);
+
+        let other = SpectateFeed::new(GameId::new(2), Position::default(), None);
+        assert_eq!(view.resync(other.snapshot()), Err(SpectateError::WrongGame.into()));
+        // an illegal move is reported rather than skipped
+        let mut feed = SpectateFeed::new(game, Position::default(), None);
+        let mut view = SpectatorView::new(feed.snapshot()).unwrap();
+        assert!(view.apply(&feed.record_move(mv("e2e5"), None)).is_err());
+        assert_eq!(view.seq(), 0);
+    }
+}
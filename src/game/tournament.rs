@@ -0,0 +1,511 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Round-robin and Swiss tournaments. Players can be any cloneable key
+//! type (an id, a name, ...); the tournament pairs them round by round,
+//! collects the results of the games by `GameId` and ranks the players.
+
+use std::collections::HashSet;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::Color;
+use super::{GameId, GameResult, Score};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TournamentFormat {
+    /// Everyone plays everyone else once.
+    RoundRobin,
+    /// Players with similar scores are paired against each other for a
+    /// fixed number of rounds, never meeting twice if it can be avoided.
+    Swiss { rounds: u16 },
+}
+
+/// A game of a round. The `result` is filled in by `Tournament::record`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Pairing<P> {
+    pub game: GameId,
+    pub white: P,
+    pub black: P,
+    pub result: Option<GameResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Round<P> {
+    pub pairings: Vec<Pairing<P>>,
+    /// The player sitting out this round, if the number of players is
+    /// odd. In a Swiss tournament a bye scores a point.
+    pub bye: Option<P>,
+}
+
+impl<P> Round<P> {
+    pub fn is_complete(&self) -> bool {
+        self.pairings.iter().all(|pairing| pairing.result.is_some())
+    }
+}
+
+/// A player's line in the standings.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Standing<P> {
+    pub player: P,
+    pub score: Score,
+    pub byes: u16,
+    /// Total score in half-points, including byes.
+    pub half_points: u32,
+    /// Sum of the opponents' half-points, the first tiebreak.
+    pub buchholz: u32,
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TournamentError {
+    #[error("A tournament needs at least two players")]
+    TooFewPlayers,
+    #[error("The current round has unfinished games")]
+    RoundInProgress,
+    #[error("All rounds have been played")]
+    Finished,
+    #[error("No game {0:?} in this tournament")]
+    UnknownGame(GameId),
+    #[error("The result of game {0:?} has already been recorded")]
+    AlreadyRecorded(GameId),
+    #[error("A pairing names a player who isn't in the tournament")]
+    UnknownPlayer,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Tournament<P> {
+    format: TournamentFormat,
+    players: Vec<P>,
+    rounds: Vec<Round<P>>,
+}
+
+impl<P: Clone + PartialEq> Tournament<P> {
+    /// Creates a tournament. The order of `players` is their seeding.
+    pub fn new(format: TournamentFormat, players: Vec<P>) -> Result<Self, TournamentError> {
+        if players.len() < 2 {
+            return Err(TournamentError::TooFewPlayers);
+        }
+        Ok(Self { format, players, rounds: Vec::new() })
+    }
+
+    pub fn format(&self) -> TournamentFormat {
+        self.format
+    }
+
+    pub fn players(&self) -> &[P] {
+        &self.players
+    }
+
+    pub fn rounds(&self) -> &[Round<P>] {
+        &self.rounds
+    }
+
+    /// Returns the number of rounds the tournament lasts.
+    pub fn total_rounds(&self) -> usize {
+        match self.format {
+            TournamentFormat::RoundRobin => self.players.len() + self.players.len() % 2 - 1,
+            TournamentFormat::Swiss { rounds } => rounds as usize,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.rounds.len() == self.total_rounds()
+            && self.rounds.iter().all(Round::is_complete)
+    }
+
+    /// Pairs the next round once every game of the current one has a
+    /// result.
+    pub fn pair_next_round(&mut self) -> Result<&Round<P>, TournamentError> {
+        self.pair_next_round_with(&mut thread_rng())
+    }
+
+    /// Like `pair_next_round`, drawing the game ids from `rng` so a seeded
+    /// tournament is reproducible.
+    pub fn pair_next_round_with<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Result<&Round<P>, TournamentError> {
+        if !self.rounds.iter().all(Round::is_complete) {
+            return Err(TournamentError::RoundInProgress);
+        }
+        if self.rounds.len() >= self.total_rounds() {
+            return Err(TournamentError::Finished);
+        }
+        let (pairs, bye) = match self.format {
+            TournamentFormat::RoundRobin => self.round_robin_pairs(),
+            TournamentFormat::Swiss { .. } => self.swiss_pairs()?,
+        };
+        let pairings = pairs.into_iter()
+            .map(|(white, black)| Pairing {
+                game: GameId::random_with(rng),
+                white: self.players[white].clone(),
+                black: self.players[black].clone(),
+                result: None,
+            })
+            .collect();
+        let bye = bye.map(|index| self.players[index].clone());
+        self.rounds.push(Round { pairings, bye });
+        Ok(self.rounds.last().unwrap())
+    }
+
+    /// Records the result of `game`.
+    pub fn record(&mut self, game: GameId, result: GameResult) -> Result<(), TournamentError> {
+        let pairing = self.rounds.iter_mut()
+            .flat_map(|round| round.pairings.iter_mut())
+            .find(|pairing| pairing.game == game)
+            .ok_or(TournamentError::UnknownGame(game))?;
+        if pairing.result.is_some() {
+            return Err(TournamentError::AlreadyRecorded(game));
+        }
+        pairing.result = Some(result);
+        Ok(())
+    }
+
+    /// Returns the players ranked by score, then Buchholz, then seeding.
+    pub fn standings(&self) -> Result<Vec<Standing<P>>, TournamentError> {
+        let games = self.games()?;
+        let half_points: Vec<u32> = (0..self.players.len())
+            .map(|index| self.half_points(index, &games))
+            .collect();
+        let mut standings: Vec<(usize, Standing<P>)> = (0..self.players.len())
+            .map(|index| {
+                let mut score = Score::default();
+                let mut buchholz = 0;
+                for (opponent, result) in games_of(index, &games) {
+                    buchholz += half_points[opponent];
+                    match result {
+                        Some(true) => score.wins += 1,
+                        Some(false) => score.losses += 1,
+                        None => score.draws += 1,
+                    }
+                }
+                let standing = Standing {
+                    player: self.players[index].clone(),
+                    score,
+                    byes: self.byes(index) as u16,
+                    half_points: half_points[index],
+                    buchholz,
+                };
+                (index, standing)
+            })
+            .collect();
+        standings.sort_by(|(a, x), (b, y)| {
+            y.half_points.cmp(&x.half_points)
+                .then(y.buchholz.cmp(&x.buchholz))
+                .then(a.cmp(b))
+        });
+        Ok(standings.into_iter().map(|(_, standing)| standing).collect())
+    }
+
+    fn index(&self, player: &P) -> Result<usize, TournamentError> {
+        self.players.iter()
+            .position(|p| p == player)
+            .ok_or(TournamentError::UnknownPlayer)
+    }
+
+    /// Returns every game paired so far by the players' indices.
+    fn games(&self) -> Result<Vec<IndexedGame>, TournamentError> {
+        self.rounds.iter()
+            .flat_map(|round| round.pairings.iter())
+            .map(|pairing| Ok(IndexedGame {
+                white: self.index(&pairing.white)?,
+                black: self.index(&pairing.black)?,
+                result: pairing.result,
+            }))
+            .collect()
+    }
+
+    fn byes(&self, index: usize) -> usize {
+        self.rounds.iter()
+            .filter(|round| round.bye.as_ref() == Some(&self.players[index]))
+            .count()
+    }
+
+    fn half_points(&self, index: usize, games: &[IndexedGame]) -> u32 {
+        let games: u32 = games_of(index, games)
+            .map(|(_, result)| match result {
+                Some(true) => 2,
+                Some(false) => 0,
+                None => 1,
+            })
+            .sum();
+        match self.format {
+            TournamentFormat::Swiss { .. } => games + 2 * self.byes(index) as u32,
+            TournamentFormat::RoundRobin => games,
+        }
+    }
+
+    /// Pairs by the circle method: the first player stays put while the
+    /// others rotate, with a dummy entry for the bye if needed.
+    fn round_robin_pairs(&self) -> RoundPairs {
+        let round = self.rounds.len();
+        let count = self.players.len() + self.players.len() % 2;
+        let slot = |position: usize| match position {
+            0 => 0,
+            _ => (round + position - 1) % (count - 1) + 1,
+        };
+        let mut pairs = Vec::new();
+        let mut bye = None;
+        for position in 0..count / 2 {
+            let (a, b) = (slot(position), slot(count - 1 - position));
+            // alternate the fixed player's color from round to round
+            let (white, black) = if position == 0 && round % 2 == 1 { (b, a) } else { (a, b) };
+            match (white < self.players.len(), black < self.players.len()) {
+                (true, true) => pairs.push((white, black)),
+                (true, false) => bye = Some(white),
+                (false, _) => bye = Some(black),
+            }
+        }
+        (pairs, bye)
+    }
+
+    /// Pairs players in order of score, each with the highest placed
+    /// opponent they haven't met yet that still lets everyone else be
+    /// paired. Rematches are only allowed if there's no other way. The
+    /// lowest placed player without a bye sits out if the number is odd.
+    fn swiss_pairs(&self) -> Result<RoundPairs, TournamentError> {
+        let games = self.games()?;
+        let mut order: Vec<usize> = (0..self.players.len()).collect();
+        let half_points: Vec<u32> = order.iter().map(|&index| self.half_points(index, &games)).collect();
+        order.sort_by(|a, b| half_points[*b].cmp(&half_points[*a]).then(a.cmp(b)));
+
+        let mut bye = None;
+        if order.len() % 2 == 1 {
+            let position = order.iter()
+                .rposition(|&index| self.byes(index) == 0)
+                .unwrap_or(order.len() - 1);
+            bye = Some(order.remove(position));
+        }
+
+        let met: HashSet<(usize, usize)> = games.iter()
+            .flat_map(|game| [(game.white, game.black), (game.black, game.white)])
+            .collect();
+        let unmet = |a, b| !met.contains(&(a, b));
+        let mut steps = PAIRING_STEPS;
+        let pairs = pair_without_rematches(&order, &unmet, &mut steps)
+            .unwrap_or_else(|| pair_in_order(&order, &unmet));
+        let pairs = pairs.into_iter()
+            .map(|(a, b)| if self.white_games(b) < self.white_games(a) { (b, a) } else { (a, b) })
+            .collect();
+        Ok((pairs, bye))
+    }
+
+    fn white_games(&self, index: usize) -> usize {
+        self.rounds.iter()
+            .flat_map(|round| round.pairings.iter())
+            .filter(|pairing| pairing.white == self.players[index])
+            .count()
+    }
+}
+
+/// The white and black players of each game of a round and the player
+/// with a bye, as indices into `Tournament::players`.
+type RoundPairs = (Vec<(usize, usize)>, Option<usize>);
+
+/// A paired game with the players as indices into `Tournament::players`.
+struct IndexedGame {
+    white: usize,
+    black: usize,
+    result: Option<GameResult>,
+}
+
+/// Returns the opponents of the player at `index` in finished games,
+/// with `Some(won)` or `None` for a draw.
+fn games_of(index: usize, games: &[IndexedGame]) -> impl Iterator<Item = (usize, Option<bool>)> + '_ {
+    games.iter().filter_map(move |game| {
+        let (color, opponent) = if game.white == index {
+            (Color::White, game.black)
+        } else if game.black == index {
+            (Color::Black, game.white)
+        } else {
+            return None;
+        };
+        match game.result? {
+            GameResult::Win(winner, _) => Some((opponent, Some(winner == color))),
+            GameResult::Draw(_) => Some((opponent, None)),
+        }
+    })
+}
+
+/// How many candidate pairs `pair_without_rematches` may try before
+/// giving up, which keeps large fields with many rematches from
+/// backtracking forever.
+const PAIRING_STEPS: usize = 10_000;
+
+/// Pairs off `players` in order with `allowed` opponents, backtracking
+/// when the remaining players can't all be paired. Returns `None` if
+/// there's no such pairing or `steps` run out first.
+fn pair_without_rematches(players: &[usize], allowed: &dyn Fn(usize, usize) -> bool, steps: &mut usize) -> Option<Vec<(usize, usize)>> {
+    let Some((&first, rest)) = players.split_first() else {
+        return Some(Vec::new());
+    };
+    for (position, &opponent) in rest.iter().enumerate() {
+        if !allowed(first, opponent) {
+            continue;
+        }
+        *steps = steps.checked_sub(1)?;
+        let mut remaining = rest.to_vec();
+        remaining.remove(position);
+        if let Some(mut pairs) = pair_without_rematches(&remaining, allowed, steps) {
+            pairs.insert(0, (first, opponent));
+            return Some(pairs);
+        }
+    }
+    None
+}
+
+/// Pairs off `players` in order without backtracking, each with the
+/// first remaining `allowed` opponent or else the next player.
+fn pair_in_order(players: &[usize], allowed: &dyn Fn(usize, usize) -> bool) -> Vec<(usize, usize)> {
+    let mut remaining = players.to_vec();
+    let mut pairs = Vec::new();
+    while remaining.len() >= 2 {
+        let first = remaining.remove(0);
+        let position = remaining.iter()
+            .position(|&opponent| allowed(first, opponent))
+            .unwrap_or(0);
+        pairs.push((first, remaining.remove(position)));
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use std::collections::HashSet;
+
+    fn play_round(tournament: &mut Tournament<&'static str>, result: impl Fn(&Pairing<&str>) -> GameResult) {
+        let round = tournament.pair_next_round().unwrap().clone();
+        for pairing in &round.pairings {
+            tournament.record(pairing.game, result(pairing)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_seeded_pairings() {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+        let pair = || {
+            let mut tournament = Tournament::new(TournamentFormat::RoundRobin, vec!["a", "b", "c", "d"]).unwrap();
+            let games: Vec<GameId> = tournament.pair_next_round_with(&mut StdRng::seed_from_u64(9)).unwrap()
+                .pairings.iter().map(|pairing| pairing.game).collect();
+            games
+        };
+        assert_eq!(pair(), pair());
+        assert_eq!(GameId::random_with(&mut StdRng::seed_from_u64(1)), GameId::random_with(&mut StdRng::seed_from_u64(1)));
+    }
+    #[test]
+    fn test_round_robin_pairs_everyone_once() {
+        let players = vec!["a", "b", "c", "d", "e"];
+        let mut tournament = Tournament::new(TournamentFormat::RoundRobin, players).unwrap();
+        assert_eq!(tournament.total_rounds(), 5);
+        let mut met = HashSet::new();
+        let mut byes = HashSet::new();
+        while !tournament.is_finished() {
+            play_round(&mut tournament, |_| GameResult::Draw(DrawReason::Agreed));
+            let round = tournament.rounds().last().unwrap();
+            assert_eq!(round.pairings.len(), 2);
+            assert!(byes.insert(round.bye.unwrap()));
+            for pairing in &round.pairings {
+                let mut key = [pairing.white, pairing.black];
+                key.sort();
+                assert!(met.insert(key));
+            }
+        }
+        assert_eq!(met.len(), 10);
+        assert_eq!(tournament.pair_next_round().err(), Some(TournamentError::Finished));
+        assert!(tournament.standings().unwrap().iter().all(|standing| standing.half_points == 4));
+    }
+
+    #[test]
+    fn test_swiss_pairs_by_score_and_ranks_by_buchholz() {
+        let players = vec!["a", "b", "c", "d"];
+        let mut tournament = Tournament::new(TournamentFormat::Swiss { rounds: 3 }, players).unwrap();
+        let white_wins = |_: &Pairing<&str>| GameResult::Win(Color::White, WinReason::CheckMate);
+        play_round(&mut tournament, white_wins);
+        assert_eq!(tournament.pair_next_round().err(), None);
+        // the winners of the first round meet in the second
+        let round = tournament.rounds().last().unwrap().clone();
+        let winners: HashSet<&str> = tournament.rounds()[0].pairings.iter().map(|p| p.white).collect();
+        let top = round.pairings.iter()
+            .find(|pairing| winners.contains(pairing.white))
+            .unwrap();
+        assert!(winners.contains(top.black));
+        assert_eq!(tournament.record(top.game, GameResult::Draw(DrawReason::Agreed)), Ok(()));
+        assert_eq!(
+            tournament.record(top.game, GameResult::Draw(DrawReason::Agreed)),
+            Err(TournamentError::AlreadyRecorded(top.game)),
+        );
+        assert_eq!(tournament.pair_next_round().err(), Some(TournamentError::RoundInProgress));
+        let bottom = round.pairings.iter().find(|pairing| pairing.game != top.game).unwrap();
+        tournament.record(bottom.game, white_wins(bottom)).unwrap();
+
+        let round = tournament.pair_next_round().unwrap().clone();
+        let met: HashSet<[&str; 2]> = tournament.rounds()[..2].iter()
+            .flat_map(|round| round.pairings.iter())
+            .map(|pairing| { let mut key = [pairing.white, pairing.black]; key.sort(); key })
+            .collect();
+        for pairing in &round.pairings {
+            let mut key = [pairing.white, pairing.black];
+            key.sort();
+            assert!(!met.contains(&key));
+            tournament.record(pairing.game, GameResult::Draw(DrawReason::Agreed)).unwrap();
+        }
+        assert!(tournament.is_finished());
+
+        let standings = tournament.standings().unwrap();
+        assert_eq!(standings[0].half_points, 4);
+        assert_eq!(standings[1].half_points, 4);
+        assert!(standings[0].buchholz >= standings[1].buchholz);
+        assert_eq!(standings[3].half_points, 1);
+    }
+
+    #[test]
+    fn test_swiss_bye_scores_a_point() {
+        let players = vec!["a", "b", "c"];
+        let mut tournament = Tournament::new(TournamentFormat::Swiss { rounds: 2 }, players).unwrap();
+        play_round(&mut tournament, |_| GameResult::Draw(DrawReason::Agreed));
+        let bye = tournament.rounds()[0].bye.unwrap();
+        let standing = tournament.standings().unwrap().into_iter().find(|s| s.player == bye).unwrap();
+        assert_eq!((standing.byes, standing.half_points), (1, 2));
+        play_round(&mut tournament, |_| GameResult::Draw(DrawReason::Agreed));
+        assert_ne!(tournament.rounds()[1].bye, Some(bye));
+        assert_eq!(
+            tournament.record(GameId::new(0), GameResult::Draw(DrawReason::Agreed)),
+            Err(TournamentError::UnknownGame(GameId::new(0))),
+        );
+        assert!(Tournament::new(TournamentFormat::RoundRobin, vec!["a"]).is_err());
+    }
+
+    #[test]
+    fn test_swiss_pairing_is_bounded() {
+        // the last player has met everyone, which no longer sends the
+        // search through every way of pairing the others
+        let players: Vec<usize> = (0..40).collect();
+        let unmet = |a, b| a != 39 && b != 39;
+        let mut steps = PAIRING_STEPS;
+        assert_eq!(pair_without_rematches(&players, &unmet, &mut steps), None);
+        assert_eq!(steps, 0);
+        let pairs = pair_in_order(&players, &unmet);
+        assert_eq!(pairs.len(), 20);
+        assert_eq!(pairs[19], (38, 39));
+
+        let mut tournament = Tournament::new(TournamentFormat::Swiss { rounds: 30 }, vec!["a", "b", "c", "d", "e", "f"]).unwrap();
+        while !tournament.is_finished() {
+            play_round(&mut tournament, |_| GameResult::Draw(DrawReason::Agreed));
+            assert_eq!(tournament.rounds().last().unwrap().pairings.len(), 3);
+        }
+        tournament.rounds[0].pairings[0].white = "z";
+        assert_eq!(tournament.standings().err(), Some(TournamentError::UnknownPlayer));
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! The monthly game archives of the Chess.com published-data API
+//! (`/pub/player/{username}/games/{YYYY}/{MM}`). Each game carries its
+//! PGN, which holds the moves and `[%clk]` readings; the plain PGN form of
+//! an archive (`.../{MM}/pgn`) can be read with `PgnGame::parse_all`.
+
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::{
+    Color, CorrespondenceControl, DrawReason, EngineBoard, GameResult, PgnGame, TimeControl,
+    WinReason,
+};
+use super::InteropError;
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct Archive {
+    pub games: Vec<ArchivedGame>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct ArchivedGame {
+    pub url: String,
+    /// Missing for some games that never started.
+    pub pgn: Option<String>,
+    /// `<seconds>` or `<seconds>+<increment>`, or `1/<seconds per move>`
+    /// for daily games.
+    pub time_control: String,
+    pub end_time: Option<u64>,
+    #[serde(default)]
+    pub rated: bool,
+    /// `chess` or `chess960` for the variants this crate plays.
+    pub rules: String,
+    pub white: ArchivedPlayer,
+    pub black: ArchivedPlayer,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct ArchivedPlayer {
+    pub username: String,
+    pub rating: Option<u32>,
+    /// How the game ended for this player, e.g. `win`, `checkmated`,
+    /// `timeout` or `agreed`.
+    pub result: String,
+}
+
+impl ArchivedGame {
+    /// Returns the clock's time control, or `None` for daily games.
+    pub fn time_control(&self) -> Option<TimeControl> {
+        if self.time_control.contains('/') {
+            return None;
+        }
+        let (initial, increment) = self.time_control.split_once('+').unwrap_or((&self.time_control, "0"));
+        let secs = |text: &str| text.parse().ok().map(Duration::from_secs);
+        Some(TimeControl::new(secs(initial)?, secs(increment)?))
+    }
+
+    /// Returns the time per move of a daily game.
+    pub fn correspondence(&self) -> Option<CorrespondenceControl> {
+        let per_move = self.time_control.strip_prefix("1/")?.parse().ok()?;
+        Some(CorrespondenceControl::new(Duration::from_secs(per_move), Duration::ZERO))
+    }
+
+    /// Returns the result from the players' results, or `None` if they
+    /// aren't understood.
+    pub fn result(&self) -> Option<GameResult> {
+        let win = |winner: Color, loser: &ArchivedPlayer| {
+            let reason = match loser.result.as_str() {
+                "checkmated" => WinReason::CheckMate,
+                "resigned" => WinReason::Resigned,
+                "timeout" => WinReason::TimeExpired,
+                "abandoned" => WinReason::Abandoned,
+                _ => WinReason::Adjudicated,
+            };
+            GameResult::Win(winner, reason)
+        };
+        let draw = |reason: &str| match reason {
+            "agreed" => Some(DrawReason::Agreed),
+            "repetition" => Some(DrawReason::Repetition),
+            "stalemate" => Some(DrawReason::StaleMate),
+            "insufficient" => Some(DrawReason::Insufficient),
+            "50move" => Some(DrawReason::FiftyMoves),
+            "timevsinsufficient" => Some(DrawReason::TimeoutVsInsufficient),
+            _ => None,
+        };
+        match (self.white.result.as_str(), self.black.result.as_str()) {
+            ("win", _) => Some(win(Color::White, &self.black)),
+            (_, "win") => Some(win(Color::Black, &self.white)),
+            (reason, _) => draw(reason).map(GameResult::Draw),
+        }
+    }
+
+    /// Reads the game's PGN into a record. Fails for variants other than
+    /// standard chess and Chess960.
+    pub fn record(&self) -> Result<PgnGame, InteropError> {
+        if self.rules != "chess" && self.rules != "chess960" {
+            return Err(InteropError::UnsupportedVariant(self.rules.clone()));
+        }
+        Ok(self.pgn.as_deref().unwrap_or_default().parse()?)
+    }
+
+    /// Replays the game on a new board.
+    pub fn board(&self) -> Result<EngineBoard, InteropError> {
+        let record = self.record()?;
+        Ok(EngineBoard::replay(Some(record.backrank_id()?), &record.moves)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BoardResult, MoveId};
+
+    const ARCHIVE: &str = r#"{"games": [{
+        "url": "https://www.chess.com/game/live/1",
+        "pgn": "[Event \"Live Chess\"]\n[Site \"Chess.com\"]\n[White \"alice\"]\n[Black \"bob\"]\n[Result \"0-1\"]\n[TimeControl \"180+2\"]\n\n1. f3 {[%clk 0:03:01.1]} 1... e5 {[%clk 0:03:00.6]} 2. g4 {[%clk 0:02:59]} 2... Qh4# {[%clk 0:02:58.2]} 0-1\n",
+        "time_control": "180+2", "end_time": 1696111111, "rated": true,
+        "tcn": "gvZR", "uuid": "x", "initial_setup": "", "fen": "", "time_class": "blitz",
+        "rules": "chess",
+        "white": {"rating": 1200, "result": "checkmated", "@id": "u", "username": "alice", "uuid": "a"},
+        "black": {"rating": 1250, "result": "win", "@id": "v", "username": "bob", "uuid": "b"}
+    }, {
+        "url": "https://www.chess.com/game/daily/2",
+        "time_control": "1/86400", "rules": "chess",
+        "white": {"username": "carol", "result": "agreed"},
+        "black": {"username": "dave", "result": "agreed"}
+    }]}"#;
+
+    #[test]
+    fn test_archive() {
+        let archive: Archive = serde_json::from_str(ARCHIVE).unwrap();
+        let [live, daily] = &archive.games[..] else { panic!("expected two games") };
+
+        assert_eq!(live.time_control(), Some(TimeControl::new(Duration::from_secs(180), Duration::from_secs(2))));
+        assert_eq!(live.correspondence(), None);
+        assert_eq!(live.result(), Some(GameResult::Win(Color::Black, WinReason::CheckMate)));
+        let record = live.record().unwrap();
+        assert_eq!(record.tag("White"), Some("alice"));
+        assert_eq!(record.clock(&MoveId::from_ply(4).unwrap()), Some(Duration::from_millis(178_200)));
+        let board = live.board().unwrap();
+        assert_eq!(board.board_result(), Some(BoardResult::CheckMate(Color::Black)));
+
+        assert_eq!(daily.time_control(), None);
+        assert_eq!(daily.correspondence(), Some(CorrespondenceControl::days(1)));
+        assert_eq!(daily.result(), Some(GameResult::Draw(DrawReason::Agreed)));
+        assert!(daily.record().unwrap().moves.is_empty());
+    }
+
+    #[test]
+    fn test_unsupported_rules() {
+        let mut archive: Archive = serde_json::from_str(ARCHIVE).unwrap();
+        let game = &mut archive.games[0];
+        game.rules = "bughouse".to_string();
+        assert_eq!(game.record(), Err(InteropError::UnsupportedVariant("bughouse".to_string())));
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! The events of a Lichess Board or Bot API game stream
+//! (`/api/board/game/stream/{gameId}`), sent as one JSON object per line.
+//! Times are in milliseconds and moves in UCI.
+
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::{
+    BackRankId, Color, DrawReason, EngineBoard, GameResult, Move, Pair, ParseError, PgnGame,
+    PgnError, Position, TimeControl, WinReason,
+};
+use super::InteropError;
+
+/// One line of a game stream.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum GameEvent {
+    /// Sent first, with the full game and its current state.
+    GameFull(Box<GameFull>),
+    /// Sent after every move and on any other change of state.
+    GameState(GameState),
+    ChatLine(ChatLine),
+    OpponentGone(OpponentGone),
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct GameFull {
+    pub id: String,
+    #[serde(default)]
+    pub rated: bool,
+    pub variant: Variant,
+    /// Missing for correspondence and unlimited games.
+    pub clock: Option<ClockSettings>,
+    #[serde(default)]
+    pub speed: String,
+    pub white: Player,
+    pub black: Player,
+    /// `startpos` or a FEN.
+    #[serde(default = "start_position")]
+    pub initial_fen: String,
+    pub state: GameState,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct Variant {
+    /// E.g. `standard`, `chess960` or `fromPosition`.
+    pub key: String,
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSettings {
+    pub initial: u64,
+    pub increment: u64,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Player {
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub title: Option<String>,
+    pub rating: Option<u32>,
+    /// Set instead of a name for the Lichess AI.
+    pub ai_level: Option<u8>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct GameState {
+    /// Every move of the game so far, in UCI and separated by spaces.
+    pub moves: String,
+    pub wtime: u64,
+    pub btime: u64,
+    pub winc: u64,
+    pub binc: u64,
+    /// E.g. `started`, `mate`, `resign`, `outoftime` or `draw`.
+    pub status: String,
+    /// `white` or `black`, once the game is won.
+    pub winner: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct ChatLine {
+    pub username: String,
+    pub text: String,
+    pub room: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct OpponentGone {
+    pub gone: bool,
+    pub claim_win_in_seconds: Option<u64>,
+}
+
+fn start_position() -> String {
+    "startpos".to_string()
+}
+
+impl GameState {
+    pub fn moves(&self) -> Result<Vec<Move>, ParseError> {
+        self.moves.split_whitespace().map(str::parse).collect()
+    }
+
+    /// Returns each side's time left as of this event.
+    pub fn remaining(&self) -> Pair<Duration> {
+        Pair::new(Duration::from_millis(self.wtime), Duration::from_millis(self.btime))
+    }
+
+    /// Returns the result of the game, or `None` while it's running or if
+    /// it was aborted.
+    pub fn result(&self) -> Option<GameResult> {
+        let winner = match self.winner.as_deref() {
+            Some("white") => Some(Color::White),
+            Some("black") => Some(Color::Black),
+            _ => None,
+        };
+        let result = match (self.status.as_str(), winner) {
+            ("mate", Some(winner)) => GameResult::Win(winner, WinReason::CheckMate),
+            ("resign", Some(winner)) => GameResult::Win(winner, WinReason::Resigned),
+            ("outoftime", Some(winner)) => GameResult::Win(winner, WinReason::TimeExpired),
+            ("outoftime", None) => GameResult::Draw(DrawReason::TimeoutVsInsufficient),
+            // Lichess reports a player who left the game as a timeout
+            ("timeout", Some(winner)) => GameResult::Win(winner, WinReason::Abandoned),
+            ("variantEnd", Some(winner)) => GameResult::Win(winner, WinReason::VariantWin),
+            ("stalemate", _) => GameResult::Draw(DrawReason::StaleMate),
+            ("draw", _) => GameResult::Draw(DrawReason::Agreed),
+            (_, Some(winner)) => GameResult::Win(winner, WinReason::Adjudicated),
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+impl GameFull {
+    /// Returns the back rank the game starts from. Fails for variants
+    /// other than standard and Chess960, and for set-up positions that
+    /// aren't a Chess960 start.
+    pub fn backrank_id(&self) -> Result<BackRankId, InteropError> {
+        if !["standard", "chess960", "fromPosition"].contains(&self.variant.key.as_str()) {
+            return Err(InteropError::UnsupportedVariant(self.variant.key.clone()));
+        }
+        if self.initial_fen == "startpos" {
+            return Ok(BackRankId::default());
+        }
+        Position::starting_id(&self.initial_fen)
+            .ok_or_else(|| PgnError::UnsupportedFen(self.initial_fen.clone()).into())
+    }
+
+    /// Returns the clock's time control, unless the game is unlimited or
+    /// correspondence.
+    pub fn time_control(&self) -> Option<TimeControl> {
+        self.clock.map(|clock| {
+            TimeControl::new(Duration::from_millis(clock.initial), Duration::from_millis(clock.increment))
+        })
+    }
+
+    /// Replays the game so far on a new board.
+    pub fn board(&self) -> Result<EngineBoard, InteropError> {
+        Ok(EngineBoard::replay(Some(self.backrank_id()?), &self.state.moves()?)?)
+    }
+
+    /// Returns a record of the game so far, tagged with the players and
+    /// the result if the game has ended.
+    pub fn record(&self) -> Result<PgnGame, InteropError> {
+        let mut game = PgnGame::new(self.backrank_id()?, self.state.moves()?);
+        game.set_tag("Site", &format!("https://lichess.org/{}", self.id));
+        for (color, player) in [("White", &self.white), ("Black", &self.black)] {
+            let name = match (&player.name, player.ai_level) {
+                (Some(name), _) => name.clone(),
+                (None, Some(level)) => format!("Lichess AI level {}", level),
+                (None, None) => "?".to_string(),
+            };
+            game.set_tag(color, &name);
+            if let Some(rating) = player.rating {
+                game.set_tag(&format!("{}Elo", color), &rating.to_string());
+            }
+        }
+        let result = self.state.result().map_or("*", |result| result.to_pgn());
+        game.set_tag("Result", result);
+        Ok(game)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAME_FULL: &str = r#"{
+        "type": "gameFull", "id": "5IrD6Gzz", "rated": true,
+        "variant": {"key": "standard", "name": "Standard", "short": "Std"},
+        "clock": {"initial": 300000, "increment": 3000},
+        "speed": "blitz", "perf": {"name": "Blitz"}, "createdAt": 1523825103562,
+        "white": {"id": "lovlas", "name": "lovlas", "title": "IM", "rating": 2500},
+        "black": {"id": null, "aiLevel": 3},
+        "initialFen": "startpos",
+        "state": {"type": "gameState", "moves": "e2e4 c7c5 f2f4 d7d6", "wtime": 290000,
+                  "btime": 300000, "winc": 3000, "binc": 3000, "status": "started"}
+    }"#;
+
+    #[test]
+    fn test_game_full() {
+        let event: GameEvent = serde_json::from_str(GAME_FULL).unwrap();
+        let GameEvent::GameFull(game) = event else { panic!("unexpected event {event:?}") };
+        assert_eq!(game.time_control(), Some(TimeControl::new(Duration::from_secs(300), Duration::from_secs(3))));
+        assert_eq!(game.state.remaining(), Pair::new(Duration::from_secs(290), Duration::from_secs(300)));
+        assert_eq!(game.state.result(), None);
+        assert_eq!(game.board().unwrap().plies(), 4);
+
+        let record = game.record().unwrap();
+        assert_eq!(record.tag("White"), Some("lovlas"));
+        assert_eq!(record.tag("WhiteElo"), Some("2500"));
+        assert_eq!(record.tag("Black"), Some("Lichess AI level 3"));
+        assert_eq!(record.tag("Result"), Some("*"));
+        assert_eq!(record.moves.len(), 4);
+    }
+
+    #[test]
+    fn test_stream_events() {
+        let lines = [
+            r#"{"type":"gameState","moves":"e2e4 e7e5 d1h5 b8c6 f1c4 g8f6 h5f7","wtime":1,"btime":2,"winc":0,"binc":0,"status":"mate","winner":"white"}"#,
+            r#"{"type":"chatLine","username":"thibault","text":"gg","room":"player"}"#,
+            r#"{"type":"opponentGone","gone":true,"claimWinInSeconds":8}"#,
+        ];
+        let events: Vec<GameEvent> = lines.iter().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let GameEvent::GameState(state) = &events[0] else { panic!("unexpected event") };
+        assert_eq!(state.result(), Some(GameResult::Win(Color::White, WinReason::CheckMate)));
+        assert_eq!(state.moves().unwrap().len(), 7);
+        assert!(matches!(&events[1], GameEvent::ChatLine(chat) if chat.text == "gg"));
+        assert_eq!(events[2], GameEvent::OpponentGone(OpponentGone { gone: true, claim_win_in_seconds: Some(8) }));
+    }
+
+    #[test]
+    fn test_unsupported_games() {
+        let mut game = match serde_json::from_str(GAME_FULL).unwrap() {
+            GameEvent::GameFull(game) => game,
+            event => panic!("unexpected event {event:?}"),
+        };
+        game.variant.key = "crazyhouse".to_string();
+        assert_eq!(game.backrank_id(), Err(InteropError::UnsupportedVariant("crazyhouse".to_string())));
+        game.variant.key = "chess960".to_string();
+        game.initial_fen = Position::starting(BackRankId::try_from(7usize).unwrap()).to_fen();
+        assert_eq!(game.backrank_id(), Ok(BackRankId::try_from(7usize).unwrap()));
+        game.state.moves = "e2e4 e7e9".to_string();
+        assert!(matches!(game.board(), Err(InteropError::Parse(_))));
+    }
+}
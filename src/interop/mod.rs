@@ -0,0 +1,41 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Types for the JSON served by online chess sites, with converters into
+//! this crate's `Move` lists, `PgnGame` records and `GameResult`s, so
+//! bots and importers can ingest external games directly. The types only
+//! derive `Deserialize`; reading the JSON is left to the caller (e.g.
+//! with `serde_json`).
+//!
+//! * `lichess`: the events of the Board and Bot API game streams.
+//! * `chess_com`: the monthly game archives of the published-data API.
+
+use thiserror::Error;
+
+use crate::{ParseError, PgnError, ReplayError};
+
+pub mod chess_com;
+pub mod lichess;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InteropError {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    Pgn(#[from] PgnError),
+    #[error(transparent)]
+    Replay(#[from] ReplayError),
+    #[error("Unsupported variant: {0:?}")]
+    UnsupportedVariant(String),
+}
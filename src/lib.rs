@@ -13,8 +13,13 @@
 //    limitations under the License.
 
 mod board;
+mod error;
 mod game;
+pub mod interop;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 pub use board::*;
+pub use error::*;
 pub use game::*;
 
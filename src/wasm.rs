@@ -0,0 +1,170 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! JavaScript bindings for browser clients (enabled by the `wasm` feature)
+//!
+//! The bindings wrap a `PlayerBoard` so that web UIs run exactly the same
+//! rules as the server. Squares are exchanged as names (`"e4"`) and moves
+//! in UCI notation (`"e2e4"`, `"e7e8q"`), both of which are plain strings
+//! that serialize naturally to JSON.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    BackRankId, Color, Move, MoveId, Pos, PlayerBoard, Review, ReviewMut, Square, Turn,
+};
+
+fn parse_square(name: &str) -> Result<Square, JsError> {
+    name.parse().map_err(|e| JsError::new(&format!("{e}")))
+}
+
+fn parse_move(uci: &str) -> Result<Move, JsError> {
+    uci.parse().map_err(|e| JsError::new(&format!("{e}")))
+}
+
+fn color_name(color: Color) -> String {
+    match color {
+        Color::White => "white".to_string(),
+        Color::Black => "black".to_string(),
+    }
+}
+
+#[wasm_bindgen(js_name = PlayerBoard)]
+pub struct WasmPlayerBoard {
+    board: PlayerBoard,
+}
+
+#[wasm_bindgen(js_class = PlayerBoard)]
+impl WasmPlayerBoard {
+    /// Creates a board for the given side. `backrank_id` selects a
+    /// Chess960 start (0..960); omit it for standard chess.
+    #[wasm_bindgen(constructor)]
+    pub fn new(plays_white: bool, backrank_id: Option<u32>) -> Result<WasmPlayerBoard, JsError> {
+        let id = match backrank_id {
+            Some(index) => Some(
+                BackRankId::try_from(index as usize)
+                    .map_err(|e| JsError::new(&format!("{e}")))?
+            ),
+            None => None,
+        };
+        let board = if plays_white {
+            PlayerBoard::plays_white(id)
+        } else {
+            PlayerBoard::plays_black(id)
+        };
+        Ok(Self { board })
+    }
+
+    pub fn turn(&self) -> String {
+        color_name(self.board.turn())
+    }
+
+    #[wasm_bindgen(js_name = ourTurn)]
+    pub fn our_turn(&self) -> bool {
+        self.board.our_turn()
+    }
+
+    /// Returns the names of the squares the piece on `from` may move
+    /// (or pre-move) to.
+    #[wasm_bindgen(js_name = moveDestinations)]
+    pub fn move_destinations(&self, from: &str) -> Result<Vec<String>, JsError> {
+        let from = parse_square(from)?;
+        let destinations = self.board.move_destinations(from);
+        Ok(destinations.iter().map(|square| square.to_string()).collect())
+    }
+
+    /// Returns every move (or pre-move) our pieces may make, as the names
+    /// of the origin and destination squares, e.g. `"g1f3"`.
+    #[wasm_bindgen(js_name = allMoveDestinations)]
+    pub fn all_move_destinations(&self) -> Vec<String> {
+        let destinations = self.board.all_move_destinations();
+        (0..64).map(Square::from_index)
+            .flat_map(|from| destinations[from.to_index()].iter().map(move |to| format!("{from}{to}")))
+            .collect()
+    }
+
+    #[wasm_bindgen(js_name = submitOurMove)]
+    pub fn submit_our_move(&mut self, uci: &str) -> Result<(), JsError> {
+        let mv = parse_move(uci)?;
+        self.board.submit_our_move(mv)
+            .map_err(|e| JsError::new(&format!("{e}")))
+    }
+
+    #[wasm_bindgen(js_name = submitTheirMove)]
+    pub fn submit_their_move(&mut self, uci: &str) -> Result<(), JsError> {
+        let mv = parse_move(uci)?;
+        self.board.submit_their_move(mv)
+            .map_err(|e| JsError::new(&format!("{e}")))
+    }
+
+    #[wasm_bindgen(js_name = preMoves)]
+    pub fn pre_moves(&self) -> Vec<String> {
+        self.board.queued_pre_moves().iter().map(Move::to_string).collect()
+    }
+
+    #[wasm_bindgen(js_name = cancelPreMoves)]
+    pub fn cancel_pre_moves(&mut self) {
+        self.board.cancel_pre_moves()
+    }
+
+    /// Returns the 64 squares of the displayed position from a8 to h1 as
+    /// FEN piece letters, with `.` for empty squares.
+    pub fn pieces(&self) -> String {
+        let view = self.board.view();
+        (0..64)
+            .map(|index| match view.contents(Square::from_index(index)) {
+                Some(material) => material.to_char(),
+                None => '.',
+            })
+            .collect()
+    }
+
+    #[wasm_bindgen(js_name = reviewLength)]
+    pub fn review_len(&self) -> usize {
+        self.board.len()
+    }
+
+    #[wasm_bindgen(js_name = reviewOffset)]
+    pub fn review_offset(&self) -> usize {
+        self.board.offset().ply()
+    }
+
+    #[wasm_bindgen(js_name = setReviewOffset)]
+    pub fn set_review_offset(&mut self, offset: usize) -> Result<(), JsError> {
+        let offset = MoveId::from_ply(offset)
+            .ok_or_else(|| JsError::new("review offset out of range"))?;
+        self.board.set_offset(offset)
+            .map_err(|e| JsError::new(&format!("{e}")))
+    }
+
+    /// Steps forward one position; returns false at the latest position.
+    pub fn forward(&mut self) -> bool {
+        self.board.forward().is_ok()
+    }
+
+    /// Steps back one position; returns false at the starting position.
+    pub fn back(&mut self) -> bool {
+        self.board.back().is_ok()
+    }
+
+    #[wasm_bindgen(js_name = skipToStart)]
+    pub fn skip_to_start(&mut self) {
+        self.board.skip_to_start()
+    }
+
+    #[wasm_bindgen(js_name = skipToEnd)]
+    pub fn skip_to_end(&mut self) {
+        self.board.skip_to_end()
+    }
+}
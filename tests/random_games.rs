@@ -0,0 +1,108 @@
+// Copyright 2023 Tobin Edwards
+//
+//    Licensed under the Apache License, Version 2.0 (the "License");
+//    you may not use this file except in compliance with the License.
+//    You may obtain a copy of the License at
+//
+//        http://www.apache.org/licenses/LICENSE-2.0
+//
+//    Unless required by applicable law or agreed to in writing, software
+//    distributed under the License is distributed on an "AS IS" BASIS,
+//    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//    See the License for the specific language governing permissions and
+//    limitations under the License.
+
+//! Property tests that play random legal games, standard and Chess960,
+//! and check the rules' invariants after every move. The same checks
+//! back the fuzz target in `fuzz/`.
+
+use std::collections::HashSet;
+
+use chesslib::*;
+use proptest::prelude::{any, proptest, ProptestConfig, TestCaseError};
+
+/// Games are cut off after this many plies; the invariants are per
+/// position, so longer games add time rather than coverage.
+const MAX_PLIES: usize = 300;
+
+/// Checks the invariants of `state`, returning a description of the first
+/// one that doesn't hold.
+fn check_invariants(state: &MoveState) -> Result<(), String> {
+    let pos: &Position = state.as_ref();
+    pos.validate().map_err(|err| format!("{:?} in {}", err, pos.to_fen()))?;
+    for color in [Color::White, Color::Black] {
+        let kings = (pos.kings() & pos.occupied_by(color)).len();
+        if kings != 1 {
+            return Err(format!("{} {:?} kings in {}", kings, color, pos.to_fen()));
+        }
+    }
+    let json = serde_json::to_string(pos).map_err(|err| err.to_string())?;
+    let restored: Position = serde_json::from_str(&json).map_err(|err| err.to_string())?;
+    if restored.to_fen() != pos.to_fen() || serde_json::to_string(&restored).unwrap() != json {
+        return Err(format!("serde round trip changed {}", pos.to_fen()));
+    }
+    Ok(())
+}
+
+/// Checks that the legal moves of every square agree with the reference
+/// generator (see `reference_moves`).
+fn check_moves(state: &MoveState) -> Result<(), String> {
+    let pos: &Position = state.as_ref();
+    for from in (0..64).map(Square::from_index) {
+        let generated: HashSet<LegalMove> = state.legal_moves(from).values().copied().collect();
+        let reference: HashSet<LegalMove> = reference_moves(pos, from).into_iter().collect();
+        if generated != reference {
+            return Err(format!(
+                "moves from {} in {}: generated {:?}, reference {:?}",
+                from, pos.to_fen(), generated, reference,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Plays a random game from the back rank `id` with moves chosen by a
+/// `RandomMover` seeded with `seed`, applying `check` to every position
+/// reached.
+fn play_random_game<F>(id: BackRankId, seed: u64, check: F) -> Result<usize, String>
+where
+    F: Fn(&MoveState) -> Result<(), String>,
+{
+    let mut state = MoveState::new(Position::starting(id).clone());
+    let mut strategy = RandomMover::seeded(seed);
+    for ply in 0..MAX_PLIES {
+        check(&state)?;
+        let Some(mv) = strategy.choose_move(&state) else { return Ok(ply) };
+        let legal_move = state.validate_move(mv).map_err(|err| format!("{:?}: {}", mv, err))?;
+        state.apply_move(legal_move).map_err(|err| format!("{:?}: {}", legal_move, err))?;
+    }
+    check(&state)?;
+    Ok(MAX_PLIES)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn standard_games_keep_invariants(seed in any::<u64>()) {
+        play_random_game(BackRankId::default(), seed, check_invariants)
+            .map_err(TestCaseError::fail)?;
+    }
+
+    #[test]
+    fn chess960_games_keep_invariants(id in 0..960usize, seed in any::<u64>()) {
+        let id = BackRankId::try_from(id).unwrap();
+        play_random_game(id, seed, check_invariants).map_err(TestCaseError::fail)?;
+    }
+
+    #[test]
+    fn standard_games_match_reference(seed in any::<u64>()) {
+        play_random_game(BackRankId::default(), seed, check_moves).map_err(TestCaseError::fail)?;
+    }
+
+    #[test]
+    fn chess960_games_match_reference(id in 0..960usize, seed in any::<u64>()) {
+        let id = BackRankId::try_from(id).unwrap();
+        play_random_game(id, seed, check_moves).map_err(TestCaseError::fail)?;
+    }
+}